@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Result};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Most memory reserved up front for a member, whatever its header claims
+const MAX_PREALLOCATION: u64 = 1024 * 1024;
+
+/// Archive formats whose members can be read
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    /// .zip and .jar
+    Zip,
+    Tar,
+    /// .tar.gz and .tgz
+    TarGz,
+}
+
+/// Archive format of `path`, judged by its name
+pub fn kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") || name.ends_with(".jar") {
+        Some(Kind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Compression formats of single compressed files
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+/// Compression of `path` (.gz, .zst, .bz2), judged by its name
+pub fn compression(path: &Path) -> Option<Compression> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    match extension.as_str() {
+        "gz" => Some(Compression::Gzip),
+        "zst" => Some(Compression::Zstd),
+        "bz2" => Some(Compression::Bzip2),
+        _ => None,
+    }
+}
+
+/// Decompressed content of `path`, unless it comes to more than `max_size`
+pub fn decompress(path: &Path, compression: Compression, max_size: u64) -> Result<Member> {
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read> = match compression {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Zstd => {
+            Box::new(ruzstd::decoding::StreamingDecoder::new(file).map_err(|e| anyhow!("{}", e))?)
+        }
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(file)),
+    };
+    let mut data = Vec::new();
+    reader
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut data)?;
+    if data.len() as u64 > max_size {
+        return Ok(Member::TooLarge);
+    }
+    Ok(Member::Data(data))
+}
+
+/// One regular file inside an archive
+pub enum Member {
+    Data(Vec<u8>),
+    /// Larger than the size limit, so not read
+    TooLarge,
+}
+
+/// Call `visit` with the name and content of each regular file in the
+/// archive, in archive order. Members larger than `max_size` aren't read.
+pub fn for_each_member(
+    path: &Path,
+    kind: Kind,
+    max_size: u64,
+    mut visit: impl FnMut(&str, Member),
+) -> Result<()> {
+    let file = BufReader::new(File::open(path)?);
+    match kind {
+        Kind::Zip => {
+            let mut archive = zip::ZipArchive::new(file)?;
+            for i in 0..archive.len() {
+                let mut member = archive.by_index(i)?;
+                if !member.is_file() {
+                    continue;
+                }
+                let name = member.name().to_string();
+                let size = member.size();
+                visit(&name, read_member(&mut member, size, max_size)?);
+            }
+        }
+        Kind::Tar => tar_members(file, max_size, visit)?,
+        Kind::TarGz => tar_members(GzDecoder::new(file), max_size, visit)?,
+    }
+    Ok(())
+}
+
+fn tar_members(input: impl Read, max_size: u64, mut visit: impl FnMut(&str, Member)) -> Result<()> {
+    let mut archive = tar::Archive::new(input);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.display().to_string();
+        let size = entry.size();
+        visit(&name, read_member(&mut entry, size, max_size)?);
+    }
+    Ok(())
+}
+
+/// Content of a member whose header gives `size`. The header isn't trusted:
+/// the buffer grows as data arrives, and reading stops past `max_size`.
+fn read_member(member: &mut impl Read, size: u64, max_size: u64) -> Result<Member> {
+    if size > max_size {
+        return Ok(Member::TooLarge);
+    }
+    let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION) as usize);
+    member
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut data)?;
+    if data.len() as u64 > max_size {
+        return Ok(Member::TooLarge);
+    }
+    Ok(Member::Data(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(kind(Path::new("a/lib.JAR")), Some(Kind::Zip));
+        assert_eq!(kind(Path::new("logs.tar")), Some(Kind::Tar));
+        assert_eq!(kind(Path::new("logs.tar.gz")), Some(Kind::TarGz));
+        assert_eq!(kind(Path::new("src.tgz")), Some(Kind::TarGz));
+        assert_eq!(kind(Path::new("notes.gz")), None);
+        assert_eq!(kind(Path::new("zip")), None);
+    }
+
+    #[test]
+    fn test_decompress() {
+        use flate2::write::GzEncoder;
+        use std::fs;
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("ftools-gz-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log.1.gz");
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"first line\nsecond line\n").unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        assert_eq!(compression(&path), Some(Compression::Gzip));
+        assert!(matches!(
+            decompress(&path, Compression::Gzip, 1024).unwrap(),
+            Member::Data(data) if data == b"first line\nsecond line\n"
+        ));
+        assert!(matches!(
+            decompress(&path, Compression::Gzip, 10).unwrap(),
+            Member::TooLarge
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_for_each_member() {
+        use std::fs;
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("ftools-members-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let zip_path = dir.join("docs.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        writer.add_directory("notes/", options).unwrap();
+        writer.start_file("notes/a.txt", options).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.start_file("big.bin", options).unwrap();
+        writer.write_all(&[1; 64]).unwrap();
+        writer.finish().unwrap();
+
+        let tar_path = dir.join("docs.tar");
+        let mut builder = tar::Builder::new(File::create(&tar_path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "notes/a.txt", &b"hello"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(64);
+        header.set_cksum();
+        builder.append_data(&mut header, "big.bin", &[1; 64][..]).unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        for (path, kind) in [(&zip_path, Kind::Zip), (&tar_path, Kind::Tar)] {
+            let mut members = Vec::new();
+            for_each_member(path, kind, 16, |name, member| {
+                members.push((
+                    name.to_string(),
+                    match member {
+                        Member::Data(data) => Some(data),
+                        Member::TooLarge => None,
+                    },
+                ))
+            })
+            .unwrap();
+            assert_eq!(
+                members,
+                [
+                    ("notes/a.txt".to_string(), Some(b"hello".to_vec())),
+                    ("big.bin".to_string(), None),
+                ]
+            );
+        }
+
+        // A header claiming more than is there is read as what is there
+        let mut short = &b"abc"[..];
+        assert!(matches!(
+            read_member(&mut short, u64::MAX / 2, u64::MAX).unwrap(),
+            Member::Data(data) if data == b"abc"
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::index::{mtime_ns, HashIndex, IndexEntry};
+use crate::ui;
+
+/// Least time between two checkpoint writes while hashing
+const INTERVAL: Duration = Duration::from_secs(10);
+
+/// One file found by a `dupes` scan, after the size and extension filters
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IndexedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Device and inode when the file has other hardlinks
+    pub link: Option<(u64, u64)>,
+    /// Whether it came from a --reference directory
+    pub reference: bool,
+}
+
+/// A finished full hash, valid while the file keeps its size and mtime
+#[derive(Serialize, Deserialize)]
+struct FileHash {
+    size: u64,
+    mtime_ns: u64,
+    digest: String,
+}
+
+/// One line of a checkpoint file: the scan it belongs to comes first, then
+/// the file list, then the hashes in the order they finished
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Line {
+    Scan(Vec<String>),
+    File(IndexedFile),
+    Hash(PathBuf, FileHash),
+}
+
+/// Progress of a `dupes` scan, written while it runs so an interrupted scan
+/// can continue with `--resume` instead of walking and hashing everything again.
+/// Each scan has its own file; hashes are appended to it as they finish.
+#[derive(Default)]
+pub struct Checkpoint {
+    /// Roots, reference directories, filters and algorithm of the scan
+    scan: Vec<String>,
+    pub files: Vec<IndexedFile>,
+    hashes: HashMap<PathBuf, FileHash>,
+    /// Hashes recorded since the last save
+    unsaved: Vec<PathBuf>,
+    last_saved: Option<Instant>,
+}
+
+impl Checkpoint {
+    pub fn new(scan: Vec<String>) -> Self {
+        Checkpoint {
+            scan,
+            ..Self::default()
+        }
+    }
+
+    /// Stored next to the hash index, named after the scan so different
+    /// scans running at once keep apart
+    fn path(scan: &[String]) -> Option<PathBuf> {
+        let key = xxh3_64(scan.join("\0").as_bytes());
+        HashIndex::default_path()
+            .map(|p| p.with_file_name(format!("dupes-checkpoint-{:016x}.jsonl", key)))
+    }
+
+    /// The saved checkpoint, if there is one for the same scan. A line cut
+    /// short by an interrupted write ends it.
+    pub fn load(scan: &[String]) -> Option<Self> {
+        let text = fs::read_to_string(Self::path(scan)?).ok()?;
+        let mut lines = text.lines().map(serde_json::from_str::<Line>);
+        match lines.next()? {
+            Ok(Line::Scan(saved)) if saved == scan => {}
+            Ok(_) => return None,
+            Err(e) => {
+                ui::print_verbose(&format!("ignoring corrupt checkpoint ({})", e));
+                return None;
+            }
+        }
+
+        let mut checkpoint = Checkpoint::new(scan.to_vec());
+        for line in lines.map_while(Result::ok) {
+            match line {
+                Line::File(file) => checkpoint.files.push(file),
+                Line::Hash(path, hash) => {
+                    checkpoint.hashes.insert(path, hash);
+                }
+                Line::Scan(_) => break,
+            }
+        }
+        Some(checkpoint)
+    }
+
+    /// Write the whole checkpoint: the scan, its file list and the hashes so far
+    pub fn save_all(&mut self) -> Result<()> {
+        self.last_saved = Some(Instant::now());
+        self.unsaved.clear();
+        let Some(path) = Self::path(&self.scan) else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("jsonl.tmp");
+        let mut out = BufWriter::new(fs::File::create(&tmp)?);
+        write_line(&mut out, &Line::Scan(self.scan.clone()))?;
+        for file in &self.files {
+            serde_json::to_writer(&mut out, &Line::File(file.clone()))?;
+            out.write_all(b"\n")?;
+        }
+        for (path, hash) in &self.hashes {
+            write_hash(&mut out, path, hash)?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Append the hashes recorded since the last save
+    pub fn save(&mut self) -> Result<()> {
+        self.last_saved = Some(Instant::now());
+        let Some(path) = Self::path(&self.scan) else {
+            return Ok(());
+        };
+        if self.unsaved.is_empty() || !path.exists() {
+            return Ok(());
+        }
+        let file = fs::OpenOptions::new().append(true).open(&path)?;
+        let mut out = BufWriter::new(file);
+        for file in self.unsaved.drain(..) {
+            if let Some(hash) = self.hashes.get(&file) {
+                write_hash(&mut out, &file, hash)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Whether enough time passed since the last write to save again
+    pub fn is_due(&self) -> bool {
+        self.last_saved.is_none_or(|t| t.elapsed() >= INTERVAL)
+    }
+
+    /// Delete the saved checkpoint once a scan got past hashing
+    pub fn clear(&self) {
+        if let Some(path) = Self::path(&self.scan) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Number of finished full hashes
+    pub fn hash_count(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Remember the full hash in `entry`, if it has one for `algorithm`
+    pub fn record(&mut self, file: &Path, entry: &IndexEntry, algorithm: &str) {
+        if let Some(digest) = entry.digest(algorithm) {
+            self.hashes.insert(
+                file.to_path_buf(),
+                FileHash {
+                    size: entry.size,
+                    mtime_ns: entry.mtime_ns,
+                    digest: digest.to_string(),
+                },
+            );
+            self.unsaved.push(file.to_path_buf());
+        }
+    }
+
+    /// Finished hash of `file`, unless the file changed since
+    pub fn hash_of(&self, file: &Path) -> Option<&str> {
+        let hash = self.hashes.get(file)?;
+        let metadata = file.metadata().ok()?;
+        (metadata.len() == hash.size && mtime_ns(&metadata) == hash.mtime_ns)
+            .then_some(hash.digest.as_str())
+    }
+}
+
+fn write_line(out: &mut impl Write, line: &Line) -> Result<()> {
+    serde_json::to_writer(&mut *out, line)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// A hash line, written without copying the path and digest
+fn write_hash(out: &mut impl Write, path: &Path, hash: &FileHash) -> Result<()> {
+    serde_json::to_writer(&mut *out, &serde_json::json!({ "hash": [path, hash] }))?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
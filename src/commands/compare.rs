@@ -1,17 +1,111 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use colored::*;
+use indicatif::ProgressBar;
+use serde_json::{json, Value};
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
-use crate::ui::{self, chars};
-use crate::utils::{format_bytes, hash_file_sha256};
+use crate::exit::{self, Exit};
+use crate::output::{self, OutputArgs, Report};
+use crate::skipped;
+use crate::ui::{self, chars, Element, Themed, Verbosity};
+use crate::utils::{format_bytes, hash_file_sha256, walk_ok};
+
+/// Which kinds of differences make `diff` exit with a failure code
+#[derive(Default)]
+struct FailOn {
+    added: bool,
+    removed: bool,
+    modified: bool,
+}
+
+impl FailOn {
+    fn parse(kinds: &[String]) -> Result<Self> {
+        if kinds.is_empty() {
+            return Ok(FailOn {
+                added: true,
+                removed: true,
+                modified: true,
+            });
+        }
+
+        let mut fail_on = FailOn::default();
+        for kind in kinds {
+            match kind.trim() {
+                "added" => fail_on.added = true,
+                "removed" => fail_on.removed = true,
+                "modified" => fail_on.modified = true,
+                "any" => {
+                    fail_on.added = true;
+                    fail_on.removed = true;
+                    fail_on.modified = true;
+                }
+                other => {
+                    return Err(anyhow!(
+                        "Unknown --fail-on value: {}. Use added, removed, modified, or any",
+                        other
+                    ));
+                }
+            }
+        }
+        Ok(fail_on)
+    }
+}
+
+pub fn run(
+    dir1: &str,
+    dir2: &str,
+    content: bool,
+    diff_only: bool,
+    quiet: bool,
+    fail_on: Vec<String>,
+    output: &OutputArgs,
+) -> Result<()> {
+    let result = compare(dir1, dir2, content, diff_only, quiet, fail_on, output);
+    if !quiet {
+        return result;
+    }
+    // --quiet prints nothing, errors included: the exit code is the whole answer
+    result.map_err(|e| {
+        let code = e.downcast_ref::<Exit>().map_or(exit::ERROR, |exit| exit.code);
+        Exit::silent(code).into()
+    })
+}
+
+fn compare(
+    dir1: &str,
+    dir2: &str,
+    content: bool,
+    diff_only: bool,
+    quiet: bool,
+    fail_on: Vec<String>,
+    output: &OutputArgs,
+) -> Result<()> {
+    let fail_on = FailOn::parse(&fail_on)?;
+    for dir in [dir1, dir2] {
+        if !Path::new(dir).is_dir() {
+            return Err(anyhow!("Not a directory: {}", dir));
+        }
+    }
+    if quiet {
+        ui::set_verbosity(Verbosity::Quiet);
+    }
 
-pub fn run(dir1: &str, dir2: &str, content: bool, diff_only: bool) -> Result<()> {
     ui::print_start("Comparing directories", "");
-    println!("  {} {}", "A:".yellow(), dir1.blue());
-    println!("  {} {}", "B:".yellow(), dir2.blue());
-    println!();
+    if !ui::is_quiet() {
+        println!(
+            "  {} {}",
+            "A:".themed(Element::Section),
+            dir1.themed(Element::Path)
+        );
+        println!(
+            "  {} {}",
+            "B:".themed(Element::Section),
+            dir2.themed(Element::Path)
+        );
+        println!();
+    }
 
     let files1 = collect_files(dir1)?;
     let files2 = collect_files(dir2)?;
@@ -25,8 +119,16 @@ pub fn run(dir1: &str, dir2: &str, content: bool, diff_only: bool) -> Result<()>
 
     let mut modified = Vec::new();
     let mut identical = Vec::new();
+    let mut unreadable = 0usize;
+
+    let pb = if content {
+        ui::work_progress(in_both.len() as u64)
+    } else {
+        ProgressBar::hidden()
+    };
 
     for name in &in_both {
+        pb.inc(1);
         let path1 = &files1[**name];
         let path2 = &files2[**name];
 
@@ -40,13 +142,17 @@ pub fn run(dir1: &str, dir2: &str, content: bool, diff_only: bool) -> Result<()>
 
         if content {
             if size_match {
-                let hash1 = hash_file_sha256(path1).ok();
-                let hash2 = hash_file_sha256(path2).ok();
-
-                if hash1 == hash2 {
-                    identical.push(**name);
-                } else {
-                    modified.push(**name);
+                match (hash_file_sha256(path1), hash_file_sha256(path2)) {
+                    (Ok(hash1), Ok(hash2)) if hash1 == hash2 => identical.push(**name),
+                    (Ok(_), Ok(_)) => modified.push(**name),
+                    (Err(e), _) => {
+                        skipped::record_error(path1, &e);
+                        unreadable += 1;
+                    }
+                    (_, Err(e)) => {
+                        skipped::record_error(path2, &e);
+                        unreadable += 1;
+                    }
                 }
             } else {
                 modified.push(**name);
@@ -60,23 +166,82 @@ pub fn run(dir1: &str, dir2: &str, content: bool, diff_only: bool) -> Result<()>
         }
     }
 
+    pb.finish_and_clear();
+
     let total_changes = only_in_1.len() + only_in_2.len() + modified.len();
+    let failing = (fail_on.removed && !only_in_1.is_empty())
+        || (fail_on.added && !only_in_2.is_empty())
+        || (fail_on.modified && !modified.is_empty());
+    let outcome = || -> Result<()> {
+        if unreadable > 0 {
+            Err(Exit::with_message(
+                exit::ERROR,
+                format!("{} files could not be read", unreadable),
+            )
+            .into())
+        } else if failing {
+            Err(Exit::silent(exit::NEGATIVE).into())
+        } else {
+            Ok(())
+        }
+    };
+
+    let size_of = |path: Option<&PathBuf>| {
+        path.and_then(|p| p.metadata().ok())
+            .map(|m| json!(m.len()))
+            .unwrap_or(Value::Null)
+    };
+    let mut report = Report::new(&["status", "path", "size_a", "size_b"]);
+    let mut sections = vec![("only_in_a", &only_in_1), ("only_in_b", &only_in_2)];
+    let modified_refs: Vec<_> = modified.iter().collect();
+    let identical_refs: Vec<_> = identical.iter().collect();
+    sections.push(("modified", &modified_refs));
+    if !diff_only {
+        sections.push(("identical", &identical_refs));
+    }
+    for (status, names) in sections {
+        for name in names {
+            report.row(vec![
+                json!(status),
+                json!(name.display().to_string()),
+                size_of(files1.get(**name)),
+                size_of(files2.get(**name)),
+            ]);
+        }
+    }
+    output::write_report(output, &report)?;
+
+    if quiet {
+        return outcome();
+    }
 
     if total_changes == 0 {
         ui::print_success("Directories are identical");
-        return Ok(());
+        return outcome();
     }
 
     // Summary header
     ui::print_header("COMPARISON RESULT");
-    println!();
-    ui::print_kv_colored("Only in A", only_in_1.len().to_string().yellow().bold());
-    ui::print_kv_colored("Only in B", only_in_2.len().to_string().yellow().bold());
-    ui::print_kv_colored("Modified", modified.len().to_string().red().bold());
+    ui::print_blank();
+    ui::print_kv_colored(
+        "Only in A",
+        only_in_1.len().to_string().themed(Element::Warning).bold(),
+    );
+    ui::print_kv_colored(
+        "Only in B",
+        only_in_2.len().to_string().themed(Element::Warning).bold(),
+    );
+    ui::print_kv_colored(
+        "Modified",
+        modified.len().to_string().themed(Element::Error).bold(),
+    );
     if !diff_only {
-        ui::print_kv_colored("Identical", identical.len().to_string().green().bold());
+        ui::print_kv_colored(
+            "Identical",
+            identical.len().to_string().themed(Element::Success).bold(),
+        );
     }
-    println!();
+    ui::print_blank();
     ui::print_line(60);
 
     // Only in A
@@ -88,8 +253,8 @@ pub fn run(dir1: &str, dir2: &str, content: bool, diff_only: bool) -> Result<()>
             let size = path.metadata().map(|m| m.len()).unwrap_or(0);
             println!(
                 "  {} {} {}",
-                chars::CROSS_MARK.red(),
-                name.red(),
+                chars::CROSS_MARK.themed(Element::Error),
+                name.display().to_string().themed(Element::Error),
                 format!("({})", format_bytes(size)).dimmed()
             );
         }
@@ -104,8 +269,8 @@ pub fn run(dir1: &str, dir2: &str, content: bool, diff_only: bool) -> Result<()>
             let size = path.metadata().map(|m| m.len()).unwrap_or(0);
             println!(
                 "  {} {} {}",
-                chars::CHECK.green(),
-                name.green(),
+                chars::CHECK.themed(Element::Success),
+                name.display().to_string().themed(Element::Success),
                 format!("({})", format_bytes(size)).dimmed()
             );
         }
@@ -122,17 +287,17 @@ pub fn run(dir1: &str, dir2: &str, content: bool, diff_only: bool) -> Result<()>
             let size2 = path2.metadata().map(|m| m.len()).unwrap_or(0);
 
             let size_diff = if size2 > size1 {
-                format!("+{}", format_bytes(size2 - size1)).green()
+                format!("+{}", format_bytes(size2 - size1)).themed(Element::Success)
             } else if size1 > size2 {
-                format!("-{}", format_bytes(size1 - size2)).red()
+                format!("-{}", format_bytes(size1 - size2)).themed(Element::Error)
             } else {
-                "content differs".yellow()
+                "content differs".themed(Element::Warning)
             };
 
             println!(
                 "  {} {} [{}]",
-                chars::BULLET.yellow(),
-                name.yellow(),
+                chars::BULLET.themed(Element::Warning),
+                name.display().to_string().themed(Element::Warning),
                 size_diff
             );
         }
@@ -141,25 +306,31 @@ pub fn run(dir1: &str, dir2: &str, content: bool, diff_only: bool) -> Result<()>
     println!();
     ui::print_line(60);
 
-    Ok(())
+    outcome()
 }
 
-fn collect_files(base: &str) -> Result<HashMap<String, PathBuf>> {
+/// Files under `base` keyed by their path relative to it (kept as `PathBuf`
+/// so names that aren't valid UTF-8 still pair up exactly)
+fn collect_files(base: &str) -> Result<HashMap<PathBuf, PathBuf>> {
     let mut files = HashMap::new();
     let base_path = PathBuf::from(base);
+    let spinner = ui::scan_spinner(&format!("Indexing {}", base));
 
     for entry in WalkDir::new(base)
         .follow_links(false)
         .into_iter()
-        .filter_map(|e| e.ok())
+        .filter_map(walk_ok)
     {
+        spinner.inc(1);
         let path = entry.path();
-        if path.is_file() {
-            if let Ok(relative) = path.strip_prefix(&base_path) {
-                files.insert(relative.display().to_string(), path.to_path_buf());
-            }
+        if entry.file_type().is_file()
+            && let Ok(relative) = path.strip_prefix(&base_path)
+        {
+            files.insert(relative.to_path_buf(), path.to_path_buf());
         }
     }
 
+    spinner.finish_and_clear();
+
     Ok(files)
 }
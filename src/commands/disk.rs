@@ -1,97 +1,1030 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::Local;
 use colored::*;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
+use glob::Pattern;
+use ratatui::crossterm::cursor::MoveTo;
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{Clear, ClearType};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, Metadata};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use walkdir::WalkDir;
 
-use crate::ui;
-use crate::utils::{format_bytes, get_extension, parse_size, should_skip};
+use crate::output::{self, OutputArgs, Report};
+use crate::skipped;
+use crate::ui::{self, chars, Element, Themed};
+use crate::utils::{
+    allocated_size, disk_space, distinct_roots, format_bytes, get_extension, glob_matches,
+    hardlink_id, is_hidden, is_ignored_dir, is_sparse, owner_ids, parse_duration, parse_size,
+    path_glob, DiskSpace, OwnerNames,
+};
 
-struct DirSize {
-    path: String,
-    size: u64,
-    file_count: usize,
+/// Size flags from the command line
+pub struct DiskOptions {
+    pub top: usize,
+    pub by_type: bool,
+    pub by_owner: bool,
+    pub sort: String,
+    pub reverse: bool,
+    pub inodes: bool,
+    pub drilldown: bool,
+    pub hidden: bool,
+    pub no_default_ignores: bool,
+    pub exclude: Vec<String>,
+    pub min: Option<String>,
+    pub min_files: usize,
+    pub depth: usize,
+    pub direct_only: bool,
+    pub du: bool,
+    pub count_links: bool,
+    pub eta: bool,
+    pub one_file_system: bool,
+    pub json: Option<String>,
+    pub html: Option<String>,
+    pub save_snapshot: Option<String>,
+    pub watch: Option<String>,
+    pub diff_snapshot: Option<String>,
 }
 
-struct ExtSize {
-    extension: String,
+/// Bytes (apparent and allocated) and number of files
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct Usage {
     size: u64,
-    file_count: usize,
+    allocated: u64,
+    files: usize,
+    /// Files plus directories, links and other entries, each taking an inode
+    #[serde(default)]
+    inodes: u64,
 }
 
-pub fn run(
-    path: &str,
-    top: usize,
-    by_type: bool,
-    hidden: bool,
-    min: Option<String>,
-    csv_output: Option<String>,
-) -> Result<()> {
-    let min_size = match &min {
-        Some(s) => parse_size(s)?,
-        None => 0,
-    };
+impl Usage {
+    /// The usage of the single file at `path`
+    fn file(path: &Path, metadata: &Metadata) -> Self {
+        Usage {
+            size: metadata.len(),
+            allocated: allocated_size(path, metadata),
+            files: 1,
+            inodes: 1,
+        }
+    }
 
-    ui::print_start("Analyzing disk usage", path);
-    println!();
+    fn add(&mut self, other: &Usage) {
+        self.size += other.size;
+        self.allocated += other.allocated;
+        self.files += other.files;
+        self.inodes += other.inodes;
+    }
 
-    if by_type {
-        analyze_by_type(path, top, hidden, min_size, csv_output)
+    /// The size reports rank by: allocated with --du, apparent otherwise
+    fn bytes(&self, du: bool) -> u64 {
+        if du {
+            self.allocated
+        } else {
+            self.size
+        }
+    }
+}
+
+/// Header for the size column, or the apparent and on-disk ones with --du
+fn size_header(du: bool) -> String {
+    let size = format!("{:>12}", "SIZE".themed(Element::Header).bold());
+    if du {
+        format!("{}  {:>12}", size, "ON DISK".themed(Element::Header).bold())
     } else {
-        analyze_by_directory(path, top, hidden, min_size, csv_output)
+        size
     }
 }
 
-fn analyze_by_directory(
-    path: &str,
-    top: usize,
-    hidden: bool,
-    min_size: u64,
-    csv_output: Option<String>,
-) -> Result<()> {
-    let mut dir_sizes: HashMap<String, (u64, usize)> = HashMap::new();
-    let mut total_size = 0u64;
-    let mut total_files = 0usize;
+fn size_cells(usage: &Usage, du: bool) -> String {
+    let size = format!(
+        "{:>12}",
+        format_bytes(usage.size).themed(Element::Size).bold()
+    );
+    if du {
+        format!(
+            "{}  {:>12}",
+            size,
+            format_bytes(usage.allocated).themed(Element::Size).bold()
+        )
+    } else {
+        size
+    }
+}
 
-    for entry in WalkDir::new(path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let entry_path = entry.path();
+/// "Total: ..." line for the scanned files, and how hardlinks were counted
+fn print_total(scan: &Scan, du: bool, count_links: bool) {
+    let total = &scan.total;
+    let mut line = format!(
+        "Total: {} in {} files",
+        format_bytes(total.size).themed(Element::Size).bold(),
+        total.files.to_string().themed(Element::Success)
+    );
+    if du {
+        line.push_str(&format!(
+            ", {} on disk",
+            format_bytes(total.allocated).themed(Element::Size).bold()
+        ));
+    }
+    ui::print_info(&line);
+    print_excluded(scan, du);
+    print_volumes(scan, du);
+    print_denied(scan, du);
+    print_sparse(scan, du);
 
-        if !hidden && should_skip(entry_path, false) {
+    let extra = &scan.extra_links;
+    if extra.files == 0 {
+        return;
+    }
+    let links = if extra.files == 1 { "link" } else { "links" };
+    if count_links {
+        ui::print_info(&format!(
+            "Hardlinks counted in full, {} of it in {} extra {}",
+            format_bytes(extra.bytes(du)),
+            extra.files,
+            links
+        ));
+    } else {
+        ui::print_info(&format!(
+            "Hardlinked data counted once; {} extra {} would add {} if broken apart (--count-links)",
+            extra.files,
+            links,
+            format_bytes(extra.bytes(du))
+        ));
+    }
+}
+
+/// The volumes the roots are on: size, used and free, and how much of the
+/// used space the scanned files account for
+fn print_volumes(scan: &Scan, du: bool) {
+    let mut volumes: Vec<(DiskSpace, Vec<&PathBuf>)> = Vec::new();
+    for root in &scan.roots {
+        let Some(space) = disk_space(root) else {
             continue;
+        };
+        match volumes.iter_mut().find(|(v, _)| *v == space) {
+            Some((_, roots)) => roots.push(root),
+            None => volumes.push((space, vec![root])),
+        }
+    }
+    for (space, roots) in volumes {
+        let scanned: u64 = roots.iter().map(|r| scan.dirs[*r].total.bytes(du)).sum();
+        let share = scanned as f64 / space.used().max(1) as f64 * 100.0;
+        let names: Vec<String> = roots.iter().map(|r| r.display().to_string()).collect();
+        ui::print_info(&format!(
+            "Volume of {}: {} used of {}, {} free; scanned {:.1}% of the used space",
+            names.join(", "),
+            format_bytes(space.used()).themed(Element::Size),
+            format_bytes(space.total).themed(Element::Size),
+            format_bytes(space.available).themed(Element::Success),
+            share
+        ));
+    }
+}
+
+/// What --exclude left out, so the tree can be read as "without caches"
+/// while still knowing what they hold
+fn print_excluded(scan: &Scan, du: bool) {
+    if scan.excluded.is_empty() {
+        return;
+    }
+    let mut excluded = Usage::default();
+    for (_, usage) in &scan.excluded {
+        excluded.size += usage.size;
+        excluded.allocated += usage.allocated;
+        excluded.files += usage.files;
+    }
+    ui::print_info(&format!(
+        "Excluded: {} in {} files; the total would be {} with them",
+        format_bytes(excluded.bytes(du)).themed(Element::Size),
+        excluded.files.to_string().themed(Element::Success),
+        format_bytes(scan.total.bytes(du) + excluded.bytes(du))
+    ));
+    if scan.excluded.len() < 2 {
+        return;
+    }
+    let width = scan
+        .excluded
+        .iter()
+        .map(|(glob, _)| glob.len())
+        .max()
+        .unwrap_or(0);
+    for (glob, usage) in &scan.excluded {
+        println!(
+            "      {:<width$}  {} in {} files",
+            glob.themed(Element::Path),
+            format_bytes(usage.bytes(du)).themed(Element::Size),
+            usage.files,
+            width = width
+        );
+    }
+}
+
+/// Top-level directories with entries that could not be read, so the
+/// sizes shown for them (and the total) are read as lower bounds
+fn print_denied(scan: &Scan, du: bool) {
+    if scan.denied.is_empty() {
+        return;
+    }
+    let entries: usize = scan.denied.values().sum();
+    ui::print_warning(&format!(
+        "{} {} unreadable (permission denied); the sizes are lower bounds",
+        entries,
+        if entries == 1 { "entry" } else { "entries" }
+    ));
+    for (top, count) in &scan.denied {
+        let scanned = scan.dirs.get(top).map_or(0, |usage| usage.total.bytes(du));
+        println!(
+            "      {} under {} ({} unreadable {} not scanned)",
+            format!("≥ {}", format_bytes(scanned)).themed(Element::Size),
+            top.display().to_string().themed(Element::Path),
+            count,
+            if *count == 1 { "entry" } else { "entries" }
+        );
+    }
+}
+
+/// How many sparse files the total line lists by name
+const SPARSE_SHOWN: usize = 5;
+
+/// Sparse files, so a VM image isn't taken for 50 GiB of used space
+fn print_sparse(scan: &Scan, du: bool) {
+    if scan.sparse.is_empty() {
+        return;
+    }
+    let mut sparse = Usage::default();
+    for (_, usage) in &scan.sparse {
+        sparse.size += usage.size;
+        sparse.allocated += usage.allocated;
+        sparse.files += usage.files;
+    }
+    let hint = if du {
+        ""
+    } else {
+        " (--du ranks by the latter)"
+    };
+    ui::print_warning(&format!(
+        "{} sparse {}: {} apparent / {} allocated{}",
+        sparse.files,
+        if sparse.files == 1 { "file" } else { "files" },
+        format_bytes(sparse.size),
+        format_bytes(sparse.allocated),
+        hint
+    ));
+    for (path, usage) in scan.sparse.iter().take(SPARSE_SHOWN) {
+        println!(
+            "      {} {} apparent / {} allocated",
+            path.display().to_string().themed(Element::Path),
+            format_bytes(usage.size).themed(Element::Size),
+            format_bytes(usage.allocated).themed(Element::Muted)
+        );
+    }
+    if scan.sparse.len() > SPARSE_SHOWN {
+        println!(
+            "      {}",
+            format!("... {} more", scan.sparse.len() - SPARSE_SHOWN).dimmed()
+        );
+    }
+}
+
+/// Report columns for the size of each row
+fn size_columns(du: bool) -> &'static [&'static str] {
+    if du {
+        &["size_bytes", "allocated_bytes", "file_count"]
+    } else {
+        &["size_bytes", "file_count"]
+    }
+}
+
+fn size_values(usage: &Usage, du: bool) -> Vec<serde_json::Value> {
+    let mut values = vec![json!(usage.size)];
+    if du {
+        values.push(json!(usage.allocated));
+    }
+    values.push(json!(usage.files));
+    values
+}
+
+#[derive(Default)]
+struct DirUsage {
+    /// Files directly in the directory
+    direct: Usage,
+    /// Files anywhere below it
+    total: Usage,
+    children: Vec<PathBuf>,
+    /// `total` by lowercase extension, only collected for --drilldown
+    extensions: HashMap<String, Usage>,
+}
+
+/// Every directory under the roots with its sizes
+struct Scan {
+    roots: Vec<PathBuf>,
+    dirs: HashMap<PathBuf, DirUsage>,
+    /// Files by lowercase extension
+    extensions: HashMap<String, Usage>,
+    /// Files by owning user and by group; None where the platform has no
+    /// owner ids
+    users: HashMap<Option<u32>, Usage>,
+    groups: HashMap<Option<u32>, Usage>,
+    total: Usage,
+    /// Further paths to hardlinked data already counted once, which
+    /// would take this much more space if the links were broken apart
+    extra_links: Usage,
+    /// Files taking far less space on disk than their size, biggest first
+    sparse: Vec<(PathBuf, Usage)>,
+    /// Unreadable entries counted against the root or top-level directory
+    /// they are under
+    denied: BTreeMap<PathBuf, usize>,
+    /// Files left out by each --exclude glob
+    excluded: Vec<(String, Usage)>,
+}
+
+/// How the roots are walked and counted
+struct ScanOptions {
+    /// Include hidden files and directories
+    hidden: bool,
+    /// Include node_modules, target and the rest of the ignore list
+    ignored: bool,
+    /// --exclude globs; matching files and directories are walked but
+    /// counted apart from the tree
+    exclude: Vec<(Pattern, String)>,
+    /// Count every hardlink to the same data in full, like `du -l`
+    count_links: bool,
+    /// Keep an extension breakdown for every directory
+    drilldown: bool,
+    /// Count the entries first so progress can show an ETA
+    eta: bool,
+    /// Stay on the file system each root is on
+    one_file_system: bool,
+}
+
+/// How often (in entries) the progress line's byte count is refreshed
+const PROGRESS_EVERY: u64 = 256;
+
+impl Scan {
+    /// Walk the roots, leaving out hidden and ignored directories with all
+    /// their contents unless `hidden` or `ignored`. Hardlinked data counts
+    /// once, at the first path found to it.
+    fn run(paths: &[String], options: &ScanOptions) -> Self {
+        let mut scan = Scan {
+            roots: Vec::new(),
+            dirs: HashMap::new(),
+            extensions: HashMap::new(),
+            users: HashMap::new(),
+            groups: HashMap::new(),
+            total: Usage::default(),
+            extra_links: Usage::default(),
+            sparse: Vec::new(),
+            denied: BTreeMap::new(),
+            excluded: options
+                .exclude
+                .iter()
+                .map(|(_, glob)| (glob.clone(), Usage::default()))
+                .collect(),
+        };
+        let skip = |path: &Path| {
+            (!options.hidden && is_hidden(path)) || (!options.ignored && is_ignored_dir(path))
+        };
+        let walk = |root: &String| {
+            WalkDir::new(root)
+                .follow_links(false)
+                .same_file_system(options.one_file_system)
+                .into_iter()
+                .filter_entry(|e| e.depth() == 0 || !skip(e.path()))
+        };
+        let roots = distinct_roots(paths);
+
+        // A quick pass without metadata, so the real one knows how far it is
+        let total = options.eta.then(|| {
+            let spinner = ui::scan_spinner("Counting");
+            let mut count = 0;
+            for root in &roots {
+                for _ in walk(root).filter_map(Result::ok) {
+                    count += 1;
+                    spinner.inc(1);
+                }
+            }
+            spinner.finish_and_clear();
+            count
+        });
+
+        let mut linked = HashSet::new();
+        let progress = ui::scan_progress(total);
+        for root in &roots {
+            // The excluded directory being walked and the glob it matched
+            let mut excluding: Option<(PathBuf, usize)> = None;
+            for entry in walk(root) {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        scan.add_error(&err);
+                        continue;
+                    }
+                };
+                progress.inc(1);
+                if progress.position().is_multiple_of(PROGRESS_EVERY) {
+                    progress.set_message(format_bytes(scan.total.size));
+                }
+                let path = entry.path();
+                let inside = excluding
+                    .as_ref()
+                    .filter(|(dir, _)| path.starts_with(dir))
+                    .map(|(_, i)| *i);
+                let matched = inside.or_else(|| {
+                    let top = entry.depth() > 0;
+                    top.then(|| {
+                        options
+                            .exclude
+                            .iter()
+                            .position(|(p, _)| glob_matches(p, path))
+                    })
+                    .flatten()
+                });
+                if let Some(i) = matched {
+                    if inside.is_none() && entry.file_type().is_dir() {
+                        excluding = Some((path.to_path_buf(), i));
+                    }
+                    if entry.file_type().is_file()
+                        && let Ok(metadata) = entry.metadata()
+                    {
+                        scan.excluded[i].1.add(&Usage::file(path, &metadata));
+                    }
+                    continue;
+                }
+                if entry.file_type().is_dir() {
+                    scan.dirs.entry(path.to_path_buf()).or_default();
+                    if entry.depth() == 0 {
+                        scan.roots.push(path.to_path_buf());
+                    } else if let Some(parent) = path.parent().and_then(|p| scan.dirs.get_mut(p)) {
+                        parent.children.push(path.to_path_buf());
+                    }
+                    scan.add_inode(path, entry.depth());
+                    continue;
+                }
+                if !entry.file_type().is_file() {
+                    scan.add_inode(path, entry.depth());
+                    continue;
+                }
+                let metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        scan.add_error(&err);
+                        continue;
+                    }
+                };
+                let file = Usage::file(path, &metadata);
+                if let Some(id) = hardlink_id(&metadata)
+                    && !linked.insert(id)
+                {
+                    scan.extra_links.add(&file);
+                    if !options.count_links {
+                        continue;
+                    }
+                }
+                scan.total.add(&file);
+                if is_sparse(file.size, file.allocated) {
+                    scan.sparse.push((path.to_path_buf(), file));
+                }
+                let extension = get_extension(path);
+                scan.extensions
+                    .entry(extension.clone())
+                    .or_default()
+                    .add(&file);
+                let owner = owner_ids(&metadata);
+                scan.users
+                    .entry(owner.map(|(uid, _)| uid))
+                    .or_default()
+                    .add(&file);
+                scan.groups
+                    .entry(owner.map(|(_, gid)| gid))
+                    .or_default()
+                    .add(&file);
+                // A file given as a root has no directory to count it in
+                for (i, dir) in path.ancestors().skip(1).take(entry.depth()).enumerate() {
+                    if let Some(usage) = scan.dirs.get_mut(dir) {
+                        if i == 0 {
+                            usage.direct.add(&file);
+                        }
+                        usage.total.add(&file);
+                        if options.drilldown {
+                            usage
+                                .extensions
+                                .entry(extension.clone())
+                                .or_default()
+                                .add(&file);
+                        }
+                    }
+                }
+            }
         }
+        progress.finish_and_clear();
+        scan.sparse
+            .sort_by_key(|(_, usage)| std::cmp::Reverse(usage.size));
+        scan
+    }
 
-        if entry_path.is_file() {
-            if let Ok(metadata) = entry_path.metadata() {
-                let size = metadata.len();
-                total_size += size;
-                total_files += 1;
-
-                if let Some(parent) = entry_path.parent() {
-                    let parent_str = parent.display().to_string();
-                    let entry = dir_sizes.entry(parent_str).or_insert((0, 0));
-                    entry.0 += size;
-                    entry.1 += 1;
+    /// Record an entry the walk could not read; permission failures are
+    /// also counted against the top-level directory they are under
+    fn add_error(&mut self, err: &walkdir::Error) {
+        skipped::record_walk(err);
+        let denied = err
+            .io_error()
+            .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied);
+        if let Some(path) = err.path()
+            && denied
+            && let Some(top) = path.ancestors().nth(err.depth().saturating_sub(1))
+        {
+            *self.denied.entry(top.to_path_buf()).or_default() += 1;
+        }
+    }
+
+    /// Count a directory, link or other non-file entry against itself and
+    /// every directory above it (files are counted by `Usage::add`)
+    fn add_inode(&mut self, path: &Path, depth: usize) {
+        self.total.inodes += 1;
+        for (i, dir) in path.ancestors().take(depth + 1).enumerate() {
+            if let Some(usage) = self.dirs.get_mut(dir) {
+                if i == 1 {
+                    usage.direct.inodes += 1;
                 }
+                usage.total.inodes += 1;
             }
         }
     }
+}
+
+fn usage_json(usage: &Usage) -> serde_json::Value {
+    json!({
+        "size_bytes": usage.size,
+        "allocated_bytes": usage.allocated,
+        "file_count": usage.files,
+        "inode_count": usage.inodes,
+    })
+}
+
+/// `dir` and everything below it, biggest subdirectories first
+fn dir_json(scan: &Scan, dir: &Path) -> serde_json::Value {
+    let usage = &scan.dirs[dir];
+    let mut children: Vec<&PathBuf> = usage.children.iter().collect();
+    children.sort_by_key(|child| std::cmp::Reverse(scan.dirs[*child].total.size));
+    let name = dir.file_name().map_or_else(
+        || dir.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    json!({
+        "path": dir.display().to_string(),
+        "name": name,
+        "size_bytes": usage.total.size,
+        "allocated_bytes": usage.total.allocated,
+        "file_count": usage.total.files,
+        "direct": usage_json(&usage.direct),
+        "children": children.into_iter().map(|child| dir_json(scan, child)).collect::<Vec<_>>(),
+    })
+}
+
+/// The whole scanned tree as nested JSON (--json), not just the rows shown
+fn write_json_tree(scan: &Scan, file: &Path) -> Result<()> {
+    let tree = json!({
+        "total": usage_json(&scan.total),
+        "excluded": scan.excluded.iter().map(|(glob, usage)| {
+            let mut value = usage_json(usage);
+            value["glob"] = json!(glob);
+            value
+        }).collect::<Vec<_>>(),
+        "unreadable": scan.denied.iter().map(|(top, count)| json!({
+            "path": top.display().to_string(),
+            "entry_count": count,
+        })).collect::<Vec<_>>(),
+        "roots": scan.roots.iter().map(|root| dir_json(scan, root)).collect::<Vec<_>>(),
+    });
+    let mut out = BufWriter::new(File::create(file)?);
+    serde_json::to_writer_pretty(&mut out, &tree)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// The page --html writes; the scan is spliced in at the placeholder
+const TREEMAP_TEMPLATE: &str = include_str!("treemap.html");
+const TREEMAP_PLACEHOLDER: &str = "/*FTOOLS_DATA*/null";
+
+/// Subdirectories smaller than this share of their root are folded into one
+/// "(smaller)" box, which keeps the page light on trees with millions of
+/// folders
+const TREEMAP_MIN_SHARE: u64 = 5000;
+
+/// `dir` as a treemap node with short keys (name, path, size, files,
+/// children); files sitting directly in it become a "(files)" box
+fn treemap_node(scan: &Scan, dir: &Path, du: bool, min_bytes: u64) -> serde_json::Value {
+    let usage = &scan.dirs[dir];
+    let mut children: Vec<&PathBuf> = usage.children.iter().collect();
+    children.sort_by_key(|child| std::cmp::Reverse(scan.dirs[*child].total.bytes(du)));
+
+    let mut nodes = Vec::new();
+    let mut smaller = Usage::default();
+    for child in children {
+        let total = &scan.dirs[child].total;
+        if total.bytes(du) >= min_bytes.max(1) {
+            nodes.push(treemap_node(scan, child, du, min_bytes));
+        } else {
+            smaller.size += total.size;
+            smaller.allocated += total.allocated;
+            smaller.files += total.files;
+        }
+    }
+    for (name, extra) in [("(files)", &usage.direct), ("(smaller)", &smaller)] {
+        if extra.bytes(du) > 0 {
+            nodes.push(json!({ "n": name, "s": extra.bytes(du), "f": extra.files }));
+        }
+    }
+    nodes.sort_by_key(|node| std::cmp::Reverse(node["s"].as_u64().unwrap_or(0)));
+
+    let name = dir.file_name().map_or_else(
+        || dir.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    json!({
+        "n": name,
+        "p": dir.display().to_string(),
+        "s": usage.total.bytes(du),
+        "f": usage.total.files,
+        "c": nodes,
+    })
+}
 
-    let mut dirs: Vec<DirSize> = dir_sizes
+/// A self-contained treemap page (--html): data and script are inlined so
+/// the file opens anywhere without a network connection
+fn write_html_treemap(scan: &Scan, du: bool, file: &Path) -> Result<()> {
+    let min_bytes = scan.total.bytes(du) / TREEMAP_MIN_SHARE;
+    let data = json!({
+        "generated": Local::now().format("%Y-%m-%d %H:%M").to_string(),
+        "total": { "s": scan.total.bytes(du), "f": scan.total.files },
+        "roots": scan
+            .roots
+            .iter()
+            .map(|root| treemap_node(scan, root, du, min_bytes))
+            .collect::<Vec<_>>(),
+    });
+    // A "</script>" inside a file name would end the script block early
+    let data = serde_json::to_string(&data)?.replace("</", "<\\/");
+    let page = TREEMAP_TEMPLATE.replacen(TREEMAP_PLACEHOLDER, &data, 1);
+    std::fs::write(file, page)?;
+    Ok(())
+}
+
+/// Every directory's total at one point in time (--save-snapshot), to
+/// compare a later scan against
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    created: String,
+    roots: Vec<PathBuf>,
+    total: Usage,
+    /// Keyed by absolute path, so a later run from elsewhere still matches
+    dirs: BTreeMap<PathBuf, Usage>,
+}
+
+impl Snapshot {
+    fn from_scan(scan: &Scan) -> Self {
+        let absolute = |p: &Path| std::path::absolute(p).unwrap_or_else(|_| p.to_path_buf());
+        Snapshot {
+            created: Local::now().to_rfc3339(),
+            roots: scan.roots.iter().map(|root| absolute(root)).collect(),
+            total: scan.total,
+            dirs: scan
+                .dirs
+                .iter()
+                .map(|(dir, usage)| (absolute(dir), usage.total))
+                .collect(),
+        }
+    }
+
+    fn load(file: &Path) -> Result<Self> {
+        let data = std::fs::read(file).map_err(|e| anyhow!("{}: {}", file.display(), e))?;
+        serde_json::from_slice(&data)
+            .map_err(|e| anyhow!("{}: not a disk usage snapshot ({})", file.display(), e))
+    }
+
+    fn save(&self, file: &Path) -> Result<()> {
+        let mut out = BufWriter::new(File::create(file)?);
+        serde_json::to_writer(&mut out, self)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// A directory that grew or shrank between two snapshots
+struct Growth {
+    path: PathBuf,
+    before: u64,
+    now: u64,
+}
+
+impl Growth {
+    fn delta(&self) -> i128 {
+        self.now as i128 - self.before as i128
+    }
+}
+
+/// A child explaining at least this share of its parent's change stands in
+/// for the parent, so the list points at where the data actually landed
+const GROWTH_EXPLAINED: f64 = 0.9;
+
+/// Directories under `now`'s roots whose size changed since `before`,
+/// biggest growth first. Directories that are gone count as shrunk to 0.
+fn growth(before: &Snapshot, now: &Snapshot, du: bool) -> Vec<Growth> {
+    let in_roots = |dir: &Path| now.roots.iter().any(|root| dir.starts_with(root));
+    let mut dirs: BTreeMap<&PathBuf, (u64, u64)> = BTreeMap::new();
+    for (dir, usage) in before.dirs.iter().filter(|(dir, _)| in_roots(dir)) {
+        dirs.entry(dir).or_default().0 = usage.bytes(du);
+    }
+    for (dir, usage) in &now.dirs {
+        dirs.entry(dir).or_default().1 = usage.bytes(du);
+    }
+
+    let delta = |(before, now): (u64, u64)| now as i128 - before as i128;
+    // The biggest change in the same direction among each directory's children
+    let mut biggest_child: HashMap<&Path, i128> = HashMap::new();
+    for (dir, sizes) in &dirs {
+        if let Some(parent) = dir.parent() {
+            let change = delta(*sizes);
+            let best = biggest_child.entry(parent).or_default();
+            if change.abs() > best.abs() {
+                *best = change;
+            }
+        }
+    }
+
+    let mut changed: Vec<Growth> = dirs
+        .iter()
+        .filter(|(dir, sizes)| {
+            let change = delta(**sizes);
+            let child = biggest_child.get(dir.as_path()).copied().unwrap_or(0);
+            let explained = child.signum() == change.signum()
+                && child.abs() as f64 >= change.abs() as f64 * GROWTH_EXPLAINED;
+            change != 0 && !explained
+        })
+        .map(|(dir, (before, now))| Growth {
+            path: dir.to_path_buf(),
+            before: *before,
+            now: *now,
+        })
+        .collect();
+    changed.sort_by(|a, b| b.delta().cmp(&a.delta()).then_with(|| a.path.cmp(&b.path)));
+    changed
+}
+
+fn format_delta(delta: i128) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_bytes(delta.unsigned_abs() as u64))
+}
+
+/// Which directories grew or shrank since a snapshot (--diff-snapshot)
+fn analyze_growth(
+    scan: &Scan,
+    file: &Path,
+    before: &Snapshot,
+    view: &View,
+    output: &OutputArgs,
+) -> Result<()> {
+    let du = view.du;
+    let now = Snapshot::from_scan(scan);
+    let changes: Vec<Growth> = growth(before, &now, du)
         .into_iter()
-        .filter(|(_, (size, _))| *size >= min_size)
-        .map(|(path, (size, count))| DirSize {
-            path,
-            size,
-            file_count: count,
+        .filter(|g| g.delta().unsigned_abs() >= view.min_size as u128)
+        .collect();
+    let since = chrono::DateTime::parse_from_rfc3339(&before.created)
+        .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| before.created.clone());
+
+    ui::print_header("DISK USAGE GROWTH");
+    ui::print_blank();
+    ui::print_info(&format!(
+        "Since {} ({})",
+        since.themed(Element::Text),
+        file.display().to_string().themed(Element::Path)
+    ));
+    let (was, is) = (before.total.bytes(du), now.total.bytes(du));
+    ui::print_info(&format!(
+        "Total: {} -> {} ({})",
+        format_bytes(was),
+        format_bytes(is).themed(Element::Size).bold(),
+        format_delta(is as i128 - was as i128).themed(Element::Size)
+    ));
+    ui::print_blank();
+
+    if changes.is_empty() {
+        ui::print_success("No directory changed size");
+        return Ok(());
+    }
+
+    // Biggest growth and biggest shrink, top N of each
+    let grown: Vec<&Growth> = changes
+        .iter()
+        .filter(|g| g.delta() > 0)
+        .take(view.top)
+        .collect();
+    let shrunk: Vec<&Growth> = changes
+        .iter()
+        .rev()
+        .filter(|g| g.delta() < 0)
+        .take(view.top)
+        .collect();
+
+    println!(
+        "  {:>12}  {:>12}  {:>12}  {}",
+        "CHANGE".themed(Element::Header).bold(),
+        "BEFORE".themed(Element::Header).bold(),
+        "NOW".themed(Element::Header).bold(),
+        "DIRECTORY".themed(Element::Header).bold()
+    );
+    ui::print_line(80);
+    for (i, rows) in [&grown, &shrunk].into_iter().enumerate() {
+        if i == 1 && !grown.is_empty() && !shrunk.is_empty() {
+            ui::print_line(80);
+        }
+        for g in rows {
+            let change = format_delta(g.delta());
+            let change = if g.delta() > 0 {
+                change.themed(Element::Warning)
+            } else {
+                change.themed(Element::Success)
+            };
+            let now = if g.now == 0 && !now.dirs.contains_key(&g.path) {
+                "(gone)".themed(Element::Muted)
+            } else {
+                format_bytes(g.now).themed(Element::Size)
+            };
+            println!(
+                "  {:>12}  {:>12}  {:>12}  {}",
+                change,
+                format_bytes(g.before).themed(Element::Muted),
+                now,
+                g.path.display().to_string().themed(Element::Path)
+            );
+        }
+    }
+    ui::print_line(80);
+
+    let mut report = Report::new(&["directory", "before_bytes", "now_bytes", "change_bytes"]);
+    for g in grown.iter().chain(&shrunk) {
+        report.row(vec![
+            json!(g.path.display().to_string()),
+            json!(g.before),
+            json!(g.now),
+            json!(g.delta() as i64),
+        ]);
+    }
+    output::write_report(output, &report)
+}
+
+struct DirSize {
+    path: String,
+    usage: Usage,
+}
+
+struct ExtSize {
+    extension: String,
+    usage: Usage,
+}
+
+pub fn run(paths: &[String], options: DiskOptions, output: &OutputArgs) -> Result<()> {
+    let DiskOptions {
+        top,
+        by_type,
+        by_owner,
+        sort,
+        reverse,
+        inodes,
+        drilldown,
+        hidden,
+        no_default_ignores,
+        exclude,
+        min,
+        min_files,
+        depth,
+        direct_only,
+        du,
+        count_links,
+        eta,
+        one_file_system,
+        json,
+        html,
+        save_snapshot,
+        diff_snapshot,
+        watch,
+    } = options;
+    let min_size = match &min {
+        Some(s) => parse_size(s)?,
+        None => 0,
+    };
+    let sort = match sort.as_str() {
+        _ if inodes => SortKey::Inodes,
+        "size" => SortKey::Size,
+        "count" => SortKey::Count,
+        "name" => SortKey::Name,
+        "path" => SortKey::Path,
+        other => {
+            return Err(anyhow!(
+                "Invalid sort key: {} (expected size, count, name or path)",
+                other
+            ))
+        }
+    };
+
+    // Read it before the long scan, so a wrong path fails fast
+    let before = match &diff_snapshot {
+        Some(file) => Some(Snapshot::load(Path::new(file))?),
+        None => None,
+    };
+
+    let scan_options = ScanOptions {
+        hidden,
+        ignored: no_default_ignores,
+        exclude: exclude
+            .into_iter()
+            .map(|glob| Ok((path_glob(&glob)?, glob)))
+            .collect::<Result<_>>()?,
+        count_links,
+        drilldown,
+        eta,
+        one_file_system,
+    };
+    let view = View {
+        top,
+        min_size,
+        du,
+        count_links,
+        drilldown,
+        sort,
+        reverse,
+        min_files,
+        inodes,
+    };
+    if let Some(interval) = watch {
+        let seconds = parse_duration(&interval)?;
+        if seconds == 0 {
+            return Err(anyhow!("--watch interval must be at least 1s"));
+        }
+        return watch_tree(paths, &scan_options, depth, &view, seconds);
+    }
+
+    ui::print_start("Analyzing disk usage", &paths.join(" "));
+    ui::print_blank();
+
+    let scan = Scan::run(paths, &scan_options);
+    if let (Some(file), Some(before)) = (&diff_snapshot, &before) {
+        analyze_growth(&scan, Path::new(file), before, &view, output)?;
+    } else if by_type {
+        analyze_by_type(&scan, &view, output)?;
+    } else if by_owner {
+        analyze_by_owner(&scan, &view, output)?;
+    } else if direct_only {
+        analyze_by_directory(&scan, &view, output)?;
+    } else {
+        analyze_tree(&scan, depth, &view, None, output)?;
+    }
+    if let Some(file) = json {
+        write_json_tree(&scan, Path::new(&file))?;
+        ui::print_success(&format!("Directory tree saved to {}", file));
+    }
+    if let Some(file) = html {
+        write_html_treemap(&scan, du, Path::new(&file))?;
+        ui::print_success(&format!("Treemap saved to {}", file));
+    }
+    if let Some(file) = save_snapshot {
+        Snapshot::from_scan(&scan).save(Path::new(&file))?;
+        ui::print_success(&format!("Snapshot saved to {}", file));
+    }
+    Ok(())
+}
+
+/// Directories ranked by the size of the files directly in them
+fn analyze_by_directory(scan: &Scan, view: &View, output: &OutputArgs) -> Result<()> {
+    let View {
+        top, min_size, du, ..
+    } = *view;
+    let total_size = scan.total.bytes(du);
+
+    let mut dirs: Vec<DirSize> = scan
+        .dirs
+        .iter()
+        .filter(|(_, usage)| usage.direct.files > 0 && usage.direct.bytes(du) >= min_size)
+        .map(|(path, usage)| DirSize {
+            path: path.display().to_string(),
+            usage: usage.direct,
         })
         .collect();
 
-    dirs.sort_by(|a, b| b.size.cmp(&a.size));
+    view.sort(&mut dirs, |d| {
+        let name = Path::new(&d.path)
+            .file_name()
+            .map_or(Cow::Borrowed(d.path.as_str()), |n| n.to_string_lossy());
+        (&d.usage, name, Cow::Borrowed(d.path.as_str()))
+    });
     dirs.truncate(top);
 
     if dirs.is_empty() {
@@ -99,108 +1032,463 @@ fn analyze_by_directory(
         return Ok(());
     }
 
-    let max_size = dirs.first().map(|d| d.size).unwrap_or(1);
+    let max_size = dirs
+        .iter()
+        .map(|d| d.usage.bytes(du))
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let width = if du { 94 } else { 80 };
 
     // Print header
     ui::print_header("DISK USAGE BY DIRECTORY");
-    println!();
-    ui::print_info(&format!(
-        "Total: {} in {} files",
-        format_bytes(total_size).bright_green().bold(),
-        total_files.to_string().bright_green()
-    ));
-    println!();
+    ui::print_blank();
+    print_total(scan, du, view.count_links);
+    ui::print_blank();
 
     // Table
     println!(
-        "  {:>12}  {:>6}  {:22}  {}",
-        "SIZE".cyan().bold(),
-        "FILES".cyan().bold(),
+        "  {}  {:>6}  {:22}  {}",
+        size_header(du),
+        "FILES".themed(Element::Header).bold(),
         "".to_string(),
-        "DIRECTORY".cyan().bold()
+        "DIRECTORY".themed(Element::Header).bold()
     );
-    ui::print_line(80);
+    ui::print_line(width);
 
     for dir in &dirs {
-        let percentage = (dir.size as f64 / total_size as f64) * 100.0;
+        let size = dir.usage.bytes(du);
+        let percentage = (size as f64 / total_size.max(1) as f64) * 100.0;
         let bar_width = 20;
-        let filled = ((dir.size as f64 / max_size as f64) * bar_width as f64) as usize;
+        let filled = ((size as f64 / max_size as f64) * bar_width as f64) as usize;
         let bar = format!(
             "{}{}",
-            "━".repeat(filled).cyan(),
+            "━".repeat(filled).themed(Element::Progress),
             "─".repeat(bar_width - filled).dimmed()
         );
 
         println!(
-            "  {:>12}  {:>6}  {} {:>5.1}%  {}",
-            format_bytes(dir.size).bright_yellow().bold(),
-            dir.file_count.to_string().bright_white(),
+            "  {}  {:>6}  {} {:>5.1}%  {}",
+            size_cells(&dir.usage, du),
+            dir.usage.files.to_string().themed(Element::Text),
             bar,
             percentage,
-            dir.path.bright_black()
+            dir.path.themed(Element::Muted)
         );
     }
 
-    ui::print_line(80);
+    ui::print_line(width);
 
-    // CSV export
-    if let Some(csv_path) = csv_output {
-        let mut file = File::create(&csv_path)?;
-        writeln!(file, "directory,size_bytes,file_count")?;
-        for dir in &dirs {
-            writeln!(file, "\"{}\",{},{}", dir.path, dir.size, dir.file_count)?;
-        }
-        ui::print_success(&format!("Exported to {}", csv_path));
+    let mut columns = vec!["directory"];
+    columns.extend(size_columns(du));
+    let mut report = Report::new(&columns);
+    for dir in &dirs {
+        let mut row = vec![json!(dir.path)];
+        row.extend(size_values(&dir.usage, du));
+        report.row(row);
     }
+    output::write_report(output, &report)
+}
 
-    Ok(())
+/// One line of the directory tree; lines that only say how many smaller
+/// directories were left out have no path
+struct TreeRow {
+    label: String,
+    path: Option<PathBuf>,
+    usage: Usage,
 }
 
-fn analyze_by_type(
-    path: &str,
-    top: usize,
-    hidden: bool,
-    min_size: u64,
-    csv_output: Option<String>,
+/// Rescan every `seconds` and redraw the tree, marking directories whose
+/// size changed since the previous refresh; runs until interrupted
+fn watch_tree(
+    paths: &[String],
+    options: &ScanOptions,
+    depth: usize,
+    view: &View,
+    seconds: u64,
 ) -> Result<()> {
-    let mut ext_sizes: HashMap<String, (u64, usize)> = HashMap::new();
-    let mut total_size = 0u64;
+    let mut previous: Option<HashMap<PathBuf, u64>> = None;
+    loop {
+        let scan = Scan::run(paths, options);
+        execute!(io::stdout(), MoveTo(0, 0), Clear(ClearType::All))?;
+        ui::print_start(
+            &format!(
+                "[{}] Disk usage every {}s (Ctrl+C to stop)",
+                Local::now().format("%H:%M:%S"),
+                seconds
+            ),
+            &paths.join(" "),
+        );
+        ui::print_blank();
+        analyze_tree(
+            &scan,
+            depth,
+            view,
+            previous.as_ref(),
+            &OutputArgs::default(),
+        )?;
+        previous = Some(
+            scan.dirs
+                .iter()
+                .map(|(dir, usage)| (dir.clone(), view.measure(&usage.total)))
+                .collect(),
+        );
+        std::thread::sleep(Duration::from_secs(seconds));
+    }
+}
 
-    for entry in WalkDir::new(path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let entry_path = entry.path();
+/// "+12 MiB" / "-3 MiB" / "new" next to a directory in --watch, or nothing
+/// if its size held still. With `inodes` the sizes are inode counts.
+fn change_marker(
+    path: &Path,
+    size: u64,
+    previous: Option<&HashMap<PathBuf, u64>>,
+    inodes: bool,
+) -> String {
+    let Some(previous) = previous else {
+        return String::new();
+    };
+    let amount = |n: u64| {
+        if inodes {
+            format!("{} inodes", n)
+        } else {
+            format_bytes(n)
+        }
+    };
+    match previous.get(path) {
+        None => format!("  {}", "new".themed(Element::Info)),
+        Some(&before) if size > before => format!(
+            "  {}",
+            format!("▲ +{}", amount(size - before)).themed(Element::Warning)
+        ),
+        Some(&before) if size < before => format!(
+            "  {}",
+            format!("▼ -{}", amount(before - size)).themed(Element::Success)
+        ),
+        Some(_) => String::new(),
+    }
+}
+
+/// Each root with its subdirectories down to `depth` levels, sized by
+/// everything below them like du
+fn analyze_tree(
+    scan: &Scan,
+    depth: usize,
+    view: &View,
+    previous: Option<&HashMap<PathBuf, u64>>,
+    output: &OutputArgs,
+) -> Result<()> {
+    let du = view.du;
+    let mut rows = Vec::new();
+    for root in &scan.roots {
+        rows.push(TreeRow {
+            label: root.display().to_string(),
+            path: Some(root.clone()),
+            usage: scan.dirs[root].total,
+        });
+        if view.drilldown {
+            rows.push(TreeRow {
+                label: drilldown_label(&scan.dirs[root], du),
+                path: None,
+                usage: Usage::default(),
+            });
+        }
+        tree_rows(scan, root, depth, view, "", &mut rows);
+    }
 
-        if !hidden && should_skip(entry_path, false) {
+    if rows.is_empty() {
+        ui::print_warning("No directories found matching criteria");
+        return Ok(());
+    }
+
+    let width = if du { 94 } else { 80 };
+    ui::print_header("DISK USAGE BY DIRECTORY");
+    ui::print_blank();
+    print_total(scan, du, view.count_links);
+    if view.inodes {
+        ui::print_info(&format!(
+            "Inodes: {} (files, directories, links), ranked by count",
+            scan.total.inodes.to_string().themed(Element::Success)
+        ));
+    }
+    ui::print_blank();
+
+    println!(
+        "  {}  {:>6}  {:22}  {}",
+        size_header(du),
+        if view.inodes { "INODES" } else { "FILES" }
+            .themed(Element::Header)
+            .bold(),
+        "".to_string(),
+        "DIRECTORY".themed(Element::Header).bold()
+    );
+    ui::print_line(width);
+
+    let total_size = view.measure(&scan.total).max(1);
+    let skip = if du { 14 } else { 0 };
+    for row in &rows {
+        if row.path.is_none() {
+            println!(
+                "  {:>w$}  {:>6}  {:27}  {}",
+                "",
+                "",
+                "",
+                row.label.dimmed(),
+                w = 12 + skip
+            );
             continue;
         }
+        let size = view.measure(&row.usage);
+        let percentage = (size as f64 / total_size as f64) * 100.0;
+        let bar_width = 20;
+        let filled = ((size as f64 / total_size as f64) * bar_width as f64) as usize;
+        let bar = format!(
+            "{}{}",
+            "━".repeat(filled).themed(Element::Progress),
+            "─".repeat(bar_width - filled).dimmed()
+        );
 
-        if entry_path.is_file() {
-            if let Ok(metadata) = entry_path.metadata() {
-                let size = metadata.len();
-                total_size += size;
+        let marker = row
+            .path
+            .as_ref()
+            .map(|path| change_marker(path, size, previous, view.inodes))
+            .unwrap_or_default();
+        let label = if marker.is_empty() {
+            row.label.themed(Element::Muted)
+        } else {
+            row.label.themed(Element::Text).bold()
+        };
+        let count = if view.inodes {
+            row.usage.inodes
+        } else {
+            row.usage.files as u64
+        };
+        println!(
+            "  {}  {:>6}  {} {:>5.1}%  {}{}",
+            size_cells(&row.usage, du),
+            count.to_string().themed(Element::Text),
+            bar,
+            percentage,
+            label,
+            marker
+        );
+    }
+
+    ui::print_line(width);
+
+    let mut columns = vec!["directory"];
+    columns.extend(size_columns(du));
+    if view.inodes {
+        columns.push("inode_count");
+    }
+    let mut report = Report::new(&columns);
+    for (path, row) in rows
+        .iter()
+        .filter_map(|row| Some((row.path.as_ref()?, row)))
+    {
+        let mut values = vec![json!(path.display().to_string())];
+        values.extend(size_values(&row.usage, du));
+        if view.inodes {
+            values.push(json!(row.usage.inodes));
+        }
+        report.row(values);
+    }
+    output::write_report(output, &report)
+}
 
-                let ext = get_extension(entry_path);
-                let entry = ext_sizes.entry(ext).or_insert((0, 0));
-                entry.0 += size;
-                entry.1 += 1;
+/// What the report shows of the scan
+#[derive(Clone, Copy)]
+struct View {
+    /// Rows to show (per directory in the tree)
+    top: usize,
+    min_size: u64,
+    du: bool,
+    count_links: bool,
+    /// Follow each directory with its biggest extensions
+    drilldown: bool,
+    sort: SortKey,
+    reverse: bool,
+    /// Fewest files an extension needs for its own row (--min-files)
+    min_files: usize,
+    /// Measure directories by inodes instead of bytes (--inodes)
+    inodes: bool,
+}
+
+/// What rows are ranked by (--sort)
+#[derive(Clone, Copy)]
+enum SortKey {
+    Size,
+    /// Number of files, for spotting inode hogs
+    Count,
+    Name,
+    Path,
+    /// Entries of any kind, for inode exhaustion
+    Inodes,
+}
+
+impl View {
+    /// What a row's bar and share show: inodes with --inodes, else bytes
+    fn measure(&self, usage: &Usage) -> u64 {
+        if self.inodes {
+            usage.inodes
+        } else {
+            usage.bytes(self.du)
+        }
+    }
+
+    /// Sort rows by --sort (size and count biggest first, name and path
+    /// A to Z), flipped by --reverse; `key` gives a row's usage, name and
+    /// path
+    fn sort<T>(&self, rows: &mut [T], key: impl Fn(&T) -> (&Usage, Cow<'_, str>, Cow<'_, str>)) {
+        rows.sort_by(|a, b| {
+            let (a_usage, a_name, a_path) = key(a);
+            let (b_usage, b_name, b_path) = key(b);
+            let ord = match self.sort {
+                SortKey::Size => b_usage.bytes(self.du).cmp(&a_usage.bytes(self.du)),
+                SortKey::Count => b_usage.files.cmp(&a_usage.files),
+                SortKey::Inodes => b_usage.inodes.cmp(&a_usage.inodes),
+                SortKey::Name => a_name.to_lowercase().cmp(&b_name.to_lowercase()),
+                SortKey::Path => a_path.cmp(&b_path),
+            }
+            .then_with(|| a_path.cmp(&b_path));
+            if self.reverse {
+                ord.reverse()
+            } else {
+                ord
             }
+        });
+    }
+}
+
+/// How many extensions --drilldown lists per directory
+const DRILLDOWN_EXTENSIONS: usize = 3;
+
+/// "what kind of data makes this folder big": its biggest extensions with
+/// their size and share, on one line
+fn drilldown_label(usage: &DirUsage, du: bool) -> String {
+    let mut exts: Vec<(&String, &Usage)> = usage.extensions.iter().collect();
+    exts.sort_by(|a, b| b.1.bytes(du).cmp(&a.1.bytes(du)).then_with(|| a.0.cmp(b.0)));
+    let total = usage.total.bytes(du).max(1);
+    exts.iter()
+        .take(DRILLDOWN_EXTENSIONS)
+        .map(|(ext, ext_usage)| {
+            let name = if *ext == "(no ext)" {
+                ext.to_string()
+            } else {
+                format!(".{}", ext)
+            };
+            let size = ext_usage.bytes(du);
+            format!(
+                "{} {} ({:.0}%)",
+                name,
+                format_bytes(size),
+                size as f64 / total as f64 * 100.0
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+/// Rows for the biggest `top` subdirectories of `dir` (and theirs, down to
+/// `depth` levels), drawn as a tree under `prefix`
+fn tree_rows(
+    scan: &Scan,
+    dir: &Path,
+    depth: usize,
+    limits: &View,
+    prefix: &str,
+    rows: &mut Vec<TreeRow>,
+) {
+    if depth == 0 {
+        return;
+    }
+    let du = limits.du;
+    let mut children: Vec<(&PathBuf, Usage)> = scan.dirs[dir]
+        .children
+        .iter()
+        .map(|child| (child, scan.dirs[child].total))
+        .filter(|(_, usage)| usage.bytes(du) >= limits.min_size)
+        .collect();
+    limits.sort(&mut children, |(child, usage)| {
+        let name = child.file_name().unwrap_or_default().to_string_lossy();
+        (usage, name, child.to_string_lossy())
+    });
+    let hidden = children.len().saturating_sub(limits.top);
+    children.truncate(limits.top);
+
+    for (i, (child, usage)) in children.iter().enumerate() {
+        let last = i + 1 == children.len() && hidden == 0;
+        let name = child.file_name().unwrap_or_default().to_string_lossy();
+        let branch = if last {
+            chars::BL_CORNER
+        } else {
+            chars::T_RIGHT
+        };
+        rows.push(TreeRow {
+            label: format!("{}{}{} {}", prefix, branch, chars::H_LINE, name),
+            path: Some(child.to_path_buf()),
+            usage: *usage,
+        });
+        let indent = if last { "   " } else { "│  " };
+        if limits.drilldown {
+            rows.push(TreeRow {
+                label: format!(
+                    "{}{}{}",
+                    prefix,
+                    indent,
+                    drilldown_label(&scan.dirs[*child], du)
+                ),
+                path: None,
+                usage: Usage::default(),
+            });
         }
+        tree_rows(
+            scan,
+            child,
+            depth - 1,
+            limits,
+            &format!("{}{}", prefix, indent),
+            rows,
+        );
     }
+    if hidden > 0 {
+        rows.push(TreeRow {
+            label: format!(
+                "{}{}{} ... {} more",
+                prefix,
+                chars::BL_CORNER,
+                chars::H_LINE,
+                hidden
+            ),
+            path: None,
+            usage: Usage::default(),
+        });
+    }
+}
 
-    let mut exts: Vec<ExtSize> = ext_sizes
-        .into_iter()
-        .filter(|(_, (size, _))| *size >= min_size)
-        .map(|(ext, (size, count))| ExtSize {
-            extension: ext,
-            size,
-            file_count: count,
+/// The by-type row for extensions without a row of their own
+const OTHER_EXTENSIONS: &str = "(other)";
+
+fn analyze_by_type(scan: &Scan, view: &View, output: &OutputArgs) -> Result<()> {
+    let View {
+        top, min_size, du, ..
+    } = *view;
+    let total_size = scan.total.bytes(du);
+
+    let mut exts: Vec<ExtSize> = scan
+        .extensions
+        .iter()
+        .filter(|(_, usage)| usage.bytes(du) >= min_size && usage.files >= view.min_files)
+        .map(|(extension, usage)| ExtSize {
+            extension: extension.clone(),
+            usage: *usage,
         })
         .collect();
 
-    exts.sort_by(|a, b| b.size.cmp(&a.size));
+    view.sort(&mut exts, |e| {
+        let name = Cow::Borrowed(e.extension.as_str());
+        (&e.usage, name.clone(), name)
+    });
     exts.truncate(top);
 
     if exts.is_empty() {
@@ -208,64 +1496,289 @@ fn analyze_by_type(
         return Ok(());
     }
 
-    let max_size = exts.first().map(|e| e.size).unwrap_or(1);
+    // Whatever was filtered out or cut off, so the rows add up to the total
+    let mut other = scan.total;
+    for ext in &exts {
+        other.size -= ext.usage.size;
+        other.allocated -= ext.usage.allocated;
+        other.files -= ext.usage.files;
+    }
+
+    let max_size = exts
+        .iter()
+        .map(|e| e.usage.bytes(du))
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let width = if du { 84 } else { 70 };
+    if other.files > 0 {
+        exts.push(ExtSize {
+            extension: OTHER_EXTENSIONS.to_string(),
+            usage: other,
+        });
+    }
 
     // Print
     ui::print_header("DISK USAGE BY FILE TYPE");
-    println!();
-    ui::print_info(&format!(
-        "Total: {}",
-        format_bytes(total_size).bright_green().bold()
-    ));
-    println!();
+    ui::print_blank();
+    print_total(scan, du, view.count_links);
+    ui::print_blank();
 
     println!(
-        "  {:>8}  {:>12}  {:>6}  {:22}  {}",
-        "EXT".cyan().bold(),
-        "SIZE".cyan().bold(),
-        "FILES".cyan().bold(),
+        "  {:>8}  {}  {:>6}  {:22}  {}",
+        "EXT".themed(Element::Header).bold(),
+        size_header(du),
+        "FILES".themed(Element::Header).bold(),
         "".to_string(),
-        "%".cyan().bold()
+        "%".themed(Element::Header).bold()
     );
-    ui::print_line(70);
+    ui::print_line(width);
 
     for ext in &exts {
-        let percentage = (ext.size as f64 / total_size as f64) * 100.0;
+        let size = ext.usage.bytes(du);
+        let percentage = (size as f64 / total_size.max(1) as f64) * 100.0;
         let bar_width = 20;
-        let filled = ((ext.size as f64 / max_size as f64) * bar_width as f64) as usize;
+        let filled = ((size as f64 / max_size as f64) * bar_width as f64) as usize;
+        let filled = filled.min(bar_width);
         let bar = format!(
             "{}{}",
-            "━".repeat(filled).green(),
+            "━".repeat(filled).themed(Element::Progress),
             "─".repeat(bar_width - filled).dimmed()
         );
 
-        let ext_display = if ext.extension == "(no ext)" {
-            ext.extension.bright_black().to_string()
+        let ext_display = if ext.extension == "(no ext)" || ext.extension == OTHER_EXTENSIONS {
+            ext.extension.themed(Element::Muted).to_string()
         } else {
-            format!(".{}", ext.extension).bright_cyan().to_string()
+            format!(".{}", ext.extension)
+                .themed(Element::Info)
+                .to_string()
         };
 
         println!(
-            "  {:>8}  {:>12}  {:>6}  {}  {:>5.1}%",
+            "  {:>8}  {}  {:>6}  {}  {:>5.1}%",
             ext_display,
-            format_bytes(ext.size).bright_yellow().bold(),
-            ext.file_count.to_string().bright_white(),
+            size_cells(&ext.usage, du),
+            ext.usage.files.to_string().themed(Element::Text),
             bar,
             percentage
         );
     }
 
-    ui::print_line(70);
+    ui::print_line(width);
+
+    let mut columns = vec!["extension"];
+    columns.extend(size_columns(du));
+    let mut report = Report::new(&columns);
+    for ext in &exts {
+        let mut row = vec![json!(ext.extension)];
+        row.extend(size_values(&ext.usage, du));
+        report.row(row);
+    }
+    output::write_report(output, &report)
+}
+
+struct OwnerSize {
+    name: String,
+    id: Option<u32>,
+    usage: Usage,
+}
+
+/// Bytes and file counts per user and per group, for finding who fills a
+/// shared volume
+fn analyze_by_owner(scan: &Scan, view: &View, output: &OutputArgs) -> Result<()> {
+    let names = OwnerNames::load();
+    let rank = |owners: &HashMap<Option<u32>, Usage>, name: &dyn Fn(u32) -> String| {
+        let mut rows: Vec<OwnerSize> = owners
+            .iter()
+            .filter(|(_, usage)| usage.bytes(view.du) >= view.min_size)
+            .map(|(id, usage)| OwnerSize {
+                name: id.map_or_else(|| "(unknown)".to_string(), name),
+                id: *id,
+                usage: *usage,
+            })
+            .collect();
+        view.sort(&mut rows, |o| {
+            let name = Cow::Borrowed(o.name.as_str());
+            (&o.usage, name.clone(), name)
+        });
+        rows.truncate(view.top);
+        rows
+    };
+    let users = rank(&scan.users, &|uid| names.user(uid));
+    let groups = rank(&scan.groups, &|gid| names.group(gid));
+
+    if users.is_empty() {
+        ui::print_warning("No owners found matching criteria");
+        return Ok(());
+    }
 
-    // CSV export
-    if let Some(csv_path) = csv_output {
-        let mut file = File::create(&csv_path)?;
-        writeln!(file, "extension,size_bytes,file_count")?;
-        for ext in &exts {
-            writeln!(file, "\"{}\",{},{}", ext.extension, ext.size, ext.file_count)?;
+    ui::print_header("DISK USAGE BY OWNER");
+    ui::print_blank();
+    print_total(scan, view.du, view.count_links);
+    if cfg!(not(unix)) {
+        ui::print_info("File owners are only read on Unix");
+    }
+    ui::print_blank();
+    print_owner_table("USER", &users, scan, view);
+    ui::print_blank();
+    print_owner_table("GROUP", &groups, scan, view);
+
+    let mut columns = vec!["kind", "name", "id"];
+    columns.extend(size_columns(view.du));
+    let mut report = Report::new(&columns);
+    for (kind, rows) in [("user", &users), ("group", &groups)] {
+        for owner in rows {
+            let mut row = vec![json!(kind), json!(owner.name), json!(owner.id)];
+            row.extend(size_values(&owner.usage, view.du));
+            report.row(row);
         }
-        ui::print_success(&format!("Exported to {}", csv_path));
     }
+    output::write_report(output, &report)
+}
 
-    Ok(())
+fn print_owner_table(label: &str, owners: &[OwnerSize], scan: &Scan, view: &View) {
+    let du = view.du;
+    let total_size = scan.total.bytes(du);
+    let max_size = owners
+        .iter()
+        .map(|o| o.usage.bytes(du))
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let width = if du { 90 } else { 76 };
+
+    println!(
+        "  {:14}  {}  {:>6}  {:22}  {}",
+        label.themed(Element::Header).bold(),
+        size_header(du),
+        "FILES".themed(Element::Header).bold(),
+        "".to_string(),
+        "%".themed(Element::Header).bold()
+    );
+    ui::print_line(width);
+    for owner in owners {
+        let size = owner.usage.bytes(du);
+        let percentage = (size as f64 / total_size.max(1) as f64) * 100.0;
+        let bar_width = 20;
+        let filled = ((size as f64 / max_size as f64) * bar_width as f64) as usize;
+        let bar = format!(
+            "{}{}",
+            "━".repeat(filled).themed(Element::Progress),
+            "─".repeat(bar_width - filled).dimmed()
+        );
+        let name = if owner.id.is_some() {
+            owner.name.themed(Element::Info)
+        } else {
+            owner.name.themed(Element::Muted)
+        };
+        println!(
+            "  {:14}  {}  {:>6}  {}  {:>5.1}%",
+            name,
+            size_cells(&owner.usage, du),
+            owner.usage.files.to_string().themed(Element::Text),
+            bar,
+            percentage
+        );
+    }
+    ui::print_line(width);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_scan_totals() {
+        let dir = std::env::temp_dir().join(format!("ftools-disk-{}", std::process::id()));
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::create_dir_all(dir.join("node_modules/x")).unwrap();
+        fs::write(dir.join("a/one"), [0; 10]).unwrap();
+        fs::write(dir.join("a/b/two"), [0; 20]).unwrap();
+        fs::write(dir.join("node_modules/x/big"), [0; 100]).unwrap();
+
+        let options = |ignored, exclude: &[&str]| ScanOptions {
+            hidden: false,
+            ignored,
+            exclude: exclude
+                .iter()
+                .map(|glob| (path_glob(glob).unwrap(), glob.to_string()))
+                .collect(),
+            count_links: false,
+            drilldown: true,
+            eta: false,
+            one_file_system: false,
+        };
+        let scan = Scan::run(&[dir.display().to_string()], &options(false, &[]));
+        let a = &scan.dirs[&dir.join("a")];
+        assert_eq!((a.direct.size, a.total.size, a.total.files), (10, 30, 2));
+        assert_eq!(drilldown_label(a, false), "(no ext) 30 B (100%)");
+        // a itself, a/b and the two files
+        assert_eq!((a.direct.inodes, a.total.inodes), (2, 4));
+        assert_eq!(scan.dirs[&dir].total.size, 30);
+        assert!(!scan.dirs.contains_key(&dir.join("node_modules")));
+
+        let scan = Scan::run(&[dir.display().to_string()], &options(false, &["b"]));
+        assert_eq!(scan.dirs[&dir.join("a")].total.size, 10);
+        assert!(!scan.dirs.contains_key(&dir.join("a/b")));
+        let (_, excluded) = &scan.excluded[0];
+        assert_eq!((excluded.size, excluded.files), (20, 1));
+        // * doesn't reach across directories: a/b/two isn't "a*o"
+        let scan = Scan::run(&[dir.display().to_string()], &options(false, &["a*o"]));
+        assert_eq!(scan.dirs[&dir.join("a")].total.size, 30);
+        assert_eq!(scan.excluded[0].1.files, 0);
+
+        fs::hard_link(dir.join("a/one"), dir.join("a/b/link")).unwrap();
+        let scan = Scan::run(&[dir.display().to_string()], &options(true, &[]));
+        assert_eq!(scan.total.size, 130);
+        if cfg!(unix) {
+            assert_eq!((scan.extra_links.size, scan.extra_links.files), (10, 1));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_growth() {
+        let snapshot = |dirs: &[(&str, u64)]| Snapshot {
+            created: String::new(),
+            roots: vec![PathBuf::from("/data")],
+            total: Usage::default(),
+            dirs: dirs
+                .iter()
+                .map(|(dir, size)| {
+                    let usage = Usage {
+                        size: *size,
+                        ..Default::default()
+                    };
+                    (PathBuf::from(dir), usage)
+                })
+                .collect(),
+        };
+        let before = snapshot(&[
+            ("/data", 100),
+            ("/data/a", 50),
+            ("/data/a/cache", 10),
+            ("/data/old", 40),
+            ("/other", 5),
+        ]);
+        let now = snapshot(&[
+            ("/data", 1060),
+            ("/data/a", 1050),
+            ("/data/a/cache", 1010),
+            ("/data/new", 10),
+        ]);
+        let changes: Vec<(String, i128)> = growth(&before, &now, false)
+            .iter()
+            .map(|g| (g.path.display().to_string(), g.delta()))
+            .collect();
+        // /data and /data/a grew because of the cache; /other is not scanned
+        assert_eq!(
+            changes,
+            [
+                ("/data/a/cache".to_string(), 1000),
+                ("/data/new".to_string(), 10),
+                ("/data/old".to_string(), -40),
+            ]
+        );
+    }
 }
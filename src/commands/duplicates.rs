@@ -1,15 +1,53 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
 use colored::*;
-use indicatif::{ProgressBar, ProgressStyle};
+use glob::Pattern;
 use rayon::prelude::*;
+use regex::Regex;
 use serde::Serialize;
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use xxhash_rust::xxh3::xxh3_64;
 
-use crate::ui::{self, chars};
-use crate::utils::{format_bytes, hash_file_sha256, matches_extensions, should_skip};
+use crate::checkpoint::{Checkpoint, IndexedFile};
+use crate::config::Config;
+use crate::exit::{self, Exit};
+use crate::index::HashIndex;
+use crate::minhash::{candidate_pairs, Signature};
+use crate::output::{self, OutputArgs, Report};
+use crate::plan::{Action, Plan};
+use crate::quarantine;
+use crate::skipped;
+use crate::spill::{self, Record, Records, SizeSorter};
+use crate::ui::{self, chars, Element, Themed};
+use crate::utils::{
+    format_bytes, hardlink_id, input_files, matches_extensions, parse_size, STDIN_PATH,
+};
+
+/// Content hashes `--algorithm` accepts
+const ALGORITHMS: [&str; 3] = ["sha256", "blake3", "xxh3"];
+const SORT_KEYS: [&str; 3] = ["waste", "count", "size"];
+
+/// Bytes hashed from each end of a file by the partial-hash prefilter
+const PARTIAL_HASH_BLOCK: u64 = 64 * 1024;
+
+/// Bytes fingerprinted at each end of a file to find near-duplicate candidates
+const FINGERPRINT_LEN: u64 = 256;
+
+/// Candidates gathered before hashing a batch when scanning with --max-memory
+const STREAM_BATCH: usize = 10_000;
+
+/// Largest file read by --fuzzy-text; bigger ones are rarely plain text
+const FUZZY_TEXT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Files by size and full digest
+type HashGroups = HashMap<(u64, String), Vec<PathBuf>>;
 
 #[derive(Serialize)]
 struct DuplicateGroup {
@@ -20,222 +58,2544 @@ struct DuplicateGroup {
 
 #[derive(Serialize)]
 struct DuplicateReport {
+    algorithm: String,
     total_groups: usize,
     total_duplicates: usize,
     wasted_space: u64,
     groups: Vec<DuplicateGroup>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    near_duplicate_groups: Vec<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    similar_text: Vec<SimilarText>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hardlinked_groups: Vec<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct SimilarText {
+    similarity: f64,
+    files: [String; 2],
+}
+
+#[derive(Serialize)]
+struct DuplicateDirGroup {
+    hash: String,
+    files: usize,
+    size: u64,
+    dirs: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct DuplicateDirReport {
+    algorithm: String,
+    total_groups: usize,
+    total_duplicates: usize,
+    wasted_space: u64,
+    groups: Vec<DuplicateDirGroup>,
+}
+
+/// How far apart two file sizes may be and still count as near-duplicates
+#[derive(Clone, Copy)]
+enum Tolerance {
+    Bytes(u64),
+    Percent(f64),
+}
+
+impl Tolerance {
+    fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        match value.strip_suffix('%') {
+            Some(percent) => {
+                let percent: f64 = percent
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid size tolerance: {}", value))?;
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err(anyhow!("Size tolerance must be between 0% and 100%"));
+                }
+                Ok(Tolerance::Percent(percent))
+            }
+            None => Ok(Tolerance::Bytes(parse_size(value)?)),
+        }
+    }
+
+    /// Largest allowed size difference for a file of `size` bytes
+    fn max_diff(self, size: u64) -> u64 {
+        match self {
+            Tolerance::Bytes(bytes) => bytes,
+            Tolerance::Percent(percent) => (size as f64 * percent / 100.0) as u64,
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Tolerance::Bytes(bytes) => format_bytes(bytes),
+            Tolerance::Percent(percent) => format!("{}%", percent),
+        }
+    }
+}
+
+/// Which file of a duplicate group is kept by --delete/--hardlink/--symlink
+enum KeepStrategy {
+    Newest,
+    Oldest,
+    ShortestPath,
+    LongestPath,
+    In(PathBuf),
+    Matching(Regex),
+}
+
+impl KeepStrategy {
+    fn parse(value: &str) -> Result<Self> {
+        if let Some(dir) = value.strip_prefix("in:") {
+            let dir = Path::new(dir);
+            return Ok(KeepStrategy::In(
+                dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf()),
+            ));
+        }
+        if let Some(pattern) = value.strip_prefix("regex:") {
+            return Ok(KeepStrategy::Matching(Regex::new(pattern)?));
+        }
+        match value {
+            "newest" => Ok(KeepStrategy::Newest),
+            "oldest" => Ok(KeepStrategy::Oldest),
+            "shortest-path" => Ok(KeepStrategy::ShortestPath),
+            "longest-path" => Ok(KeepStrategy::LongestPath),
+            _ => Err(anyhow!(
+                "Invalid keep strategy: {}. Use newest, oldest, shortest-path, longest-path, in:<dir> or regex:<pattern>",
+                value
+            )),
+        }
+    }
+
+    /// Sort key; the file with the smallest key is kept
+    fn rank(&self, path: &Path) -> i128 {
+        let modified = || {
+            path.metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos() as i128)
+                .unwrap_or(0)
+        };
+        let length = path.as_os_str().len() as i128;
+        match self {
+            KeepStrategy::Newest => -modified(),
+            KeepStrategy::Oldest => modified(),
+            KeepStrategy::ShortestPath => length,
+            KeepStrategy::LongestPath => -length,
+            KeepStrategy::In(dir) => {
+                let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+                i128::from(!path.starts_with(dir))
+            }
+            KeepStrategy::Matching(re) => i128::from(!re.is_match(&path.to_string_lossy())),
+        }
+    }
+}
+
+/// Duplicate search flags from the command line
+pub struct DupesOptions {
+    pub min_size: String,
+    pub max_size: Option<String>,
+    pub extensions: Option<String>,
+    pub output: OutputArgs,
+    pub csv: Option<String>,
+    pub delete: bool,
+    pub hardlink: bool,
+    pub symlink: Option<String>,
+    pub move_to: Option<String>,
+    pub restore: Option<String>,
+    pub keep: Option<String>,
+    pub prompt: bool,
+    pub dry_run: bool,
+    pub yes: bool,
+    pub size_tolerance: Option<String>,
+    pub algorithm: String,
+    pub no_cache: bool,
+    pub reference: Vec<String>,
+    pub dirs: bool,
+    pub resume: bool,
+    pub paranoid: bool,
+    pub script: Option<String>,
+    pub max_memory: Option<String>,
+    pub sort: Option<String>,
+    pub limit: Option<usize>,
+    pub fuzzy_text: Option<String>,
+    pub protect: Vec<String>,
+    pub one_file_system: bool,
 }
 
-pub fn run(
-    path: &str,
-    min_size: u64,
+/// Which files are listed and how they are hashed
+struct ScanOptions<'a> {
+    paths: &'a [String],
+    reference: Vec<String>,
+    sizes: RangeInclusive<u64>,
     extensions: Option<String>,
-    output: Option<String>,
+    one_file_system: bool,
+    algorithm: String,
+    no_cache: bool,
+    resume: bool,
+    /// Memory budget of the file list (--max-memory)
+    max_memory: Option<u64>,
+    /// Keep files of every size for the near-duplicate and similar-text passes
+    keep_all: bool,
+}
+
+/// How duplicate groups are ordered and which copy of each is kept
+struct SelectOptions {
+    keep: Option<KeepStrategy>,
+    protect: Vec<Pattern>,
+    tolerance: Option<Tolerance>,
+    fuzzy_threshold: Option<f64>,
+    sort: Option<String>,
+    limit: Option<usize>,
+}
+
+/// What is done with the duplicates, if anything
+struct ActOptions {
     delete: bool,
-) -> Result<()> {
-    ui::print_start("Scanning for duplicates", path);
-    println!();
+    hardlink: bool,
+    /// Whether --symlink writes absolute links rather than relative ones
+    symlink_absolute: Option<bool>,
+    quarantine_dir: Option<PathBuf>,
+    prompt: bool,
+    dry_run: bool,
+    yes: bool,
+    paranoid: bool,
+    script: Option<String>,
+}
 
-    // Step 1: Collect all files and group by size
-    let mut size_groups: HashMap<u64, Vec<PathBuf>> = HashMap::new();
-    let mut file_count = 0u64;
+impl ActOptions {
+    fn linking(&self) -> bool {
+        self.hardlink || self.symlink_absolute.is_some()
+    }
 
-    for entry in WalkDir::new(path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-
-        if path.is_file() && !should_skip(path, false) {
-            if let Ok(metadata) = path.metadata() {
-                let size = metadata.len();
-
-                if size >= min_size && matches_extensions(path, &extensions) {
-                    size_groups
-                        .entry(size)
-                        .or_default()
-                        .push(path.to_path_buf());
-                    file_count += 1;
-                }
+    fn changes(&self) -> bool {
+        self.delete
+            || self.linking()
+            || self.quarantine_dir.is_some()
+            || self.prompt
+            || self.script.is_some()
+    }
+}
+
+/// Files of each size, from the checkpoint, memory or the spilled runs
+type SizeBuckets = Box<dyn Iterator<Item = io::Result<(u64, Vec<IndexedFile>)>>>;
+
+/// One file of a duplicate group
+struct GroupFile {
+    path: PathBuf,
+    /// Under a --reference directory
+    reference: bool,
+    /// Its digest came from the index or the checkpoint rather than from
+    /// reading it in this run
+    reused: bool,
+    /// It or one of its hardlinks matches --protect
+    protected: bool,
+    /// Its other hardlinks, handled along with it
+    links: Vec<PathBuf>,
+}
+
+impl GroupFile {
+    /// Every path of the file: the one compared, then its other hardlinks
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.path).chain(&self.links)
+    }
+
+    /// Files kept unless chosen otherwise: every reference copy, else the
+    /// first file of the group, and every protected file
+    fn kept_by_default(&self, first: bool, by_reference: bool) -> bool {
+        self.protected || if by_reference { self.reference } else { first }
+    }
+}
+
+/// Files of the same size and digest
+struct Group {
+    digest: String,
+    size: u64,
+    files: Vec<GroupFile>,
+}
+
+impl Record for Group {
+    /// size, digest, file count, then per file its flags (reference,
+    /// reused, protected), path, link count and links
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        spill::write_u64(out, self.size)?;
+        spill::write_bytes(out, self.digest.as_bytes())?;
+        spill::write_u64(out, self.files.len() as u64)?;
+        for file in &self.files {
+            let flags = u8::from(file.reference)
+                | u8::from(file.reused) << 1
+                | u8::from(file.protected) << 2;
+            out.write_all(&[flags])?;
+            spill::write_path(out, &file.path)?;
+            spill::write_u64(out, file.links.len() as u64)?;
+            for link in &file.links {
+                spill::write_path(out, link)?;
             }
         }
+        Ok(())
     }
 
-    println!(
-        "  {} {} files indexed",
-        chars::BULLET.bright_blue(),
-        file_count.to_string().bright_green().bold()
-    );
+    fn read(input: &mut dyn Read) -> io::Result<Option<Self>> {
+        let Some(size) = spill::read_first_u64(input)? else {
+            return Ok(None);
+        };
+        let digest = String::from_utf8_lossy(&spill::read_bytes(input)?).into_owned();
+        let mut files = Vec::new();
+        for _ in 0..spill::read_u64(input)? {
+            let mut flags = [0u8];
+            input.read_exact(&mut flags)?;
+            let path = spill::read_path(input)?;
+            let links = (0..spill::read_u64(input)?)
+                .map(|_| spill::read_path(input))
+                .collect::<io::Result<_>>()?;
+            files.push(GroupFile {
+                path,
+                reference: flags[0] & 1 != 0,
+                reused: flags[0] & 2 != 0,
+                protected: flags[0] & 4 != 0,
+                links,
+            });
+        }
+        Ok(Some(Group {
+            digest,
+            size,
+            files,
+        }))
+    }
+}
 
-    // Step 2: Filter groups with more than one file (potential duplicates)
-    let potential_dupes: Vec<(u64, Vec<PathBuf>)> = size_groups
-        .into_iter()
-        .filter(|(_, files)| files.len() > 1)
-        .collect();
+/// Paths that are already hardlinks of one file of `size` bytes
+struct LinkGroup {
+    size: u64,
+    paths: Vec<PathBuf>,
+}
+
+impl Record for LinkGroup {
+    /// size, path count, paths
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        spill::write_u64(out, self.size)?;
+        spill::write_u64(out, self.paths.len() as u64)?;
+        for path in &self.paths {
+            spill::write_path(out, path)?;
+        }
+        Ok(())
+    }
+
+    fn read(input: &mut dyn Read) -> io::Result<Option<Self>> {
+        let Some(size) = spill::read_first_u64(input)? else {
+            return Ok(None);
+        };
+        let paths = (0..spill::read_u64(input)?)
+            .map(|_| spill::read_path(input))
+            .collect::<io::Result<_>>()?;
+        Ok(Some(LinkGroup { size, paths }))
+    }
+}
+
+/// What hashing found: the groups of files with the same size and digest,
+/// in memory or with --max-memory on disk
+struct Found {
+    groups: Records<Group>,
+    hardlinked: Records<LinkGroup>,
+    /// Files of every size, kept for the near-duplicate pass
+    all_files: Vec<(PathBuf, u64)>,
+}
+
+/// The duplicate groups to report, each ordered so the file to keep comes first
+struct Duplicates {
+    /// Groups after --limit
+    groups: Records<Group>,
+    /// Groups found, before --limit
+    total_groups: usize,
+    total_duplicates: usize,
+    wasted_space: u64,
+    near_duplicates: Vec<Vec<(PathBuf, u64)>>,
+    similar_text: Vec<(f64, PathBuf, PathBuf)>,
+    hardlinked: Records<LinkGroup>,
+    /// Whether any file is protected by --protect
+    protected: bool,
+    /// Whether --reference was given, so reference copies rather than the
+    /// first file of each group are kept
+    by_reference: bool,
+}
+
+impl Duplicates {
+    fn is_empty(&self) -> bool {
+        self.groups.is_empty() && self.near_duplicates.is_empty() && self.similar_text.is_empty()
+    }
+
+    /// Files of a group that go unless chosen otherwise
+    fn dupes_in(&self, group: &Group) -> usize {
+        group
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(i, f)| !f.kept_by_default(*i == 0, self.by_reference))
+            .count()
+    }
+}
+
+pub fn run(paths: &[String], options: DupesOptions) -> Result<()> {
+    let DupesOptions {
+        min_size,
+        max_size,
+        extensions,
+        output,
+        csv,
+        delete,
+        hardlink,
+        symlink,
+        move_to,
+        restore,
+        keep,
+        prompt,
+        dry_run,
+        yes,
+        size_tolerance,
+        algorithm,
+        no_cache,
+        reference,
+        dirs,
+        resume,
+        paranoid,
+        script,
+        max_memory,
+        sort,
+        limit,
+        fuzzy_text,
+        protect,
+        one_file_system,
+    } = options;
+
+    let algorithm = algorithm.to_lowercase();
+    if !ALGORITHMS.contains(&algorithm.as_str()) {
+        return Err(anyhow!(
+            "Unsupported algorithm: {}. Use {}",
+            algorithm,
+            ALGORITHMS.join(", ")
+        ));
+    }
+
+    if let Some(dir) = restore {
+        return quarantine::restore(Path::new(&dir));
+    }
+    if dirs {
+        return run_dirs(
+            paths,
+            &output,
+            csv.as_deref(),
+            &algorithm,
+            no_cache,
+            one_file_system,
+        );
+    }
+    let symlink_absolute = match symlink.as_deref() {
+        None => None,
+        Some("relative") => Some(false),
+        Some("absolute") => Some(true),
+        Some(mode) => {
+            return Err(anyhow!(
+                "Invalid symlink mode: {}. Use relative or absolute",
+                mode
+            ))
+        }
+    };
+    let act = ActOptions {
+        delete,
+        hardlink,
+        symlink_absolute,
+        quarantine_dir: move_to.map(PathBuf::from),
+        prompt,
+        dry_run,
+        yes,
+        paranoid,
+        script,
+    };
 
-    if potential_dupes.is_empty() {
+    // The config file may protect paths, so nothing is changed when it can't be read
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) if act.changes() => return Err(e),
+        Err(_) => Config::default(),
+    };
+    let protect = compile_protect(protect.iter().chain(&config.dupes.protect))?;
+
+    let min_size = parse_size(&min_size)?;
+    let max_size = max_size.as_deref().map(parse_size).transpose()?;
+    if max_size.is_some_and(|max| max < min_size) {
+        return Err(anyhow!("--max-size must not be smaller than --min-size"));
+    }
+    let max_memory = max_memory.as_deref().map(parse_size).transpose()?;
+
+    let keep = keep.as_deref().map(KeepStrategy::parse).transpose()?;
+    if let Some(key) = &sort
+        && !SORT_KEYS.contains(&key.as_str())
+    {
+        return Err(anyhow!(
+            "Unknown sort key: {}. Use {}",
+            key,
+            SORT_KEYS.join(", ")
+        ));
+    }
+    let tolerance = size_tolerance
+        .as_deref()
+        .map(Tolerance::parse)
+        .transpose()?;
+    let fuzzy_threshold = fuzzy_text.as_deref().map(parse_similarity).transpose()?;
+
+    let scan = ScanOptions {
+        paths,
+        reference,
+        sizes: min_size..=max_size.unwrap_or(u64::MAX),
+        extensions,
+        one_file_system,
+        algorithm,
+        no_cache,
+        resume,
+        max_memory,
+        keep_all: tolerance.is_some() || fuzzy_threshold.is_some(),
+    };
+    let select = SelectOptions {
+        keep,
+        protect,
+        tolerance,
+        fuzzy_threshold,
+        sort,
+        limit,
+    };
+
+    ui::print_start("Scanning for duplicates", &paths.join(" "));
+    ui::print_blank();
+
+    let (buckets, checkpoint) = scan_files(&scan)?;
+    let Some(found) = hash_files(buckets, checkpoint, &scan)? else {
+        ui::print_success("No duplicate files found");
+        return Ok(());
+    };
+    let duplicates = select_duplicates(found, &select, !scan.reference.is_empty())?;
+    if duplicates.is_empty() {
         ui::print_success("No duplicate files found");
         return Ok(());
     }
 
-    let total_to_hash: usize = potential_dupes.iter().map(|(_, f)| f.len()).sum();
-    println!(
-        "  {} {} candidates with matching sizes",
-        chars::BULLET.bright_yellow(),
-        total_to_hash.to_string().bright_yellow().bold()
-    );
+    if ui::is_path_list() {
+        print_paths(&duplicates)?;
+    } else {
+        print_report(&duplicates, &select)?;
+    }
+    // Write the report files if requested
+    if output.output.is_some() || csv.is_some() {
+        write_reports(&duplicates, &scan.algorithm, &output, csv.as_deref())?;
+    }
+
+    // Delete, link or move the duplicates, keeping one copy per group, if requested
+    if act.changes() {
+        return act_on(duplicates, &act, &scan.algorithm);
+    }
+    Ok(())
+}
 
-    // Step 3: Calculate hashes for potential duplicates
-    let pb = ProgressBar::new(total_to_hash as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("  [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len}")?
-            .progress_chars("━━─"),
+/// Step 1: Collect all files to be grouped by size. Reference trees are
+/// indexed first; scanned files inside them count as reference files. A
+/// resumed scan takes the file list from its checkpoint instead, and with
+/// --max-memory the list is sorted by size on disk.
+fn scan_files(scan: &ScanOptions) -> Result<(SizeBuckets, Checkpoint)> {
+    let key = checkpoint_scan(
+        scan.paths,
+        &scan.reference,
+        &scan.sizes,
+        &scan.extensions,
+        &scan.algorithm,
     );
+    let resumed = if scan.resume {
+        let saved = Checkpoint::load(&key);
+        if saved.is_none() {
+            ui::print_warning("No checkpoint for this scan, starting from the beginning");
+        }
+        saved
+    } else {
+        None
+    };
+    let mut checkpoint = Checkpoint::new(key);
+    let index = |emit: &mut dyn FnMut(IndexedFile) -> io::Result<()>| {
+        index_files(
+            scan.paths,
+            &scan.reference,
+            &scan.sizes,
+            &scan.extensions,
+            scan.one_file_system,
+            emit,
+        )
+    };
 
-    let mut hash_groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    if let Some(budget) = scan.max_memory {
+        let mut sorter = SizeSorter::new(budget);
+        index(&mut |file| sorter.push(file))?;
+        if sorter.spills() > 0 {
+            ui::print_verbose(&format!(
+                "file list spilled to disk in {} runs",
+                sorter.spills()
+            ));
+        }
+        return Ok((Box::new(sorter.into_groups()?), checkpoint));
+    }
 
-    for (_, files) in potential_dupes {
-        let hashes: Vec<(PathBuf, Option<String>)> = files
-            .par_iter()
-            .map(|f| {
-                let hash = hash_file_sha256(f).ok();
-                pb.inc(1);
-                (f.clone(), hash)
+    match resumed {
+        Some(saved) => {
+            checkpoint = saved;
+            if !ui::is_quiet() {
+                println!(
+                    "  {} Resuming: {} files indexed, {} hashes done",
+                    chars::BULLET.themed(Element::Info),
+                    checkpoint
+                        .files
+                        .len()
+                        .to_string()
+                        .themed(Element::Success)
+                        .bold(),
+                    checkpoint
+                        .hash_count()
+                        .to_string()
+                        .themed(Element::Success)
+                        .bold()
+                );
+            }
+        }
+        None => {
+            index(&mut |file| {
+                checkpoint.files.push(file);
+                Ok(())
+            })?;
+            if let Err(e) = checkpoint.save_all() {
+                ui::print_verbose(&format!("could not save the checkpoint ({})", e));
+            }
+        }
+    }
+    // The file list is saved by now; later saves only add hashes
+    let mut by_size: HashMap<u64, Vec<IndexedFile>> = HashMap::new();
+    for file in std::mem::take(&mut checkpoint.files) {
+        by_size.entry(file.size).or_default().push(file);
+    }
+    Ok((Box::new(by_size.into_iter().map(Ok)), checkpoint))
+}
+
+/// Same-size files waiting to be hashed, with what is known about them
+#[derive(Default)]
+struct Batch {
+    groups: Vec<(u64, Vec<PathBuf>)>,
+    files: usize,
+    /// Files under a --reference directory
+    references: HashSet<PathBuf>,
+    /// The other hardlinks of each file
+    links: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+/// Steps 2 to 4: Keep sizes shared by more than one file, then group those
+/// files by partial and full hashes. With --max-memory they are hashed a
+/// batch at a time while the sizes stream in, and the groups found go to
+/// disk; otherwise all are hashed together once every size is known. `None`
+/// when no two files share a size and there is no near-duplicate pass to run.
+fn hash_files(
+    buckets: SizeBuckets,
+    mut checkpoint: Checkpoint,
+    scan: &ScanOptions,
+) -> Result<Option<Found>> {
+    let algorithm = scan.algorithm.as_str();
+    let bounded = scan.max_memory.is_some();
+    // Hashes from earlier runs, reused for files whose size, mtime and inode
+    // are unchanged. The index is read and written whole, so a memory budget
+    // goes without it.
+    let no_cache = scan.no_cache || bounded;
+    let mut index = if no_cache {
+        HashIndex::default()
+    } else {
+        HashIndex::load()
+    };
+
+    let mut file_count = 0usize;
+    let mut link_count = 0usize;
+    let mut candidate_count = 0usize;
+    let mut partial_count = 0usize;
+    let mut found = Found {
+        groups: Records::new(bounded),
+        hardlinked: Records::new(bounded),
+        all_files: Vec::new(),
+    };
+    let mut batch = Batch::default();
+    let mut hash_batch = |batch: Batch, checkpoint: Option<&mut Checkpoint>| -> io::Result<usize> {
+        let (remaining, groups, reused) =
+            hash_candidates(batch.groups, &mut index, checkpoint, algorithm, no_cache);
+        let mut links = batch.links;
+        let mut groups: Vec<Group> = groups
+            .into_iter()
+            .filter(|(_, files)| files.len() > 1)
+            .map(|((size, digest), mut files)| {
+                files.sort();
+                let files = files
+                    .into_iter()
+                    .map(|path| GroupFile {
+                        reference: batch.references.contains(&path),
+                        reused: reused.contains(&path),
+                        protected: false,
+                        links: links.remove(&path).unwrap_or_default(),
+                        path,
+                    })
+                    .collect();
+                Group {
+                    digest,
+                    size,
+                    files,
+                }
             })
             .collect();
+        // Spilled groups are read back in this order
+        groups.sort_by(|a, b| (a.size, &a.files[0].path).cmp(&(b.size, &b.files[0].path)));
+        for group in groups {
+            found.groups.push(group)?;
+        }
+        Ok(remaining)
+    };
 
-        for (file, hash) in hashes {
-            if let Some(h) = hash {
-                hash_groups.entry(h).or_default().push(file);
+    for bucket in buckets {
+        let (size, bucket) = bucket?;
+        let in_reference: Vec<PathBuf> = bucket
+            .iter()
+            .filter(|f| f.reference)
+            .map(|f| f.path.clone())
+            .collect();
+        // Hardlinks share their data (and size), so each file is compared
+        // once; its other paths are listed under it and handled along with it
+        let (files, link_groups) = collapse_hardlinks(bucket);
+        for group in link_groups {
+            link_count += group.len() - 1;
+            if files.len() > 1 {
+                batch.links.insert(group[0].clone(), group[1..].to_vec());
             }
+            found.hardlinked.push(LinkGroup { size, paths: group })?;
+        }
+        file_count += files.len();
+        if scan.keep_all {
+            found
+                .all_files
+                .extend(files.iter().map(|f| (f.clone(), size)));
+        }
+        if files.len() < 2 {
+            continue;
+        }
+
+        batch.references.extend(in_reference);
+        candidate_count += files.len();
+        batch.files += files.len();
+        batch.groups.push((size, files));
+        if bounded && batch.files >= STREAM_BATCH {
+            partial_count += hash_batch(std::mem::take(&mut batch), None)?;
         }
     }
+    if let Records::Memory(hardlinked) = &mut found.hardlinked {
+        hardlinked.sort_by(|a, b| a.paths[0].cmp(&b.paths[0]));
+    }
+    found.hardlinked.finish()?;
 
-    pb.finish_and_clear();
+    if !ui::is_quiet() {
+        println!(
+            "  {} {} files indexed",
+            chars::BULLET.themed(Element::Info),
+            file_count.to_string().themed(Element::Success).bold()
+        );
+        if link_count > 0 {
+            println!(
+                "  {} {} more paths are hardlinks to indexed files",
+                chars::BULLET.themed(Element::Info),
+                link_count.to_string().themed(Element::Success).bold()
+            );
+        }
+    }
 
-    // Step 4: Filter to actual duplicates
-    let duplicates: Vec<(String, Vec<PathBuf>)> = hash_groups
-        .into_iter()
-        .filter(|(_, files)| files.len() > 1)
-        .collect();
+    if candidate_count == 0 && !scan.keep_all {
+        checkpoint.clear();
+        return Ok(None);
+    }
 
-    if duplicates.is_empty() {
-        ui::print_success("No duplicate files found");
-        return Ok(());
+    if !ui::is_quiet() {
+        println!(
+            "  {} {} candidates with matching sizes",
+            chars::BULLET.themed(Element::Warning),
+            candidate_count.to_string().themed(Element::Warning).bold()
+        );
     }
 
-    // Calculate statistics
-    let total_groups = duplicates.len();
-    let total_duplicates: usize = duplicates.iter().map(|(_, f)| f.len() - 1).sum();
-    let wasted_space: u64 = duplicates
-        .iter()
-        .filter_map(|(_, files)| {
-            files.first().and_then(|f| f.metadata().ok()).map(|m| {
-                m.len() * (files.len() as u64 - 1)
-            })
-        })
-        .sum();
+    // Steps 3 and 4: partial, then full hashes of what is left
+    let resumable = (!bounded).then_some(&mut checkpoint);
+    partial_count += hash_batch(batch, resumable)?;
+    found.groups.finish()?;
+    if !no_cache && let Err(e) = index.save() {
+        ui::print_verbose(&format!("could not save the hash index ({})", e));
+    }
+    checkpoint.clear();
+
+    if !ui::is_quiet() {
+        println!(
+            "  {} {} candidates after partial hashing",
+            chars::BULLET.themed(Element::Warning),
+            partial_count.to_string().themed(Element::Warning).bold()
+        );
+    }
+    Ok(Some(found))
+}
+
+/// Steps 5 to 7: Filter the hash groups to actual duplicates and look for
+/// near-duplicates and similar text, then order the groups for the report.
+/// Groups on disk (--max-memory) stay in the order they were found,
+/// smallest files first, since sorting them would need them all at once.
+fn select_duplicates(
+    found: Found,
+    select: &SelectOptions,
+    by_reference: bool,
+) -> Result<Duplicates> {
+    let Found {
+        groups: found_groups,
+        hardlinked,
+        all_files,
+    } = found;
+    let mut duplicates = Duplicates {
+        groups: Records::new(false),
+        total_groups: 0,
+        total_duplicates: 0,
+        wasted_space: 0,
+        near_duplicates: Vec::new(),
+        similar_text: Vec::new(),
+        hardlinked,
+        protected: false,
+        by_reference,
+    };
+    let limit = select.limit.unwrap_or(usize::MAX);
+    let mut groups = Records::new(found_groups.on_disk());
+
+    // Step 5: Filter to actual duplicates, ordered so the file to keep comes first.
+    // Paths are sorted first so the choice never depends on hashing or walk order.
+    // With --reference, only groups with a copy on each side count, and the
+    // reference copies come first, then protected ones.
+    for group in found_groups.drain()? {
+        let mut group = group?;
+        let references = group.files.iter().filter(|f| f.reference).count();
+        if by_reference && (references == 0 || references == group.files.len()) {
+            continue;
+        }
+        // A hardlink of a protected path protects them all
+        if !select.protect.is_empty() {
+            for file in &mut group.files {
+                let protected = file.paths().any(|p| matches_protect(&select.protect, p));
+                file.protected = protected;
+            }
+        }
+        if let Some(strategy) = &select.keep {
+            group.files.sort_by_cached_key(|f| strategy.rank(&f.path));
+        }
+        group.files.sort_by_key(|f| (!f.reference, !f.protected));
+
+        // Calculate statistics
+        let dupes = duplicates.dupes_in(&group);
+        duplicates.total_groups += 1;
+        duplicates.total_duplicates += dupes;
+        duplicates.wasted_space += group.size * dupes as u64;
+        duplicates.protected |= group.files.iter().any(|f| f.protected);
+        if !groups.on_disk() || duplicates.total_groups <= limit {
+            groups.push(group)?;
+        }
+    }
+    groups.finish()?;
+
+    if let Records::Memory(groups) = &mut groups {
+        groups.sort_by(|a, b| a.files[0].path.cmp(&b.files[0].path));
+
+        // Step 6: Files of slightly different sizes where one is the other plus a header/footer
+        if let Some(tolerance) = select.tolerance {
+            duplicates.near_duplicates = find_near_duplicates(&all_files, groups, tolerance);
+        }
+        // Step 7: Text files that are mostly the same (edited copies)
+        if let Some(threshold) = select.fuzzy_threshold {
+            duplicates.similar_text = find_similar_text(&all_files, groups, threshold);
+        }
+
+        // Largest groups first; ties keep the path order
+        match select.sort.as_deref() {
+            Some("waste") => groups.sort_by_cached_key(|group| {
+                std::cmp::Reverse(group.size * duplicates.dupes_in(group) as u64)
+            }),
+            Some("count") => groups.sort_by_key(|group| std::cmp::Reverse(group.files.len())),
+            Some("size") => groups.sort_by_key(|group| std::cmp::Reverse(group.size)),
+            _ => {}
+        }
+        groups.truncate(limit);
+    }
+    duplicates.groups = groups;
+    Ok(duplicates)
+}
+
+/// The paths that would go, for --raw-paths and --null
+fn print_paths(duplicates: &Duplicates) -> Result<()> {
+    duplicates.groups.for_each(|group| {
+        for (i, file) in group.files.iter().enumerate() {
+            if !file.kept_by_default(i == 0, duplicates.by_reference) {
+                for path in file.paths() {
+                    ui::emit_path(path);
+                }
+            }
+        }
+        Ok(())
+    })
+}
 
-    // Print results
+fn print_report(duplicates: &Duplicates, select: &SelectOptions) -> Result<()> {
+    let shown = duplicates.groups.len();
     ui::print_header("DUPLICATE FILES REPORT");
-    println!();
-    ui::print_kv("Duplicate groups", &total_groups.to_string());
-    ui::print_kv("Total duplicates", &total_duplicates.to_string());
-    ui::print_kv_colored("Wasted space", format_bytes(wasted_space).red().bold());
+    ui::print_blank();
+    ui::print_kv("Duplicate groups", &duplicates.total_groups.to_string());
+    ui::print_kv("Total duplicates", &duplicates.total_duplicates.to_string());
+    ui::print_kv_colored(
+        "Wasted space",
+        format_bytes(duplicates.wasted_space)
+            .themed(Element::Error)
+            .bold(),
+    );
+    if shown < duplicates.total_groups {
+        ui::print_kv("Shown", &format!("first {} groups (--limit)", shown));
+    }
     println!();
     ui::print_line(60);
 
     // Print each group
-    for (hash, files) in &duplicates {
-        let size = files
-            .first()
-            .and_then(|f| f.metadata().ok())
-            .map(|m| m.len())
-            .unwrap_or(0);
-
+    duplicates.groups.for_each(|group| {
         println!();
         println!(
             "  {} {} files, {} each",
-            chars::BULLET.bright_yellow(),
-            files.len().to_string().bright_yellow().bold(),
-            format_bytes(size).bright_black()
+            chars::BULLET.themed(Element::Warning),
+            group.files.len().to_string().themed(Element::Warning).bold(),
+            format_bytes(group.size).themed(Element::Muted)
+        );
+        println!(
+            "    {} {}",
+            "hash:".themed(Element::Muted),
+            &group.digest[..16].themed(Element::Muted)
         );
-        println!("    {} {}", "hash:".bright_black(), &hash[..16].bright_black());
 
-        for (i, file) in files.iter().enumerate() {
-            let (prefix, label) = if i == 0 {
-                (chars::T_RIGHT.green(), "keep".green())
+        for (i, file) in group.files.iter().enumerate() {
+            let (prefix, label) = if duplicates.by_reference && file.reference {
+                (
+                    chars::T_RIGHT.themed(Element::Success),
+                    "ref".themed(Element::Success),
+                )
+            } else if file.protected {
+                (
+                    chars::T_RIGHT.themed(Element::Success),
+                    "protected".themed(Element::Success),
+                )
+            } else if i == 0 {
+                (
+                    chars::T_RIGHT.themed(Element::Success),
+                    "keep".themed(Element::Success),
+                )
             } else {
-                (chars::T_RIGHT.red(), "dupe".red())
+                (
+                    chars::T_RIGHT.themed(Element::Error),
+                    "dupe".themed(Element::Error),
+                )
             };
-            println!("    {} [{}] {}", prefix, label, file.display());
+            println!("    {} [{}] {}", prefix, label, file.path.display());
+            for link in &file.links {
+                println!(
+                    "    {} [{}] {}",
+                    chars::T_RIGHT.themed(Element::Muted),
+                    "link".themed(Element::Muted),
+                    link.display()
+                );
+            }
+        }
+        Ok::<_, io::Error>(())
+    })?;
+
+    if let Some(tolerance) = select.tolerance
+        && !duplicates.near_duplicates.is_empty()
+    {
+        println!();
+        ui::print_section(&format!(
+            "Near Duplicates (within {})",
+            tolerance.describe()
+        ));
+        for group in &duplicates.near_duplicates {
+            println!();
+            println!(
+                "  {} {} files, sizes differ only by a leading/trailing part",
+                chars::BULLET.themed(Element::Warning),
+                group.len().to_string().themed(Element::Warning).bold()
+            );
+            for (file, size) in group {
+                println!(
+                    "    {} {} {}",
+                    chars::T_RIGHT.themed(Element::Warning),
+                    file.display(),
+                    format!("({})", format_bytes(*size)).themed(Element::Muted)
+                );
+            }
+        }
+    }
+
+    if let Some(threshold) = select.fuzzy_threshold
+        && !duplicates.similar_text.is_empty()
+    {
+        println!();
+        ui::print_section(&format!(
+            "Similar Text (at least {:.0}%)",
+            threshold * 100.0
+        ));
+        for (similarity, a, b) in &duplicates.similar_text {
+            println!();
+            println!(
+                "  {} {} similar",
+                chars::BULLET.themed(Element::Warning),
+                format!("{:.0}%", similarity * 100.0)
+                    .themed(Element::Warning)
+                    .bold()
+            );
+            for file in [a, b] {
+                println!(
+                    "    {} {}",
+                    chars::T_RIGHT.themed(Element::Warning),
+                    file.display()
+                );
+            }
         }
     }
 
+    if !duplicates.hardlinked.is_empty() {
+        println!();
+        ui::print_section("Already Hardlinked");
+        duplicates.hardlinked.for_each(|group| {
+            println!();
+            println!(
+                "  {} {} paths share one file of {}",
+                chars::BULLET.themed(Element::Info),
+                group.paths.len().to_string().themed(Element::Info).bold(),
+                format_bytes(group.size).themed(Element::Muted)
+            );
+            for path in &group.paths {
+                println!(
+                    "    {} {}",
+                    chars::T_RIGHT.themed(Element::Info),
+                    path.display()
+                );
+            }
+            Ok::<_, io::Error>(())
+        })?;
+    }
+
     println!();
     ui::print_line(60);
+    Ok(())
+}
 
-    // Export to JSON if requested
-    if let Some(output_path) = output {
-        let report = DuplicateReport {
-            total_groups,
-            total_duplicates,
-            wasted_space,
-            groups: duplicates
+/// The --output and --csv files, built in memory
+fn write_reports(
+    duplicates: &Duplicates,
+    algorithm: &str,
+    output: &OutputArgs,
+    csv: Option<&str>,
+) -> Result<()> {
+    let mut report = Report::new(&["group", "hash", "size", "status", "path"]);
+    let mut groups = Vec::with_capacity(duplicates.groups.len());
+    duplicates.groups.for_each(|group| {
+        let number = groups.len() + 1;
+        for (i, file) in group.files.iter().enumerate() {
+            let status = if duplicates.by_reference && file.reference {
+                "reference"
+            } else if file.protected {
+                "protected"
+            } else if i == 0 {
+                "keep"
+            } else {
+                "dupe"
+            };
+            for path in file.paths() {
+                report.row(vec![
+                    json!(number),
+                    json!(group.digest),
+                    json!(group.size),
+                    json!(status),
+                    json!(path.display().to_string()),
+                ]);
+            }
+        }
+        groups.push(DuplicateGroup {
+            hash: group.digest.clone(),
+            size: group.size,
+            files: group
+                .files
                 .iter()
-                .map(|(hash, files)| {
-                    let size = files
-                        .first()
-                        .and_then(|f| f.metadata().ok())
-                        .map(|m| m.len())
-                        .unwrap_or(0);
-                    DuplicateGroup {
-                        hash: hash.clone(),
-                        size,
-                        files: files.iter().map(|f| f.display().to_string()).collect(),
-                    }
-                })
+                .flat_map(GroupFile::paths)
+                .map(|f| f.display().to_string())
                 .collect(),
-        };
+        });
+        Ok::<_, io::Error>(())
+    })?;
 
-        let json = serde_json::to_string_pretty(&report)?;
-        fs::write(&output_path, json)?;
-        ui::print_success(&format!("Report saved to {}", output_path));
+    let near_duplicates = &duplicates.near_duplicates;
+    for (i, group) in near_duplicates.iter().enumerate() {
+        for (file, size) in group {
+            report.row(vec![
+                json!(groups.len() + i + 1),
+                json!(""),
+                json!(size),
+                json!("near"),
+                json!(file.display().to_string()),
+            ]);
+        }
     }
+    let similar_text = &duplicates.similar_text;
+    let first_similar = groups.len() + near_duplicates.len();
+    for (i, (_, a, b)) in similar_text.iter().enumerate() {
+        for file in [a, b] {
+            report.row(vec![
+                json!(first_similar + i + 1),
+                json!(""),
+                json!(file.metadata().map(|m| m.len()).unwrap_or(0)),
+                json!("similar"),
+                json!(file.display().to_string()),
+            ]);
+        }
+    }
+    let mut first_linked = first_similar + similar_text.len();
+    let mut hardlinked_groups = Vec::new();
+    duplicates.hardlinked.for_each(|group| {
+        first_linked += 1;
+        for path in &group.paths {
+            report.row(vec![
+                json!(first_linked),
+                json!(""),
+                json!(group.size),
+                json!("linked"),
+                json!(path.display().to_string()),
+            ]);
+        }
+        hardlinked_groups.push(group.paths.iter().map(|p| p.display().to_string()).collect());
+        Ok::<_, io::Error>(())
+    })?;
 
-    // Delete duplicates if requested
-    if delete {
-        println!();
-        ui::print_warning("Deleting duplicates (keeping first occurrence)...");
-
-        let mut deleted_count = 0;
-        let mut freed_space = 0u64;
+    let json = DuplicateReport {
+        algorithm: algorithm.to_string(),
+        total_groups: duplicates.total_groups,
+        total_duplicates: duplicates.total_duplicates,
+        wasted_space: duplicates.wasted_space,
+        groups,
+        near_duplicate_groups: near_duplicates
+            .iter()
+            .map(|group| group.iter().map(|(f, _)| f.display().to_string()).collect())
+            .collect(),
+        similar_text: similar_text
+            .iter()
+            .map(|(similarity, a, b)| SimilarText {
+                similarity: *similarity,
+                files: [a.display().to_string(), b.display().to_string()],
+            })
+            .collect(),
+        hardlinked_groups,
+    };
+    write_csv(csv, &report)?;
+    output::write_report(output, &report.with_json(serde_json::to_value(&json)?))
+}
 
-        for (_, files) in &duplicates {
-            for file in files.iter().skip(1) {
-                if let Ok(metadata) = file.metadata() {
-                    freed_space += metadata.len();
+/// Step 8: Delete, link or move the duplicates as --delete, --hardlink,
+/// --symlink or --move-to ask, after --prompt or a confirmation, or write
+/// those changes to a --script instead. The groups are read into memory
+/// here, since the changes are planned, confirmed and rolled back as one.
+fn act_on(duplicates: Duplicates, act: &ActOptions, algorithm: &str) -> Result<()> {
+    let groups = duplicates
+        .groups
+        .drain()?
+        .collect::<io::Result<Vec<Group>>>()?;
+    let by_reference = duplicates.by_reference;
+    let quarantine_dir = act.quarantine_dir.as_deref();
+    let keepers = if act.prompt {
+        match prompt_keepers(&groups)? {
+            // Protected files stay whatever was selected
+            Some(mut keepers) => {
+                for (keep, group) in keepers.iter_mut().zip(&groups) {
+                    keep.extend((0..group.files.len()).filter(|&i| group.files[i].protected));
+                    keep.sort_unstable();
+                    keep.dedup();
                 }
-                if fs::remove_file(file).is_ok() {
-                    deleted_count += 1;
-                    println!(
-                        "    {} {}",
-                        chars::CROSS_MARK.red(),
-                        file.display().to_string().dimmed()
-                    );
+                keepers
+            }
+            None => {
+                ui::print_warning("Aborted, nothing was changed");
+                return Ok(());
+            }
+        }
+    } else {
+        groups
+            .iter()
+            .map(|group| {
+                (0..group.files.len())
+                    .filter(|&i| group.files[i].kept_by_default(i == 0, by_reference))
+                    .collect()
+            })
+            .collect()
+    };
+
+    // A group is only changed once every file that would go has been
+    // compared byte for byte with the kept copy: always with --paranoid
+    // or xxh3 (64 bits are few enough to collide), otherwise when one of
+    // their digests was reused from an earlier run, since a file can
+    // change without its size or mtime changing
+    let always_verify = act.paranoid || algorithm == "xxh3";
+    let verified: Vec<bool> = groups
+        .par_iter()
+        .zip(&keepers)
+        .map(|(group, keep)| {
+            let from_cache = group
+                .files
+                .iter()
+                .enumerate()
+                .any(|(i, f)| (i == keep[0] || !keep.contains(&i)) && f.reused);
+            !(always_verify || from_cache) || verify_group(&group.files, keep)
+        })
+        .collect();
+
+    let mut plan = Plan::new();
+    // Hash and kept copy of each file to move, for the quarantine manifest
+    let mut moved_from: HashMap<&Path, (&str, &Path)> = HashMap::new();
+    // One comment per group for --script, before the group's first action
+    let mut notes: Vec<(usize, String)> = Vec::new();
+    for ((group, keep), verified) in groups.iter().zip(&keepers).zip(verified) {
+        if !verified {
+            continue;
+        }
+        let files = &group.files;
+        let kept = &files[keep[0]].path;
+        notes.push((
+            plan.len(),
+            format!(
+                "{} {}: keeping {}",
+                algorithm,
+                &group.digest[..16],
+                keep.iter()
+                    .map(|&k| files[k].path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ));
+        for (i, file) in files.iter().enumerate() {
+            // Reference copies are never touched, whatever was selected
+            if keep.contains(&i) || file.reference {
+                continue;
+            }
+            // Every hardlink of a duplicate goes, or its data stays on disk
+            for path in file.paths() {
+                if act.hardlink {
+                    plan.hardlink(path, kept);
+                } else if let Some(absolute) = act.symlink_absolute {
+                    plan.symlink(path, kept, absolute);
+                } else if let Some(dir) = quarantine_dir {
+                    plan.move_file(path, &quarantine::target_path(dir, path));
+                    moved_from.insert(path, (&group.digest, kept));
+                } else {
+                    plan.delete_file(path);
                 }
             }
         }
+    }
 
-        println!();
+    if plan.is_empty() {
+        ui::print_info("Keeping every file, nothing to change");
+        return Ok(());
+    }
+
+    if act.dry_run {
+        plan.print_dry_run();
+        return Ok(());
+    }
+
+    if let Some(script) = &act.script {
+        if quarantine_dir.is_some() {
+            notes.insert(
+                0,
+                (
+                    0,
+                    "Moves made by this script are not recorded for --restore".to_string(),
+                ),
+            );
+        }
+        // An existing file may be a script from an earlier run, or anything else
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(script)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::AlreadyExists => {
+                    anyhow!("{} already exists, refusing to overwrite it", script)
+                }
+                _ => anyhow!("Could not create {}: {}", script, e),
+            })?;
+        let mut out = BufWriter::new(file);
+        plan.write_script(&mut out, &notes)?;
+        out.flush()?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(script, std::fs::Permissions::from_mode(0o755))?;
+        }
+        ui::print_success(&format!(
+            "Wrote {} to {}; nothing was changed",
+            plan.summary(),
+            script
+        ));
+        return Ok(());
+    }
+
+    println!();
+    // Choosing files one group at a time already was the confirmation
+    if !plan.confirm(act.yes || act.prompt)? {
+        ui::print_warning("Aborted, nothing was changed");
+        return Ok(());
+    }
+
+    let kept = if act.prompt {
+        "the selected copies"
+    } else if by_reference {
+        "the reference copies"
+    } else if duplicates.protected {
+        "the copies marked [keep] or [protected]"
+    } else {
+        "the copies marked [keep]"
+    };
+    if act.hardlink {
+        ui::print_warning(&format!(
+            "Replacing duplicates with hardlinks to {}...",
+            kept
+        ));
+    } else if act.symlink_absolute.is_some() {
+        ui::print_warning(&format!(
+            "Replacing duplicates with symlinks to {}...",
+            kept
+        ));
+    } else if let Some(dir) = quarantine_dir {
+        ui::print_warning(&format!(
+            "Moving duplicates to {} (keeping {})...",
+            dir.display(),
+            kept
+        ));
+    } else {
+        ui::print_warning(&format!("Deleting duplicates (keeping {})...", kept));
+    }
+
+    let outcome = match quarantine_dir {
+        // Each move is in the manifest before it happens, so an
+        // interrupted run can still be restored
+        Some(dir) => plan.execute_with(|action| {
+            let Action::Move { from, to } = action else {
+                return Ok(());
+            };
+            if to.symlink_metadata().is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", to.display()),
+                ));
+            }
+            let (hash, kept) = moved_from[from.as_path()];
+            let size = from.metadata()?.len();
+            let relative = to.strip_prefix(dir).unwrap_or(to);
+            quarantine::record(dir, &quarantine::Entry::new(from, relative, size, hash, kept))
+        }),
+        None => plan.execute(),
+    };
+
+    println!();
+    if act.linking() {
         ui::print_success(&format!(
-            "Deleted {} files, freed {}",
-            deleted_count,
-            format_bytes(freed_space)
+            "Linked {} files, freed {}",
+            outcome.linked,
+            format_bytes(outcome.freed)
         ));
+        return exit::partial(outcome.failed, "links");
+    }
+    if let Some(dir) = quarantine_dir {
+        ui::print_success(&format!(
+            "Moved {} files to {} (undo with --restore {})",
+            outcome.moved.len(),
+            dir.display(),
+            dir.display()
+        ));
+        return exit::partial(outcome.failed, "moves");
+    }
+    ui::print_success(&format!(
+        "Deleted {} files, freed {}",
+        outcome.files_deleted,
+        format_bytes(outcome.freed)
+    ));
+    exit::partial(outcome.failed, "deletions")
+}
+
+/// Report directories whose whole contents are identical: same relative
+/// paths with the same file hashes. Only directories that match another one
+/// in file count and total size are hashed.
+fn run_dirs(
+    paths: &[String],
+    output: &OutputArgs,
+    csv: Option<&str>,
+    algorithm: &str,
+    no_cache: bool,
+    one_file_system: bool,
+) -> Result<()> {
+    ui::print_start("Scanning for duplicate directories", &paths.join(" "));
+    ui::print_blank();
+
+    let roots: Vec<PathBuf> = paths
+        .iter()
+        .filter(|p| *p != STDIN_PATH)
+        .map(PathBuf::from)
+        .collect();
+
+    // Step 1: Every file counts towards each of its directories up to the scan root
+    let mut dirs: HashMap<PathBuf, Vec<(PathBuf, u64)>> = HashMap::new();
+    let mut file_count = 0u64;
+
+    let spinner = ui::scan_spinner("Indexing");
+    for path in input_files(paths, false, one_file_system)? {
+        spinner.inc(1);
+        let Ok(metadata) = path.metadata() else {
+            continue;
+        };
+        file_count += 1;
+        for dir in path.ancestors().skip(1) {
+            dirs.entry(dir.to_path_buf())
+                .or_default()
+                .push((path.clone(), metadata.len()));
+            if !roots
+                .iter()
+                .any(|root| dir != root && dir.starts_with(root))
+            {
+                break;
+            }
+        }
+    }
+    spinner.finish_and_clear();
+
+    if !ui::is_quiet() {
+        println!(
+            "  {} {} files in {} directories indexed",
+            chars::BULLET.themed(Element::Info),
+            file_count.to_string().themed(Element::Success).bold(),
+            dirs.len().to_string().themed(Element::Success).bold()
+        );
+    }
+
+    // Step 2: Directories can only be identical if file count and total size match
+    let mut shapes: HashMap<(usize, u64), Vec<&Path>> = HashMap::new();
+    for (dir, files) in &dirs {
+        let total: u64 = files.iter().map(|(_, size)| size).sum();
+        shapes
+            .entry((files.len(), total))
+            .or_default()
+            .push(dir.as_path());
+    }
+    let candidates: Vec<&Path> = shapes
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .flatten()
+        .collect();
+
+    if candidates.is_empty() {
+        ui::print_success("No duplicate directories found");
+        return Ok(());
+    }
+
+    // Step 3: Hash the files of the candidate directories, reusing cached hashes
+    let mut files: Vec<PathBuf> = candidates
+        .iter()
+        .flat_map(|dir| dirs[*dir].iter().map(|(f, _)| f.clone()))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    files.sort();
+    if !ui::is_quiet() {
+        println!(
+            "  {} {} candidate directories, {} files to hash",
+            chars::BULLET.themed(Element::Warning),
+            candidates.len().to_string().themed(Element::Warning).bold(),
+            files.len().to_string().themed(Element::Warning).bold()
+        );
+    }
+
+    let mut index = if no_cache {
+        HashIndex::default()
+    } else {
+        HashIndex::load()
+    };
+    let entries = index.update(&files, algorithm);
+    if !no_cache && let Err(e) = index.save() {
+        ui::print_verbose(&format!("could not save the hash index ({})", e));
+    }
+    let digests: HashMap<&Path, &str> = files
+        .iter()
+        .zip(&entries)
+        .filter_map(|(file, entry)| Some((file.as_path(), entry.as_ref()?.digest(algorithm)?)))
+        .collect();
+
+    // Step 4: Roll the file hashes up into one digest per directory
+    let mut hash_groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for dir in candidates {
+        if let Some(digest) = dir_digest(dir, &dirs[dir], &digests) {
+            hash_groups
+                .entry(digest)
+                .or_default()
+                .push(dir.to_path_buf());
+        }
+    }
+    let mut duplicates: Vec<(String, Vec<PathBuf>)> = hash_groups
+        .into_iter()
+        .filter(|(_, dirs)| dirs.len() > 1)
+        .collect();
+
+    // Step 5: Drop groups that are only the insides of a larger duplicate group
+    let duplicated: HashSet<PathBuf> = duplicates
+        .iter()
+        .flat_map(|(_, dirs)| dirs.iter().cloned())
+        .collect();
+    duplicates.retain(|(_, dirs)| {
+        !dirs
+            .iter()
+            .all(|d| d.parent().is_some_and(|p| duplicated.contains(p)))
+    });
+    for (_, dirs) in &mut duplicates {
+        dirs.sort();
+    }
+    duplicates.sort_by(|a, b| a.1[0].cmp(&b.1[0]));
+
+    if duplicates.is_empty() {
+        ui::print_success("No duplicate directories found");
+        return Ok(());
+    }
+
+    let contents = |dir: &Path| -> (usize, u64) {
+        let files = &dirs[dir];
+        (files.len(), files.iter().map(|(_, size)| size).sum())
+    };
+    let total_groups = duplicates.len();
+    let total_duplicates: usize = duplicates.iter().map(|(_, d)| d.len() - 1).sum();
+    let wasted_space: u64 = duplicates
+        .iter()
+        .map(|(_, d)| contents(&d[0]).1 * (d.len() as u64 - 1))
+        .sum();
+
+    if ui::is_path_list() {
+        for (_, dirs) in &duplicates {
+            for dir in dirs.iter().skip(1) {
+                ui::emit_path(dir);
+            }
+        }
+    } else {
+        ui::print_header("DUPLICATE DIRECTORIES REPORT");
+        ui::print_blank();
+        ui::print_kv("Duplicate groups", &total_groups.to_string());
+        ui::print_kv("Total duplicates", &total_duplicates.to_string());
+        ui::print_kv_colored(
+            "Wasted space",
+            format_bytes(wasted_space).themed(Element::Error).bold(),
+        );
+        println!();
+        ui::print_line(60);
+
+        for (hash, dirs) in &duplicates {
+            let (count, size) = contents(&dirs[0]);
+            println!();
+            println!(
+                "  {} {} directories, {} files ({}) each",
+                chars::BULLET.themed(Element::Warning),
+                dirs.len().to_string().themed(Element::Warning).bold(),
+                count,
+                format_bytes(size).themed(Element::Muted)
+            );
+            println!(
+                "    {} {}",
+                "hash:".themed(Element::Muted),
+                &hash[..16].themed(Element::Muted)
+            );
+            for (i, dir) in dirs.iter().enumerate() {
+                let (prefix, label) = if i == 0 {
+                    (
+                        chars::T_RIGHT.themed(Element::Success),
+                        "keep".themed(Element::Success),
+                    )
+                } else {
+                    (
+                        chars::T_RIGHT.themed(Element::Error),
+                        "dupe".themed(Element::Error),
+                    )
+                };
+                println!("    {} [{}] {}", prefix, label, dir.display());
+            }
+        }
+
+        println!();
+        ui::print_line(60);
+    }
+
+    if output.output.is_some() || csv.is_some() {
+        let json = DuplicateDirReport {
+            algorithm: algorithm.to_string(),
+            total_groups,
+            total_duplicates,
+            wasted_space,
+            groups: duplicates
+                .iter()
+                .map(|(hash, dirs)| {
+                    let (files, size) = contents(&dirs[0]);
+                    DuplicateDirGroup {
+                        hash: hash.clone(),
+                        files,
+                        size,
+                        dirs: dirs.iter().map(|d| d.display().to_string()).collect(),
+                    }
+                })
+                .collect(),
+        };
+
+        let mut report = Report::new(&["group", "hash", "files", "size", "status", "path"]);
+        for (i, (hash, dirs)) in duplicates.iter().enumerate() {
+            let (files, size) = contents(&dirs[0]);
+            for (j, dir) in dirs.iter().enumerate() {
+                report.row(vec![
+                    json!(i + 1),
+                    json!(hash),
+                    json!(files),
+                    json!(size),
+                    json!(if j == 0 { "keep" } else { "dupe" }),
+                    json!(dir.display().to_string()),
+                ]);
+            }
+        }
+        write_csv(csv, &report)?;
+        output::write_report(output, &report.with_json(serde_json::to_value(&json)?))?;
     }
 
     Ok(())
 }
+
+/// Write the `--csv` spreadsheet, one row per file, next to any `--output` report
+fn write_csv(path: Option<&str>, report: &Report) -> Result<()> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    let args = OutputArgs {
+        output: Some(path.to_string()),
+        output_format: Some("csv".to_string()),
+        ..OutputArgs::default()
+    };
+    output::write_report(&args, report)
+}
+
+/// Digest of a directory's contents: the sorted relative paths of its files,
+/// each with its content hash. `None` if a file couldn't be hashed.
+fn dir_digest(
+    dir: &Path,
+    files: &[(PathBuf, u64)],
+    digests: &HashMap<&Path, &str>,
+) -> Option<String> {
+    let mut entries: Vec<(String, &str)> = files
+        .iter()
+        .map(|(file, _)| {
+            let relative = file.strip_prefix(dir).ok()?;
+            let relative: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            Some((relative.join("/"), *digests.get(file.as_path())?))
+        })
+        .collect::<Option<_>>()?;
+    entries.sort();
+
+    let mut hasher = Sha256::new();
+    for (path, digest) in entries {
+        hasher.update(path.as_bytes());
+        hasher.update([0]);
+        hasher.update(digest.as_bytes());
+        hasher.update(b"\n");
+    }
+    Some(hex::encode(hasher.finalize()))
+}
+
+/// Walk the reference directories, then the scan roots, and list the files
+/// passing the size and extension filters. Scanned files that are also under
+/// a reference directory are listed once, as reference files.
+fn index_files(
+    paths: &[String],
+    reference: &[String],
+    sizes: &RangeInclusive<u64>,
+    extensions: &Option<String>,
+    one_file_system: bool,
+    mut emit: impl FnMut(IndexedFile) -> io::Result<()>,
+) -> Result<()> {
+    let spinner = ui::scan_spinner("Indexing");
+    let references: Vec<PathBuf> = if reference.is_empty() {
+        Vec::new()
+    } else {
+        input_files(reference, false, one_file_system)?.collect()
+    };
+    let reference_canonical: HashSet<PathBuf> = references
+        .iter()
+        .filter_map(|f| f.canonicalize().ok())
+        .collect();
+    let scanned = input_files(paths, false, one_file_system)?.filter(|p| {
+        reference_canonical.is_empty()
+            || !p
+                .canonicalize()
+                .is_ok_and(|c| reference_canonical.contains(&c))
+    });
+
+    let reference_count = references.len();
+    for (i, path) in references.into_iter().chain(scanned).enumerate() {
+        spinner.inc(1);
+        if let Ok(metadata) = path.metadata() {
+            let size = metadata.len();
+            if sizes.contains(&size) && matches_extensions(&path, extensions) {
+                emit(IndexedFile {
+                    link: hardlink_id(&metadata),
+                    reference: i < reference_count,
+                    path,
+                    size,
+                })?;
+            }
+        }
+    }
+    spinner.finish_and_clear();
+    Ok(())
+}
+
+/// What a checkpoint must match to be resumed: absolute roots and reference
+/// directories, the filters and the algorithm
+fn checkpoint_scan(
+    paths: &[String],
+    reference: &[String],
+    sizes: &RangeInclusive<u64>,
+    extensions: &Option<String>,
+    algorithm: &str,
+) -> Vec<String> {
+    let absolute = |p: &String| {
+        std::path::absolute(p)
+            .map(|a| a.display().to_string())
+            .unwrap_or_else(|_| p.clone())
+    };
+    paths
+        .iter()
+        .map(absolute)
+        .chain(
+            reference
+                .iter()
+                .map(|r| format!("reference={}", absolute(r))),
+        )
+        .chain([
+            format!("sizes={:?}", sizes),
+            format!("extensions={}", extensions.as_deref().unwrap_or("")),
+            format!("algorithm={}", algorithm),
+        ])
+        .collect()
+}
+
+/// An answer to the per-group "keep which?" question
+#[derive(Clone, Debug, PartialEq)]
+enum Selection {
+    /// Zero-based positions of the files to keep
+    Keep(Vec<usize>),
+    KeepAll,
+    Quit,
+}
+
+/// Parse "2", "1,3", "a" or "q", optionally suffixed with "!" to reuse the
+/// answer for every remaining group. An empty answer keeps the first file.
+fn parse_selection(input: &str, count: usize) -> Result<(Selection, bool)> {
+    let input = input.trim();
+    let (input, for_rest) = match input.strip_suffix('!') {
+        Some(rest) => (rest.trim(), true),
+        None => (input, false),
+    };
+
+    let selection = match input.to_lowercase().as_str() {
+        "" => Selection::Keep(vec![0]),
+        "a" | "all" => Selection::KeepAll,
+        "q" | "quit" => Selection::Quit,
+        list => {
+            let mut keep = Vec::new();
+            for part in list.split([',', ' ']).filter(|p| !p.is_empty()) {
+                let n: usize = part
+                    .parse()
+                    .ok()
+                    .filter(|n| (1..=count).contains(n))
+                    .ok_or_else(|| anyhow!("Enter numbers between 1 and {}", count))?;
+                if !keep.contains(&(n - 1)) {
+                    keep.push(n - 1);
+                }
+            }
+            Selection::Keep(keep)
+        }
+    };
+    Ok((selection, for_rest))
+}
+
+/// Ask which files to keep in each duplicate group. Returns the positions to
+/// keep per group (the first one is the link target for --hardlink/--symlink), or `None`
+/// when the user quits.
+fn prompt_keepers(duplicates: &[Group]) -> Result<Option<Vec<Vec<usize>>>> {
+    if !io::stdin().is_terminal() {
+        return Err(Exit::with_message(
+            exit::ERROR,
+            "--prompt needs a terminal (stdin is not a terminal)",
+        )
+        .into());
+    }
+
+    let mut keep = Vec::with_capacity(duplicates.len());
+    let mut reuse: Option<Selection> = None;
+
+    for (i, group) in duplicates.iter().enumerate() {
+        let files = &group.files;
+        let selection = match &reuse {
+            Some(selection) => selection.clone(),
+            None => {
+                ui::print_section(&format!("Group {}/{}", i + 1, duplicates.len()));
+                for (n, file) in files.iter().enumerate() {
+                    let metadata = file.path.metadata().ok();
+                    let size = metadata.as_ref().map(|m| m.len()).unwrap_or(0);
+                    let modified = metadata
+                        .and_then(|m| m.modified().ok())
+                        .map(|t| {
+                            DateTime::<Local>::from(t)
+                                .format("%Y-%m-%d %H:%M")
+                                .to_string()
+                        })
+                        .unwrap_or_default();
+                    println!(
+                        "  {} {}  {}  {}",
+                        format!("[{}]", n + 1).themed(Element::Info).bold(),
+                        format!("{:>10}", format_bytes(size)).themed(Element::Size),
+                        modified.themed(Element::Muted),
+                        file.path.display()
+                    );
+                }
+
+                let (selection, for_rest) = loop {
+                    let Some(answer) = ui::prompt(&format!(
+                        "Keep which? [1-{}, comma-separated, a = all, q = quit, ! = same for rest] (1):",
+                        files.len()
+                    )) else {
+                        return Ok(None);
+                    };
+                    match parse_selection(&answer, files.len()) {
+                        Ok(parsed) => break parsed,
+                        Err(e) => ui::print_warning(&e.to_string()),
+                    }
+                };
+                if for_rest {
+                    reuse = Some(selection.clone());
+                }
+                selection
+            }
+        };
+
+        keep.push(match selection {
+            Selection::Quit => return Ok(None),
+            Selection::KeepAll => (0..files.len()).collect(),
+            Selection::Keep(positions) => {
+                // Positions past the end of a smaller group fall back to its first file
+                let valid: Vec<usize> =
+                    positions.into_iter().filter(|&p| p < files.len()).collect();
+                if valid.is_empty() {
+                    vec![0]
+                } else {
+                    valid
+                }
+            }
+        });
+    }
+
+    Ok(Some(keep))
+}
+
+/// Split a same-size bucket into the files to compare and groups of paths
+/// that are hardlinks of one file. Each linked file is compared under its
+/// first path in sorted order (reference paths first), which leads its group.
+fn collapse_hardlinks(bucket: Vec<IndexedFile>) -> (Vec<PathBuf>, Vec<Vec<PathBuf>>) {
+    let mut files = Vec::new();
+    let mut linked: HashMap<(u64, u64), Vec<(bool, PathBuf)>> = HashMap::new();
+    for file in bucket {
+        match file.link {
+            Some(id) => linked
+                .entry(id)
+                .or_default()
+                .push((!file.reference, file.path)),
+            None => files.push(file.path),
+        }
+    }
+
+    let mut groups = Vec::new();
+    for mut paths in linked.into_values() {
+        paths.sort();
+        let paths: Vec<PathBuf> = paths.into_iter().map(|(_, path)| path).collect();
+        files.push(paths[0].clone());
+        if paths.len() > 1 {
+            groups.push(paths);
+        }
+    }
+    (files, groups)
+}
+
+/// Hash both ends of each file in the same-size groups, then fully hash the
+/// files whose ends still match, unless cached or already in `checkpoint`.
+/// Returns how many files the partial hash left, the files by full digest and
+/// the files whose digest was reused instead of read.
+fn hash_candidates(
+    groups: Vec<(u64, Vec<PathBuf>)>,
+    index: &mut HashIndex,
+    mut checkpoint: Option<&mut Checkpoint>,
+    algorithm: &str,
+    no_cache: bool,
+) -> (usize, HashGroups, HashSet<PathBuf>) {
+    // Files whose ends differ can't be duplicates
+    let groups = prefilter_partial_hash(groups, |f| {
+        checkpoint.as_ref().is_some_and(|c| c.hash_of(f).is_some()) || index.is_fresh(f, algorithm)
+    });
+    let candidates: Vec<(u64, PathBuf)> = groups
+        .into_iter()
+        .flat_map(|(size, files)| files.into_iter().map(move |f| (size, f)))
+        .collect();
+
+    // Progress goes to the checkpoint every few seconds
+    let pending: Vec<PathBuf> = candidates
+        .iter()
+        .map(|(_, f)| f)
+        .filter(|f| checkpoint.as_ref().is_none_or(|c| c.hash_of(f).is_none()))
+        .cloned()
+        .collect();
+    let reused: HashSet<PathBuf> = candidates
+        .iter()
+        .map(|(_, f)| f)
+        .filter(|f| {
+            checkpoint.as_ref().is_some_and(|c| c.hash_of(f).is_some())
+                || index.is_fresh(f, algorithm)
+        })
+        .cloned()
+        .collect();
+    let entries = index.update_batched(&pending, algorithm, |index, files, entries| {
+        let Some(checkpoint) = checkpoint.as_deref_mut() else {
+            return;
+        };
+        for (file, entry) in files.iter().zip(entries) {
+            if let Some(entry) = entry {
+                checkpoint.record(file, entry, algorithm);
+            }
+        }
+        if checkpoint.is_due() {
+            if let Err(e) = checkpoint.save() {
+                ui::print_verbose(&format!("could not save the checkpoint ({})", e));
+            }
+            if !no_cache && let Err(e) = index.save() {
+                ui::print_verbose(&format!("could not save the hash index ({})", e));
+            }
+        }
+    });
+    let mut digests: HashMap<&Path, String> = pending
+        .iter()
+        .zip(entries)
+        .filter_map(|(file, entry)| Some((file.as_path(), entry?.digest(algorithm)?.to_string())))
+        .collect();
+
+    // Grouped by size as well, so files whose digests merely collide stay apart
+    let mut hash_groups = HashGroups::new();
+    for (size, file) in &candidates {
+        let digest = digests
+            .remove(file.as_path())
+            .or_else(|| checkpoint.as_ref()?.hash_of(file).map(str::to_string));
+        if let Some(digest) = digest {
+            hash_groups.entry((*size, digest)).or_default().push(file.clone());
+        }
+    }
+    (candidates.len(), hash_groups, reused)
+}
+
+/// Split each same-size group by a hash of the first and last
+/// `PARTIAL_HASH_BLOCK` bytes, keeping only sub-groups that still have more
+/// than one file. Files small enough to be read whole anyway, and groups
+/// whose full hashes are all known already (`is_hashed`), are left as-is.
+fn prefilter_partial_hash(
+    groups: Vec<(u64, Vec<PathBuf>)>,
+    is_hashed: impl Fn(&Path) -> bool,
+) -> Vec<(u64, Vec<PathBuf>)> {
+    let (direct, groups): (Vec<_>, Vec<_>) = groups.into_iter().partition(|(size, files)| {
+        *size <= 2 * PARTIAL_HASH_BLOCK || files.iter().all(|f| is_hashed(f))
+    });
+    let total: usize = groups.iter().map(|(_, files)| files.len()).sum();
+    let pb = ui::work_progress(total as u64);
+
+    let mut candidates = direct;
+    for (size, files) in groups {
+        let hashes: Vec<(PathBuf, Option<String>)> = files
+            .into_par_iter()
+            .map(|f| {
+                let hash = match hash_file_partial(&f, size) {
+                    Ok(h) => Some(h),
+                    Err(e) => {
+                        skipped::record_io(&f, &e);
+                        None
+                    }
+                };
+                pb.inc(1);
+                (f, hash)
+            })
+            .collect();
+
+        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for (file, hash) in hashes {
+            if let Some(h) = hash {
+                by_hash.entry(h).or_default().push(file);
+            }
+        }
+        candidates.extend(
+            by_hash
+                .into_values()
+                .filter(|files| files.len() > 1)
+                .map(|files| (size, files)),
+        );
+    }
+
+    pb.finish_and_clear();
+    candidates
+}
+
+/// SHA256 of the first and last `PARTIAL_HASH_BLOCK` bytes of a `size`-byte file
+fn hash_file_partial(path: &Path, size: u64) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; PARTIAL_HASH_BLOCK as usize];
+
+    let head = PARTIAL_HASH_BLOCK.min(size) as usize;
+    file.read_exact(&mut buffer[..head])?;
+    hasher.update(&buffer[..head]);
+
+    let tail_start = size.saturating_sub(PARTIAL_HASH_BLOCK).max(head as u64);
+    let tail = (size - tail_start) as usize;
+    file.seek(SeekFrom::Start(tail_start))?;
+    file.read_exact(&mut buffer[..tail])?;
+    hasher.update(&buffer[..tail]);
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Compare the files of a group that would be changed with the kept copy,
+/// byte for byte. Warns and returns false on the first one that differs.
+fn verify_group(files: &[GroupFile], keep: &[usize]) -> bool {
+    let kept = &files[keep[0]].path;
+    for (i, file) in files.iter().enumerate() {
+        if keep.contains(&i) || file.reference {
+            continue;
+        }
+        let problem = match same_contents(&file.path, kept) {
+            Ok(true) => continue,
+            Ok(false) => "contents differ despite equal hashes".to_string(),
+            Err(e) => e.to_string(),
+        };
+        ui::print_warning(&format!(
+            "Skipping group of {}: {} ({})",
+            kept.display(),
+            file.path.display(),
+            problem
+        ));
+        return false;
+    }
+    true
+}
+
+/// Whether two files hold exactly the same bytes
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let mut a = BufReader::with_capacity(PARTIAL_HASH_BLOCK as usize, File::open(a)?);
+    let mut b = BufReader::with_capacity(PARTIAL_HASH_BLOCK as usize, File::open(b)?);
+    loop {
+        let chunk_a = a.fill_buf()?;
+        if chunk_a.is_empty() {
+            return Ok(b.fill_buf()?.is_empty());
+        }
+        let chunk_b = b.fill_buf()?;
+        let n = chunk_a.len().min(chunk_b.len());
+        if n == 0 || chunk_a[..n] != chunk_b[..n] {
+            return Ok(false);
+        }
+        a.consume(n);
+        b.consume(n);
+    }
+}
+
+/// Group files whose sizes differ within `tolerance` and where the smaller file
+/// is the start or the end of the larger one (an appended newline, a metadata
+/// footer or header). Exact duplicate groups take part through their first file.
+///
+/// Only files sharing the fingerprint of their first or last `FINGERPRINT_LEN`
+/// bytes are compared, so files smaller than that are left out.
+fn find_near_duplicates(
+    all_files: &[(PathBuf, u64)],
+    duplicates: &[Group],
+    tolerance: Tolerance,
+) -> Vec<Vec<(PathBuf, u64)>> {
+    let redundant: HashSet<&PathBuf> = duplicates
+        .iter()
+        .flat_map(|group| group.files.iter().skip(1).map(|f| &f.path))
+        .collect();
+    let mut files: Vec<(PathBuf, u64)> = all_files
+        .iter()
+        .filter(|(f, size)| *size >= FINGERPRINT_LEN && !redundant.contains(f))
+        .cloned()
+        .collect();
+    files.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
+    let pb = ui::work_progress(files.len() as u64);
+    let fingerprints: Vec<Option<(u64, u64)>> = files
+        .par_iter()
+        .map(|(path, size)| {
+            pb.inc(1);
+            edge_fingerprints(path, *size)
+                .map_err(|e| skipped::record_io(path, &e))
+                .ok()
+        })
+        .collect();
+    pb.finish_and_clear();
+
+    // Files whose first or last bytes are the same, in size order
+    let mut buckets: HashMap<(Edge, u64), Vec<usize>> = HashMap::new();
+    for (i, fingerprint) in fingerprints.iter().enumerate() {
+        if let Some((head, tail)) = *fingerprint {
+            buckets.entry((Edge::Head, head)).or_default().push(i);
+            buckets.entry((Edge::Tail, tail)).or_default().push(i);
+        }
+    }
+
+    // Candidate pairs: files of a bucket within the allowed difference
+    let mut pairs = HashSet::new();
+    for ((edge, _), members) in &buckets {
+        for (n, &i) in members.iter().enumerate() {
+            for &j in &members[n + 1..] {
+                let (small, big) = (files[i].1, files[j].1);
+                if big.saturating_sub(small) > tolerance.max_diff(big) {
+                    break;
+                }
+                if big > small {
+                    pairs.insert((i, j, *edge));
+                }
+            }
+        }
+    }
+
+    let pb = ui::work_progress(pairs.len() as u64);
+    let mut matches: Vec<(usize, usize)> = pairs
+        .into_par_iter()
+        .filter(|&(i, j, edge)| {
+            pb.inc(1);
+            is_contained(&files[i].0, &files[j].0, edge).unwrap_or_else(|e| {
+                skipped::record_io(&files[j].0, &e);
+                false
+            })
+        })
+        .map(|(i, j, _)| (i, j))
+        .collect();
+    pb.finish_and_clear();
+    matches.sort_unstable();
+
+    // Union the matching pairs into groups
+    let mut parent: Vec<usize> = (0..files.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (i, j) in matches {
+        let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+        parent[rj] = ri;
+    }
+
+    let mut groups: HashMap<usize, Vec<(PathBuf, u64)>> = HashMap::new();
+    for (i, file) in files.into_iter().enumerate() {
+        groups.entry(root(&mut parent, i)).or_default().push(file);
+    }
+    let mut groups: Vec<_> = groups.into_values().filter(|g| g.len() > 1).collect();
+    groups.sort_by(|a, b| a[0].0.cmp(&b[0].0));
+    groups
+}
+
+/// Which end of a file a near-duplicate fingerprint covers
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Edge {
+    Head,
+    Tail,
+}
+
+/// xxh3 of the first and of the last `FINGERPRINT_LEN` bytes of a `size`-byte file
+fn edge_fingerprints(path: &Path, size: u64) -> io::Result<(u64, u64)> {
+    let mut file = File::open(path)?;
+    let mut buffer = [0u8; FINGERPRINT_LEN as usize];
+    file.read_exact(&mut buffer)?;
+    let head = xxh3_64(&buffer);
+    file.seek(SeekFrom::Start(size - FINGERPRINT_LEN))?;
+    file.read_exact(&mut buffer)?;
+    Ok((head, xxh3_64(&buffer)))
+}
+
+/// Parse a --fuzzy-text similarity: a percentage ("95%", "95") or a fraction ("0.95")
+fn parse_similarity(value: &str) -> Result<f64> {
+    let value = value.trim();
+    let number = value.strip_suffix('%').unwrap_or(value).trim();
+    let mut similarity: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid similarity: {}", value))?;
+    if value.ends_with('%') || similarity > 1.0 {
+        similarity /= 100.0;
+    }
+    if !(similarity > 0.0 && similarity <= 1.0) {
+        return Err(anyhow!("Similarity must be between 0% and 100%"));
+    }
+    Ok(similarity)
+}
+
+/// --protect and `[dupes] protect` globs; a leading `~/` is the home directory
+fn compile_protect<'a>(globs: impl Iterator<Item = &'a String>) -> Result<Vec<Pattern>> {
+    globs
+        .map(|glob| {
+            let expanded = match (glob.strip_prefix("~/"), std::env::var_os("HOME")) {
+                (Some(rest), Some(home)) => Path::new(&home).join(rest).display().to_string(),
+                _ => glob.clone(),
+            };
+            Pattern::new(&expanded).map_err(|e| anyhow!("Invalid --protect glob {}: {}", glob, e))
+        })
+        .collect()
+}
+
+/// Whether `path`, as scanned or made absolute, matches a --protect glob
+fn matches_protect(protect: &[Pattern], path: &Path) -> bool {
+    protect.iter().any(|p| p.matches_path(path))
+        || path
+            .canonicalize()
+            .is_ok_and(|absolute| protect.iter().any(|p| p.matches_path(&absolute)))
+}
+
+/// Pairs of text files whose word shingles are at least `threshold` similar,
+/// most similar first. Exact duplicate groups take part through their first
+/// file; files with a NUL byte near the start are taken for binary and skipped.
+fn find_similar_text(
+    all_files: &[(PathBuf, u64)],
+    duplicates: &[Group],
+    threshold: f64,
+) -> Vec<(f64, PathBuf, PathBuf)> {
+    let redundant: HashSet<&PathBuf> = duplicates
+        .iter()
+        .flat_map(|group| group.files.iter().skip(1).map(|f| &f.path))
+        .collect();
+    let files: Vec<&PathBuf> = all_files
+        .iter()
+        .filter(|(f, size)| *size <= FUZZY_TEXT_MAX_SIZE && !redundant.contains(f))
+        .map(|(f, _)| f)
+        .collect();
+
+    let pb = ui::work_progress(files.len() as u64);
+    let signatures: Vec<(&PathBuf, Signature)> = files
+        .into_par_iter()
+        .filter_map(|file| {
+            pb.inc(1);
+            let data = std::fs::read(file)
+                .map_err(|e| skipped::record_io(file, &e))
+                .ok()?;
+            if data.iter().take(8192).any(|&b| b == 0) {
+                return None;
+            }
+            Some((file, Signature::of(&String::from_utf8_lossy(&data))?))
+        })
+        .collect();
+    pb.finish_and_clear();
+
+    let sigs: Vec<&Signature> = signatures.iter().map(|(_, s)| s).collect();
+    let mut pairs: Vec<(f64, PathBuf, PathBuf)> = candidate_pairs(&sigs)
+        .into_iter()
+        .filter_map(|(i, j)| {
+            let similarity = signatures[i].1.similarity(&signatures[j].1);
+            let (a, b) = (signatures[i].0, signatures[j].0);
+            let (a, b) = if a <= b { (a, b) } else { (b, a) };
+            (similarity >= threshold).then(|| (similarity, a.clone(), b.clone()))
+        })
+        .collect();
+    pairs.sort_by(|a, b| {
+        b.0.total_cmp(&a.0)
+            .then_with(|| (&a.1, &a.2).cmp(&(&b.1, &b.2)))
+    });
+    pairs
+}
+
+/// Whether `small` is byte-for-byte the start (`Edge::Head`) or the end
+/// (`Edge::Tail`) of `big`
+fn is_contained(small: &Path, big: &Path, edge: Edge) -> io::Result<bool> {
+    let small_len = small.metadata()?.len();
+    let big_len = big.metadata()?.len();
+    // Either may have changed since the scan
+    if small_len > big_len {
+        return Ok(false);
+    }
+
+    match edge {
+        Edge::Head => ranges_equal(small, big, 0, small_len),
+        Edge::Tail => ranges_equal(small, big, big_len - small_len, small_len),
+    }
+}
+
+/// Compare all of `small` with `len` bytes of `big` starting at `offset`, stopping at the first difference
+fn ranges_equal(small: &Path, big: &Path, offset: u64, len: u64) -> io::Result<bool> {
+    let mut a = BufReader::new(File::open(small)?);
+    let mut big_file = File::open(big)?;
+    big_file.seek(SeekFrom::Start(offset))?;
+    let mut b = BufReader::new(big_file.take(len));
+
+    let mut buf_a = [0u8; 8192];
+    let mut buf_b = [0u8; 8192];
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tolerance() {
+        assert_eq!(Tolerance::parse("1KB").unwrap().max_diff(10), 1024);
+        assert_eq!(Tolerance::parse("10%").unwrap().max_diff(2000), 200);
+        assert!(Tolerance::parse("150%").is_err());
+        assert!(Tolerance::parse("abc").is_err());
+    }
+
+    #[test]
+    fn test_find_near_duplicates() {
+        let dir = std::env::temp_dir().join(format!("ftools-near-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let mut newline = data.clone();
+        newline.push(b'\n');
+        let mut header = b"HEADER".to_vec();
+        header.extend_from_slice(&data);
+        let mut changed = data.clone();
+        changed[100] ^= 1;
+        changed.push(b'\n');
+        let mut far = data.clone();
+        far.extend_from_slice(&[0; 1024]);
+        for (name, contents) in [
+            ("a", &data),
+            ("b", &newline),
+            ("c", &header),
+            ("d", &changed),
+            ("e", &far),
+        ] {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let files: Vec<(PathBuf, u64)> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|name| (dir.join(name), std::fs::metadata(dir.join(name)).unwrap().len()))
+            .collect();
+
+        let groups = find_near_duplicates(&files, &[], Tolerance::Bytes(16));
+        assert_eq!(groups.len(), 1);
+        let mut names: Vec<_> = groups[0].iter().map(|(p, _)| p.file_name().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, ["a", "b", "c"]);
+
+        // Files smaller than the fingerprint are left out
+        let small = [(dir.join("x"), 10), (dir.join("y"), 11)];
+        assert!(find_near_duplicates(&small, &[], Tolerance::Bytes(16)).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_matches_protect() {
+        let globs = ["*/Originals/*".to_string(), "*.cr2".to_string()];
+        let protect = compile_protect(globs.iter()).unwrap();
+        assert!(matches_protect(&protect, Path::new("/photos/Originals/a.jpg")));
+        assert!(matches_protect(&protect, Path::new("copies/IMG_1.cr2")));
+        assert!(!matches_protect(&protect, Path::new("/photos/copies/a.jpg")));
+        assert!(compile_protect(["[".to_string()].iter()).is_err());
+    }
+
+    #[test]
+    fn test_parse_similarity() {
+        assert_eq!(parse_similarity("95%").unwrap(), 0.95);
+        assert_eq!(parse_similarity("0.9").unwrap(), 0.9);
+        assert_eq!(parse_similarity("80").unwrap(), 0.8);
+        assert!(parse_similarity("0").is_err());
+        assert!(parse_similarity("120%").is_err());
+    }
+
+    #[test]
+    fn test_keep_strategy() {
+        let mut files = [PathBuf::from("a/bb/c.txt"), PathBuf::from("z.txt")];
+        let strategy = KeepStrategy::parse("shortest-path").unwrap();
+        files.sort_by_cached_key(|f| strategy.rank(f));
+        assert_eq!(files[0], PathBuf::from("z.txt"));
+
+        let strategy = KeepStrategy::parse("regex:/bb/").unwrap();
+        files.sort_by_cached_key(|f| strategy.rank(f));
+        assert_eq!(files[0], PathBuf::from("a/bb/c.txt"));
+
+        assert!(KeepStrategy::parse("largest").is_err());
+        assert!(KeepStrategy::parse("regex:(").is_err());
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(
+            parse_selection("", 3).unwrap(),
+            (Selection::Keep(vec![0]), false)
+        );
+        assert_eq!(
+            parse_selection("3, 1 3", 3).unwrap(),
+            (Selection::Keep(vec![2, 0]), false)
+        );
+        assert_eq!(
+            parse_selection("2!", 3).unwrap(),
+            (Selection::Keep(vec![1]), true)
+        );
+        assert_eq!(
+            parse_selection("A", 3).unwrap(),
+            (Selection::KeepAll, false)
+        );
+        assert_eq!(parse_selection("q", 3).unwrap(), (Selection::Quit, false));
+        assert!(parse_selection("4", 3).is_err());
+        assert!(parse_selection("x", 3).is_err());
+    }
+
+    #[test]
+    fn test_hash_file_partial() {
+        let dir = std::env::temp_dir().join(format!("ftools-partial-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let size = 3 * PARTIAL_HASH_BLOCK as usize;
+        let base = vec![0u8; size];
+        let mut middle = base.clone();
+        middle[size / 2] = 1;
+        let mut end = base.clone();
+        end[size - 1] = 1;
+        for (name, data) in [("base", &base), ("middle", &middle), ("end", &end)] {
+            std::fs::write(dir.join(name), data).unwrap();
+        }
+
+        let hash = |name: &str| hash_file_partial(&dir.join(name), size as u64).unwrap();
+        assert_eq!(hash("base"), hash("middle"));
+        assert_ne!(hash("base"), hash("end"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_same_contents() {
+        let dir = std::env::temp_dir().join(format!("ftools-same-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = vec![7u8; 3 * PARTIAL_HASH_BLOCK as usize];
+        let mut changed = data.clone();
+        changed[2 * PARTIAL_HASH_BLOCK as usize + 1] = 0;
+        std::fs::write(dir.join("a"), &data).unwrap();
+        std::fs::write(dir.join("b"), &data).unwrap();
+        std::fs::write(dir.join("c"), &changed).unwrap();
+        std::fs::write(dir.join("d"), &data[1..]).unwrap();
+
+        let same = |x: &str, y: &str| same_contents(&dir.join(x), &dir.join(y)).unwrap();
+        assert!(same("a", "b"));
+        assert!(!same("a", "c"));
+        assert!(!same("a", "d"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_groups_on_disk() {
+        let file = |path: &str, reference, links: &[&str]| GroupFile {
+            path: PathBuf::from(path),
+            reference,
+            reused: !reference,
+            protected: false,
+            links: links.iter().map(PathBuf::from).collect(),
+        };
+        let mut records = Records::new(true);
+        for (size, digest) in [(10, "aa"), (20, "bb")] {
+            records
+                .push(Group {
+                    digest: digest.to_string(),
+                    size,
+                    files: vec![file("a/x", true, &[]), file("b/x", false, &["c/x", "d/x"])],
+                })
+                .unwrap();
+        }
+        records.finish().unwrap();
+        assert_eq!(records.len(), 2);
+
+        let mut sizes = Vec::new();
+        records
+            .for_each(|group| {
+                sizes.push(group.size);
+                Ok::<_, io::Error>(())
+            })
+            .unwrap();
+        assert_eq!(sizes, [10, 20]);
+
+        let groups: Vec<Group> = records.drain().unwrap().map(Result::unwrap).collect();
+        assert_eq!(groups[1].digest, "bb");
+        let files = &groups[1].files;
+        assert!(files[0].reference && !files[0].reused && files[0].links.is_empty());
+        assert!(!files[1].reference && files[1].reused);
+        assert_eq!(files[1].paths().collect::<Vec<_>>(), ["b/x", "c/x", "d/x"]);
+    }
+
+    #[test]
+    fn test_dir_digest() {
+        let files = |root: &str, second: &str| {
+            vec![
+                (PathBuf::from(root).join("a.txt"), 1),
+                (PathBuf::from(root).join(second), 1),
+            ]
+        };
+        let digests: HashMap<&Path, &str> = [
+            (Path::new("x/a.txt"), "1"),
+            (Path::new("x/sub/b.txt"), "2"),
+            (Path::new("y/sub/b.txt"), "2"),
+            (Path::new("y/a.txt"), "1"),
+            (Path::new("z/a.txt"), "1"),
+            (Path::new("z/b.txt"), "2"),
+        ]
+        .into_iter()
+        .collect();
+
+        let x = dir_digest(Path::new("x"), &files("x", "sub/b.txt"), &digests);
+        // Same contents listed in another order
+        let mut y_files = files("y", "sub/b.txt");
+        y_files.reverse();
+        let y = dir_digest(Path::new("y"), &y_files, &digests);
+        // Same hashes, different layout
+        let z = dir_digest(Path::new("z"), &files("z", "b.txt"), &digests);
+
+        assert!(x.is_some());
+        assert_eq!(x, y);
+        assert_ne!(x, z);
+        assert_eq!(
+            dir_digest(Path::new("w"), &files("w", "b.txt"), &digests),
+            None
+        );
+    }
+}
@@ -1,16 +1,29 @@
 use anyhow::Result;
 use colored::*;
+use serde_json::json;
 use std::fs;
 use walkdir::WalkDir;
 
-use crate::ui::{self, chars};
-
-pub fn run(path: &str, dirs_only: bool, files_only: bool, delete: bool) -> Result<()> {
+use crate::exit;
+use crate::output::{self, OutputArgs, Report};
+use crate::plan::Plan;
+use crate::ui::{self, chars, Element, Themed};
+use crate::utils::walk_ok;
+
+pub fn run(
+    path: &str,
+    dirs_only: bool,
+    files_only: bool,
+    delete: bool,
+    dry_run: bool,
+    yes: bool,
+    output: &OutputArgs,
+) -> Result<()> {
     ui::print_start("Finding empty items", path);
-    println!();
+    ui::print_blank();
 
-    let find_dirs = dirs_only || (!dirs_only && !files_only);
-    let find_files = files_only || (!dirs_only && !files_only);
+    let find_dirs = dirs_only || !files_only;
+    let find_files = files_only || !dirs_only;
 
     let mut empty_dirs = Vec::new();
     let mut empty_files = Vec::new();
@@ -20,15 +33,14 @@ pub fn run(path: &str, dirs_only: bool, files_only: bool, delete: bool) -> Resul
         for entry in WalkDir::new(path)
             .follow_links(false)
             .into_iter()
-            .filter_map(|e| e.ok())
+            .filter_map(walk_ok)
         {
             let entry_path = entry.path();
-            if entry_path.is_file() {
-                if let Ok(metadata) = entry_path.metadata() {
-                    if metadata.len() == 0 {
-                        empty_files.push(entry_path.to_path_buf());
-                    }
-                }
+            if entry.file_type().is_file()
+                && let Ok(metadata) = entry.metadata()
+                && metadata.len() == 0
+            {
+                empty_files.push(entry_path.to_path_buf());
             }
         }
     }
@@ -38,8 +50,8 @@ pub fn run(path: &str, dirs_only: bool, files_only: bool, delete: bool) -> Resul
         let mut all_dirs: Vec<_> = WalkDir::new(path)
             .follow_links(false)
             .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_dir())
+            .filter_map(walk_ok)
+            .filter(|e| e.file_type().is_dir())
             .map(|e| e.path().to_path_buf())
             .collect();
 
@@ -56,75 +68,98 @@ pub fn run(path: &str, dirs_only: bool, files_only: bool, delete: bool) -> Resul
         }
     }
 
+    let mut report = Report::new(&["type", "path"]);
+    for (kind, items) in [("file", &empty_files), ("dir", &empty_dirs)] {
+        for item in items {
+            report.row(vec![json!(kind), json!(item.display().to_string())]);
+        }
+    }
+    output::write_report(output, &report)?;
+
     if empty_files.is_empty() && empty_dirs.is_empty() {
         ui::print_success("No empty items found");
         return Ok(());
     }
 
-    if !empty_files.is_empty() {
+    if ui::is_path_list() {
+        for item in empty_files.iter().chain(&empty_dirs) {
+            ui::emit_path(item);
+        }
+        if !delete {
+            return Ok(());
+        }
+    } else if !empty_files.is_empty() {
         ui::print_section(&format!("Empty Files ({})", empty_files.len()));
         for file in &empty_files {
-            println!("  {} {}", chars::DOT.bright_yellow(), file.display());
+            println!(
+                "  {} {}",
+                chars::DOT.themed(Element::Warning),
+                file.display()
+            );
         }
     }
 
-    if !empty_dirs.is_empty() {
+    if !empty_dirs.is_empty() && !ui::is_path_list() {
         ui::print_section(&format!("Empty Directories ({})", empty_dirs.len()));
         for dir in &empty_dirs {
-            println!("  {} {}", chars::DOT.bright_yellow(), dir.display());
+            println!(
+                "  {} {}",
+                chars::DOT.themed(Element::Warning),
+                dir.display()
+            );
         }
     }
 
     // Delete if requested
     if delete {
-        ui::print_warning("Deleting empty items...");
-        println!();
-
-        let mut deleted_files = 0;
-        let mut deleted_dirs = 0;
-        let mut errors = 0;
-
-        // Delete files first
+        // Files first, then directories (already sorted deepest first)
+        let mut plan = Plan::new();
         for file in &empty_files {
-            match fs::remove_file(file) {
-                Ok(_) => {
-                    deleted_files += 1;
-                    println!(
-                        "  {} {}",
-                        chars::CROSS_MARK.red(),
-                        file.display().to_string().dimmed()
-                    );
-                }
-                Err(_) => errors += 1,
-            }
+            plan.delete_file(file);
         }
-
-        // Delete directories (already sorted deepest first)
         for dir in &empty_dirs {
-            match fs::remove_dir(dir) {
-                Ok(_) => {
-                    deleted_dirs += 1;
-                    println!(
-                        "  {} {}",
-                        chars::CROSS_MARK.red(),
-                        dir.display().to_string().dimmed()
-                    );
-                }
-                Err(_) => errors += 1,
-            }
+            plan.delete_dir(dir);
+        }
+
+        if dry_run {
+            plan.print_dry_run();
+            return Ok(());
         }
 
+        println!();
+        if !plan.confirm(yes)? {
+            ui::print_warning("Aborted, nothing was deleted");
+            return Ok(());
+        }
+
+        ui::print_warning("Deleting empty items...");
+        println!();
+
+        let outcome = plan.execute();
+
         println!();
         ui::print_line(50);
         println!(
             "{} Deleted: {} files, {} directories",
-            chars::ARROW.bright_black(),
-            deleted_files.to_string().bright_green().bold(),
-            deleted_dirs.to_string().bright_green().bold()
+            chars::ARROW.themed(Element::Muted),
+            outcome
+                .files_deleted
+                .to_string()
+                .themed(Element::Success)
+                .bold(),
+            outcome
+                .dirs_deleted
+                .to_string()
+                .themed(Element::Success)
+                .bold()
         );
-        if errors > 0 {
-            ui::print_error(&format!("Encountered {} errors during deletion", errors));
+        if outcome.failed > 0 {
+            ui::print_error(&format!(
+                "Encountered {} errors during deletion",
+                outcome.failed
+            ));
         }
+        return exit::partial(outcome.failed, "deletions");
     }
 
     Ok(())
@@ -1,11 +1,16 @@
 use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
 use colored::*;
 use rayon::prelude::*;
 use serde::Serialize;
-use std::path::Path;
+use serde_json::json;
+use std::path::PathBuf;
 
-use crate::ui::{self, chars};
-use crate::utils::{hash_file_sha256, hash_file_sha512, hash_file_md5};
+use crate::exit::{self, Exit};
+use crate::output::{self, OutputArgs, Report};
+use crate::ui::{self, chars, Element, Themed};
+use crate::utils::{hash_file_with, read_stdin_paths, HASH_ALGORITHMS, STDIN_PATH};
 
 #[derive(Serialize)]
 struct HashResult {
@@ -14,47 +19,130 @@ struct HashResult {
     hash: String,
 }
 
+/// How digests are rendered in the output
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Encoding {
+    Hex,
+    HexUpper,
+    Base64,
+}
+
+impl Encoding {
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "hex" => Ok(Encoding::Hex),
+            "HEX" => Ok(Encoding::HexUpper),
+            "base64" | "BASE64" => Ok(Encoding::Base64),
+            _ => Err(anyhow!(
+                "Unsupported encoding: {}. Use hex, HEX, or base64",
+                s
+            )),
+        }
+    }
+
+    /// Re-encode a lowercase hex digest
+    fn encode(self, hex_digest: &str) -> Result<String> {
+        Ok(match self {
+            Encoding::Hex => hex_digest.to_lowercase(),
+            Encoding::HexUpper => hex_digest.to_uppercase(),
+            Encoding::Base64 => STANDARD.encode(hex::decode(hex_digest)?),
+        })
+    }
+}
+
+/// Compare a hex digest against an expected value given as hex (any case,
+/// prefixes allowed), base64, or an SRI string like `sha256-<base64>`
+fn digest_matches(hex_digest: &str, expected: &str) -> bool {
+    let expected = expected.trim();
+    let expected = match expected.split_once('-') {
+        Some((algo, rest)) if ["sha256", "sha512", "md5"].contains(&algo) => rest,
+        _ => expected,
+    };
+
+    if !expected.is_empty() && expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        let expected = expected.to_lowercase();
+        let actual = hex_digest.to_lowercase();
+        if actual == expected || actual.starts_with(&expected) || expected.starts_with(&actual) {
+            return true;
+        }
+    }
+
+    let Ok(actual) = hex::decode(hex_digest) else {
+        return false;
+    };
+    [
+        STANDARD.decode(expected),
+        URL_SAFE_NO_PAD.decode(expected.trim_end_matches('=')),
+    ]
+    .into_iter()
+    .flatten()
+    .any(|bytes| bytes == actual)
+}
+
+/// Replace a "-" argument with the file list read from stdin
+fn expand_stdin(files: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::with_capacity(files.len());
+    for file in files {
+        if file.as_os_str() == STDIN_PATH {
+            expanded.extend(read_stdin_paths()?);
+        } else {
+            expanded.push(file);
+        }
+    }
+    Ok(expanded)
+}
+
 pub fn run(
-    files: Vec<String>,
+    files: Vec<PathBuf>,
     algorithm: &str,
     verify: Option<String>,
     format: &str,
+    encoding: &str,
+    output: &OutputArgs,
 ) -> Result<()> {
+    let files = expand_stdin(files)?;
     if files.is_empty() {
         return Err(anyhow!("No files specified"));
     }
 
+    let encoding = Encoding::parse(encoding)?;
+
     let algorithm = algorithm.to_lowercase();
-    
-    if !["sha256", "sha512", "md5"].contains(&algorithm.as_str()) {
+
+    if !HASH_ALGORITHMS.contains(&algorithm.as_str()) {
         return Err(anyhow!(
-            "Unsupported algorithm: {}. Use sha256, sha512, or md5",
-            algorithm
+            "Unsupported algorithm: {}. Use {}",
+            algorithm,
+            HASH_ALGORITHMS.join(", ")
         ));
     }
 
-    let results: Vec<(String, Result<String>)> = files
+    // Each successful result carries the raw hex digest and its encoded form
+    let results: Vec<(&PathBuf, Result<(String, String)>)> = files
         .par_iter()
-        .map(|file| {
-            let path = Path::new(file);
+        .map(|path| {
             if !path.exists() {
-                return (file.clone(), Err(anyhow!("File not found")));
+                return (path, Err(anyhow!("File not found")));
             }
             if !path.is_file() {
-                return (file.clone(), Err(anyhow!("Not a file")));
+                return (path, Err(anyhow!("Not a file")));
             }
 
-            let hash_result = match algorithm.as_str() {
-                "sha256" => hash_file_sha256(path),
-                "sha512" => hash_file_sha512(path),
-                "md5" => hash_file_md5(path),
-                _ => Err(anyhow!("Unsupported algorithm")),
-            };
+            let hash_result = hash_file_with(path, &algorithm)
+                .and_then(|hash| encoding.encode(&hash).map(|encoded| (hash, encoded)));
 
-            (file.clone(), hash_result)
+            (path, hash_result)
         })
         .collect();
 
+    let mut report = Report::new(&["file", "algorithm", "hash"]);
+    for (file, result) in &results {
+        if let Ok((_, hash)) = result {
+            report.row(vec![json!(file.display().to_string()), json!(algorithm), json!(hash)]);
+        }
+    }
+    output::write_report(output, &report)?;
+
     // Verify mode
     if let Some(expected_hash) = verify {
         if files.len() != 1 {
@@ -63,32 +151,37 @@ pub fn run(
 
         let (file, result) = &results[0];
         match result {
-            Ok(hash) => {
-                let expected = expected_hash.to_lowercase();
-                let actual = hash.to_lowercase();
-
-                if actual == expected || actual.starts_with(&expected) || expected.starts_with(&actual) {
+            Ok((hex_digest, actual)) => {
+                if digest_matches(hex_digest, &expected_hash) {
                     println!(
                         "{} {} {}",
-                        format!("[{}]", chars::CHECK).green().bold(),
-                        file.green().bold(),
-                        "MATCH".green().bold()
+                        format!("[{}]", chars::CHECK)
+                            .themed(Element::Success)
+                            .bold(),
+                        file.display().to_string().themed(Element::Success).bold(),
+                        "MATCH".themed(Element::Success).bold()
                     );
                     return Ok(());
                 } else {
                     println!(
                         "{} {} {}",
-                        format!("[{}]", chars::CROSS_MARK).red().bold(),
-                        file.red().bold(),
-                        "MISMATCH".red().bold()
+                        format!("[{}]", chars::CROSS_MARK)
+                            .themed(Element::Error)
+                            .bold(),
+                        file.display().to_string().themed(Element::Error).bold(),
+                        "MISMATCH".themed(Element::Error).bold()
                     );
-                    ui::print_kv("Expected", &expected);
-                    ui::print_kv_colored("Actual", actual.red());
-                    return Err(anyhow!("Hash verification failed"));
+                    ui::print_kv("Expected", &expected_hash);
+                    ui::print_kv_colored("Actual", actual.themed(Element::Error));
+                    return Err(Exit::silent(exit::NEGATIVE).into());
                 }
             }
             Err(e) => {
-                return Err(anyhow!("Failed to hash {}: {}", file, e));
+                return Err(Exit::with_message(
+                    exit::ERROR,
+                    format!("Failed to hash {}: {}", file.display(), e),
+                )
+                .into());
             }
         }
     }
@@ -99,8 +192,8 @@ pub fn run(
             let json_results: Vec<HashResult> = results
                 .iter()
                 .filter_map(|(file, result)| {
-                    result.as_ref().ok().map(|hash| HashResult {
-                        file: file.clone(),
+                    result.as_ref().ok().map(|(_, hash)| HashResult {
+                        file: file.display().to_string(),
                         algorithm: algorithm.clone(),
                         hash: hash.clone(),
                     })
@@ -112,24 +205,60 @@ pub fn run(
         _ => {
             println!(
                 "{} File Hashes ({})",
-                chars::BULLET.cyan(),
-                algorithm.to_uppercase().yellow()
+                chars::BULLET.themed(Element::Info),
+                algorithm.to_uppercase().themed(Element::Section)
             );
             ui::print_line(80);
 
             for (file, result) in &results {
                 match result {
-                    Ok(hash) => {
-                        println!("{}", hash.green());
+                    Ok((_, hash)) => {
+                        println!("{}", hash.themed(Element::Success));
+                        let file = file.display().to_string();
                         println!("  {} {}", chars::BL_CORNER.dimmed(), file.dimmed());
                     }
                     Err(e) => {
-                        ui::print_error(&format!("{} ({})", file, e));
+                        ui::print_error(&format!("{} ({})", file.display(), e));
                     }
                 }
             }
         }
     }
 
-    Ok(())
+    let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+    exit::partial(failed, "files")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            Encoding::HexUpper.encode(ABC_SHA256).unwrap(),
+            ABC_SHA256.to_uppercase()
+        );
+        assert_eq!(
+            Encoding::Base64.encode(ABC_SHA256).unwrap(),
+            "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        );
+    }
+
+    #[test]
+    fn test_digest_matches_any_encoding() {
+        assert!(digest_matches(ABC_SHA256, &ABC_SHA256.to_uppercase()));
+        assert!(digest_matches(ABC_SHA256, "ba7816bf"));
+        assert!(digest_matches(
+            ABC_SHA256,
+            "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        ));
+        assert!(digest_matches(
+            ABC_SHA256,
+            "sha256-ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        ));
+        assert!(!digest_matches(ABC_SHA256, "deadbeef"));
+    }
 }
@@ -1,84 +1,207 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use colored::*;
-use walkdir::WalkDir;
-
-use crate::ui;
-use crate::utils::{format_bytes, parse_size, should_skip};
-
-pub fn run(path: &str, size_str: &str, top: usize) -> Result<()> {
+use rayon::prelude::*;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::output::{self, OutputArgs, Report};
+use crate::skipped;
+use crate::ui::{self, Element, Themed};
+use crate::utils::{
+    allocated_size, format_bytes, hash_file_for_digest_len, is_sparse, parse_size,
+    read_checksum_manifest, should_skip, walk_roots,
+};
+
+pub fn run(
+    paths: &[String],
+    size_str: &str,
+    top: usize,
+    not_in: Option<String>,
+    one_file_system: bool,
+    output: &OutputArgs,
+) -> Result<()> {
     let min_size = parse_size(size_str)?;
 
     ui::print_start(
-        &format!("Finding large files (>= {})", format_bytes(min_size).bright_green()),
-        path,
+        &format!(
+            "Finding large files (>= {})",
+            format_bytes(min_size).themed(Element::Size)
+        ),
+        &paths.join(" "),
     );
-    println!();
+    ui::print_blank();
 
-    let mut large_files: Vec<(String, u64)> = Vec::new();
+    let mut large_files: Vec<LargeFile> = Vec::new();
 
-    for entry in WalkDir::new(path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
+    let spinner = ui::scan_spinner("Scanning");
+    for entry in walk_roots(paths, one_file_system) {
         let entry_path = entry.path();
+        spinner.inc(1);
 
-        if !entry_path.is_file() || should_skip(entry_path, false) {
+        if !entry.file_type().is_file() || should_skip(entry_path, false) {
             continue;
         }
 
-        if let Ok(metadata) = entry_path.metadata() {
+        if let Ok(metadata) = entry.metadata() {
             let size = metadata.len();
             if size >= min_size {
-                large_files.push((entry_path.display().to_string(), size));
+                large_files.push(LargeFile {
+                    path: entry_path.to_path_buf(),
+                    size,
+                    allocated: allocated_size(entry_path, &metadata),
+                });
             }
         }
     }
 
-    large_files.sort_by(|a, b| b.1.cmp(&a.1));
+    spinner.finish_and_clear();
+
+    if let Some(manifest) = &not_in {
+        let before = large_files.len();
+        large_files = exclude_backed_up(large_files, Path::new(manifest))?;
+        ui::print_info(&format!(
+            "{} files already listed in {}",
+            (before - large_files.len())
+                .to_string()
+                .themed(Element::Success),
+            manifest
+        ));
+    }
+
+    large_files.sort_by_key(|f| std::cmp::Reverse(f.size));
     large_files.truncate(top);
 
+    let mut report = Report::new(&["path", "size_bytes", "allocated_bytes", "sparse"]);
+    for file in &large_files {
+        report.row(vec![
+            json!(file.path.display().to_string()),
+            json!(file.size),
+            json!(file.allocated),
+            json!(is_sparse(file.size, file.allocated)),
+        ]);
+    }
+    output::write_report(output, &report)?;
+
+    if ui::is_path_list() {
+        for file in &large_files {
+            ui::emit_path(&file.path);
+        }
+        return Ok(());
+    }
+
     if large_files.is_empty() {
         ui::print_warning(&format!("No files found >= {}", format_bytes(min_size)));
         return Ok(());
     }
 
-    let total_size: u64 = large_files.iter().map(|(_, s)| s).sum();
-    let max_size = large_files.first().map(|(_, s)| *s).unwrap_or(1);
+    let total_size: u64 = large_files.iter().map(|f| f.size).sum();
+    let max_size = large_files.first().map(|f| f.size).unwrap_or(1);
 
     ui::print_info(&format!(
         "Found {} files, total {}",
-        large_files.len().to_string().bright_green().bold(),
-        format_bytes(total_size).bright_green().bold()
+        large_files
+            .len()
+            .to_string()
+            .themed(Element::Success)
+            .bold(),
+        format_bytes(total_size).themed(Element::Size).bold()
     ));
-    println!();
+    let sparse = large_files
+        .iter()
+        .filter(|f| is_sparse(f.size, f.allocated))
+        .count();
+    if sparse > 0 {
+        ui::print_info(&format!(
+            "{} of them sparse, taking far less space on disk than their size",
+            sparse.to_string().themed(Element::Warning)
+        ));
+    }
+    ui::print_blank();
 
     // Table header
     println!(
         "  {:>4}  {:>12}  {:20}  {}",
-        "#".bright_black(),
-        "SIZE".bright_cyan().bold(),
+        "#".themed(Element::Muted),
+        "SIZE".themed(Element::Header).bold(),
         "".to_string(),
-        "FILE".bright_cyan().bold()
+        "FILE".themed(Element::Header).bold()
     );
     ui::print_line(80);
 
-    for (i, (file_path, size)) in large_files.iter().enumerate() {
-        let rank = format!("{:>4}", i + 1).bright_black();
-        let size_str = format!("{:>12}", format_bytes(*size)).bright_yellow().bold();
+    for (i, file) in large_files.iter().enumerate() {
+        let size = &file.size;
+        let rank = format!("{:>4}", i + 1).themed(Element::Muted);
+        let size_str = format!("{:>12}", format_bytes(*size))
+            .themed(Element::Size)
+            .bold();
 
         let bar_width = 20;
         let filled = ((*size as f64 / max_size as f64) * bar_width as f64) as usize;
         let bar = format!(
             "{}{}",
-            "━".repeat(filled).bright_green(),
-            "─".repeat(bar_width - filled).bright_black()
+            "━".repeat(filled).themed(Element::Progress),
+            "─".repeat(bar_width - filled).themed(Element::Muted)
         );
 
-        println!("  {}  {}  {}  {}", rank, size_str, bar, file_path);
+        let sparse = if is_sparse(file.size, file.allocated) {
+            format!("  (sparse: {} allocated)", format_bytes(file.allocated))
+                .themed(Element::Warning)
+                .to_string()
+        } else {
+            String::new()
+        };
+        println!(
+            "  {}  {}  {}  {}{}",
+            rank,
+            size_str,
+            bar,
+            file.path.display(),
+            sparse
+        );
     }
 
     ui::print_count(large_files.len(), "large file", "large files");
 
     Ok(())
 }
+
+/// A file over the size threshold
+struct LargeFile {
+    path: PathBuf,
+    size: u64,
+    /// Space taken on disk, far below `size` for sparse files
+    allocated: u64,
+}
+
+/// Drop files whose content hash appears in the checksum manifest
+fn exclude_backed_up(files: Vec<LargeFile>, manifest: &Path) -> Result<Vec<LargeFile>> {
+    let digests = read_checksum_manifest(manifest)?;
+    if digests.is_empty() {
+        return Err(anyhow!("No checksums found in {}", manifest.display()));
+    }
+
+    // A manifest may mix algorithms; hash each candidate once per digest length
+    let lengths: BTreeSet<usize> = digests.iter().map(|d| d.len()).collect();
+
+    let pb = ui::work_progress(files.len() as u64);
+    let remaining = files
+        .into_par_iter()
+        .filter(|file| {
+            pb.inc(1);
+            !lengths
+                .iter()
+                .any(|&len| match hash_file_for_digest_len(&file.path, len) {
+                    Ok(hash) => digests.contains(&hash),
+                    Err(e) => {
+                        skipped::record_error(&file.path, &e);
+                        false
+                    }
+                },
+            )
+        })
+        .collect();
+    pb.finish_and_clear();
+
+    Ok(remaining)
+}
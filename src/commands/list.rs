@@ -2,13 +2,18 @@ use anyhow::Result;
 use chrono::{DateTime, Local};
 use colored::*;
 use glob::Pattern;
+use serde_json::json;
 use std::cmp::Ordering;
+use std::path::PathBuf;
 use walkdir::WalkDir;
 
-use crate::ui;
-use crate::utils::{format_bytes, get_extension};
+use crate::output::{self, OutputArgs, Report};
+use crate::ui::{self, Element, Themed};
+use crate::utils::{format_bytes, get_extension, walk_ok};
 
 struct FileInfo {
+    path: PathBuf,
+    /// File name for display (lossy if it isn't valid UTF-8)
     name: String,
     size: u64,
     modified: DateTime<Local>,
@@ -23,8 +28,9 @@ pub fn run(
     recursive: bool,
     pattern: Option<String>,
     long: bool,
+    output: &OutputArgs,
 ) -> Result<()> {
-    let glob_pattern = pattern.as_ref().map(|p| Pattern::new(p).ok()).flatten();
+    let glob_pattern = pattern.as_ref().and_then(|p| Pattern::new(p).ok());
 
     let walker = if recursive {
         WalkDir::new(path).follow_links(false)
@@ -34,10 +40,10 @@ pub fn run(
 
     let mut files: Vec<FileInfo> = Vec::new();
 
-    for entry in walker.into_iter().filter_map(|e| e.ok()) {
+    for entry in walker.into_iter().filter_map(walk_ok) {
         let entry_path = entry.path();
 
-        if entry_path.to_string_lossy() == path {
+        if entry.depth() == 0 {
             continue;
         }
 
@@ -46,23 +52,24 @@ pub fn run(
             .map(|n| n.to_string_lossy().to_string())
             .unwrap_or_default();
 
-        if let Some(ref pat) = glob_pattern {
-            if !pat.matches(&name) {
-                continue;
-            }
+        if let Some(ref pat) = glob_pattern
+            && !pat.matches(&name)
+        {
+            continue;
         }
 
         let metadata = entry_path.metadata().ok();
-        let is_dir = entry_path.is_dir();
+        let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
 
         let size = metadata.as_ref().map(|m| m.len()).unwrap_or(0);
         let modified = metadata
             .as_ref()
             .and_then(|m| m.modified().ok())
-            .map(|t| DateTime::<Local>::from(t))
+            .map(DateTime::<Local>::from)
             .unwrap_or_else(Local::now);
 
         files.push(FileInfo {
+            path: entry_path.to_path_buf(),
             name,
             size,
             modified,
@@ -97,25 +104,46 @@ pub fn run(
         }
     });
 
+    let mut report = Report::new(&["path", "type", "size", "modified"]);
+    for file in &files {
+        report.row(vec![
+            json!(file.path.display().to_string()),
+            json!(if file.is_dir { "dir" } else { "file" }),
+            json!(file.size),
+            json!(file.modified.to_rfc3339()),
+        ]);
+    }
+    output::write_report(output, &report)?;
+
+    if ui::is_path_list() {
+        for file in &files {
+            ui::emit_path(&file.path);
+        }
+        return Ok(());
+    }
+
     // Print
     if long {
         println!(
             "  {:>12}  {:>19}  {}",
-            "SIZE".bright_cyan().bold(),
-            "MODIFIED".bright_cyan().bold(),
-            "NAME".bright_cyan().bold()
+            "SIZE".themed(Element::Header).bold(),
+            "MODIFIED".themed(Element::Header).bold(),
+            "NAME".themed(Element::Header).bold()
         );
         ui::print_line(70);
 
         for file in &files {
             let size_str = if file.is_dir {
-                format!("{:>12}", "<DIR>".bright_blue())
+                format!("{:>12}", "<DIR>".themed(Element::Path))
             } else {
-                format!("{:>12}", format_bytes(file.size).bright_yellow())
+                format!("{:>12}", format_bytes(file.size).themed(Element::Size))
             };
- 
+
             let name_str = if file.is_dir {
-                format!("{}/", file.name).bright_blue().bold().to_string()
+                format!("{}/", file.name)
+                    .themed(Element::Path)
+                    .bold()
+                    .to_string()
             } else {
                 file.name.clone()
             };
@@ -123,7 +151,10 @@ pub fn run(
             println!(
                 "  {}  {}  {}",
                 size_str,
-                file.modified.format("%Y-%m-%d %H:%M:%S").to_string().bright_black(),
+                file.modified
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+                    .themed(Element::Muted),
                 name_str
             );
         }
@@ -137,7 +168,10 @@ pub fn run(
             print!("  ");
             for file in chunk {
                 let name = if file.is_dir {
-                    format!("{}/", file.name).bright_blue().bold().to_string()
+                    format!("{}/", file.name)
+                        .themed(Element::Path)
+                        .bold()
+                        .to_string()
                 } else {
                     file.name.clone()
                 };
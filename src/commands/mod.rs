@@ -5,6 +5,7 @@ pub mod empty;
 pub mod hash;
 pub mod large;
 pub mod list;
+pub mod organize;
 pub mod recent;
 pub mod rename;
 pub mod search;
@@ -0,0 +1,558 @@
+use anyhow::{anyhow, Result};
+use colored::*;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::exit;
+use crate::output::{self, OutputArgs, Report};
+use crate::plan::Plan;
+use crate::ui::{self, chars, Element, Themed};
+use crate::utils::{get_extension, walk_ok};
+
+/// Category for files no rule matches
+const FALLBACK_CATEGORY: &str = "Others";
+
+const RULE_KEYS: [&str; 3] = ["category", "extensions", "globs"];
+
+/// Ordered category rules; the first matching rule decides a file's category
+#[derive(Serialize, Deserialize)]
+struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Rule {
+    category: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extensions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    globs: Vec<String>,
+}
+
+impl Rule {
+    fn new(category: &str, extensions: &[&str]) -> Self {
+        Rule {
+            category: category.to_string(),
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            globs: Vec::new(),
+        }
+    }
+}
+
+/// A rule with its globs compiled and extensions normalized
+struct CompiledRule {
+    category: String,
+    extensions: Vec<String>,
+    globs: Vec<Pattern>,
+}
+
+impl CompiledRule {
+    fn matches(&self, path: &Path) -> bool {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        self.extensions.contains(&get_extension(path))
+            || self.globs.iter().any(|g| g.matches(&name))
+    }
+}
+
+fn builtin_rules() -> RuleSet {
+    RuleSet {
+        rules: vec![
+            Rule::new(
+                "Images",
+                &[
+                    "jpg", "jpeg", "png", "gif", "bmp", "webp", "svg", "heic", "tiff",
+                ],
+            ),
+            Rule::new(
+                "Videos",
+                &["mp4", "mkv", "mov", "avi", "webm", "wmv", "flv"],
+            ),
+            Rule::new("Audio", &["mp3", "wav", "flac", "aac", "ogg", "m4a"]),
+            Rule::new(
+                "Documents",
+                &[
+                    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "txt", "md", "csv",
+                ],
+            ),
+            Rule::new("Archives", &["zip", "rar", "7z", "tar", "gz", "bz2", "xz"]),
+            Rule::new(
+                "Installers",
+                &["exe", "msi", "dmg", "pkg", "deb", "rpm", "apk"],
+            ),
+            Rule::new(
+                "Code",
+                &[
+                    "rs", "py", "js", "ts", "java", "c", "cpp", "h", "go", "rb", "php", "html",
+                    "css", "json",
+                ],
+            ),
+        ],
+    }
+}
+
+/// Severity of a rule-file finding
+#[derive(PartialEq, Eq)]
+enum Level {
+    Error,
+    Warning,
+}
+
+struct Issue {
+    level: Level,
+    message: String,
+}
+
+impl Issue {
+    fn error(message: String) -> Self {
+        Issue {
+            level: Level::Error,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Issue {
+            level: Level::Warning,
+            message,
+        }
+    }
+}
+
+/// Organize flags from the command line
+pub struct OrganizeOptions {
+    pub rules: Option<String>,
+    pub print_rules: bool,
+    pub check_rules: Option<String>,
+    pub apply: bool,
+    pub dry_run: bool,
+    pub yes: bool,
+}
+
+pub fn run(path: &str, options: OrganizeOptions, output: &OutputArgs) -> Result<()> {
+    let OrganizeOptions {
+        rules,
+        print_rules,
+        check_rules,
+        apply,
+        dry_run,
+        yes,
+    } = options;
+
+    if print_rules {
+        let rule_set = match &rules {
+            Some(file) => load_rules(Path::new(file))?,
+            None => builtin_rules(),
+        };
+        print!("{}", toml::to_string_pretty(&rule_set)?);
+        return Ok(());
+    }
+
+    if let Some(file) = check_rules {
+        return check_rules_file(Path::new(&file));
+    }
+
+    let rule_set = match &rules {
+        Some(file) => load_rules(Path::new(file))?,
+        None => builtin_rules(),
+    };
+    let compiled = compile(&rule_set)?;
+
+    ui::print_start("Organizing", path);
+    ui::print_blank();
+
+    let base = PathBuf::from(path);
+    let mut plan: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+
+    for entry in WalkDir::new(path)
+        .min_depth(1)
+        .max_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(walk_ok)
+    {
+        let file_path = entry.path();
+        if !file_path.is_file() {
+            continue;
+        }
+
+        let category = compiled
+            .iter()
+            .find(|r| r.matches(file_path))
+            .map(|r| r.category.as_str())
+            .unwrap_or(FALLBACK_CATEGORY);
+        plan.entry(category.to_string())
+            .or_default()
+            .push(file_path.to_path_buf());
+    }
+
+    if plan.is_empty() {
+        ui::print_warning("No files to organize");
+        return Ok(());
+    }
+
+    let mut report = Report::new(&["path", "category", "target"]);
+    for (category, files) in &plan {
+        for file in files {
+            let target = base
+                .join(category)
+                .join(file.file_name().unwrap_or_default());
+            report.row(vec![
+                json!(file.display().to_string()),
+                json!(category),
+                json!(target.display().to_string()),
+            ]);
+        }
+    }
+    output::write_report(output, &report)?;
+
+    for (category, files) in &plan {
+        ui::print_section(&format!("{} ({})", category, files.len()));
+        for file in files {
+            println!(
+                "  {} {}",
+                chars::BULLET.dimmed(),
+                file.file_name().unwrap_or_default().to_string_lossy()
+            );
+        }
+    }
+    println!();
+
+    if !apply {
+        ui::print_info("Run with --apply to move files into category folders");
+        return Ok(());
+    }
+
+    // Files whose target is taken stay where they are
+    let mut plan_moves = Plan::new();
+    let mut conflicts = 0;
+    for (category, files) in &plan {
+        for file in files {
+            let target = base
+                .join(category)
+                .join(file.file_name().unwrap_or_default());
+            if target.symlink_metadata().is_ok() {
+                ui::print_warning(&format!("{} already exists", target.display()));
+                conflicts += 1;
+                continue;
+            }
+            plan_moves.rename(file, &target);
+        }
+    }
+    if plan_moves.is_empty() {
+        return exit::partial(conflicts, "moves");
+    }
+
+    if dry_run {
+        plan_moves.print_dry_run();
+        return Ok(());
+    }
+
+    if !plan_moves.confirm(yes)? {
+        ui::print_warning("Aborted, nothing was moved");
+        return Ok(());
+    }
+
+    // A run that fails partway is undone rather than left half-sorted
+    let outcome = plan_moves.execute_or_roll_back();
+
+    println!();
+    ui::print_line(50);
+    print!(
+        "{} {} moved, {} failed",
+        chars::ARROW.dimmed(),
+        outcome.renamed.to_string().themed(Element::Success).bold(),
+        (outcome.failed + conflicts).to_string().themed(Element::Error)
+    );
+    if outcome.rolled_back > 0 {
+        print!(
+            ", {} rolled back",
+            outcome.rolled_back.to_string().themed(Element::Warning)
+        );
+    }
+    println!();
+
+    exit::partial(outcome.failed + conflicts, "moves")
+}
+
+fn load_rules(path: &Path) -> Result<RuleSet> {
+    let content = fs::read_to_string(path)?;
+    let issues = validate(&content);
+    if let Some(issue) = issues.iter().find(|i| i.level == Level::Error) {
+        return Err(anyhow!(
+            "Invalid rules in {}: {} (run --check-rules for details)",
+            path.display(),
+            issue.message
+        ));
+    }
+    Ok(toml::from_str(&content)?)
+}
+
+fn compile(rule_set: &RuleSet) -> Result<Vec<CompiledRule>> {
+    rule_set
+        .rules
+        .iter()
+        .map(|rule| {
+            Ok(CompiledRule {
+                category: rule.category.clone(),
+                extensions: rule.extensions.iter().map(|e| normalize_ext(e)).collect(),
+                globs: rule
+                    .globs
+                    .iter()
+                    .map(|g| Pattern::new(g))
+                    .collect::<Result<_, _>>()?,
+            })
+        })
+        .collect()
+}
+
+fn normalize_ext(ext: &str) -> String {
+    ext.trim().trim_start_matches('.').to_lowercase()
+}
+
+fn check_rules_file(path: &Path) -> Result<()> {
+    let content = fs::read_to_string(path)?;
+    let issues = validate(&content);
+
+    ui::print_start("Checking rules", &path.display().to_string());
+    ui::print_blank();
+
+    let errors = issues.iter().filter(|i| i.level == Level::Error).count();
+    let warnings = issues.len() - errors;
+
+    for issue in &issues {
+        match issue.level {
+            Level::Error => ui::print_error(&issue.message),
+            Level::Warning => ui::print_warning(&issue.message),
+        }
+    }
+
+    if errors > 0 {
+        return Err(anyhow!(
+            "{} errors, {} warnings in {}",
+            errors,
+            warnings,
+            path.display()
+        ));
+    }
+
+    ui::print_success(&format!("Rules are valid ({} warnings)", warnings));
+    Ok(())
+}
+
+/// Validate a rules file, collecting every problem instead of stopping at the first
+fn validate(content: &str) -> Vec<Issue> {
+    let table: toml::Table = match content.parse() {
+        Ok(table) => table,
+        Err(e) => return vec![Issue::error(format!("TOML syntax error: {}", e.message()))],
+    };
+
+    let mut issues = Vec::new();
+
+    for key in table.keys().filter(|k| k.as_str() != "rules") {
+        issues.push(Issue::error(format!(
+            "unknown top-level key `{}` (expected `rules`)",
+            key
+        )));
+    }
+
+    let Some(rules) = table.get("rules") else {
+        issues.push(Issue::error("missing `rules` array".to_string()));
+        return issues;
+    };
+    let Some(rules) = rules.as_array() else {
+        issues.push(Issue::error(
+            "`rules` must be an array of tables ([[rules]])".to_string(),
+        ));
+        return issues;
+    };
+
+    // (extension or glob, rule number) pairs seen so far, in rule order
+    let mut seen_exts: Vec<(String, usize)> = Vec::new();
+    let mut seen_globs: Vec<(String, usize)> = Vec::new();
+
+    for (i, rule) in rules.iter().enumerate() {
+        let n = i + 1;
+        let Some(rule) = rule.as_table() else {
+            issues.push(Issue::error(format!("rule {}: expected a table", n)));
+            continue;
+        };
+
+        for key in rule.keys().filter(|k| !RULE_KEYS.contains(&k.as_str())) {
+            issues.push(Issue::error(format!(
+                "rule {}: unknown key `{}` (expected one of: {})",
+                n,
+                key,
+                RULE_KEYS.join(", ")
+            )));
+        }
+
+        let category = match rule.get("category").and_then(|c| c.as_str()) {
+            Some(c) if !c.trim().is_empty() => c.to_string(),
+            _ => {
+                issues.push(Issue::error(format!(
+                    "rule {}: missing `category` string",
+                    n
+                )));
+                format!("#{}", n)
+            }
+        };
+        let label = format!("rule {} ({})", n, category);
+
+        let extensions = string_list(rule, "extensions", &label, &mut issues);
+        let globs = string_list(rule, "globs", &label, &mut issues);
+
+        if extensions.is_empty() && globs.is_empty() {
+            issues.push(Issue::error(format!(
+                "{}: needs at least one of `extensions` or `globs`",
+                label
+            )));
+            continue;
+        }
+
+        let mut reachable = false;
+
+        for ext in extensions.iter().map(|e| normalize_ext(e)) {
+            let shadow = seen_exts
+                .iter()
+                .find(|(e, _)| *e == ext)
+                .map(|(_, r)| *r)
+                .or_else(|| catch_all_rule(&seen_globs));
+            match shadow {
+                Some(r) if r == n => issues.push(Issue::warning(format!(
+                    "{}: extension `{}` listed twice",
+                    label, ext
+                ))),
+                Some(r) => issues.push(Issue::warning(format!(
+                    "{}: extension `{}` is already claimed by rule {}",
+                    label, ext, r
+                ))),
+                None => reachable = true,
+            }
+            seen_exts.push((ext, n));
+        }
+
+        for glob in &globs {
+            if let Err(e) = Pattern::new(glob) {
+                issues.push(Issue::error(format!(
+                    "{}: invalid glob `{}`: {}",
+                    label, glob, e
+                )));
+                continue;
+            }
+
+            // A glob like `*.png` overlaps an earlier `png` extension exactly
+            let ext_overlap = glob
+                .strip_prefix("*.")
+                .filter(|e| !e.contains(['*', '?', '[']))
+                .and_then(|e| seen_exts.iter().find(|(s, _)| *s == normalize_ext(e)))
+                .map(|(_, r)| *r);
+            let shadow = seen_globs
+                .iter()
+                .find(|(g, _)| g == glob)
+                .map(|(_, r)| *r)
+                .or(ext_overlap)
+                .or_else(|| catch_all_rule(&seen_globs));
+
+            match shadow {
+                Some(r) => issues.push(Issue::warning(format!(
+                    "{}: glob `{}` overlaps rule {} which matches first",
+                    label, glob, r
+                ))),
+                None => reachable = true,
+            }
+            seen_globs.push((glob.clone(), n));
+        }
+
+        if !reachable {
+            issues.push(Issue::error(format!(
+                "{}: unreachable, every pattern is matched by an earlier rule",
+                label
+            )));
+        }
+    }
+
+    issues
+}
+
+/// Earliest rule with a catch-all glob (`*`), which shadows everything after it
+fn catch_all_rule(seen_globs: &[(String, usize)]) -> Option<usize> {
+    seen_globs
+        .iter()
+        .find(|(g, _)| g.chars().all(|c| c == '*'))
+        .map(|(_, r)| *r)
+}
+
+fn string_list(rule: &toml::Table, key: &str, label: &str, issues: &mut Vec<Issue>) -> Vec<String> {
+    match rule.get(key) {
+        None => Vec::new(),
+        Some(toml::Value::Array(items)) => items
+            .iter()
+            .filter_map(|item| match item.as_str() {
+                Some(s) => Some(s.to_string()),
+                None => {
+                    issues.push(Issue::error(format!(
+                        "{}: `{}` must contain only strings",
+                        label, key
+                    )));
+                    None
+                }
+            })
+            .collect(),
+        Some(_) => {
+            issues.push(Issue::error(format!(
+                "{}: `{}` must be an array of strings",
+                label, key
+            )));
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(content: &str) -> Vec<String> {
+        validate(content).into_iter().map(|i| i.message).collect()
+    }
+
+    #[test]
+    fn test_builtin_rules_are_valid() {
+        let dumped = toml::to_string_pretty(&builtin_rules()).unwrap();
+        assert!(validate(&dumped).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_keys_and_unreachable_rules() {
+        let found = messages(
+            r#"
+            colour = "red"
+
+            [[rules]]
+            category = "Images"
+            extensions = ["png", "jpg"]
+
+            [[rules]]
+            category = "Screenshots"
+            extension = ["png"]
+            globs = ["*.png"]
+            "#,
+        );
+        assert!(found
+            .iter()
+            .any(|m| m.contains("unknown top-level key `colour`")));
+        assert!(found.iter().any(|m| m.contains("unknown key `extension`")));
+        assert!(found
+            .iter()
+            .any(|m| m.contains("rule 2 (Screenshots): unreachable")));
+    }
+}
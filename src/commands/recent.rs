@@ -1,46 +1,84 @@
 use anyhow::Result;
 use chrono::{DateTime, Local};
 use colored::*;
-use std::time::SystemTime;
-use walkdir::WalkDir;
-
-use crate::ui;
-use crate::utils::{format_bytes, parse_duration, should_skip};
-
-pub fn run(path: &str, within: &str, top: usize) -> Result<()> {
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::index::HashIndex;
+use crate::output::{self, OutputArgs, Report};
+use crate::ui::{self, Element, Themed};
+use crate::utils::{format_bytes, parse_duration, should_skip, walk_roots};
+
+pub fn run(
+    paths: &[String],
+    within: &str,
+    top: usize,
+    content_changed: bool,
+    output: &OutputArgs,
+) -> Result<()> {
     let seconds = parse_duration(within)?;
-    let cutoff = SystemTime::now() - std::time::Duration::from_secs(seconds);
-
-    ui::print_start(&format!("Finding files modified within {}", within.bright_green()), path);
-    println!();
+    let cutoff = SystemTime::now() - Duration::from_secs(seconds);
+
+    ui::print_start(
+        &format!(
+            "Finding files modified within {}",
+            within.themed(Element::Success)
+        ),
+        &paths.join(" "),
+    );
+    ui::print_blank();
 
-    let mut recent_files: Vec<(String, u64, DateTime<Local>)> = Vec::new();
+    let mut recent_files: Vec<(PathBuf, u64, DateTime<Local>)> = Vec::new();
 
-    for entry in WalkDir::new(path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
+    for entry in walk_roots(paths, false) {
         let entry_path = entry.path();
 
-        if !entry_path.is_file() || should_skip(entry_path, false) {
+        if !entry.file_type().is_file() || should_skip(entry_path, false) {
             continue;
         }
 
-        if let Ok(metadata) = entry_path.metadata() {
-            if let Ok(modified) = metadata.modified() {
-                if modified > cutoff {
-                    let size = metadata.len();
-                    let datetime = DateTime::<Local>::from(modified);
-                    recent_files.push((entry_path.display().to_string(), size, datetime));
-                }
-            }
+        if let Ok(metadata) = entry.metadata()
+            && let Ok(modified) = metadata.modified()
+            && modified > cutoff
+        {
+            let size = metadata.len();
+            let datetime = DateTime::<Local>::from(modified);
+            recent_files.push((entry_path.to_path_buf(), size, datetime));
         }
     }
 
-    recent_files.sort_by(|a, b| b.2.cmp(&a.2));
+    if content_changed {
+        let before = recent_files.len();
+        recent_files = keep_content_changes(recent_files, cutoff)?;
+        ui::print_info(&format!(
+            "{} files touched without content changes",
+            (before - recent_files.len())
+                .to_string()
+                .themed(Element::Success)
+        ));
+    }
+
+    recent_files.sort_by_key(|f| std::cmp::Reverse(f.2));
     recent_files.truncate(top);
 
+    let mut report = Report::new(&["path", "size_bytes", "modified"]);
+    for (file, size, modified) in &recent_files {
+        report.row(vec![
+            json!(file.display().to_string()),
+            json!(size),
+            json!(modified.to_rfc3339()),
+        ]);
+    }
+    output::write_report(output, &report)?;
+
+    if ui::is_path_list() {
+        for (file, _, _) in &recent_files {
+            ui::emit_path(file);
+        }
+        return Ok(());
+    }
+
     if recent_files.is_empty() {
         ui::print_warning(&format!("No files modified within {}", within));
         return Ok(());
@@ -48,16 +86,20 @@ pub fn run(path: &str, within: &str, top: usize) -> Result<()> {
 
     ui::print_info(&format!(
         "Found {} files",
-        recent_files.len().to_string().green().bold()
+        recent_files
+            .len()
+            .to_string()
+            .themed(Element::Success)
+            .bold()
     ));
-    println!();
+    ui::print_blank();
 
     // Table header
     println!(
         "  {:>19}  {:>12}  {}",
-        "MODIFIED".bright_cyan().bold(),
-        "SIZE".bright_cyan().bold(),
-        "FILE".bright_cyan().bold()
+        "MODIFIED".themed(Element::Header).bold(),
+        "SIZE".themed(Element::Header).bold(),
+        "FILE".themed(Element::Header).bold()
     );
     ui::print_line(80);
 
@@ -78,15 +120,18 @@ pub fn run(path: &str, within: &str, top: usize) -> Result<()> {
 
         let time_str = format!(
             "{} {}",
-            modified.format("%Y-%m-%d %H:%M").to_string().bright_black(),
-            format!("({})", relative_time).bright_yellow()
+            modified
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+                .themed(Element::Muted),
+            format!("({})", relative_time).themed(Element::Warning)
         );
 
         println!(
             "  {}  {:>12}  {}",
             time_str,
-            format_bytes(*size).bright_yellow(),
-            file_path
+            format_bytes(*size).themed(Element::Size),
+            file_path.display()
         );
     }
 
@@ -94,3 +139,25 @@ pub fn run(path: &str, within: &str, top: usize) -> Result<()> {
 
     Ok(())
 }
+
+/// Keep only files whose content (not just mtime) changed after `cutoff`,
+/// according to the persistent hash index. Files never indexed before count as changed.
+fn keep_content_changes(
+    files: Vec<(PathBuf, u64, DateTime<Local>)>,
+    cutoff: SystemTime,
+) -> Result<Vec<(PathBuf, u64, DateTime<Local>)>> {
+    let mut index = HashIndex::load();
+    let paths: Vec<PathBuf> = files.iter().map(|(p, _, _)| p.clone()).collect();
+    let entries = index.update(&paths, "sha256");
+    index.save()?;
+
+    Ok(files
+        .into_iter()
+        .zip(entries)
+        .filter(|(_, entry)| match entry {
+            Some(entry) => UNIX_EPOCH + Duration::from_nanos(entry.content_mtime_ns) > cutoff,
+            None => true,
+        })
+        .map(|(file, _)| file)
+        .collect())
+}
@@ -1,166 +1,1951 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDateTime};
 use colored::*;
-use regex::Regex;
-use std::fs;
-use std::path::PathBuf;
+use encoding_rs::Encoding;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 use walkdir::WalkDir;
 
-use crate::ui::{self, chars};
-use crate::utils::matches_extensions;
+use crate::exit::{self, Exit};
+use crate::magic::{self, FileType};
+use crate::output::{self, OutputArgs, Report};
+use crate::plan::{self, Plan};
+use crate::skipped;
+use crate::tui;
+use crate::ui::{self, chars, Element, Themed};
+use crate::utils::{self, matches_extensions, parse_duration, parse_size, walk_ok, Globs};
 
-pub fn run(
-    path: &str,
-    find: &str,
-    replace: &str,
-    extensions: Option<String>,
-    dry_run: bool,
-    recursive: bool,
-) -> Result<()> {
-    let regex = Regex::new(find)?;
+/// Rename flags from the command line
+#[derive(Default)]
+pub struct RenameOptions {
+    pub find: Option<String>,
+    pub ignore_case: bool,
+    pub regex_flags: Option<String>,
+    pub full_path: bool,
+    pub replace: Option<String>,
+    pub case: Option<String>,
+    pub sanitize: bool,
+    pub ascii: bool,
+    pub lowercase_ext: bool,
+    pub fix_extensions: bool,
+    pub fix_encoding: Option<String>,
+    pub undo: Option<String>,
+    pub from_file: Option<String>,
+    pub extensions: Option<String>,
+    pub include: Vec<String>,
+    pub older_than: Option<String>,
+    pub newer_than: Option<String>,
+    pub min_size: Option<String>,
+    pub max_size: Option<String>,
+    pub dry_run: bool,
+    pub recursive: bool,
+    pub dirs: bool,
+    pub on_conflict: Option<String>,
+    pub interactive: bool,
+    pub edit: bool,
+    pub output: OutputArgs,
+}
 
-    ui::print_start("Bulk rename", path);
-    println!(
-        "  {} '{}' {} '{}'",
-        "Pattern:".dimmed(),
-        find.yellow(),
-        chars::ARROW.dimmed(),
-        replace.green()
-    );
-    println!(
-        "  {} {}",
-        "Mode:".dimmed(),
-        if dry_run {
-            "DRY RUN (preview only)".yellow()
+/// Compile --find with -i and the --regex-flags letters; as in `(?i-u)`,
+/// letters after a `-` turn their flag off
+fn build_regex(find: &str, ignore_case: bool, flags: &str) -> Result<Regex> {
+    let mut builder = RegexBuilder::new(find);
+    builder.case_insensitive(ignore_case);
+    let mut on = true;
+    for flag in flags.chars() {
+        match flag {
+            '-' => {
+                on = false;
+                continue;
+            }
+            'i' => builder.case_insensitive(on),
+            'm' => builder.multi_line(on),
+            's' => builder.dot_matches_new_line(on),
+            'x' => builder.ignore_whitespace(on),
+            'U' => builder.swap_greed(on),
+            'u' => builder.unicode(on),
+            _ => return Err(anyhow!("Invalid regex flag: {}. Use i, m, s, x, U or u", flag)),
+        };
+    }
+    Ok(builder.build()?)
+}
+
+/// Case styles for --case
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Case {
+    Lower,
+    Upper,
+    Title,
+    Snake,
+    Kebab,
+    Camel,
+}
+
+impl Case {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "lower" => Ok(Case::Lower),
+            "upper" => Ok(Case::Upper),
+            "title" => Ok(Case::Title),
+            "snake" => Ok(Case::Snake),
+            "kebab" => Ok(Case::Kebab),
+            "camel" => Ok(Case::Camel),
+            _ => Err(anyhow!(
+                "Invalid case: {}. Use lower, upper, title, snake, kebab or camel",
+                value
+            )),
+        }
+    }
+
+    /// Convert `stem`. Lower and upper keep the separators; the others split
+    /// it into words at spaces, punctuation and camelCase humps.
+    fn apply(self, stem: &str) -> String {
+        match self {
+            Case::Lower => stem.to_lowercase(),
+            Case::Upper => stem.to_uppercase(),
+            Case::Title => words(stem)
+                .iter()
+                .map(|w| capitalize(w))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Case::Snake => words(stem)
+                .iter()
+                .map(|w| w.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("_"),
+            Case::Kebab => words(stem)
+                .iter()
+                .map(|w| w.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("-"),
+            Case::Camel => words(stem)
+                .iter()
+                .enumerate()
+                .map(|(i, w)| {
+                    if i == 0 {
+                        w.to_lowercase()
+                    } else {
+                        capitalize(w)
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Split a name into words at non-alphanumeric characters, lower-to-upper
+/// changes (`myFile`) and the end of an acronym (`HTTPServer`)
+fn words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        if let Some(&prev) = word.chars().last().as_ref() {
+            let hump = prev.is_lowercase() && c.is_uppercase();
+            let acronym_end = prev.is_uppercase()
+                && c.is_uppercase()
+                && chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if hump || acronym_end {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// A --replace string split at its counter, date and file tokens
+struct Template {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Text(String),
+    /// `{n}`, `{n+offset}`, `{n:width}` (zero-padded) or `{n+offset:width}`
+    Counter {
+        offset: usize,
+        width: usize,
+    },
+    /// `{mtime:format}`, `{ctime:format}` or `{exif_date:format}`, with a
+    /// strftime format (%Y-%m-%d when left out)
+    Date {
+        source: DateSource,
+        format: String,
+    },
+    /// `{parent}`, `{ext}`, `{size}`, `{hash8}`, `{width}` or `{height}`
+    Field(Field),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Field {
+    /// Name of the directory the file is in
+    Parent,
+    /// Original extension, without the dot
+    Ext,
+    /// Size in bytes
+    Size,
+    /// First 8 hex digits of the SHA-256 of the content
+    Hash8,
+    /// Image dimensions in pixels
+    Width,
+    Height,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DateSource {
+    Modified,
+    /// Creation time, or modification time where the filesystem has none
+    Created,
+    /// When the photo was taken, or modification time without EXIF data
+    Exif,
+}
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+impl Template {
+    fn parse(replace: &str) -> Result<Self> {
+        // `${n}` is a named capture group for the regex, not a counter
+        let token = Regex::new(
+            r"(\$?)\{(?:n(?:\+(\d+))?(?::(\d+))?|(mtime|ctime|exif_date)(?::([^}]*))?|(parent|ext|size|hash8|width|height))\}",
+        )?;
+        let mut parts = Vec::new();
+        let mut last = 0;
+        for caps in token.captures_iter(replace) {
+            let whole = caps.get(0).unwrap();
+            if !caps[1].is_empty() {
+                continue;
+            }
+            parts.push(Part::Text(replace[last..whole.start()].to_string()));
+            if let Some(field) = caps.get(6) {
+                parts.push(Part::Field(match field.as_str() {
+                    "parent" => Field::Parent,
+                    "ext" => Field::Ext,
+                    "size" => Field::Size,
+                    "hash8" => Field::Hash8,
+                    "width" => Field::Width,
+                    _ => Field::Height,
+                }));
+            } else if let Some(source) = caps.get(4) {
+                let source = match source.as_str() {
+                    "mtime" => DateSource::Modified,
+                    "ctime" => DateSource::Created,
+                    _ => DateSource::Exif,
+                };
+                let format = caps.get(5).map_or(DEFAULT_DATE_FORMAT, |m| m.as_str());
+                // chrono panics when displaying a bad format, so check it here
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(anyhow!("Invalid date format in {}", whole.as_str()));
+                }
+                if format.contains('/') {
+                    return Err(anyhow!(
+                        "Date format in {} can't contain '/'",
+                        whole.as_str()
+                    ));
+                }
+                parts.push(Part::Date {
+                    source,
+                    format: format.to_string(),
+                });
+            } else {
+                let number = |i: usize| -> Result<usize> {
+                    caps.get(i)
+                        .map_or(Ok(0), |m| m.as_str().parse())
+                        .map_err(|_| anyhow!("Invalid counter: {}", whole.as_str()))
+                };
+                parts.push(Part::Counter {
+                    offset: number(2)?,
+                    width: number(3)?,
+                });
+            }
+            last = whole.end();
+        }
+        parts.push(Part::Text(replace[last..].to_string()));
+        Ok(Template { parts })
+    }
+
+    /// Whether the replacement is the same for every file
+    fn is_literal(&self) -> bool {
+        self.parts.iter().all(|p| matches!(p, Part::Text(_)))
+    }
+
+    /// The replacement for `path`, the `n`th renamed file (1-based)
+    fn render(&self, n: usize, path: &Path) -> Result<String> {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Counter { offset, width } => {
+                    rendered.push_str(&format!("{:0width$}", n + offset, width = width))
+                }
+                Part::Date { source, format } => {
+                    let date = file_date(path, *source)?;
+                    rendered.push_str(&date.format(format).to_string());
+                }
+                Part::Field(field) => rendered.push_str(&file_field(path, *field)?),
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+fn file_date(path: &Path, source: DateSource) -> Result<NaiveDateTime> {
+    let metadata = fs::metadata(path)?;
+    let time = match source {
+        DateSource::Modified => metadata.modified()?,
+        DateSource::Created => metadata.created().or_else(|_| metadata.modified())?,
+        DateSource::Exif => match exif_date(path) {
+            Some(date) => return Ok(date),
+            None => {
+                ui::print_verbose(&format!(
+                    "{}: no EXIF date, using modification time",
+                    path.display()
+                ));
+                metadata.modified()?
+            }
+        },
+    };
+    Ok(DateTime::<Local>::from(time).naive_local())
+}
+
+fn file_field(path: &Path, field: Field) -> Result<String> {
+    let value = match field {
+        Field::Parent => {
+            // "." and ".." have no name of their own, so look at the real path
+            let parent = path.parent().unwrap_or(Path::new(""));
+            let parent = match parent.file_name() {
+                Some(name) => PathBuf::from(name),
+                None => fs::canonicalize(if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                })?,
+            };
+            parent
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        }
+        Field::Ext => path
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        Field::Size => fs::metadata(path)?.len().to_string(),
+        Field::Hash8 => utils::hash_file_sha256(path)?[..8].to_string(),
+        Field::Width | Field::Height => {
+            let (width, height) = magic::image_size(path)?
+                .ok_or_else(|| anyhow!("not an image with a known size"))?;
+            if field == Field::Width { width } else { height }.to_string()
+        }
+    };
+    Ok(value)
+}
+
+/// When the photo at `path` was taken, from its EXIF DateTimeOriginal (or
+/// DateTime) field
+fn exif_date(path: &Path) -> Option<NaiveDateTime> {
+    let mut file = BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut file).ok()?;
+    [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| {
+            let field = exif.get_field(tag, exif::In::PRIMARY)?;
+            let exif::Value::Ascii(values) = &field.value else {
+                return None;
+            };
+            let text = std::str::from_utf8(values.first()?).ok()?;
+            NaiveDateTime::parse_from_str(text.trim(), "%Y:%m:%d %H:%M:%S").ok()
+        })
+}
+
+/// `name` with its stem converted by `case`; the extension (of files) and
+/// the leading dot of hidden names are kept as is
+fn change_case(name: &str, case: Case, is_dir: bool) -> String {
+    let (dot, name) = match name.strip_prefix('.') {
+        Some(rest) => (".", rest),
+        None => ("", name),
+    };
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !is_dir => {
+            format!("{}{}.{}", dot, case.apply(stem), extension)
+        }
+        _ => format!("{}{}", dot, case.apply(name)),
+    }
+}
+
+/// How --on-conflict handles a new name that is already taken
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum OnConflict {
+    /// Refuse to rename anything (the default)
+    Abort,
+    Skip,
+    /// Replace the existing file. Two renames to the same new name are never
+    /// allowed to overwrite each other; the later one is skipped.
+    Overwrite,
+    /// Add " (1)", " (2)"... before the extension until the name is free
+    Suffix,
+}
+
+impl OnConflict {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "abort" => Ok(OnConflict::Abort),
+            "skip" => Ok(OnConflict::Skip),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            "suffix" => Ok(OnConflict::Suffix),
+            _ => Err(anyhow!(
+                "Invalid conflict action: {}. Use skip, overwrite, suffix or abort",
+                value
+            )),
+        }
+    }
+}
+
+/// A planned rename: old path and new path
+type Change = (PathBuf, PathBuf);
+
+/// A conflicting change and what was done about it
+type Conflict = (PathBuf, PathBuf, String);
+
+/// Find the changes whose new name exists already (and isn't renamed away
+/// in this run) or is the new name of an earlier change, and deal with them
+/// as `on_conflict` says. Returns the changes to make and each conflicting
+/// change with what was done about it.
+fn resolve_conflicts(
+    changes: Vec<Change>,
+    on_conflict: OnConflict,
+) -> (Vec<Change>, Vec<Conflict>) {
+    let mut sources: HashSet<PathBuf> = changes.iter().map(|(old, _)| old.clone()).collect();
+    loop {
+        let (resolved, conflicts) = resolve_once(&changes, &sources, on_conflict);
+        // A skipped change leaves its file in place, so renaming another file
+        // onto it is a conflict too
+        if resolved.len() == sources.len() {
+            return (resolved, conflicts);
+        }
+        sources = resolved.iter().map(|(old, _)| old.clone()).collect();
+    }
+}
+
+/// One pass of resolve_conflicts, assuming the files at `sources` are all
+/// renamed away
+fn resolve_once(
+    changes: &[Change],
+    sources: &HashSet<PathBuf>,
+    on_conflict: OnConflict,
+) -> (Vec<Change>, Vec<Conflict>) {
+    let mut taken: HashSet<PathBuf> = HashSet::new();
+    let mut resolved = Vec::with_capacity(changes.len());
+    let mut conflicts = Vec::new();
+
+    for (old, new) in changes.iter().cloned() {
+        let duplicate = taken.contains(&new);
+        let exists = !duplicate && new.exists() && !sources.contains(&new);
+        if !duplicate && !exists {
+            taken.insert(new.clone());
+            resolved.push((old, new));
+            continue;
+        }
+
+        let reason = if duplicate {
+            "duplicate target"
         } else {
-            "LIVE (will rename files)".red().bold()
+            "already exists"
+        };
+        match on_conflict {
+            OnConflict::Abort => {
+                conflicts.push((old.clone(), new.clone(), reason.to_string()));
+                taken.insert(new.clone());
+                resolved.push((old, new));
+            }
+            OnConflict::Skip => conflicts.push((old.clone(), new, format!("{}, skipped", reason))),
+            OnConflict::Overwrite if duplicate => {
+                conflicts.push((old.clone(), new, format!("{}, skipped", reason)))
+            }
+            OnConflict::Overwrite => {
+                conflicts.push((old.clone(), new.clone(), format!("{}, overwritten", reason)));
+                taken.insert(new.clone());
+                resolved.push((old, new));
+            }
+            OnConflict::Suffix => {
+                let free = (1..)
+                    .map(|n| with_suffix(&new, n, old.is_dir()))
+                    .find(|candidate| !taken.contains(candidate) && !candidate.exists())
+                    .expect("some suffix is free");
+                conflicts.push((
+                    old.clone(),
+                    new,
+                    format!("{}, renamed to {}", reason, file_name(&free)),
+                ));
+                taken.insert(free.clone());
+                resolved.push((old, free));
+            }
+        }
+    }
+    (resolved, conflicts)
+}
+
+/// Put the changes in an order that never renames onto a file before it has
+/// been moved out of the way: in a chain (a→b, b→c) the last rename goes
+/// first, and a cycle (a→b, b→a) is broken by first moving one file to a
+/// temporary name next to it. Otherwise the order is kept.
+fn order_changes(changes: &[Change]) -> Vec<Change> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Waiting,
+        InChain,
+        Done,
+    }
+
+    let by_old: HashMap<&PathBuf, usize> = changes
+        .iter()
+        .enumerate()
+        .map(|(i, (old, _))| (old, i))
+        .collect();
+    let mut state = vec![State::Waiting; changes.len()];
+    // Where each file is when its rename runs (a temporary name in a cycle)
+    let mut source: Vec<PathBuf> = changes.iter().map(|(old, _)| old.clone()).collect();
+    let mut ordered = Vec::with_capacity(changes.len());
+
+    for start in 0..changes.len() {
+        if state[start] == State::Done {
+            continue;
         }
+        // Follow the chain of renames whose new name is the next one's old name
+        let mut chain = Vec::new();
+        let mut current = start;
+        loop {
+            state[current] = State::InChain;
+            chain.push(current);
+            match by_old.get(&changes[current].1) {
+                Some(&next) if state[next] == State::Waiting => current = next,
+                Some(&next) if state[next] == State::InChain => {
+                    let temporary = temporary_name(&changes[next].0, changes);
+                    ordered.push((changes[next].0.clone(), temporary.clone()));
+                    source[next] = temporary;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        for &i in chain.iter().rev() {
+            ordered.push((source[i].clone(), changes[i].1.clone()));
+            state[i] = State::Done;
+        }
+    }
+    ordered
+}
+
+/// Existing files (not directories) that `changes` rename onto without
+/// moving them away first
+fn overwritten_files(changes: &[Change]) -> Vec<PathBuf> {
+    let sources: HashSet<&PathBuf> = changes.iter().map(|(old, _)| old).collect();
+    changes
+        .iter()
+        .filter(|(old, new)| {
+            !sources.contains(new)
+                && fs::symlink_metadata(new).is_ok_and(|m| !m.is_dir())
+                // A case-only rename on a case-insensitive filesystem finds itself
+                && !same_file(old, new)
+        })
+        .map(|(_, new)| new.clone())
+        .collect()
+}
+
+/// Whether two paths name the same file
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// A free name next to `path` to park it under while a cycle is renamed, or
+/// before a rename overwrites it
+fn temporary_name(path: &Path, changes: &[Change]) -> PathBuf {
+    let name = file_name(path);
+    (0..)
+        .map(|n| path.with_file_name(format!(".{}.ftools-tmp{}", name, n)))
+        .find(|candidate| !candidate.exists() && !changes.iter().any(|(_, new)| new == candidate))
+        .expect("some temporary name is free")
+}
+
+/// `path` with " (n)" added before the extension (of files)
+fn with_suffix(path: &Path, n: usize, is_dir: bool) -> PathBuf {
+    let name = file_name(path);
+    let suffixed = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !is_dir => {
+            format!("{} ({}).{}", stem, n, extension)
+        }
+        _ => format!("{} ({})", name, n),
+    };
+    path.with_file_name(suffixed)
+}
+
+/// Ask about each change in turn (--interactive), returning the accepted
+/// ones with any edited names, or `None` when the user quits
+fn review(changes: Vec<(PathBuf, PathBuf)>) -> Result<Option<Vec<(PathBuf, PathBuf)>>> {
+    if !io::stdin().is_terminal() {
+        return Err(Exit::with_message(
+            exit::ERROR,
+            "--interactive needs a terminal (stdin is not a terminal)",
+        )
+        .into());
+    }
+
+    let total = changes.len();
+    let mut accepted = Vec::new();
+    let mut changes = changes.into_iter().enumerate();
+    while let Some((i, (old, mut new))) = changes.next() {
+        println!(
+            "{} {}  {}  {}",
+            format!("[{}/{}]", i + 1, total)
+                .themed(Element::Info)
+                .bold(),
+            old.display().to_string().themed(Element::Error),
+            chars::ARROW.dimmed(),
+            file_name(&new).themed(Element::Success)
+        );
+        loop {
+            let Some(answer) = ui::prompt("Rename? [y]es/[n]o/[e]dit/[a]ll/[q]uit:") else {
+                return Ok(None);
+            };
+            match answer.to_lowercase().as_str() {
+                "y" | "yes" => {
+                    accepted.push((old, new));
+                    break;
+                }
+                "n" | "no" => break,
+                "a" | "all" => {
+                    accepted.push((old, new));
+                    accepted.extend(changes.by_ref().map(|(_, change)| change));
+                    return Ok(Some(accepted));
+                }
+                "q" | "quit" => return Ok(None),
+                "e" | "edit" => {
+                    let Some(name) = ui::prompt(&format!("New name ({}):", file_name(&new))) else {
+                        return Ok(None);
+                    };
+                    if name.is_empty() {
+                        continue;
+                    }
+                    if name.contains(['/', std::path::MAIN_SEPARATOR])
+                        || name == "."
+                        || name == ".."
+                    {
+                        ui::print_warning("A name can't be a path");
+                        continue;
+                    }
+                    new = new.with_file_name(&name);
+                    if new == old {
+                        break;
+                    }
+                    accepted.push((old, new));
+                    break;
+                }
+                _ => ui::print_warning("Answer y, n, e, a or q"),
+            }
+        }
+    }
+    Ok(Some(accepted))
+}
+
+/// Let the user edit the new names in $EDITOR (--edit), returning the
+/// changes as edited, or `None` when they give up on a list that isn't valid
+fn edit_changes(candidates: Vec<Change>) -> Result<Option<Vec<Change>>> {
+    if !io::stdin().is_terminal() {
+        return Err(Exit::with_message(
+            exit::ERROR,
+            "--edit needs a terminal (stdin is not a terminal)",
+        )
+        .into());
+    }
+
+    // A tab or line break in a name would break up its line, and a name
+    // that isn't UTF-8 wouldn't survive the round trip through the editor
+    let (candidates, awkward): (Vec<Change>, Vec<Change>) =
+        candidates.into_iter().partition(|(old, new)| {
+            old.to_str()
+                .is_some_and(|old| !old.contains(['\t', '\n', '\r']))
+                && new.file_name().is_some_and(|name| name.to_str().is_some())
+        });
+    for (old, _) in &awkward {
+        let reason = if old.to_str().is_some() {
+            "name contains a tab or line break"
+        } else {
+            "name is not valid UTF-8"
+        };
+        ui::print_warning(&format!("skipped: {} ({})", old.display(), reason));
+    }
+
+    let mut text = String::from(
+        "# Change the names after the tab, then save and quit. Lines that are\n\
+         # deleted or left alone aren't renamed; keep the part before the tab.\n",
     );
-    println!();
+    for (old, new) in &candidates {
+        text.push_str(&format!("{}\t{}\n", old.display(), file_name(new)));
+    }
+    // A fresh private directory, so the list can't be swapped or read by
+    // another user while the editor has it open
+    let dir = utils::private_temp_dir("ftools-rename")?;
+    let file = dir.join("rename.txt");
+    let written = fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(&file)
+        .and_then(|mut f| f.write_all(text.as_bytes()));
+    if let Err(e) = written {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(e.into());
+    }
 
-    let walker = if recursive {
-        WalkDir::new(path).follow_links(false)
-    } else {
-        WalkDir::new(path).max_depth(1).follow_links(false)
+    let result = loop {
+        if let Err(e) = tui::open_in_editor(&file, 3) {
+            break Err(e);
+        }
+        let edited = match fs::read_to_string(&file) {
+            Ok(edited) => edited,
+            Err(e) => break Err(e.into()),
+        };
+        match parse_edited(&edited, &candidates) {
+            Ok(changes) => break Ok(Some(changes)),
+            Err(e) => {
+                ui::print_error(&e.to_string());
+                if !ui::confirm("Edit the list again?") {
+                    break Ok(None);
+                }
+            }
+        }
+    };
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// The renames in a list edited for --edit. Every line names a file from
+/// `candidates` before the tab and its new name after it.
+fn parse_edited(text: &str, candidates: &[Change]) -> Result<Vec<Change>> {
+    let by_old: HashMap<String, &PathBuf> = candidates
+        .iter()
+        .map(|(old, _)| (old.display().to_string(), old))
+        .collect();
+    let mut seen = HashSet::new();
+    let mut targets: HashMap<PathBuf, &str> = HashMap::new();
+    let mut changes = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let n = n + 1;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((old, new)) = line.split_once('\t') else {
+            return Err(anyhow!("Line {}: no tab between the old and new name", n));
+        };
+        let Some(&old_path) = by_old.get(old) else {
+            return Err(anyhow!("Line {}: {} is not in the list", n, old));
+        };
+        if !seen.insert(old) {
+            return Err(anyhow!("Line {}: {} is listed twice", n, old));
+        }
+        if new.is_empty() || new == "." || new == ".." {
+            return Err(anyhow!("Line {}: '{}' is not a file name", n, new));
+        }
+        if new.contains(['/', std::path::MAIN_SEPARATOR]) {
+            return Err(anyhow!(
+                "Line {}: {} is a path; only names can change",
+                n,
+                new
+            ));
+        }
+
+        let new_path = old_path.with_file_name(new);
+        if new_path == *old_path {
+            continue;
+        }
+        if let Some(other) = targets.insert(new_path.clone(), old) {
+            return Err(anyhow!(
+                "Line {}: {} is also the new name of {}",
+                n,
+                new,
+                other
+            ));
+        }
+        changes.push((old_path.clone(), new_path));
+    }
+    Ok(changes)
+}
+
+/// Renames listed in a CSV or TSV file (--from-file). Tab-separated files
+/// are recognised by a .tsv/.tab extension or a tab in the first line.
+fn read_mapping(file: &Path) -> Result<Vec<Change>> {
+    let text = fs::read_to_string(file)
+        .map_err(|e| anyhow!("Could not read {}: {}", file.display(), e))?;
+    // Spreadsheets often save CSV with a byte order mark
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+    let tab_separated = matches!(
+        file.extension().and_then(|e| e.to_str()),
+        Some("tsv" | "tab")
+    ) || text.lines().next().is_some_and(|line| line.contains('\t'));
+    parse_mapping(text, if tab_separated { b'\t' } else { b',' })
+        .map_err(|e| anyhow!("{}: {}", file.display(), e))
+}
+
+fn parse_mapping(text: &str, delimiter: u8) -> Result<Vec<Change>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(text.as_bytes());
+    let mut seen = HashSet::new();
+    let mut changes = Vec::new();
+
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let n = record.position().map_or(0, |p| p.line());
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        if record.len() != 2 {
+            return Err(anyhow!(
+                "Line {}: expected an old and a new path, found {} fields",
+                n,
+                record.len()
+            ));
+        }
+        let (old, new) = (&record[0], &record[1]);
+        // A header row, e.g. from a --output csv report
+        if i == 0
+            && matches!(
+                (old.to_lowercase().as_str(), new.to_lowercase().as_str()),
+                ("from", "to") | ("old", "new") | ("source", "target")
+            )
+        {
+            continue;
+        }
+        if old.is_empty() || new.is_empty() {
+            return Err(anyhow!("Line {}: empty path", n));
+        }
+        let (old, new) = (PathBuf::from(old), PathBuf::from(new));
+        if old.symlink_metadata().is_err() {
+            return Err(anyhow!("Line {}: {} doesn't exist", n, old.display()));
+        }
+        if !seen.insert(old.clone()) {
+            return Err(anyhow!("Line {}: {} is listed twice", n, old.display()));
+        }
+        if old != new {
+            changes.push((old, new));
+        }
+    }
+    Ok(changes)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// `name` with the extension `file_type` calls for, or `None` when its
+/// extension is already right. Names without an extension get one; an
+/// extension no known format uses is kept, as it may be a format built on
+/// this one (a .cr3 raw is MP4 inside, an .xlsb is a zip).
+fn fix_extension(name: &str, file_type: &FileType) -> Option<String> {
+    let stem = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            if file_type.matches(extension) || !magic::is_known_extension(extension) {
+                return None;
+            }
+            stem
+        }
+        _ => name,
     };
+    Some(format!("{}.{}", stem, file_type.extension))
+}
 
-    let mut changes: Vec<(PathBuf, PathBuf)> = Vec::new();
+/// Directory of `path` below `root` with / separators ("" for the root
+/// itself), or `None` if it isn't valid UTF-8
+fn relative_dir(root: &Path, path: &Path) -> Option<String> {
+    let dir = path.parent()?.strip_prefix(root).ok()?;
+    let parts: Option<Vec<&str>> = dir.components().map(|c| c.as_os_str().to_str()).collect();
+    Some(parts?.join("/"))
+}
 
-    for entry in walker.into_iter().filter_map(|e| e.ok()) {
-        let file_path = entry.path();
+/// A --full-path replacement split into the directory below the root and
+/// the new name. Paths that would leave the root are refused.
+fn split_new_path(new: &str) -> Result<(&str, &str)> {
+    let (dir, name) = new.rsplit_once('/').unwrap_or(("", new));
+    if new.starts_with('/') || Path::new(new).is_absolute() {
+        return Err(anyhow!("new path {} must be relative", new));
+    }
+    if dir.split('/').any(|part| part == "..") {
+        return Err(anyhow!("new path {} leaves the directory", new));
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(anyhow!("new path {} doesn't end in a file name", new));
+    }
+    Ok((dir, name))
+}
+
+/// `name` with its extension in lowercase (--lowercase-ext). Names without
+/// one, like `README` or `.bashrc`, are left as they are.
+fn lowercase_extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}.{}", stem, extension.to_lowercase())
+        }
+        _ => name.to_string(),
+    }
+}
 
-        if !file_path.is_file() {
+/// `name` in proper UTF-8 (--fix-encoding), or `None` if it doesn't need or
+/// allow repair. Names that aren't UTF-8 are decoded from `encoding`; UTF-8
+/// names that were garbled by being decoded as `encoding` (Ã© for é) are
+/// encoded back, which only gives valid UTF-8 for real mojibake.
+fn repair_name(name: &OsStr, encoding: &'static Encoding) -> Option<String> {
+    let bytes = name.as_encoded_bytes();
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        let (decoded, had_errors) = encoding.decode_without_bom_handling(bytes);
+        return (!had_errors).then(|| decoded.into_owned());
+    };
+    if text.is_ascii() {
+        return None;
+    }
+    let (encoded, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return None;
+    }
+    match String::from_utf8(encoded.into_owned()) {
+        Ok(repaired) if repaired != text => Some(repaired),
+        _ => None,
+    }
+}
+
+/// Characters Windows doesn't allow in names
+const ILLEGAL_CHARS: &[char] = &[':', '<', '>', '?', '*', '"', '|', '\\', '/'];
+
+/// Names Windows reserves for devices, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// `name` made safe to copy anywhere (--sanitize): illegal characters become
+/// `_`, control characters are dropped, runs of whitespace become one space,
+/// trailing dots and spaces are trimmed and reserved device names get a `_`.
+/// With `ascii`, accented letters lose their accents.
+fn sanitize_name(name: &str, ascii: bool) -> String {
+    let mut clean = String::with_capacity(name.len());
+    let mut last_space = false;
+    for c in name.chars() {
+        let c = if ILLEGAL_CHARS.contains(&c) {
+            '_'
+        } else if c.is_whitespace() {
+            ' '
+        } else if c.is_control() {
+            continue;
+        } else {
+            c
+        };
+        if c == ' ' && (last_space || clean.is_empty()) {
             continue;
         }
+        last_space = c == ' ';
+        clean.push(c);
+    }
+    if ascii {
+        clean = ascii_fold(&clean);
+    }
+
+    let mut clean = clean.trim_end_matches(['.', ' ']).to_string();
+    let stem = clean.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        clean.insert(stem.len(), '_');
+    }
+    if clean.is_empty() {
+        clean.push('_');
+    }
+    clean
+}
 
-        if !matches_extensions(file_path, &extensions) {
+/// `text` with accents removed (é → e, Ấ → A). Letters that don't decompose
+/// into a base letter and marks are looked up; anything else is kept.
+fn ascii_fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.nfd() {
+        if is_combining_mark(c) {
             continue;
         }
+        match c {
+            'đ' => folded.push('d'),
+            'Đ' => folded.push('D'),
+            'ß' => folded.push_str("ss"),
+            'æ' => folded.push_str("ae"),
+            'Æ' => folded.push_str("AE"),
+            'œ' => folded.push_str("oe"),
+            'Œ' => folded.push_str("OE"),
+            'ø' => folded.push('o'),
+            'Ø' => folded.push('O'),
+            'ł' => folded.push('l'),
+            'Ł' => folded.push('L'),
+            'þ' => folded.push_str("th"),
+            'Þ' => folded.push_str("Th"),
+            _ => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// Start of the names of the files that record a live run for --undo
+const UNDO_PREFIX: &str = "ftools-rename-";
+
+/// One rename recorded for --undo
+#[derive(Serialize, Deserialize)]
+struct Renamed {
+    #[serde(with = "raw_path")]
+    from: PathBuf,
+    #[serde(with = "raw_path")]
+    to: PathBuf,
+}
+
+/// Paths in rename records: a string, or an array of bytes for names that
+/// aren't UTF-8 (such as the ones --fix-encoding repairs)
+mod raw_path {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::{Path, PathBuf};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Text(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(text) => Raw::Text(text.to_string()),
+            None => Raw::Bytes(path.as_os_str().as_encoded_bytes().to_vec()),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Text(text) => PathBuf::from(text),
+            #[cfg(unix)]
+            Raw::Bytes(bytes) => {
+                use std::os::unix::ffi::OsStringExt;
+                PathBuf::from(std::ffi::OsString::from_vec(bytes))
+            }
+            #[cfg(not(unix))]
+            Raw::Bytes(bytes) => PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()),
+        })
+    }
+}
+
+fn is_undo_file(name: &str) -> bool {
+    name.starts_with(UNDO_PREFIX) && name.ends_with(".json")
+}
+
+/// Record `renames` in a new ftools-rename-<time>.json in `dir`. Paths are
+/// stored absolute so the undo works from any directory.
+fn write_undo_file(dir: &Path, renames: &[(PathBuf, PathBuf)]) -> Result<PathBuf> {
+    let absolute = |p: &Path| std::path::absolute(p).unwrap_or_else(|_| p.to_path_buf());
+    let entries: Vec<Renamed> = renames
+        .iter()
+        .map(|(from, to)| Renamed {
+            from: absolute(from),
+            to: absolute(to),
+        })
+        .collect();
+    let stamp = Local::now().format("%Y%m%d-%H%M%S");
+    let mut path = dir.join(format!("{}{}.json", UNDO_PREFIX, stamp));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{}{}-{}.json", UNDO_PREFIX, stamp, n));
+    }
+    fs::write(&path, serde_json::to_vec_pretty(&entries)?)?;
+    Ok(path)
+}
+
+/// Rename every file listed in `file` back, newest first. Renames that can't
+/// be reversed stay in the file; it is removed once all of them are.
+fn undo(file: &Path, dry_run: bool) -> Result<()> {
+    let entries: Vec<Renamed> = serde_json::from_slice(&fs::read(file)?)
+        .map_err(|e| anyhow!("{} is not a rename record: {}", file.display(), e))?;
+
+    ui::print_start("Undo rename", &file.display().to_string());
+    ui::print_blank();
 
-        if let Some(file_name) = file_path.file_name().and_then(|n| n.to_str()) {
-            if regex.is_match(file_name) {
-                let new_name = regex.replace_all(file_name, replace);
-                if new_name != file_name {
-                    let new_path = file_path.with_file_name(new_name.as_ref());
-                    changes.push((file_path.to_path_buf(), new_path));
+    let mut plan = Plan::new();
+    let mut planned = UndoSteps::default();
+    let mut remaining = Vec::new();
+    for entry in entries.into_iter().rev() {
+        if !planned.exists_after(&entry.to) {
+            ui::print_warning(&format!("{} no longer exists", entry.to.display()));
+            remaining.push(entry);
+        } else if planned.exists_after(&entry.from) {
+            ui::print_warning(&format!("{} already exists", entry.from.display()));
+            remaining.push(entry);
+        } else {
+            plan.rename(&entry.to, &entry.from);
+            planned.push(entry);
+        }
+    }
+    if plan.is_empty() {
+        return Err(Exit::with_message(exit::ERROR, "Nothing to undo").into());
+    }
+
+    if dry_run {
+        plan.print_dry_run();
+        println!();
+        ui::print_info("Run with --dry-run=false to apply changes");
+        return Ok(());
+    }
+
+    let outcome = plan.execute();
+    // Directories a --full-path rename moved files into go once they're empty
+    for (moved, back) in &outcome.renames {
+        plan::remove_empty_parents(moved, back);
+    }
+    let undone: HashSet<&PathBuf> = outcome.renames.iter().map(|(to, _)| to).collect();
+    remaining.extend(
+        planned
+            .steps
+            .into_iter()
+            .filter(|entry| !undone.contains(&entry.to)),
+    );
+    if remaining.is_empty() {
+        fs::remove_file(file)?;
+    } else {
+        remaining.reverse();
+        fs::write(file, serde_json::to_vec_pretty(&remaining)?)?;
+    }
+
+    println!();
+    ui::print_success(&format!("Undid {} renames", outcome.renamed));
+    exit::partial(remaining.len(), "undos")
+}
+
+/// Renames an undo will make, each moving `to` back to `from`, in order
+#[derive(Default)]
+struct UndoSteps {
+    steps: Vec<Renamed>,
+    /// Indices of the steps that start or end at each path
+    by_path: HashMap<PathBuf, Vec<usize>>,
+}
+
+impl UndoSteps {
+    fn push(&mut self, step: Renamed) {
+        let i = self.steps.len();
+        self.by_path.entry(step.from.clone()).or_default().push(i);
+        self.by_path.entry(step.to.clone()).or_default().push(i);
+        self.steps.push(step);
+    }
+
+    /// Whether `path` will exist once the steps so far have run. A file
+    /// inside a renamed directory only reappears at its recorded path after
+    /// the directory has been renamed back.
+    fn exists_after(&self, path: &Path) -> bool {
+        let mut path = path.to_path_buf();
+        let mut before = self.steps.len();
+        loop {
+            // The last earlier step that moves `path` or one of its parents
+            let latest = path
+                .ancestors()
+                .filter_map(|a| self.by_path.get(a))
+                .flat_map(|ids| ids.iter().copied().filter(|&i| i < before))
+                .max();
+            let Some(i) = latest else {
+                return path.exists();
+            };
+            let step = &self.steps[i];
+            match path.strip_prefix(&step.from) {
+                // Joining an empty path would add a trailing slash
+                Ok(rest) if rest.as_os_str().is_empty() => path = step.to.clone(),
+                Ok(rest) => path = step.to.join(rest),
+                // Moved away by the step
+                Err(_) => return false,
+            }
+            before = i;
+        }
+    }
+}
+
+pub fn run(path: &str, options: RenameOptions) -> Result<()> {
+    let RenameOptions {
+        find,
+        ignore_case,
+        regex_flags,
+        full_path,
+        replace,
+        case,
+        sanitize,
+        ascii,
+        lowercase_ext,
+        fix_extensions,
+        fix_encoding,
+        undo: undo_file,
+        from_file,
+        extensions,
+        include,
+        older_than,
+        newer_than,
+        min_size,
+        max_size,
+        dry_run,
+        recursive,
+        dirs,
+        on_conflict,
+        interactive,
+        edit,
+        output,
+    } = options;
+    if let Some(file) = undo_file {
+        return undo(Path::new(&file), dry_run);
+    }
+    // Each rename is confirmed as it's reviewed or edited, so there's nothing
+    // to preview
+    let dry_run = dry_run && !interactive && !edit;
+    let regex = find
+        .as_deref()
+        .map(|find| build_regex(find, ignore_case, regex_flags.as_deref().unwrap_or("")))
+        .transpose()?;
+    let replace = replace.unwrap_or_default();
+    let template = Template::parse(&replace)?;
+    let case_name = case;
+    let case = case_name.as_deref().map(Case::parse).transpose()?;
+    let encoding = fix_encoding
+        .as_deref()
+        .map(|label| {
+            Encoding::for_label(label.as_bytes()).ok_or_else(|| {
+                anyhow!(
+                    "Unknown encoding: {}. Use a name like latin1, cp1251, shift_jis or gbk",
+                    label
+                )
+            })
+        })
+        .transpose()?;
+    let include = Globs::new(&include, &[path])?;
+    let min_size = min_size.as_deref().map(parse_size).transpose()?;
+    let max_size = max_size.as_deref().map(parse_size).transpose()?;
+    if let (Some(min), Some(max)) = (min_size, max_size)
+        && max < min
+    {
+        return Err(anyhow!("--max-size must not be smaller than --min-size"));
+    }
+    let now = SystemTime::now();
+    let cutoff = |age: &Option<String>| -> Result<Option<SystemTime>> {
+        age.as_deref()
+            .map(|age| Ok(now - Duration::from_secs(parse_duration(age)?)))
+            .transpose()
+    };
+    let (modified_before, modified_after) = (cutoff(&older_than)?, cutoff(&newer_than)?);
+    if let (Some(before), Some(after)) = (modified_before, modified_after)
+        && before < after
+    {
+        return Err(anyhow!("--newer-than must be longer than --older-than"));
+    }
+    let filter_metadata = min_size.is_some()
+        || max_size.is_some()
+        || modified_before.is_some()
+        || modified_after.is_some();
+    let on_conflict = on_conflict
+        .as_deref()
+        .map(OnConflict::parse)
+        .transpose()?
+        .unwrap_or(OnConflict::Abort);
+
+    ui::print_start("Bulk rename", path);
+    if !ui::is_quiet() {
+        if let Some(find) = &find {
+            println!(
+                "  {} '{}' {} '{}'",
+                "Pattern:".dimmed(),
+                find.themed(Element::Warning),
+                chars::ARROW.dimmed(),
+                replace.themed(Element::Success)
+            );
+        }
+        if let Some(case) = &case_name {
+            println!("  {} {}", "Case:".dimmed(), case.themed(Element::Success));
+        }
+        if let Some(file) = &from_file {
+            println!(
+                "  {} {}",
+                "Mapping:".dimmed(),
+                file.themed(Element::Success)
+            );
+        }
+        if let Some(encoding) = encoding {
+            println!(
+                "  {} {}",
+                "Encoding:".dimmed(),
+                format!("{} {} UTF-8", encoding.name(), chars::ARROW).themed(Element::Success)
+            );
+        }
+        if fix_extensions {
+            println!(
+                "  {} {}",
+                "Extensions:".dimmed(),
+                "fixed to match content".themed(Element::Success)
+            );
+        } else if lowercase_ext {
+            println!(
+                "  {} {}",
+                "Extensions:".dimmed(),
+                "lowercase".themed(Element::Success)
+            );
+        }
+        if sanitize {
+            println!(
+                "  {} {}",
+                "Sanitize:".dimmed(),
+                if ascii { "yes, ASCII only" } else { "yes" }.themed(Element::Success)
+            );
+        }
+        println!(
+            "  {} {}",
+            "Mode:".dimmed(),
+            if interactive {
+                "INTERACTIVE (asks before each rename)".themed(Element::Warning)
+            } else if edit {
+                "EDIT (renames as edited in $EDITOR)".themed(Element::Warning)
+            } else if dry_run {
+                "DRY RUN (preview only)".themed(Element::Warning)
+            } else {
+                "LIVE (will rename files)".themed(Element::Error).bold()
+            }
+        );
+        println!();
+    }
+
+    let mut changes: Vec<Change> = Vec::new();
+    // Files whose content was recognised, and those with a wrong extension
+    let (mut sniffed, mut mismatched) = (0, 0);
+    if let Some(file) = &from_file {
+        changes = read_mapping(Path::new(file))?;
+    } else {
+        // Sorted so that counters are numbered in the same order on every run
+        let walker = if recursive {
+            WalkDir::new(path).follow_links(false)
+        } else {
+            WalkDir::new(path).max_depth(1).follow_links(false)
+        }
+        .sort_by_file_name();
+        let mut counter = 0;
+
+        for entry in walker.into_iter().filter_map(walk_ok) {
+            let file_path = entry.path();
+
+            // The directory being renamed in is left alone
+            let is_dir = dirs && entry.depth() > 0 && entry.file_type().is_dir();
+            if !is_dir && !file_path.is_file() {
+                continue;
+            }
+
+            if !is_dir && !matches_extensions(file_path, &extensions) {
+                continue;
+            }
+            if entry.depth() > 0 && !include.matches(file_path) {
+                continue;
+            }
+            if !is_dir && filter_metadata {
+                let metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(e) => {
+                        skipped::record_walk(&e);
+                        continue;
+                    }
+                };
+                let size = metadata.len();
+                if min_size.is_some_and(|min| size < min) || max_size.is_some_and(|max| size > max)
+                {
+                    continue;
+                }
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                if modified_before.is_some_and(|before| modified > before)
+                    || modified_after.is_some_and(|after| modified < after)
+                {
+                    continue;
+                }
+            }
+
+            let Some(original) = file_path.file_name() else {
+                continue;
+            };
+            let repaired = encoding.and_then(|encoding| repair_name(original, encoding));
+            // Patterns are matched against text, so names that aren't valid UTF-8 are
+            // left alone rather than renamed from a lossy copy
+            let Some(file_name) = repaired.as_deref().or_else(|| original.to_str()) else {
+                ui::print_verbose(&format!(
+                    "skipped: {} (file name is not valid UTF-8)",
+                    file_path.display()
+                ));
+                continue;
+            };
+            if is_undo_file(file_name) {
+                continue;
+            }
+            // --full-path matches "dir/sub/name" below the root instead of the name
+            let full_name;
+            let subject = if full_path {
+                let Some(dir) = relative_dir(Path::new(path), file_path) else {
+                    ui::print_verbose(&format!(
+                        "skipped: {} (path is not valid UTF-8)",
+                        file_path.display()
+                    ));
+                    continue;
+                };
+                full_name = if dir.is_empty() {
+                    file_name.to_string()
+                } else {
+                    format!("{}/{}", dir, file_name)
+                };
+                full_name.as_str()
+            } else {
+                file_name
+            };
+
+            let mut new_name = match &regex {
+                Some(regex) if !regex.is_match(subject) => continue,
+                Some(regex) if !template.is_literal() => {
+                    counter += 1;
+                    let replacement = match template.render(counter, file_path) {
+                        Ok(replacement) => replacement,
+                        Err(e) => {
+                            skipped::record_error(file_path, &e);
+                            counter -= 1;
+                            continue;
+                        }
+                    };
+                    regex
+                        .replace_all(subject, replacement.as_str())
+                        .into_owned()
                 }
+                Some(regex) => regex.replace_all(subject, replace.as_str()).into_owned(),
+                None => file_name.to_string(),
+            };
+            let mut new_dir = None;
+            if full_path {
+                let (dir, name) = split_new_path(&new_name)
+                    .map_err(|e| anyhow!("{}: {}", file_path.display(), e))?;
+                new_dir = Some(Path::new(path).join(dir));
+                new_name = name.to_string();
+            }
+            if let Some(case) = case {
+                new_name = change_case(&new_name, case, is_dir);
+            }
+            if sanitize {
+                new_name = sanitize_name(&new_name, ascii);
+            }
+            if lowercase_ext && !is_dir {
+                new_name = lowercase_extension(&new_name);
+            }
+            if fix_extensions && !is_dir {
+                match magic::detect_file(file_path) {
+                    Ok(Some(file_type)) => {
+                        sniffed += 1;
+                        if let Some(fixed) = fix_extension(&new_name, file_type) {
+                            mismatched += 1;
+                            new_name = fixed;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => skipped::record_io(file_path, &e),
+                }
+            }
+            let new_path = match &new_dir {
+                Some(dir) => dir.join(&new_name),
+                None => file_path.with_file_name(&new_name),
+            };
+            // --edit lists every file, changed or not
+            if new_path != file_path || edit {
+                changes.push((file_path.to_path_buf(), new_path));
             }
         }
     }
 
+    if fix_extensions {
+        ui::print_info(&format!(
+            "{} of {} recognised files have an extension that doesn't match their content",
+            mismatched, sniffed
+        ));
+        println!();
+    }
     if changes.is_empty() {
-        ui::print_warning("No files match the pattern");
+        ui::print_warning(if from_file.is_some() {
+            "No renames in the mapping"
+        } else {
+            "No files match the pattern"
+        });
         return Ok(());
     }
-
-    // Check for conflicts
-    let mut conflicts = Vec::new();
-    let new_names: Vec<_> = changes.iter().map(|(_, new)| new.clone()).collect();
-    for (i, (_, new_path)) in changes.iter().enumerate() {
-        if new_path.exists() && !changes.iter().any(|(old, _)| old == new_path) {
-            conflicts.push((new_path.clone(), "already exists"));
+    if edit {
+        match edit_changes(changes)? {
+            Some(edited) if !edited.is_empty() => changes = edited,
+            Some(_) => {
+                ui::print_warning("No names changed");
+                return Ok(());
+            }
+            None => return Err(Exit::silent(exit::NEGATIVE).into()),
         }
-        for (j, other) in new_names.iter().enumerate() {
-            if i != j && new_path == other {
-                conflicts.push((new_path.clone(), "duplicate target"));
+    }
+    // Deepest first, so a directory's contents are renamed while its old
+    // path is still valid
+    if dirs || from_file.is_some() {
+        changes.sort_by_key(|(old, _)| std::cmp::Reverse(old.components().count()));
+    }
+    if interactive {
+        match review(changes)? {
+            Some(accepted) if !accepted.is_empty() => changes = accepted,
+            Some(_) => {
+                ui::print_warning("No renames accepted");
+                return Ok(());
             }
+            None => return Err(Exit::silent(exit::NEGATIVE).into()),
         }
     }
 
+    let (changes, conflicts) = resolve_conflicts(changes, on_conflict);
     if !conflicts.is_empty() {
-        ui::print_section("Conflicts Detected");
-        for (path, reason) in &conflicts {
+        ui::print_section(if on_conflict == OnConflict::Abort {
+            "Conflicts Detected"
+        } else {
+            "Conflicts Resolved"
+        });
+        for (old, new, reason) in &conflicts {
             println!(
-                "  {} {} ({})",
-                chars::CROSS_MARK.red(),
-                path.display(),
-                reason.red()
+                "  {} {}  {}  {} ({})",
+                chars::CROSS_MARK.themed(Element::Error),
+                old.display(),
+                chars::ARROW.dimmed(),
+                file_name(new),
+                reason.themed(Element::Error)
             );
         }
         println!();
-        if !dry_run {
-            ui::print_error("Aborting due to conflicts");
-            return Ok(());
+        if on_conflict == OnConflict::Abort && !dry_run {
+            return Err(Exit::with_message(exit::ERROR, "Aborting due to conflicts").into());
         }
     }
+    if changes.is_empty() {
+        ui::print_warning("Nothing left to rename");
+        return Ok(());
+    }
 
-    // Display changes
-    ui::print_section(&format!("Changes ({})", changes.len()));
-    println!();
+    // Files about to be overwritten are first renamed out of the way, so a
+    // rollback can bring them back; they are deleted once every rename worked
+    let set_aside: Vec<Change> = if on_conflict == OnConflict::Overwrite && !dry_run {
+        overwritten_files(&changes)
+            .into_iter()
+            .map(|file| {
+                let temporary = temporary_name(&file, &changes);
+                (file, temporary)
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
 
+    let mut plan = Plan::new();
+    let mut report = Report::new(&["from", "to"]);
+    for (file, temporary) in &set_aside {
+        plan.rename(file, temporary);
+    }
+    for (old, new) in order_changes(&changes) {
+        plan.rename(&old, &new);
+    }
     for (old, new) in &changes {
-        let old_name = old.file_name().unwrap_or_default().to_string_lossy();
-        let new_name = new.file_name().unwrap_or_default().to_string_lossy();
-        println!(
-            "  {} {}  {}  {}",
-            chars::BULLET.dimmed(),
-            old_name.red(),
-            chars::ARROW.dimmed(),
-            new_name.green()
-        );
+        report.row(vec![
+            json!(old.display().to_string()),
+            json!(new.display().to_string()),
+        ]);
     }
+    output::write_report(&output, &report)?;
 
-    // Execute if not dry run
-    if !dry_run {
+    if dry_run {
+        plan.print_dry_run();
         println!();
-        ui::print_section("Executing");
-
-        let mut success_count = 0;
-        let mut error_count = 0;
-
-        for (old, new) in &changes {
-            match fs::rename(old, new) {
-                Ok(_) => {
-                    success_count += 1;
-                    println!(
-                        "  {} {}",
-                        chars::CHECK.green(),
-                        new.file_name().unwrap_or_default().to_string_lossy()
-                    );
-                }
-                Err(e) => {
-                    error_count += 1;
-                    println!(
-                        "  {} {} ({})",
-                        chars::CROSS_MARK.red(),
-                        old.file_name().unwrap_or_default().to_string_lossy(),
-                        e.to_string().red()
-                    );
-                }
+        ui::print_info("Run with --dry-run=false to apply changes");
+        return Ok(());
+    }
+
+    ui::print_section(&format!("Executing ({})", plan.len()));
+    println!();
+
+    // A run that fails partway is undone rather than left half-renamed
+    let mut outcome = plan.execute_or_roll_back();
+    if outcome.failed == 0 && !set_aside.is_empty() {
+        for (_, temporary) in &set_aside {
+            if let Err(e) = fs::remove_file(temporary) {
+                ui::print_warning(&format!(
+                    "Could not delete the replaced {} ({})",
+                    temporary.display(),
+                    e
+                ));
             }
         }
+        outcome.renames.retain(|rename| !set_aside.contains(rename));
+        outcome.renamed -= set_aside.len();
+    }
 
-        println!();
-        ui::print_line(50);
-        println!(
-            "{} {} renamed, {} failed",
-            chars::ARROW.dimmed(),
-            success_count.to_string().green().bold(),
-            error_count.to_string().red()
+    println!();
+    ui::print_line(50);
+    print!(
+        "{} {} renamed, {} failed",
+        chars::ARROW.dimmed(),
+        outcome.renamed.to_string().themed(Element::Success).bold(),
+        outcome.failed.to_string().themed(Element::Error)
+    );
+    if outcome.rolled_back > 0 {
+        print!(
+            ", {} rolled back",
+            outcome.rolled_back.to_string().themed(Element::Warning)
         );
-    } else {
-        println!();
-        ui::print_info("Run without --dry-run to apply changes");
+    }
+    println!();
+    if !outcome.renames.is_empty() {
+        match write_undo_file(Path::new(path), &outcome.renames) {
+            Ok(file) => ui::print_info(&format!(
+                "Undo with: ftools rename --undo {}",
+                file.display()
+            )),
+            Err(e) => ui::print_warning(&format!("Could not record renames for --undo: {}", e)),
+        }
+    }
+    exit::partial(outcome.failed, "renames")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let path = Path::new("unused");
+        let template = Template::parse("vacation-{n:03}").unwrap();
+        assert_eq!(template.render(7, path).unwrap(), "vacation-007");
+        let template = Template::parse("${n}_{n+10}").unwrap();
+        assert_eq!(template.render(1, path).unwrap(), "${n}_11");
+        assert!(Template::parse("photo_$1").unwrap().is_literal());
+        assert!(Template::parse("{mtime:%Q}").is_err());
+        assert!(Template::parse("{mtime:%Y/%m}").is_err());
+        let template = Template::parse("{parent}-{n}.{ext}").unwrap();
+        let photo = Path::new("trip/IMG_1.JPG");
+        assert_eq!(template.render(2, photo).unwrap(), "trip-2.JPG");
+    }
+
+    #[test]
+    fn test_overwrite_rolls_back() {
+        let dir = std::env::temp_dir().join(format!("ftools-overwrite-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.txt"), "b").unwrap();
+
+        let changes = vec![
+            (dir.join("a.txt"), dir.join("b.txt")),
+            (dir.join("missing.txt"), dir.join("c.txt")),
+        ];
+        assert_eq!(overwritten_files(&changes), [dir.join("b.txt")]);
+
+        // The replaced file is set aside first, so the rollback restores it
+        let mut plan = Plan::new();
+        let temporary = temporary_name(&dir.join("b.txt"), &changes);
+        plan.rename(&dir.join("b.txt"), &temporary);
+        for (old, new) in order_changes(&changes) {
+            plan.rename(&old, &new);
+        }
+        let outcome = plan.execute_or_roll_back();
+        assert_eq!(outcome.rolled_back, 2);
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "b");
+        assert!(!temporary.exists());
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    Ok(())
+    #[test]
+    fn test_undo_steps_follow_renamed_dirs() {
+        let dir = std::env::temp_dir().join(format!("ftools-undo-{}", std::process::id()));
+        fs::create_dir_all(dir.join("new")).unwrap();
+        fs::write(dir.join("new/b.txt"), "").unwrap();
+
+        let mut planned = UndoSteps::default();
+        planned.push(Renamed {
+            from: dir.join("old"),
+            to: dir.join("new"),
+        });
+        assert!(planned.exists_after(&dir.join("old/b.txt")));
+        assert!(!planned.exists_after(&dir.join("new/b.txt")));
+        assert!(!planned.exists_after(&dir.join("old/a.txt")));
+        assert!(planned.exists_after(&dir.join("old")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_conflicts() {
+        let dir = Path::new("/nonexistent-ftools");
+        let changes = || {
+            vec![
+                (dir.join("a.txt"), dir.join("x.txt")),
+                (dir.join("b.txt"), dir.join("x.txt")),
+                (dir.join("c"), dir.join("x.txt")),
+            ]
+        };
+        let (resolved, conflicts) = resolve_conflicts(changes(), OnConflict::Suffix);
+        let names: Vec<_> = resolved.iter().map(|(_, new)| file_name(new)).collect();
+        assert_eq!(names, ["x.txt", "x (1).txt", "x (2).txt"]);
+        assert_eq!(conflicts.len(), 2);
+
+        let (resolved, _) = resolve_conflicts(changes(), OnConflict::Skip);
+        assert_eq!(resolved, [(dir.join("a.txt"), dir.join("x.txt"))]);
+        let (resolved, conflicts) = resolve_conflicts(changes(), OnConflict::Abort);
+        assert_eq!((resolved.len(), conflicts.len()), (3, 2));
+    }
+
+    #[test]
+    fn test_order_changes() {
+        let p = |name: &str| PathBuf::from(format!("/nonexistent-ftools/{}", name));
+        let names = |changes: Vec<Change>| -> Vec<(String, String)> {
+            changes
+                .iter()
+                .map(|(old, new)| (file_name(old), file_name(new)))
+                .collect()
+        };
+        let pair = |a: &str, b: &str| (a.to_string(), b.to_string());
+
+        let chain = order_changes(&[(p("1"), p("2")), (p("2"), p("3"))]);
+        assert_eq!(names(chain), [pair("2", "3"), pair("1", "2")]);
+
+        let swap = order_changes(&[(p("a"), p("b")), (p("b"), p("a"))]);
+        assert_eq!(
+            names(swap),
+            [
+                pair("a", ".a.ftools-tmp0"),
+                pair("b", "a"),
+                pair(".a.ftools-tmp0", "b")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skipped_rename_keeps_its_name_taken() {
+        let dir = std::env::temp_dir().join(format!("ftools-skip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        // b -> x is skipped as a duplicate, so b stays and c can't take its name
+        let changes = vec![
+            (dir.join("a"), dir.join("x")),
+            (dir.join("b"), dir.join("x")),
+            (dir.join("c"), dir.join("b")),
+        ];
+        let (resolved, conflicts) = resolve_conflicts(changes, OnConflict::Skip);
+        assert_eq!(resolved, [(dir.join("a"), dir.join("x"))]);
+        assert_eq!(conflicts.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_stays_within_directories() {
+        let dir = std::env::temp_dir().join(format!("ftools-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("IMG_dir")).unwrap();
+        fs::write(dir.join("IMG_1.jpg"), "").unwrap();
+        fs::write(dir.join("IMG_dir/Notes.txt"), "").unwrap();
+
+        let options = RenameOptions {
+            case: Some("lower".to_string()),
+            include: vec!["IMG_*".to_string()],
+            recursive: true,
+            ..RenameOptions::default()
+        };
+        run(&dir.display().to_string(), options).unwrap();
+        assert!(dir.join("img_1.jpg").exists());
+        assert!(dir.join("IMG_dir/Notes.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        let dir = std::env::temp_dir().join(format!("ftools-mapping-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "").unwrap();
+        fs::write(dir.join("b, c.txt"), "").unwrap();
+        let (a, bc) = (dir.join("a.txt"), dir.join("b, c.txt"));
+
+        let text = format!(
+            "from,to\n# comment\n{},{}\n\"{}\",\"{}\"\n",
+            a.display(),
+            dir.join("x.txt").display(),
+            bc.display(),
+            bc.display()
+        );
+        let changes = parse_mapping(&text, b',').unwrap();
+        assert_eq!(changes, [(a.clone(), dir.join("x.txt"))]);
+
+        let tsv = format!("{}\ty.txt\n{}\tz.txt\n", a.display(), a.display());
+        assert!(parse_mapping(&tsv, b'\t').is_err());
+        let missing = format!("{},x\n", dir.join("missing").display());
+        assert!(parse_mapping(&missing, b',').is_err());
+        assert!(parse_mapping("only-one-field\n", b',').is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_new_path() {
+        assert_eq!(
+            split_new_path("2024/05/a.jpg").unwrap(),
+            ("2024/05", "a.jpg")
+        );
+        assert_eq!(split_new_path("a.jpg").unwrap(), ("", "a.jpg"));
+        assert!(split_new_path("../a.jpg").is_err());
+        assert!(split_new_path("/tmp/a.jpg").is_err());
+        assert!(split_new_path("2024/").is_err());
+        assert_eq!(
+            relative_dir(Path::new("."), Path::new("./2024/05/a.jpg")).unwrap(),
+            "2024/05"
+        );
+        assert_eq!(
+            relative_dir(Path::new("."), Path::new("./a.jpg")).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_lowercase_extension() {
+        assert_eq!(lowercase_extension("IMG_0001.JPG"), "IMG_0001.jpg");
+        assert_eq!(lowercase_extension("Backup.Tar.GZ"), "Backup.Tar.gz");
+        assert_eq!(lowercase_extension(".BASHRC"), ".BASHRC");
+        assert_eq!(lowercase_extension("README"), "README");
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("Q3: \"Plan\"?.txt", false), "Q3_ _Plan__.txt");
+        assert_eq!(sanitize_name("  my \t  notes\u{7}. . ", false), "my notes");
+        assert_eq!(sanitize_name("con.txt", false), "con_.txt");
+        assert_eq!(
+            sanitize_name("Tiếng Việt đẹp.md", true),
+            "Tieng Viet dep.md"
+        );
+        assert_eq!(sanitize_name("...", false), "_");
+    }
+
+    #[test]
+    fn test_parse_edited() {
+        let candidates = vec![
+            (PathBuf::from("./a.txt"), PathBuf::from("./a.txt")),
+            (PathBuf::from("./b.txt"), PathBuf::from("./B.txt")),
+        ];
+        let changes = parse_edited(
+            "# comment\n./a.txt\tfirst.txt\n./b.txt\tb.txt\n",
+            &candidates,
+        );
+        assert_eq!(
+            changes.unwrap(),
+            [(PathBuf::from("./a.txt"), PathBuf::from("./first.txt"))]
+        );
+        assert!(parse_edited("./a.txt\tx.txt\n./b.txt\tx.txt\n", &candidates).is_err());
+        assert!(parse_edited("./a.txt\tsub/x.txt\n", &candidates).is_err());
+        assert!(parse_edited("./c.txt\tx.txt\n", &candidates).is_err());
+    }
+
+    #[test]
+    fn test_fix_extension() {
+        let jpeg = magic::detect(b"\xff\xd8\xff\xe0").unwrap();
+        assert_eq!(fix_extension("photo.JPEG", jpeg), None);
+        assert_eq!(fix_extension("photo.png", jpeg).unwrap(), "photo.jpg");
+        assert_eq!(fix_extension("download", jpeg).unwrap(), "download.jpg");
+        assert_eq!(fix_extension(".hidden", jpeg).unwrap(), ".hidden.jpg");
+
+        // Formats built on a known one keep their own extension
+        let cr3 = magic::detect(b"\0\0\0\x18ftypcrx \0\0\0\x01").unwrap();
+        assert_eq!(fix_extension("IMG_0001.CR3", cr3), None);
+        let zip = magic::detect(b"PK\x03\x04\x14\0").unwrap();
+        assert_eq!(fix_extension("budget.xlsb", zip), None);
+        assert_eq!(fix_extension("budget.pdf", zip).unwrap(), "budget.zip");
+    }
+
+    #[test]
+    fn test_repair_name() {
+        let latin1 = Encoding::for_label(b"latin1").unwrap();
+        let cp1251 = Encoding::for_label(b"cp1251").unwrap();
+        assert_eq!(
+            repair_name(OsStr::new("CafÃ© menu.pdf"), latin1).unwrap(),
+            "Café menu.pdf"
+        );
+        assert_eq!(repair_name(OsStr::new("Café menu.pdf"), latin1), None);
+        assert_eq!(repair_name(OsStr::new("plain.txt"), latin1), None);
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let raw = OsStr::from_bytes(b"\xcf\xf0\xe8\xe2\xe5\xf2.txt");
+            assert_eq!(repair_name(raw, cp1251).unwrap(), "Привет.txt");
+        }
+    }
+
+    #[test]
+    fn test_build_regex() {
+        assert!(build_regex(r"\.jpg$", true, "").unwrap().is_match("A.JPG"));
+        assert!(!build_regex(r"\.jpg$", false, "").unwrap().is_match("A.JPG"));
+        assert!(build_regex(r"a . b", false, "xi").unwrap().is_match("A-B"));
+        assert!(build_regex("a", false, "g").is_err());
+        assert!(build_regex(r"^\w+$", false, "u").unwrap().is_match("tệp"));
+        assert!(!build_regex(r"^\w+$", false, "-u").unwrap().is_match("tệp"));
+        assert!(!build_regex("a", true, "-i").unwrap().is_match("A"));
+    }
+
+    #[test]
+    fn test_change_case() {
+        assert_eq!(
+            change_case("My File Name.PDF", Case::Kebab, false),
+            "my-file-name.PDF"
+        );
+        assert_eq!(
+            change_case("My File Name.PDF", Case::Snake, false),
+            "my_file_name.PDF"
+        );
+        assert_eq!(
+            change_case("parseHTTPServer.rs", Case::Title, false),
+            "Parse Http Server.rs"
+        );
+        assert_eq!(
+            change_case("old_report-v2.txt", Case::Camel, false),
+            "oldReportV2.txt"
+        );
+        assert_eq!(change_case("Notes.tar", Case::Upper, false), "NOTES.tar");
+        assert_eq!(change_case(".vimRC", Case::Snake, false), ".vim_rc");
+        assert_eq!(change_case("Release 1.2", Case::Kebab, true), "release-1-2");
+    }
 }
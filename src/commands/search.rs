@@ -1,162 +1,1903 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use colored::*;
-use regex::{Regex, RegexBuilder};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
-use walkdir::WalkDir;
-
-use crate::ui::{self, chars};
-use crate::utils::{matches_extensions, should_skip};
-
-pub fn run(
-    pattern: &str,
-    path: &str,
-    extensions: Option<String>,
-    ignore_case: bool,
-    files_only: bool,
-    line_numbers: bool,
-    context: usize,
-) -> Result<()> {
-    let regex = RegexBuilder::new(pattern)
+use indicatif::ProgressBar;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use regex::{bytes, Regex, RegexBuilder};
+use serde_json::json;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{IsTerminal, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::archive::{self, Member};
+use crate::config::Config;
+use crate::exit::{self, Exit};
+use crate::output::{self, OutputArgs, Report};
+use crate::skipped;
+use crate::tui::{self, Hit};
+use crate::ui::{self, chars, Element, Themed};
+use crate::utils::{
+    format_bytes, input_files, link_count, matches_extensions, parse_duration, parse_size, Globs,
+};
+
+/// Matches found in one file, with the formatted lines to print
+struct FileMatches {
+    count: usize,
+    lines: Vec<String>,
+    /// Line number (1-based) and text of each matching line
+    hits: Vec<(usize, String)>,
+}
+
+/// Unchanged lines around each change in --diff output, unless -C is given
+const DIFF_CONTEXT: usize = 3;
+
+/// Files at least this large are memory-mapped, and searched for matches
+/// without splitting all of them into lines
+const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Files listed in the --stats table
+const STATS_TOP: usize = 10;
+
+/// Bytes at the start of a file checked for a NUL byte to tell binary files
+const BINARY_PROBE: usize = 512;
+
+/// Files searched in parallel before their results are printed
+const SEARCH_BATCH: usize = 256;
+
+/// How lines are selected in each file
+struct Matcher<'a> {
+    regex: &'a Regex,
+    /// The pattern with ^ and $ matching at line breaks, to find the
+    /// candidate lines of large files
+    finder: &'a Regex,
+    /// The pattern for raw bytes, so JSON output can match lines that
+    /// aren't UTF-8 and give offsets into the file
+    bytes: &'a bytes::Regex,
+    /// Shown in place of each match (--replace)
+    replacement: Option<&'a str>,
+    /// Search the file as one buffer so matches can span lines
+    multiline: bool,
+    /// Select the lines that don't match
+    invert: bool,
+    /// Most lines selected in a file (matches with --multiline)
+    max_count: usize,
+}
+
+/// Search flags from the command line
+pub struct SearchOptions {
+    pub extensions: Option<String>,
+    pub globs: Vec<String>,
+    pub modified_within: Option<String>,
+    pub ignore_case: bool,
+    pub smart_case: bool,
+    pub case_sensitive: bool,
+    pub word_regexp: bool,
+    pub files_only: bool,
+    pub count: bool,
+    pub only_matching: bool,
+    pub with_filename: bool,
+    pub json: bool,
+    pub stream: bool,
+    pub tui: bool,
+    pub stats: bool,
+    pub max_filesize: String,
+    pub archives: bool,
+    pub search_zip: bool,
+    pub invert_match: bool,
+    pub line_numbers: bool,
+    pub context: usize,
+    pub max_count: Option<usize>,
+    pub max_total: Option<usize>,
+    pub multiline: bool,
+    pub by_dir: bool,
+    pub replace: Option<String>,
+    pub diff: bool,
+    pub write: bool,
+    pub backup: bool,
+    pub output: OutputArgs,
+}
+
+pub fn run(pattern: &str, paths: &[String], options: SearchOptions) -> Result<()> {
+    let SearchOptions {
+        extensions,
+        globs,
+        modified_within,
+        ignore_case,
+        smart_case,
+        case_sensitive,
+        word_regexp,
+        files_only,
+        count,
+        only_matching,
+        with_filename,
+        json,
+        stream,
+        tui,
+        stats,
+        max_filesize,
+        archives,
+        search_zip,
+        invert_match,
+        line_numbers,
+        context,
+        max_count,
+        max_total,
+        multiline,
+        by_dir,
+        replace,
+        diff,
+        write,
+        backup,
+        output,
+    } = options;
+
+    // Files are read whole, so very large ones are skipped; 0 lifts the cap
+    let max_filesize = match parse_size(&max_filesize)? {
+        0 => u64::MAX,
+        size => size,
+    };
+
+    let globs = Globs::new(&globs, paths)?;
+    let cutoff = match &modified_within {
+        Some(within) => Some(SystemTime::now() - Duration::from_secs(parse_duration(within)?)),
+        None => None,
+    };
+
+    // Smart case (from the flag or the config) ignores case unless the
+    // pattern has an uppercase letter; -i and -s override it
+    let smart_case = smart_case || Config::load().is_ok_and(|c| c.search.smart_case);
+    let ignore_case =
+        ignore_case || (smart_case && !case_sensitive && !has_uppercase_literal(pattern));
+
+    let expression = if word_regexp {
+        word_pattern(pattern)
+    } else {
+        pattern.to_string()
+    };
+    let regex = RegexBuilder::new(&expression)
+        .case_insensitive(ignore_case)
+        .multi_line(multiline)
+        .build()?;
+    let finder = RegexBuilder::new(&expression)
+        .case_insensitive(ignore_case)
+        .multi_line(true)
+        .crlf(true)
+        .build()?;
+    let bytes = bytes::RegexBuilder::new(&expression)
         .case_insensitive(ignore_case)
         .build()?;
 
-    ui::print_start(&format!("Searching for '{}'", pattern.bright_yellow()), path);
-    println!();
+    // Inverted file lists (-l, path lists) name the files without any match;
+    // otherwise each file's non-matching lines are selected
+    let invert_files = invert_match && (files_only || ui::is_path_list());
+    let invert_lines = invert_match && !invert_files;
 
-    let mut total_matches = 0usize;
-    let mut files_with_matches = 0usize;
+    if tui && !std::io::stdout().is_terminal() {
+        return Err(anyhow!("--tui needs a terminal"));
+    }
+    if !json && !tui {
+        ui::print_start(
+            &format!("Searching for '{}'", pattern.themed(Element::Match)),
+            &paths.join(" "),
+        );
+        ui::print_blank();
+    }
+    let started = Instant::now();
+    let mut report = match replace {
+        Some(_) => Report::new(&["file", "line", "text", "replacement"]),
+        None if count => Report::new(&["file", "count"]),
+        None => Report::new(&["file", "line", "text"]),
+    };
 
-    for entry in WalkDir::new(path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let file_path = entry.path();
+    let search = Search {
+        matcher: Matcher {
+            regex: &regex,
+            finder: &finder,
+            bytes: &bytes,
+            replacement: replace.as_deref(),
+            multiline,
+            invert: invert_lines,
+            max_count: max_count.unwrap_or(usize::MAX),
+        },
+        files_only,
+        count,
+        only_matching,
+        with_filename,
+        json,
+        tui,
+        invert_files,
+        by_dir,
+        line_numbers,
+        context,
+        diff,
+        max_filesize,
+        archives,
+        search_zip,
+        extensions: &extensions,
+        write,
+        backup,
+        rows: output.output.is_some(),
+    };
 
-        if !file_path.is_file() || should_skip(file_path, false) {
-            continue;
+    // Files are searched in parallel a batch at a time; each batch is printed
+    // in walk order once done, or with --stream file by file as they finish
+    let spinner = ui::scan_spinner("Searching");
+    let tally = Mutex::new(Tally::default());
+    let mut walked = 0;
+    let mut searched = 0;
+    let mut files = input_files(paths, false, false)?
+        .inspect(|_| {
+            walked += 1;
+            spinner.inc(1);
+        })
+        .filter(|p| {
+            globs.matches(p)
+                && cutoff.is_none_or(|cutoff| is_modified_since(p, cutoff))
+                && (matches_extensions(p, &extensions)
+                    || (archives && archive::kind(p).is_some())
+                    || (search_zip
+                        && archive::compression(p).is_some()
+                        && matches_extensions(&p.with_extension(""), &extensions)))
+        });
+    // --max-total stops the search once as many matches were found; the
+    // file reaching it is still shown whole. Matches are counted as files
+    // finish, so the rest of a batch isn't searched once the total is reached
+    let max_total = max_total.unwrap_or(usize::MAX);
+    let found = AtomicUsize::new(0);
+    let reached = || found.load(Ordering::Relaxed) >= max_total;
+    let search_one = |path: &PathBuf| {
+        if reached() {
+            return Vec::new();
+        }
+        let outcomes = search_path(path, &search);
+        let count: usize = outcomes.iter().map(|(_, outcome)| outcome.count).sum();
+        found.fetch_add(count, Ordering::Relaxed);
+        outcomes
+    };
+    let record = |outcomes: Vec<(PathBuf, Outcome)>| {
+        if let Ok(mut tally) = tally.lock() {
+            for (path, outcome) in outcomes {
+                if tally.total_matches < max_total {
+                    tally.record(&path, outcome, by_dir, &spinner);
+                }
+            }
+        }
+    };
+    while !reached() {
+        let batch: Vec<PathBuf> = files.by_ref().take(SEARCH_BATCH).collect();
+        if batch.is_empty() {
+            break;
         }
+        searched += batch.len();
+        if stream {
+            batch.par_iter().for_each(|path| record(search_one(path)));
+        } else {
+            let outcomes: Vec<Vec<(PathBuf, Outcome)>> = batch.par_iter().map(search_one).collect();
+            record(outcomes.into_iter().flatten().collect());
+        }
+    }
+    spinner.finish_and_clear();
+    drop(files);
 
-        if !matches_extensions(file_path, &extensions) {
-            continue;
+    let Tally {
+        total_matches,
+        files_with_matches,
+        unreadable,
+        unwritable,
+        too_large,
+        binary,
+        bytes_read,
+        mut file_matches,
+        dir_matches,
+        rows,
+        hits,
+        json: json_stats,
+    } = tally.into_inner().unwrap_or_default();
+    for row in rows {
+        report.row(row);
+    }
+
+    if tui {
+        if hits.is_empty() {
+            ui::print_warning("No matches");
+            return Err(Exit::silent(exit::NEGATIVE).into());
         }
+        return tui::browse(hits);
+    }
 
-        if is_binary_file(file_path) {
-            continue;
+    if by_dir {
+        let mut dirs: Vec<_> = dir_matches.into_iter().collect();
+        dirs.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(&b.0)));
+        print_by_dir(&dirs);
+
+        report = Report::new(&["directory", "matches", "files"]);
+        for (dir, (matches, files)) in &dirs {
+            report.row(vec![json!(dir), json!(matches), json!(files)]);
         }
+    }
+    output::write_report(&output, &report)?;
 
-        match search_file(file_path, &regex, files_only, line_numbers, context) {
-            Ok(matches) if !matches.is_empty() => {
-                files_with_matches += 1;
-                total_matches += matches.len();
+    // Summary
+    if json {
+        let mut summary = String::new();
+        push_json_message(
+            &mut summary,
+            "summary",
+            json!({
+                "elapsed_total": json_duration(started.elapsed()),
+                "stats": json_stats.to_json(),
+            }),
+        );
+        print!("{}", summary);
+    } else if invert_files && !ui::is_path_list() {
+        ui::print_count(
+            files_with_matches,
+            "file without a match",
+            "files without a match",
+        );
+    } else if !ui::is_path_list() && !only_matching {
+        if invert_lines {
+            ui::print_count(total_matches, "non-matching line", "non-matching lines");
+        } else {
+            ui::print_count(total_matches, "match", "matches");
+        }
+        println!(
+            "{} {} in {} files",
+            chars::ARROW.themed(Element::Muted),
+            if write { "replaced" } else { "found" },
+            files_with_matches
+                .to_string()
+                .themed(Element::Success)
+                .bold()
+        );
+        if replace.is_some() && !write && files_with_matches > 0 {
+            ui::print_info("Preview only, nothing was changed; run with --write to apply");
+        }
+    }
 
-                if files_only {
-                    println!("{}", file_path.display().to_string().green());
-                } else {
-                    println!("{}", file_path.display().to_string().bright_magenta().bold());
-                    for m in matches {
-                        println!("{}", m);
-                    }
-                    println!();
+    if stats {
+        ui::print_section("Search Statistics");
+        ui::print_kv("Files scanned", &walked.to_string());
+        ui::print_kv("Files searched", &searched.to_string());
+        ui::print_kv("Ignored by filters", &(walked - searched).to_string());
+        ui::print_kv("Binary skipped", &binary.to_string());
+        ui::print_kv("Too large skipped", &too_large.to_string());
+        ui::print_kv("Unreadable", &unreadable.to_string());
+        ui::print_kv("Bytes read", &format_bytes(bytes_read));
+        ui::print_kv(
+            "Elapsed",
+            &format!("{:.2}s", started.elapsed().as_secs_f64()),
+        );
+
+        file_matches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        print_top_files(&file_matches[..file_matches.len().min(STATS_TOP)]);
+    }
+
+    if total_matches >= max_total && !json {
+        ui::print_info(&format!(
+            "Stopped after {} matches (--max-total)",
+            max_total
+        ));
+    }
+    if too_large > 0 && !json {
+        ui::print_warning(&format!(
+            "Skipped {} files larger than {} (see --max-filesize)",
+            too_large,
+            format_bytes(max_filesize)
+        ));
+    }
+
+    if unwritable > 0 {
+        return exit::partial(unwritable, "files that could not be changed");
+    }
+    if unreadable > 0 {
+        return exit::partial(unreadable, "unreadable files");
+    }
+    if files_with_matches == 0 {
+        return Err(Exit::silent(exit::NEGATIVE).into());
+    }
+
+    Ok(())
+}
+
+/// Whether `pattern` has an uppercase letter outside of escapes such as
+/// `\W` or `\p{Lu}`
+fn has_uppercase_literal(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                // A Unicode class name is one letter or in braces
+                if let Some('p' | 'P') = chars.next()
+                    && chars.next() == Some('{')
+                {
+                    chars.by_ref().take_while(|&c| c != '}').for_each(drop);
                 }
             }
-            Err(_) => continue,
-            _ => continue,
+            c if c.is_uppercase() => return true,
+            _ => {}
         }
     }
+    false
+}
 
-    // Summary
-    ui::print_count(total_matches, "match", "matches");
-    println!(
-        "{} found in {} files",
-        chars::ARROW.bright_black(),
-        files_with_matches.to_string().bright_green().bold()
-    );
+/// Whether `path` changed after `cutoff`; files whose time can't be read
+/// are kept, to be reported when searched
+fn is_modified_since(path: &Path, cutoff: SystemTime) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_or(true, |modified| modified > cutoff)
+}
 
-    Ok(())
+/// `pattern` matching only as a whole word
+fn word_pattern(pattern: &str) -> String {
+    format!(r"\b(?:{})\b", pattern)
 }
 
-fn search_file(
-    path: &Path,
-    regex: &Regex,
+/// Everything needed to search one file and format what it prints
+struct Search<'a> {
+    matcher: Matcher<'a>,
     files_only: bool,
+    count: bool,
+    only_matching: bool,
+    with_filename: bool,
+    json: bool,
+    /// Collect the matching lines for the --tui browser instead of printing them
+    tui: bool,
+    /// List the files without any match (-v with -l or a path list)
+    invert_files: bool,
+    by_dir: bool,
     line_numbers: bool,
     context: usize,
-) -> Result<Vec<String>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let lines: Vec<String> = reader.lines().filter_map(|l| l.ok()).collect();
+    /// Show the --replace changes as unified diffs
+    diff: bool,
+    /// Larger files are skipped instead of read
+    max_filesize: u64,
+    /// Search the members of .zip, .jar, .tar and .tar.gz files
+    archives: bool,
+    /// Search .gz, .zst and .bz2 files decompressed
+    search_zip: bool,
+    /// Extension filter, applied to archive members
+    extensions: &'a Option<String>,
+    /// Apply --replace to the files, keeping a .bak copy with `backup`
+    write: bool,
+    backup: bool,
+    /// Collect report rows (--output)
+    rows: bool,
+}
 
-    let mut results = Vec::new();
-    let mut matched_lines: Vec<usize> = Vec::new();
+/// What searching one file produced, tallied and printed by `Tally::record`
+#[derive(Default)]
+struct Outcome {
+    /// Whether the file counts as found: it has selected lines, or none with -v -l
+    found: bool,
+    /// Matches (selected lines) for the summary
+    count: usize,
+    /// Output for the file, already formatted
+    text: String,
+    /// Line number and text of each selected line, for --tui
+    hits: Vec<(usize, String)>,
+    rows: Vec<Vec<serde_json::Value>>,
+    json: JsonStats,
+    /// Skipped for being over --max-filesize
+    too_large: bool,
+    /// Skipped as binary
+    binary: bool,
+    /// Size of the content searched
+    bytes: u64,
+    failure: Option<Failure>,
+}
+
+enum Failure {
+    Unreadable(anyhow::Error),
+    /// Could not write the --replace changes
+    Unwritable(anyhow::Error),
+}
 
-    for (i, line) in lines.iter().enumerate() {
-        if regex.is_match(line) {
-            matched_lines.push(i);
+/// Totals over the files searched so far
+#[derive(Default)]
+struct Tally {
+    total_matches: usize,
+    files_with_matches: usize,
+    unreadable: usize,
+    unwritable: usize,
+    too_large: usize,
+    binary: usize,
+    bytes_read: u64,
+    /// Matches in each file with any
+    file_matches: Vec<(PathBuf, usize)>,
+    /// directory -> (matches, files with matches)
+    dir_matches: HashMap<String, (usize, usize)>,
+    rows: Vec<Vec<serde_json::Value>>,
+    hits: Vec<Hit>,
+    json: JsonStats,
+}
+
+impl Tally {
+    /// Add one file's outcome and print its output
+    fn record(&mut self, path: &Path, outcome: Outcome, by_dir: bool, spinner: &ProgressBar) {
+        match outcome.failure {
+            Some(Failure::Unreadable(e)) => {
+                skipped::record_error(path, &e);
+                self.unreadable += 1;
+                return;
+            }
+            Some(Failure::Unwritable(e)) => {
+                skipped::record_error(path, &e);
+                self.unwritable += 1;
+                return;
+            }
+            None => {}
+        }
+        if outcome.too_large {
+            self.too_large += 1;
+            return;
+        }
+        if outcome.binary {
+            self.binary += 1;
+        }
+        self.bytes_read += outcome.bytes;
+        self.json.add(&outcome.json);
+        if !outcome.found {
+            return;
+        }
+        self.files_with_matches += 1;
+        self.total_matches += outcome.count;
+        self.file_matches.push((path.to_path_buf(), outcome.count));
+        self.rows.extend(outcome.rows);
+        self.hits
+            .extend(outcome.hits.into_iter().map(|(line, text)| Hit {
+                path: path.to_path_buf(),
+                line,
+                text,
+            }));
+
+        if ui::is_path_list() {
+            ui::emit_path(path);
+        } else if by_dir {
+            let dir = path
+                .parent()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default();
+            let entry = self.dir_matches.entry(dir).or_insert((0, 0));
+            entry.0 += outcome.count;
+            entry.1 += 1;
+        } else {
+            spinner.suspend(|| print!("{}", outcome.text));
         }
     }
+}
 
-    if matched_lines.is_empty() {
-        return Ok(results);
+/// Search one file as `search` says, formatting its output. An archive with
+/// --archives gives an outcome per member, named `archive.zip!inner/path`.
+fn search_path(path: &Path, search: &Search) -> Vec<(PathBuf, Outcome)> {
+    if search.archives
+        && let Some(kind) = archive::kind(path)
+    {
+        return search_archive(path, kind, search);
+    }
+    if search.search_zip
+        && let Some(compression) = archive::compression(path)
+    {
+        let outcome = match archive::decompress(path, compression, search.max_filesize) {
+            Ok(Member::Data(data)) => search_data(path, &data, search),
+            Ok(Member::TooLarge) => too_large(path, search),
+            Err(e) => Outcome {
+                failure: Some(Failure::Unreadable(e)),
+                ..Outcome::default()
+            },
+        };
+        return vec![(path.to_path_buf(), outcome)];
+    }
+    let outcome = match fs::metadata(path) {
+        Ok(metadata) if metadata.len() > search.max_filesize => too_large(path, search),
+        Ok(metadata) if metadata.len() >= MMAP_THRESHOLD => match map_file(path) {
+            Ok(map) => search_data(path, &map, search),
+            Err(e) => Outcome {
+                failure: Some(Failure::Unreadable(e.into())),
+                ..Outcome::default()
+            },
+        },
+        Ok(_) => match is_binary_file(path).and_then(|binary| {
+            if binary {
+                Ok(None)
+            } else {
+                fs::read(path).map(Some)
+            }
+        }) {
+            Ok(Some(data)) => search_data(path, &data, search),
+            Ok(None) => {
+                ui::print_trace(&format!("skip {} (binary)", path.display()));
+                Outcome {
+                    binary: true,
+                    ..Outcome::default()
+                }
+            }
+            Err(e) => Outcome {
+                failure: Some(Failure::Unreadable(e.into())),
+                ..Outcome::default()
+            },
+        },
+        Err(e) => Outcome {
+            failure: Some(Failure::Unreadable(e.into())),
+            ..Outcome::default()
+        },
+    };
+    vec![(path.to_path_buf(), outcome)]
+}
+
+fn map_file(path: &Path) -> std::io::Result<Mmap> {
+    let file = fs::File::open(path)?;
+    // Safety: the map is only read, and is dropped once the file is searched;
+    // a file truncated meanwhile by another process is the caller's risk as
+    // with any mmap-based search tool
+    unsafe { Mmap::map(&file) }
+}
+
+fn search_archive(path: &Path, kind: archive::Kind, search: &Search) -> Vec<(PathBuf, Outcome)> {
+    let mut outcomes = Vec::new();
+    let result = archive::for_each_member(path, kind, search.max_filesize, |name, member| {
+        if !matches_extensions(Path::new(name), search.extensions) {
+            return;
+        }
+        let inner = PathBuf::from(format!("{}!{}", path.display(), name));
+        let outcome = match member {
+            Member::Data(data) => search_data(&inner, &data, search),
+            Member::TooLarge => too_large(&inner, search),
+        };
+        outcomes.push((inner, outcome));
+    });
+    if let Err(e) = result {
+        outcomes.push((
+            path.to_path_buf(),
+            Outcome {
+                failure: Some(Failure::Unreadable(e)),
+                ..Outcome::default()
+            },
+        ));
+    }
+    outcomes
+}
+
+fn too_large(path: &Path, search: &Search) -> Outcome {
+    ui::print_trace(&format!(
+        "skip {} (larger than {})",
+        path.display(),
+        format_bytes(search.max_filesize)
+    ));
+    Outcome {
+        too_large: true,
+        ..Outcome::default()
+    }
+}
+
+/// Search the content `data` of `path`
+fn search_data(path: &Path, data: &[u8], search: &Search) -> Outcome {
+    let mut outcome = Outcome {
+        bytes: data.len() as u64,
+        ..Outcome::default()
+    };
+    let raw = data;
+    let (data, encoding) = decode_text(data);
+    if let Some(encoding) = encoding {
+        ui::print_trace(&format!("decode {} as {}", path.display(), encoding));
+    }
+    let data = &*data;
+    if is_binary(data) {
+        ui::print_trace(&format!("skip {} (binary)", path.display()));
+        outcome.binary = true;
+        return outcome;
+    }
+    let matcher = &search.matcher;
+    let name = path.display().to_string();
+
+    if search.json {
+        // Like rg, only UTF-16 is converted; other text that isn't UTF-8 is
+        // searched as it is, so offsets point into the file
+        let data = if encoding == Some("Latin-1") { raw } else { data };
+        match write_json_events(path, data, matcher, search.context, &mut outcome.text) {
+            Ok((selected, stats)) => {
+                outcome.found = selected > 0;
+                outcome.count = selected;
+                outcome.json = stats;
+            }
+            Err(e) => outcome.failure = Some(Failure::Unreadable(e)),
+        }
+        return outcome;
+    }
+
+    // Counting only reads the lines, nothing is kept or formatted
+    if search.count && !search.invert_files {
+        match count_matches(data, matcher) {
+            Ok(n) => {
+                outcome.found = n > 0;
+                outcome.count = n;
+                outcome.text = format!(
+                    "{}:{}\n",
+                    name.themed(Element::Path),
+                    n.to_string().themed(Element::Success).bold()
+                );
+                outcome.rows.push(vec![json!(name), json!(n)]);
+            }
+            Err(e) => outcome.failure = Some(Failure::Unreadable(e)),
+        }
+        return outcome;
+    }
+
+    // Only the matched text, one per line, ready for `sort | uniq -c`
+    if search.only_matching {
+        match matched_parts(data, matcher) {
+            Ok(parts) => {
+                outcome.found = !parts.is_empty();
+                outcome.count = parts.len();
+                for (line, part) in parts {
+                    if search.with_filename {
+                        let _ = writeln!(
+                            outcome.text,
+                            "{}:{}:{}",
+                            name.themed(Element::Path),
+                            line.to_string().themed(Element::Muted),
+                            part
+                        );
+                    } else {
+                        let _ = writeln!(outcome.text, "{}", part);
+                    }
+                    if search.rows {
+                        outcome
+                            .rows
+                            .push(vec![json!(name), json!(line), json!(part)]);
+                    }
+                }
+            }
+            Err(e) => outcome.failure = Some(Failure::Unreadable(e)),
+        }
+        return outcome;
+    }
+
+    let print_lines =
+        !search.files_only && !search.by_dir && !search.tui && !search.diff && !ui::is_path_list();
+    match search_file(
+        data,
+        matcher,
+        print_lines,
+        search.line_numbers,
+        search.context,
+    ) {
+        Ok(matches) if (matches.count > 0) != search.invert_files => {
+            if search.write
+                && let Some(replacement) = matcher.replacement
+                && let Err(e) = replace_in_file(path, matcher.regex, replacement, search.backup)
+            {
+                outcome.failure = Some(Failure::Unwritable(e));
+                return outcome;
+            }
+            outcome.found = true;
+            outcome.count = matches.count;
+
+            if search.rows && search.invert_files {
+                outcome
+                    .rows
+                    .push(vec![json!(name), json!(null), json!(null)]);
+            } else if search.rows && !search.by_dir {
+                for (line, text) in &matches.hits {
+                    let mut row = vec![json!(name), json!(line), json!(text)];
+                    if let Some(replacement) = matcher.replacement {
+                        row.push(json!(matcher.regex.replace_all(text, replacement)));
+                    }
+                    outcome.rows.push(row);
+                }
+            }
+
+            if search.tui {
+                outcome.hits = matches.hits;
+            } else if search.diff
+                && let Some(replacement) = matcher.replacement
+            {
+                let context = if search.context > 0 {
+                    search.context
+                } else {
+                    DIFF_CONTEXT
+                };
+                let text = String::from_utf8_lossy(data);
+                for line in unified_diff(&name, &text, matcher.regex, replacement, context) {
+                    let _ = writeln!(outcome.text, "{}", color_diff_line(&line));
+                }
+                outcome.text.push('\n');
+            } else if search.files_only {
+                let _ = writeln!(outcome.text, "{}", name.themed(Element::Path));
+            } else {
+                let _ = writeln!(outcome.text, "{}", name.themed(Element::Path).bold());
+                for line in matches.lines {
+                    let _ = writeln!(outcome.text, "{}", line);
+                }
+                outcome.text.push('\n');
+            }
+        }
+        Ok(_) => {}
+        Err(e) => outcome.failure = Some(Failure::Unreadable(e)),
     }
+    outcome
+}
 
-    if files_only {
-        results.push(String::new());
-        return Ok(results);
+/// Print the files with the most matches, for --stats
+fn print_top_files(files: &[(PathBuf, usize)]) {
+    if files.is_empty() {
+        return;
     }
 
-    let mut displayed: std::collections::HashSet<usize> = std::collections::HashSet::new();
+    println!();
+    println!(
+        "  {:>8}  {}",
+        "MATCHES".themed(Element::Header).bold(),
+        "FILE".themed(Element::Header).bold()
+    );
+    ui::print_line(80);
+    for (path, matches) in files {
+        println!(
+            "  {:>8}  {}",
+            matches.to_string().themed(Element::Warning).bold(),
+            path.display().to_string().themed(Element::Path)
+        );
+    }
+    ui::print_line(80);
+}
+
+/// Print match counts per directory, most matches first
+fn print_by_dir(dirs: &[(String, (usize, usize))]) {
+    if dirs.is_empty() {
+        return;
+    }
+
+    println!(
+        "  {:>8}  {:>6}  {}",
+        "MATCHES".themed(Element::Header).bold(),
+        "FILES".themed(Element::Header).bold(),
+        "DIRECTORY".themed(Element::Header).bold()
+    );
+    ui::print_line(80);
+
+    for (dir, (matches, files)) in dirs {
+        let dir = if dir.is_empty() { "." } else { dir.as_str() };
+        println!(
+            "  {:>8}  {:>6}  {}",
+            matches.to_string().themed(Element::Warning).bold(),
+            files.to_string().themed(Element::Text),
+            dir.themed(Element::Muted)
+        );
+    }
+
+    ui::print_line(80);
+}
+
+fn search_file(
+    data: &[u8],
+    matcher: &Matcher,
+    print_lines: bool,
+    line_numbers: bool,
+    context: usize,
+) -> Result<FileMatches> {
+    let Matcher {
+        regex,
+        multiline,
+        invert,
+        max_count,
+        ..
+    } = *matcher;
+    if is_large(data, matcher) {
+        let text = String::from_utf8_lossy(data);
+        return Ok(search_large(
+            &text,
+            matcher,
+            print_lines,
+            line_numbers,
+            context,
+        ));
+    }
+    let mut results = Vec::new();
+    // Parts of each line covered by a match, for matches spanning lines
+    let mut spans: HashMap<usize, Vec<Range<usize>>> = HashMap::new();
+    let (lines, matched_lines, count, hits) = if multiline {
+        let text = String::from_utf8_lossy(data);
+        let (lines, found) = multiline_matches(&text, regex, max_count, &mut spans);
+        let mut matched_lines: Vec<usize> = spans.keys().copied().collect();
+        matched_lines.sort_unstable();
+        (lines, matched_lines, found.len(), found)
+    } else {
+        let lines: Vec<String> = String::from_utf8_lossy(data)
+            .lines()
+            .map(str::to_string)
+            .collect();
+        let matched_lines: Vec<usize> = (0..lines.len())
+            .filter(|&i| regex.is_match(&lines[i]) != invert)
+            .take(max_count)
+            .collect();
+        let hits = hits(&lines, &matched_lines);
+        (lines, matched_lines, hits.len(), hits)
+    };
+
+    if matched_lines.is_empty() || !print_lines {
+        return Ok(FileMatches {
+            count,
+            lines: results,
+            hits,
+        });
+    }
+
+    let is_match: HashSet<usize> = matched_lines.iter().copied().collect();
+    let mut displayed: HashSet<usize> = HashSet::new();
 
     for &match_line in &matched_lines {
         let start = match_line.saturating_sub(context);
         let end = (match_line + context + 1).min(lines.len());
 
-        for i in start..end {
+        for (i, content) in lines.iter().enumerate().take(end).skip(start) {
             if displayed.contains(&i) {
                 continue;
             }
             displayed.insert(i);
 
-            let line_num = if line_numbers {
-                format!("{:>4} {} ", i + 1, chars::V_LINE).dimmed().to_string()
-            } else {
-                String::new()
-            };
+            results.push(format_line(
+                i,
+                content,
+                is_match.contains(&i),
+                spans.get(&i),
+                matcher,
+                line_numbers,
+            ));
+        }
+
+        if context > 0 && end < lines.len() {
+            results.push(format!("  {}", chars::DOT.repeat(3).themed(Element::Muted)));
+        }
+    }
+
+    Ok(FileMatches {
+        count,
+        lines: results,
+        hits,
+    })
+}
 
-            let content = &lines[i];
-            let formatted = if i == match_line {
-                let highlighted = regex.replace_all(content, |caps: &regex::Captures| {
-                    caps[0].red().bold().to_string()
-                });
-                format!("{}{}", line_num, highlighted)
+/// One line of output: its number, then its text with the matches (or their
+/// replacement) highlighted, or dimmed for a context line
+fn format_line(
+    index: usize,
+    content: &str,
+    selected: bool,
+    spans: Option<&Vec<Range<usize>>>,
+    matcher: &Matcher,
+    line_numbers: bool,
+) -> String {
+    let line_num = if line_numbers {
+        format!("{:>4} {} ", index + 1, chars::V_LINE)
+            .dimmed()
+            .to_string()
+    } else {
+        String::new()
+    };
+
+    if let Some(ranges) = spans {
+        format!("{}{}", line_num, highlight(content, ranges))
+    } else if selected {
+        // With --replace, the replaced text is highlighted instead of the match
+        let highlighted = matcher
+            .regex
+            .replace_all(content, |caps: &regex::Captures| {
+                let mut text = caps[0].to_string();
+                if let Some(replacement) = matcher.replacement {
+                    text.clear();
+                    caps.expand(replacement, &mut text);
+                }
+                text.themed(Element::Match).bold().to_string()
+            });
+        format!("{}{}", line_num, highlighted)
+    } else {
+        format!("{}{}", line_num, content.dimmed())
+    }
+}
+
+/// Whether `data` is searched with `selected_lines` instead of line by line
+fn is_large(data: &[u8], matcher: &Matcher) -> bool {
+    data.len() as u64 >= MMAP_THRESHOLD && !matcher.multiline && !matcher.invert
+}
+
+/// Index and byte range (without the line break) of each selected line of
+/// `text`. The whole text is searched at once, and line breaks are only
+/// counted up to each match, so lines without a match cost nothing.
+fn selected_lines(text: &str, matcher: &Matcher) -> Vec<(usize, Range<usize>)> {
+    let bytes = text.as_bytes();
+    let mut selected = Vec::new();
+    // Line index at byte `counted`
+    let (mut counted, mut line) = (0, 0);
+    let mut pos = 0;
+    while selected.len() < matcher.max_count && pos <= text.len() {
+        let Some(m) = matcher.finder.find_at(text, pos) else {
+            break;
+        };
+        let start = bytes[..m.start()]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let end = bytes[m.start()..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(text.len(), |i| m.start() + i);
+        line += bytes[counted..start]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count();
+        counted = start;
+
+        // The line must match on its own, as when searched line by line
+        let content = text[start..end]
+            .strip_suffix('\r')
+            .unwrap_or(&text[start..end]);
+        if matcher.regex.is_match(content) {
+            selected.push((line, start..start + content.len()));
+        }
+        pos = end + 1;
+    }
+    selected
+}
+
+/// Byte ranges of up to `count` lines before the line starting at `start`,
+/// nearest last
+fn lines_before(text: &str, start: usize, count: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut end = start;
+    while ranges.len() < count && end > 0 {
+        let line_end = end - 1;
+        let line_start = text[..line_end].rfind('\n').map_or(0, |i| i + 1);
+        ranges.push(line_start..line_end);
+        end = line_start;
+    }
+    ranges.reverse();
+    ranges
+}
+
+/// Byte ranges of up to `count` lines after the line ending at `end`, and
+/// whether more lines follow them
+fn lines_after(text: &str, end: usize, count: usize) -> (Vec<Range<usize>>, bool) {
+    let mut ranges = Vec::new();
+    let mut start = end + 1;
+    while ranges.len() < count && start < text.len() {
+        let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+        ranges.push(start..line_end);
+        start = line_end + 1;
+    }
+    (ranges, start < text.len())
+}
+
+/// `search_file` for large texts, reading only the selected lines and their context
+fn search_large(
+    text: &str,
+    matcher: &Matcher,
+    print_lines: bool,
+    line_numbers: bool,
+    context: usize,
+) -> FileMatches {
+    let trim = |range: Range<usize>| {
+        let line = &text[range];
+        line.strip_suffix('\r').unwrap_or(line)
+    };
+    let selected = selected_lines(text, matcher);
+    let hits: Vec<(usize, String)> = selected
+        .iter()
+        .map(|(i, range)| (i + 1, text[range.clone()].to_string()))
+        .collect();
+    let mut lines = Vec::new();
+    if !print_lines {
+        return FileMatches {
+            count: hits.len(),
+            lines,
+            hits,
+        };
+    }
+
+    let is_match: HashSet<usize> = selected.iter().map(|(i, _)| *i).collect();
+    // First line index not shown yet
+    let mut next = 0;
+    for (index, range) in &selected {
+        let before = lines_before(text, range.start, context);
+        let first = index - before.len();
+        for (k, range) in before.into_iter().enumerate() {
+            if first + k >= next {
+                lines.push(format_line(
+                    first + k,
+                    trim(range),
+                    false,
+                    None,
+                    matcher,
+                    line_numbers,
+                ));
+            }
+        }
+        if *index >= next {
+            lines.push(format_line(
+                *index,
+                &text[range.clone()],
+                true,
+                None,
+                matcher,
+                line_numbers,
+            ));
+        }
+
+        // The line break after the line may be a \r\n
+        let end = text[range.end..]
+            .find('\n')
+            .map_or(text.len(), |i| range.end + i);
+        let (after, more) = lines_after(text, end, context);
+        for (k, range) in after.into_iter().enumerate() {
+            let i = index + 1 + k;
+            if i >= next {
+                lines.push(format_line(
+                    i,
+                    trim(range),
+                    is_match.contains(&i),
+                    None,
+                    matcher,
+                    line_numbers,
+                ));
+            }
+            next = next.max(i + 1);
+        }
+        next = next.max(index + 1);
+        if context > 0 && more {
+            lines.push(format!("  {}", chars::DOT.repeat(3).themed(Element::Muted)));
+        }
+    }
+
+    FileMatches {
+        count: hits.len(),
+        lines,
+        hits,
+    }
+}
+
+/// Number of selected lines in `data` (of matches with --multiline)
+fn count_matches(data: &[u8], matcher: &Matcher) -> Result<usize> {
+    let text = String::from_utf8_lossy(data);
+    if is_large(data, matcher) {
+        return Ok(selected_lines(&text, matcher).len());
+    }
+    if matcher.multiline {
+        return Ok(matcher
+            .regex
+            .find_iter(&text)
+            .take(matcher.max_count)
+            .count());
+    }
+    Ok(text
+        .lines()
+        .filter(|line| matcher.regex.is_match(line) != matcher.invert)
+        .take(matcher.max_count)
+        .count())
+}
+
+/// Line number (1-based) and text of every match in `data`
+fn matched_parts(data: &[u8], matcher: &Matcher) -> Result<Vec<(usize, String)>> {
+    let text = String::from_utf8_lossy(data);
+    if is_large(data, matcher) {
+        let mut parts = Vec::new();
+        for (i, range) in selected_lines(&text, matcher) {
+            parts.extend(
+                matcher
+                    .regex
+                    .find_iter(&text[range])
+                    .map(|m| (i + 1, m.as_str().to_string())),
+            );
+        }
+        return Ok(parts);
+    }
+    if matcher.multiline {
+        let found = multiline_matches(&text, matcher.regex, matcher.max_count, &mut HashMap::new());
+        return Ok(found.1);
+    }
+    let mut parts = Vec::new();
+    let matching = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| matcher.regex.is_match(line))
+        .take(matcher.max_count);
+    for (i, line) in matching {
+        parts.extend(
+            matcher
+                .regex
+                .find_iter(line)
+                .map(|m| (i + 1, m.as_str().to_string())),
+        );
+    }
+    Ok(parts)
+}
+
+/// Lines of `text` and its first `limit` matches, as their first line number
+/// (1-based) and matched text. `spans` gets the part of each line that a match covers.
+fn multiline_matches(
+    text: &str,
+    regex: &Regex,
+    limit: usize,
+    spans: &mut HashMap<usize, Vec<Range<usize>>>,
+) -> (Vec<String>, Vec<(usize, String)>) {
+    // An empty file has no lines for an empty match to point at
+    if text.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+    // Byte offset where each line starts, and the line without its line break
+    let mut starts = Vec::new();
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        starts.push(offset);
+        offset += line.len();
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        lines.push(line.strip_suffix('\r').unwrap_or(line).to_string());
+    }
+
+    let mut found = Vec::new();
+    for m in regex.find_iter(text).take(limit) {
+        let line_of = |pos: usize| starts.partition_point(|&s| s <= pos).saturating_sub(1);
+        let first = line_of(m.start());
+        let last = line_of(m.end().saturating_sub(1).max(m.start()));
+        for i in first..=last {
+            let len = lines[i].len();
+            let start = m.start().saturating_sub(starts[i]).min(len);
+            let end = (m.end() - starts[i]).min(len);
+            spans.entry(i).or_default().push(start..end);
+        }
+        found.push((first + 1, m.as_str().to_string()));
+    }
+    (lines, found)
+}
+
+/// `line` with the byte `ranges` (in order, not overlapping) highlighted
+fn highlight(line: &str, ranges: &[Range<usize>]) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut pos = 0;
+    for range in ranges {
+        out.push_str(&line[pos..range.start]);
+        out.push_str(
+            &line[range.clone()]
+                .themed(Element::Match)
+                .bold()
+                .to_string(),
+        );
+        pos = range.end;
+    }
+    out.push_str(&line[pos..]);
+    out
+}
+
+/// Counters of ripgrep's JSON `end` and `summary` messages
+#[derive(Default)]
+struct JsonStats {
+    elapsed: Duration,
+    searches: usize,
+    searches_with_match: usize,
+    bytes_searched: usize,
+    bytes_printed: usize,
+    matched_lines: usize,
+    matches: usize,
+}
+
+impl JsonStats {
+    fn add(&mut self, other: &JsonStats) {
+        self.elapsed += other.elapsed;
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.bytes_searched += other.bytes_searched;
+        self.bytes_printed += other.bytes_printed;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "elapsed": json_duration(self.elapsed),
+            "searches": self.searches,
+            "searches_with_match": self.searches_with_match,
+            "bytes_searched": self.bytes_searched,
+            "bytes_printed": self.bytes_printed,
+            "matched_lines": self.matched_lines,
+            "matches": self.matches,
+        })
+    }
+}
+
+fn json_duration(duration: Duration) -> serde_json::Value {
+    json!({
+        "secs": duration.as_secs(),
+        "nanos": duration.subsec_nanos(),
+        "human": format!("{:.6}s", duration.as_secs_f64()),
+    })
+}
+
+/// Add one JSON Lines message to `out`, returning its length
+fn push_json_message(out: &mut String, kind: &str, data: serde_json::Value) -> usize {
+    let line = json!({ "type": kind, "data": data }).to_string();
+    out.push_str(&line);
+    out.push('\n');
+    line.len() + 1
+}
+
+/// Write the begin/match/context/end messages for `path`, whose content is
+/// `data`, to `out`, as
+/// `rg --json` does. Files without a selected line get no messages.
+/// Returns the number of selected lines and the file's counters.
+fn write_json_events(
+    path: &Path,
+    data: &[u8],
+    matcher: &Matcher,
+    context: usize,
+    out: &mut String,
+) -> Result<(usize, JsonStats)> {
+    let started = Instant::now();
+    let mut stats = JsonStats {
+        searches: 1,
+        bytes_searched: data.len(),
+        ..JsonStats::default()
+    };
+
+    // Each line with its byte offset, content without the line break, and whether it's selected
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for raw in data.split_inclusive(|&b| b == b'\n') {
+        let body = raw.strip_suffix(b"\n").unwrap_or(raw);
+        let body = body.strip_suffix(b"\r").unwrap_or(body);
+        let selected = matcher.bytes.is_match(body) != matcher.invert;
+        lines.push((offset, raw, body, selected));
+        offset += raw.len();
+    }
+    // Lines past --max-count are only context
+    let mut remaining = matcher.max_count;
+    for line in lines.iter_mut().filter(|line| line.3) {
+        line.3 = remaining > 0;
+        remaining = remaining.saturating_sub(1);
+    }
+    let selected: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].3).collect();
+    if selected.is_empty() {
+        stats.elapsed = started.elapsed();
+        return Ok((0, stats));
+    }
+
+    let path_json = json_data(path.as_os_str().as_encoded_bytes());
+    stats.searches_with_match = 1;
+    stats.bytes_printed += push_json_message(out, "begin", json!({ "path": path_json }));
+    let mut shown: HashSet<usize> = HashSet::new();
+    for &line in &selected {
+        let end = (line + context + 1).min(lines.len());
+        let start = line.saturating_sub(context);
+        for (i, (offset, raw, body, is_selected)) in lines.iter().enumerate().take(end).skip(start)
+        {
+            if !shown.insert(i) {
+                continue;
+            }
+            // Inverted matches select lines without anything to point at
+            let submatches: Vec<serde_json::Value> = if *is_selected && !matcher.invert {
+                matcher
+                    .bytes
+                    .find_iter(body)
+                    .map(|m| {
+                        json!({ "match": json_data(m.as_bytes()), "start": m.start(), "end": m.end() })
+                    })
+                    .collect()
             } else {
-                format!("{}{}", line_num, content.dimmed())
+                Vec::new()
             };
+            if *is_selected {
+                stats.matched_lines += 1;
+                stats.matches += submatches.len();
+            }
+            stats.bytes_printed += push_json_message(
+                out,
+                if *is_selected { "match" } else { "context" },
+                json!({
+                    "path": path_json,
+                    "lines": json_data(raw),
+                    "line_number": i + 1,
+                    "absolute_offset": offset,
+                    "submatches": submatches,
+                }),
+            );
+        }
+    }
+
+    stats.elapsed = started.elapsed();
+    let end = json!({
+        "path": path_json,
+        "binary_offset": null,
+        "stats": stats.to_json(),
+    });
+    stats.bytes_printed += push_json_message(out, "end", end);
+    Ok((selected.len(), stats))
+}
 
-            results.push(formatted);
+/// `data` the way `rg --json` writes it: as text when it is UTF-8,
+/// otherwise base64-encoded
+fn json_data(data: &[u8]) -> serde_json::Value {
+    match std::str::from_utf8(data) {
+        Ok(text) => json!({ "text": text }),
+        Err(_) => json!({ "bytes": STANDARD.encode(data) }),
+    }
+}
+
+/// Replace the matches on every line of `path`, keeping its line endings.
+/// The result is written next to the file and renamed over it, after
+/// copying the original to `<file>.bak` with `backup`. Symlinks and files
+/// with other hardlinks are refused, since the rename would split them off.
+fn replace_in_file(path: &Path, regex: &Regex, replacement: &str, backup: bool) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        return Err(anyhow!("is a symlink; edit the file it points to instead"));
+    }
+    if link_count(&metadata) > 1 {
+        return Err(anyhow!("has other hardlinks, which would keep the old content"));
+    }
+
+    let original = fs::read_to_string(path)?;
+    let mut replaced = String::with_capacity(original.len());
+    for line in original.split_inclusive('\n') {
+        let text = line
+            .strip_suffix('\n')
+            .map(|l| l.strip_suffix('\r').unwrap_or(l))
+            .unwrap_or(line);
+        replaced.push_str(&regex.replace_all(text, replacement));
+        replaced.push_str(&line[text.len()..]);
+    }
+    if replaced == original {
+        return Ok(());
+    }
+
+    if backup {
+        write_backup(path)?;
+    }
+    let (tmp, mut file) = create_temporary(path)?;
+    let written = std::io::Write::write_all(&mut file, replaced.as_bytes())
+        .and_then(|_| file.set_permissions(metadata.permissions()))
+        .and_then(|_| keep_owner(&tmp, &metadata));
+    drop(file);
+    let written = written.and_then(|_| fs::rename(&tmp, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Create a new, empty file next to `path` to write its new content to,
+/// named `.<file>.ftools-tmp<n>` with the first `n` not taken. Nothing
+/// already there is opened, so a symlink or file by that name is left alone.
+fn create_temporary(path: &Path) -> std::io::Result<(PathBuf, fs::File)> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    for n in 0.. {
+        let tmp = path.with_file_name(format!(".{}.ftools-tmp{}", name, n));
+        match fs::File::options().write(true).create_new(true).open(&tmp) {
+            Ok(file) => return Ok((tmp, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
         }
+    }
+    unreachable!()
+}
 
-        if context > 0 && end < lines.len() {
-            results.push(format!("  {}", chars::DOT.repeat(3).bright_black()));
+/// Copy `path` to `<file>.bak`, or `<file>.bak.1`, `.bak.2`... when an
+/// earlier backup is in the way
+fn write_backup(path: &Path) -> Result<()> {
+    let mut original = fs::File::open(path)?;
+    for n in 0.. {
+        let suffix = match n {
+            0 => ".bak".to_string(),
+            n => format!(".bak.{}", n),
+        };
+        let target = with_name_suffix(path, &suffix);
+        match fs::File::options().write(true).create_new(true).open(&target) {
+            Ok(mut copy) => {
+                std::io::copy(&mut original, &mut copy)?;
+                copy.set_permissions(original.metadata()?.permissions())?;
+                return Ok(());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
         }
     }
+    unreachable!()
+}
 
-    Ok(results)
+/// Give the rewritten file the owner and group of the original
+#[cfg(unix)]
+fn keep_owner(path: &Path, original: &fs::Metadata) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let current = fs::metadata(path)?;
+    if (current.uid(), current.gid()) == (original.uid(), original.gid()) {
+        return Ok(());
+    }
+    std::os::unix::fs::chown(path, Some(original.uid()), Some(original.gid()))
+}
+
+#[cfg(not(unix))]
+fn keep_owner(_path: &Path, _original: &fs::Metadata) -> std::io::Result<()> {
+    Ok(())
 }
 
-fn is_binary_file(path: &Path) -> bool {
-    if let Ok(file) = File::open(path) {
-        let mut reader = BufReader::new(file);
-        let mut buffer = [0u8; 512];
-        if let Ok(bytes_read) = std::io::Read::read(&mut reader, &mut buffer) {
-            for byte in &buffer[..bytes_read] {
-                if *byte == 0 {
-                    return true;
+/// The changes --replace makes to `text` as a unified diff of `name`, with
+/// `context` unchanged lines around each change. Empty if nothing changes.
+fn unified_diff(
+    name: &str,
+    text: &str,
+    regex: &Regex,
+    replacement: &str,
+    context: usize,
+) -> Vec<String> {
+    let old: Vec<&str> = text.lines().collect();
+    let new: Vec<String> = old
+        .iter()
+        .map(|line| regex.replace_all(line, replacement).into_owned())
+        .collect();
+    let changed: Vec<usize> = (0..old.len()).filter(|&i| old[i] != new[i]).collect();
+    if changed.is_empty() {
+        return Vec::new();
+    }
+
+    // Changes closer than twice the context share a hunk
+    let mut hunks: Vec<Range<usize>> = Vec::new();
+    for &i in &changed {
+        let range = i.saturating_sub(context)..(i + context + 1).min(old.len());
+        match hunks.last_mut() {
+            Some(last) if range.start <= last.end => last.end = range.end,
+            _ => hunks.push(range),
+        }
+    }
+
+    let mut diff = vec![format!("--- a/{}", name), format!("+++ b/{}", name)];
+    // A replacement may add line breaks, shifting the new line numbers
+    let mut shift = 0isize;
+    for hunk in hunks {
+        let mut body = Vec::new();
+        let (mut removed, mut added) = (Vec::new(), Vec::new());
+        let mut new_len = 0;
+        for i in hunk.clone() {
+            if old[i] == new[i] {
+                body.append(&mut removed);
+                body.append(&mut added);
+                body.push(format!(" {}", old[i]));
+                new_len += 1;
+            } else {
+                removed.push(format!("-{}", old[i]));
+                for line in new[i].split('\n') {
+                    added.push(format!("+{}", line));
+                    new_len += 1;
                 }
             }
         }
+        body.append(&mut removed);
+        body.append(&mut added);
+
+        let old_len = hunk.len();
+        let new_start = (hunk.start as isize + shift) as usize;
+        diff.push(format!(
+            "@@ -{},{} +{},{} @@",
+            hunk.start + 1,
+            old_len,
+            new_start + 1,
+            new_len
+        ));
+        diff.extend(body);
+        shift += new_len as isize - old_len as isize;
+    }
+    diff
+}
+
+fn color_diff_line(line: &str) -> String {
+    if line.starts_with("---") || line.starts_with("+++") {
+        line.bold().to_string()
+    } else if line.starts_with("@@") {
+        line.themed(Element::Info).to_string()
+    } else if line.starts_with('-') {
+        line.themed(Element::Error).to_string()
+    } else if line.starts_with('+') {
+        line.themed(Element::Success).to_string()
+    } else {
+        line.to_string()
+    }
+}
+
+/// `path` with `suffix` appended to its file name
+fn with_name_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn hits(lines: &[String], matched_lines: &[usize]) -> Vec<(usize, String)> {
+    matched_lines
+        .iter()
+        .map(|&i| (i + 1, lines[i].clone()))
+        .collect()
+}
+
+/// `data` as UTF-8, with the encoding it was converted from: UTF-16 (by its
+/// BOM, or mostly ASCII text with every other byte zero) or Latin-1 when it
+/// isn't valid UTF-8. A UTF-8 BOM is dropped.
+fn decode_text(data: &[u8]) -> (Cow<'_, [u8]>, Option<&'static str>) {
+    if let Some(rest) = data.strip_prefix(b"\xEF\xBB\xBF") {
+        return (Cow::Borrowed(rest), None);
+    }
+    let utf16 = match data {
+        [0xFF, 0xFE, rest @ ..] => Some((rest, "UTF-16LE")),
+        [0xFE, 0xFF, rest @ ..] => Some((rest, "UTF-16BE")),
+        _ => {
+            let sample = &data[..data.len().min(512) & !1];
+            let zeros = |parity: usize| {
+                sample
+                    .iter()
+                    .skip(parity)
+                    .step_by(2)
+                    .filter(|&&b| b == 0)
+                    .count()
+            };
+            let (even, odd) = (zeros(0), zeros(1));
+            let pairs = sample.len() / 2;
+            if pairs >= 2 && even == 0 && odd * 2 >= pairs {
+                Some((data, "UTF-16LE"))
+            } else if pairs >= 2 && odd == 0 && even * 2 >= pairs {
+                Some((data, "UTF-16BE"))
+            } else {
+                None
+            }
+        }
+    };
+    if let Some((bytes, encoding)) = utf16 {
+        let units = bytes.chunks_exact(2).map(|pair| match encoding {
+            "UTF-16LE" => u16::from_le_bytes([pair[0], pair[1]]),
+            _ => u16::from_be_bytes([pair[0], pair[1]]),
+        });
+        let text: String = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        return (Cow::Owned(text.into_bytes()), Some(encoding));
+    }
+    if std::str::from_utf8(data).is_ok() || is_binary(data) {
+        return (Cow::Borrowed(data), None);
+    }
+    let text: String = data.iter().map(|&b| b as char).collect();
+    (Cow::Owned(text.into_bytes()), Some("Latin-1"))
+}
+
+fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_PROBE)].contains(&0)
+}
+
+/// Whether the file at `path` is binary, judged by its first bytes so
+/// binary files aren't read in full
+fn is_binary_file(path: &Path) -> std::io::Result<bool> {
+    let mut head = Vec::with_capacity(BINARY_PROBE);
+    fs::File::open(path)?
+        .take(BINARY_PROBE as u64)
+        .read_to_end(&mut head)?;
+    Ok(is_binary(&decode_text(&head).0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_large_matches_line_by_line() {
+        let text = "fn a()\r\nlet x = a;\n\nfn b() {}\nx\ny\nz\nfn c\nend fn";
+        for (pattern, context, max_count) in [
+            ("^fn", 0, usize::MAX),
+            ("^fn", 1, usize::MAX),
+            (r"\)$", 2, usize::MAX),
+            ("fn", 1, 2),
+            ("nothing", 1, usize::MAX),
+        ] {
+            let regex = Regex::new(pattern).unwrap();
+            let finder = RegexBuilder::new(pattern)
+                .multi_line(true)
+                .crlf(true)
+                .build()
+                .unwrap();
+            let bytes = bytes::Regex::new(pattern).unwrap();
+            let matcher = Matcher {
+                regex: &regex,
+                finder: &finder,
+                bytes: &bytes,
+                replacement: None,
+                multiline: false,
+                invert: false,
+                max_count,
+            };
+            let expected = search_file(text.as_bytes(), &matcher, true, true, context).unwrap();
+            let large = search_large(text, &matcher, true, true, context);
+            assert_eq!(large.lines, expected.lines, "{}", pattern);
+            assert_eq!(large.count, expected.count);
+            assert_eq!(large.hits, expected.hits);
+        }
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let text = "a\nold 1\nb\nc\nd\ne\nf\ng\nh\nold 2\n";
+        let regex = Regex::new(r"old (\d)").unwrap();
+        let diff = unified_diff("f.txt", text, &regex, "new $1", 1);
+        assert_eq!(
+            diff,
+            [
+                "--- a/f.txt",
+                "+++ b/f.txt",
+                "@@ -1,3 +1,3 @@",
+                " a",
+                "-old 1",
+                "+new 1",
+                " b",
+                "@@ -9,2 +9,2 @@",
+                " h",
+                "-old 2",
+                "+new 2",
+            ]
+        );
+        assert!(unified_diff("f.txt", "x\n", &regex, "y", 3).is_empty());
+
+        let diff = unified_diff("f.txt", "old 1\nold 2\nz\n", &regex, "n$1\nm", 3);
+        assert_eq!(diff[2], "@@ -1,3 +1,5 @@");
+        assert_eq!(diff[3..7], ["-old 1", "-old 2", "+n1", "+m"]);
+    }
+
+    #[test]
+    fn test_has_uppercase_literal() {
+        assert!(!has_uppercase_literal("todo"));
+        assert!(has_uppercase_literal("TODO"));
+        assert!(has_uppercase_literal(r"fn \w+Error"));
+        assert!(!has_uppercase_literal(r"\W\S\D\B\p{Lu}\PL x"));
+        assert!(has_uppercase_literal(r"\p{Greek}A"));
+    }
+
+    #[test]
+    fn test_word_pattern() {
+        let regex = Regex::new(&word_pattern("id|name")).unwrap();
+        assert!(regex.is_match("let id = 1;"));
+        assert!(regex.is_match("name"));
+        assert!(!regex.is_match("identifier void rename"));
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let utf16: Vec<u8> = "héllo\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let with_bom = [&[0xFF, 0xFE][..], &utf16].concat();
+        for data in [&with_bom, &utf16] {
+            let (text, encoding) = decode_text(data);
+            assert_eq!(&*text, "héllo\n".as_bytes());
+            assert_eq!(encoding, Some("UTF-16LE"));
+        }
+        let utf16be: Vec<u8> = [0xFE, 0xFF]
+            .into_iter()
+            .chain("ab".encode_utf16().flat_map(u16::to_be_bytes))
+            .collect();
+        assert_eq!(&*decode_text(&utf16be).0, b"ab");
+
+        assert_eq!(
+            decode_text(b"\xEF\xBB\xBFplain"),
+            (Cow::Borrowed(&b"plain"[..]), None)
+        );
+        let (text, encoding) = decode_text(b"caf\xE9");
+        assert_eq!(&*text, "café".as_bytes());
+        assert_eq!(encoding, Some("Latin-1"));
+        assert!(decode_text(b"bin\0\xFF\xFE").1.is_none());
+    }
+
+    #[test]
+    fn test_multiline_matches() {
+        let regex = RegexBuilder::new(r"fn \w+\(\)\s*\{")
+            .multi_line(true)
+            .build()
+            .unwrap();
+        let mut spans = HashMap::new();
+        let (lines, found) =
+            multiline_matches("x\nfn main()\r\n{\n}\n", &regex, usize::MAX, &mut spans);
+        assert_eq!(lines, ["x", "fn main()", "{", "}"]);
+        assert_eq!(found, [(2, "fn main()\r\n{".to_string())]);
+        assert_eq!(spans[&1], vec![Range { start: 0, end: 9 }]);
+        assert_eq!(spans[&2], vec![Range { start: 0, end: 1 }]);
+        assert!(!spans.contains_key(&3));
+
+        // Patterns matching the empty string find nothing in an empty file
+        for pattern in ["^", "x*"] {
+            let regex = RegexBuilder::new(pattern).multi_line(true).build().unwrap();
+            let mut spans = HashMap::new();
+            let (lines, found) = multiline_matches("", &regex, usize::MAX, &mut spans);
+            assert!(lines.is_empty() && found.is_empty() && spans.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_replace_in_file() {
+        let dir = std::env::temp_dir().join(format!("ftools-replace-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "let x = foo(1);\r\nfoo(2)\nbar\n").unwrap();
+
+        let regex = Regex::new(r"foo\((\d)\)").unwrap();
+        replace_in_file(&file, &regex, "baz($1, 0)", true).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "let x = baz(1, 0);\r\nbaz(2, 0)\nbar\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("a.txt.bak")).unwrap(),
+            "let x = foo(1);\r\nfoo(2)\nbar\n"
+        );
+
+        // An earlier backup is kept
+        let regex = Regex::new("baz").unwrap();
+        replace_in_file(&file, &regex, "qux", true).unwrap();
+        assert!(fs::read_to_string(dir.join("a.txt.bak")).unwrap().contains("foo"));
+        assert!(fs::read_to_string(dir.join("a.txt.bak.1")).unwrap().contains("baz"));
+
+        // A file already using the temporary name is left alone
+        let taken = dir.join(".b.txt.ftools-tmp0");
+        fs::write(&taken, "mine").unwrap();
+        fs::write(dir.join("b.txt"), "qux\n").unwrap();
+        replace_in_file(&dir.join("b.txt"), &Regex::new("qux").unwrap(), "quux", false).unwrap();
+        assert_eq!(fs::read_to_string(&taken).unwrap(), "mine");
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "quux\n");
+
+        // Rewriting a hardlink or symlink would split it from its file
+        fs::hard_link(&file, dir.join("link.txt")).unwrap();
+        let regex = Regex::new("qux").unwrap();
+        assert!(replace_in_file(&file, &regex, "quux", false).is_err());
+        #[cfg(unix)]
+        {
+            fs::remove_file(dir.join("link.txt")).unwrap();
+            std::os::unix::fs::symlink(&file, dir.join("sym.txt")).unwrap();
+            assert!(replace_in_file(&dir.join("sym.txt"), &regex, "quux", false).is_err());
+        }
+        assert!(fs::read_to_string(&file).unwrap().contains("qux"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_events_non_utf8() {
+        let regex = Regex::new("caf").unwrap();
+        let finder = Regex::new("(?m)caf").unwrap();
+        let bytes = bytes::Regex::new("caf").unwrap();
+        let matcher = Matcher {
+            regex: &regex,
+            finder: &finder,
+            bytes: &bytes,
+            replacement: None,
+            multiline: false,
+            invert: false,
+            max_count: usize::MAX,
+        };
+        // Latin-1 "é" before the match, so decoding it would shift the offsets
+        let data = b"ok\n\xE9 caf\xE9\n";
+        let mut out = String::new();
+        let (selected, _) =
+            write_json_events(Path::new("menu.txt"), data, &matcher, 0, &mut out).unwrap();
+        assert_eq!(selected, 1);
+
+        let messages: Vec<serde_json::Value> =
+            out.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        let found = &messages[1]["data"];
+        assert_eq!(found["lines"], json!({ "bytes": STANDARD.encode(b"\xE9 caf\xE9\n") }));
+        assert_eq!(found["absolute_offset"], 3);
+        let submatch = &found["submatches"][0];
+        assert_eq!(submatch["match"], json!({ "text": "caf" }));
+        assert_eq!(submatch["start"], 2);
+        assert_eq!(submatch["end"], 5);
+        assert_eq!(&data[3 + 2..3 + 5], b"caf");
     }
-    false
 }
@@ -1,82 +1,156 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use colored::*;
-use std::collections::HashMap;
-use walkdir::WalkDir;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
 
-use crate::ui;
-use crate::utils::{format_bytes, get_extension, should_skip};
+use crate::output::{self, OutputArgs, Report};
+use crate::ui::{self, Element, Themed};
+use crate::utils::{format_bytes, get_extension, should_skip, walk_roots};
 
-pub fn run(path: &str, hidden: bool) -> Result<()> {
-    ui::print_start("Analyzing directory stats", path);
-    println!();
+/// Upper bounds (exclusive) of the size distribution buckets
+const SIZE_BUCKETS: [(&str, u64); 5] = [
+    ("< 1 KiB", 1024),
+    ("1 KiB - 1 MiB", 1024 * 1024),
+    ("1 MiB - 100 MiB", 100 * 1024 * 1024),
+    ("100 MiB - 1 GiB", 1024 * 1024 * 1024),
+    (">= 1 GiB", u64::MAX),
+];
 
-    let mut total_files = 0u64;
-    let mut total_dirs = 0u64;
-    let mut total_size = 0u64;
-    let mut max_size = 0u64;
-    let mut max_file = String::new();
-    let mut extension_count: HashMap<String, usize> = HashMap::new();
-    let mut extension_size: HashMap<String, u64> = HashMap::new();
+/// Aggregated statistics for one directory tree
+#[derive(Default)]
+struct DirStats {
+    total_files: u64,
+    total_dirs: u64,
+    total_size: u64,
+    max_size: u64,
+    max_file: String,
+    extension_count: HashMap<String, u64>,
+    extension_size: HashMap<String, u64>,
+    size_buckets: [u64; SIZE_BUCKETS.len()],
+}
 
-    for entry in WalkDir::new(path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let entry_path = entry.path();
+impl DirStats {
+    fn collect(paths: &[String], hidden: bool, one_file_system: bool) -> Self {
+        let mut stats = DirStats::default();
 
-        if !hidden && should_skip(entry_path, false) {
-            continue;
-        }
+        let spinner = ui::scan_spinner(&format!("Scanning {}", paths.join(" ")));
+        for entry in walk_roots(paths, one_file_system) {
+            let entry_path = entry.path();
+            spinner.inc(1);
+
+            if !hidden && should_skip(entry_path, false) {
+                continue;
+            }
 
-        if entry_path.is_dir() {
-            total_dirs += 1;
-        } else if entry_path.is_file() {
-            total_files += 1;
+            if entry.file_type().is_dir() {
+                stats.total_dirs += 1;
+            } else if entry.file_type().is_file() {
+                stats.total_files += 1;
 
-            if let Ok(metadata) = entry_path.metadata() {
-                let size = metadata.len();
-                total_size += size;
+                if let Ok(metadata) = entry.metadata() {
+                    let size = metadata.len();
+                    stats.total_size += size;
 
-                if size > max_size {
-                    max_size = size;
-                    max_file = entry_path.display().to_string();
-                }
+                    if size > stats.max_size {
+                        stats.max_size = size;
+                        stats.max_file = entry_path.display().to_string();
+                    }
+
+                    let ext = get_extension(entry_path);
+                    *stats.extension_count.entry(ext.clone()).or_insert(0) += 1;
+                    *stats.extension_size.entry(ext).or_insert(0) += size;
 
-                let ext = get_extension(entry_path);
-                *extension_count.entry(ext.clone()).or_insert(0) += 1;
-                *extension_size.entry(ext).or_insert(0) += size;
+                    let bucket = SIZE_BUCKETS
+                        .iter()
+                        .position(|(_, limit)| size < *limit)
+                        .unwrap_or(SIZE_BUCKETS.len() - 1);
+                    stats.size_buckets[bucket] += 1;
+                }
             }
         }
+
+        spinner.finish_and_clear();
+        stats
     }
 
-    let avg_size = if total_files > 0 {
-        total_size / total_files
-    } else {
-        0
-    };
+    fn avg_size(&self) -> u64 {
+        self.total_size.checked_div(self.total_files).unwrap_or(0)
+    }
+}
+
+pub fn run(
+    paths: &[String],
+    hidden: bool,
+    compare: bool,
+    one_file_system: bool,
+    output: &OutputArgs,
+) -> Result<()> {
+    if compare {
+        let [a, b] = paths else {
+            return Err(anyhow!("--compare needs exactly two paths"));
+        };
+        return run_compare(a, b, hidden, one_file_system, output);
+    }
+
+    ui::print_start("Analyzing directory stats", &paths.join(" "));
+    ui::print_blank();
 
-    let mut ext_by_count: Vec<_> = extension_count.iter().collect();
+    let stats = DirStats::collect(paths, hidden, one_file_system);
+    let total_files = stats.total_files;
+    let total_size = stats.total_size;
+
+    let mut ext_by_count: Vec<_> = stats.extension_count.iter().collect();
     ext_by_count.sort_by(|a, b| b.1.cmp(a.1));
 
-    let mut ext_by_size: Vec<_> = extension_size.iter().collect();
+    let mut ext_by_size: Vec<_> = stats.extension_size.iter().collect();
     ext_by_size.sort_by(|a, b| b.1.cmp(a.1));
 
+    let mut report = Report::new(&["section", "key", "value"]);
+    for (key, value) in [
+        ("total_files", total_files),
+        ("total_dirs", stats.total_dirs),
+        ("total_size", total_size),
+        ("avg_size", stats.avg_size()),
+        ("max_size", stats.max_size),
+    ] {
+        report.row(vec![json!("overview"), json!(key), json!(value)]);
+    }
+    for (ext, count) in &ext_by_count {
+        report.row(vec![json!("ext_count"), json!(ext), json!(count)]);
+    }
+    for (ext, size) in &ext_by_size {
+        report.row(vec![json!("ext_size"), json!(ext), json!(size)]);
+    }
+    for (i, (label, _)) in SIZE_BUCKETS.iter().enumerate() {
+        report.row(vec![
+            json!("size_bucket"),
+            json!(label),
+            json!(stats.size_buckets[i]),
+        ]);
+    }
+    output::write_report(output, &report)?;
+
     // Print statistics
     ui::print_header("DIRECTORY STATISTICS");
-    println!();
+    ui::print_blank();
 
     ui::print_section("Overview");
     ui::print_kv("Total files", &total_files.to_string());
-    ui::print_kv("Total directories", &total_dirs.to_string());
-    ui::print_kv_colored("Total size", format_bytes(total_size).green().bold());
-    ui::print_kv("Average file size", &format_bytes(avg_size));
+    ui::print_kv("Total directories", &stats.total_dirs.to_string());
+    ui::print_kv_colored(
+        "Total size",
+        format_bytes(total_size).themed(Element::Size).bold(),
+    );
+    ui::print_kv("Average file size", &format_bytes(stats.avg_size()));
 
-    if !max_file.is_empty() {
+    if !stats.max_file.is_empty() {
         println!();
         ui::print_section("Largest File");
-        ui::print_kv_colored("Size", format_bytes(max_size).red().bold());
-        ui::print_kv("Path", &max_file);
+        ui::print_kv_colored(
+            "Size",
+            format_bytes(stats.max_size).themed(Element::Size).bold(),
+        );
+        ui::print_kv("Path", &stats.max_file);
     }
 
     println!();
@@ -90,7 +164,7 @@ pub fn run(path: &str, hidden: bool) -> Result<()> {
         let ext_display = if *ext == "(no ext)" {
             ext.dimmed().to_string()
         } else {
-            format!(".{}", ext).cyan().to_string()
+            format!(".{}", ext).themed(Element::Info).to_string()
         };
 
         println!(
@@ -113,7 +187,7 @@ pub fn run(path: &str, hidden: bool) -> Result<()> {
         let ext_display = if *ext == "(no ext)" {
             ext.dimmed().to_string()
         } else {
-            format!(".{}", ext).cyan().to_string()
+            format!(".{}", ext).themed(Element::Info).to_string()
         };
 
         println!(
@@ -130,3 +204,190 @@ pub fn run(path: &str, hidden: bool) -> Result<()> {
 
     Ok(())
 }
+
+/// Show the statistics of two trees in adjacent columns with the change from A to B
+fn run_compare(
+    a: &str,
+    b: &str,
+    hidden: bool,
+    one_file_system: bool,
+    output: &OutputArgs,
+) -> Result<()> {
+    ui::print_start("Comparing directory stats", "");
+    if !ui::is_quiet() {
+        println!(
+            "  {} {}",
+            "A:".themed(Element::Section),
+            a.themed(Element::Path)
+        );
+        println!(
+            "  {} {}",
+            "B:".themed(Element::Section),
+            b.themed(Element::Path)
+        );
+        println!();
+    }
+
+    let sa = DirStats::collect(&[a.to_string()], hidden, one_file_system);
+    let sb = DirStats::collect(&[b.to_string()], hidden, one_file_system);
+
+    let mut report = Report::new(&["section", "key", "a", "b"]);
+    for (key, value_a, value_b) in [
+        ("total_files", sa.total_files, sb.total_files),
+        ("total_dirs", sa.total_dirs, sb.total_dirs),
+        ("total_size", sa.total_size, sb.total_size),
+        ("avg_size", sa.avg_size(), sb.avg_size()),
+        ("max_size", sa.max_size, sb.max_size),
+    ] {
+        report.row(vec![
+            json!("overview"),
+            json!(key),
+            json!(value_a),
+            json!(value_b),
+        ]);
+    }
+    for (section, map_a, map_b) in [
+        ("ext_count", &sa.extension_count, &sb.extension_count),
+        ("ext_size", &sa.extension_size, &sb.extension_size),
+    ] {
+        for ext in top_extensions(map_a, map_b) {
+            let value_a = map_a.get(&ext).copied().unwrap_or(0);
+            let value_b = map_b.get(&ext).copied().unwrap_or(0);
+            report.row(vec![
+                json!(section),
+                json!(ext),
+                json!(value_a),
+                json!(value_b),
+            ]);
+        }
+    }
+    for (i, (label, _)) in SIZE_BUCKETS.iter().enumerate() {
+        report.row(vec![
+            json!("size_bucket"),
+            json!(label),
+            json!(sa.size_buckets[i]),
+            json!(sb.size_buckets[i]),
+        ]);
+    }
+    output::write_report(output, &report)?;
+
+    ui::print_header("STATS COMPARISON");
+    ui::print_blank();
+
+    ui::print_section("Overview");
+    print_compare_header();
+    print_count_row("Total files", sa.total_files, sb.total_files);
+    print_count_row("Total directories", sa.total_dirs, sb.total_dirs);
+    print_size_row("Total size", sa.total_size, sb.total_size);
+    print_size_row("Average file size", sa.avg_size(), sb.avg_size());
+    print_size_row("Largest file", sa.max_size, sb.max_size);
+
+    println!();
+    ui::print_section("Top Extensions by Count");
+    print_compare_header();
+    for ext in top_extensions(&sa.extension_count, &sb.extension_count) {
+        let count_a = sa.extension_count.get(&ext).copied().unwrap_or(0);
+        let count_b = sb.extension_count.get(&ext).copied().unwrap_or(0);
+        print_count_row(&ext_label(&ext), count_a, count_b);
+    }
+
+    println!();
+    ui::print_section("Top Extensions by Size");
+    print_compare_header();
+    for ext in top_extensions(&sa.extension_size, &sb.extension_size) {
+        let size_a = sa.extension_size.get(&ext).copied().unwrap_or(0);
+        let size_b = sb.extension_size.get(&ext).copied().unwrap_or(0);
+        print_size_row(&ext_label(&ext), size_a, size_b);
+    }
+
+    println!();
+    ui::print_section("Size Distribution");
+    print_compare_header();
+    for (i, (label, _)) in SIZE_BUCKETS.iter().enumerate() {
+        print_count_row(label, sa.size_buckets[i], sb.size_buckets[i]);
+    }
+
+    println!();
+    ui::print_line(64);
+
+    Ok(())
+}
+
+/// The ten extensions with the largest combined value across both trees
+fn top_extensions(a: &HashMap<String, u64>, b: &HashMap<String, u64>) -> Vec<String> {
+    let names: HashSet<&String> = a.keys().chain(b.keys()).collect();
+    let mut combined: Vec<(&String, u64)> = names
+        .into_iter()
+        .map(|ext| {
+            let combined = a.get(ext).unwrap_or(&0) + b.get(ext).unwrap_or(&0);
+            (ext, combined)
+        })
+        .collect();
+    combined.sort_by(|x, y| y.1.cmp(&x.1).then_with(|| x.0.cmp(y.0)));
+    combined
+        .into_iter()
+        .take(10)
+        .map(|(ext, _)| ext.clone())
+        .collect()
+}
+
+fn ext_label(ext: &str) -> String {
+    if ext == "(no ext)" {
+        ext.to_string()
+    } else {
+        format!(".{}", ext)
+    }
+}
+
+fn print_compare_header() {
+    println!(
+        "  {:<22} {:>12} {:>12} {:>12}",
+        "",
+        "A".themed(Element::Header).bold(),
+        "B".themed(Element::Header).bold(),
+        "Δ".themed(Element::Muted)
+    );
+}
+
+fn print_count_row(label: &str, a: u64, b: u64) {
+    let delta = b as i128 - a as i128;
+    let delta_str = match delta {
+        0 => "=".to_string(),
+        d if d > 0 => format!("+{}", d),
+        d => d.to_string(),
+    };
+    print_row(label, &a.to_string(), &b.to_string(), &delta_str, delta);
+}
+
+fn print_size_row(label: &str, a: u64, b: u64) {
+    let delta_str = if b > a {
+        format!("+{}", format_bytes(b - a))
+    } else if a > b {
+        format!("-{}", format_bytes(a - b))
+    } else {
+        "=".to_string()
+    };
+    print_row(
+        label,
+        &format_bytes(a),
+        &format_bytes(b),
+        &delta_str,
+        b as i128 - a as i128,
+    );
+}
+
+fn print_row(label: &str, a: &str, b: &str, delta_str: &str, delta: i128) {
+    let delta_str = format!("{:>12}", delta_str);
+    let delta_display = match delta.signum() {
+        1 => delta_str.themed(Element::Success),
+        -1 => delta_str.themed(Element::Error),
+        _ => delta_str.dimmed(),
+    };
+    println!(
+        "  {:<22} {:>12} {:>12} {}",
+        label.themed(Element::Muted),
+        a.themed(Element::Text),
+        b.themed(Element::Text),
+        delta_display
+    );
+}
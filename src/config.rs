@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// User settings read from `config.toml`; every section is optional
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub theme: ThemeConfig,
+    pub dupes: DupesConfig,
+    pub search: SearchConfig,
+    pub ignore: IgnoreConfig,
+}
+
+/// `[theme]` table: a theme name, user palettes and per-element overrides
+///
+/// ```toml
+/// [theme]
+/// name = "solarized"            # built-in (default, light, monochrome) or a palette below
+///
+/// [theme.palettes.solarized]
+/// base = "light"                # built-in theme the palette starts from
+/// path = "#268bd2"
+///
+/// [theme.colors]                # applied last, on top of the chosen theme
+/// size = "magenta"
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub name: Option<String>,
+    pub palettes: BTreeMap<String, BTreeMap<String, String>>,
+    pub colors: BTreeMap<String, String>,
+}
+
+/// `[dupes]` table: files `dupes` must always keep
+///
+/// ```toml
+/// [dupes]
+/// protect = ["~/Photos/Originals/*", "*.cr2"]
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct DupesConfig {
+    pub protect: Vec<String>,
+}
+
+/// `[search]` table: defaults for `search`
+///
+/// ```toml
+/// [search]
+/// smart_case = true             # as if --smart-case were always given
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    pub smart_case: bool,
+}
+
+/// `[ignore]` table: directory names scans leave out
+///
+/// ```toml
+/// [ignore]
+/// add = ["Pods", ".gradle"]     # on top of the built-in list
+/// keep = ["build", "dist"]      # scan these after all
+/// # dirs = ["node_modules"]     # replace the built-in list entirely
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct IgnoreConfig {
+    pub dirs: Option<Vec<String>>,
+    pub add: Vec<String>,
+    pub keep: Vec<String>,
+}
+
+impl Config {
+    /// Location of the config file (`$FTOOLS_CONFIG`, else `config.toml` in
+    /// `$XDG_CONFIG_HOME/ftools`, `%APPDATA%\ftools` or `~/.config/ftools`)
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("FTOOLS_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+        Some(base.join("ftools").join("config.toml"))
+    }
+
+    /// Load the config file; a missing file is the same as an empty one
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::default_path() else {
+            return Ok(Self::default());
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(anyhow!("{}: {}", path.display(), e)),
+        };
+        toml::from_str(&content).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+// Process exit codes shared by every command:
+// 0 success, 1 negative answer, 2 error, 3 partial failure
+
+/// The command ran fine but the answer is "no": nothing matched,
+/// directories differ, a hash did not verify
+pub const NEGATIVE: i32 = 1;
+
+/// Bad arguments or an I/O error stopped the command (clap also uses 2)
+pub const ERROR: i32 = 2;
+
+/// The command finished but some items could not be processed
+pub const PARTIAL: i32 = 3;
+
+/// Error value that ends the process with a specific exit code
+#[derive(Debug)]
+pub struct Exit {
+    pub code: i32,
+    pub message: Option<String>,
+}
+
+impl Exit {
+    /// Exit with `code` without printing anything further
+    pub fn silent(code: i32) -> Self {
+        Exit {
+            code,
+            message: None,
+        }
+    }
+
+    /// Exit with `code` after printing `message` as an error
+    pub fn with_message(code: i32, message: impl Into<String>) -> Self {
+        Exit {
+            code,
+            message: Some(message.into()),
+        }
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}", message),
+            None => write!(f, "exit code {}", self.code),
+        }
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// Result for a run where `failed` items could not be processed
+pub fn partial(failed: usize, what: &str) -> anyhow::Result<()> {
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(Exit::with_message(PARTIAL, format!("{} {} failed", failed, what)).into())
+    }
+}
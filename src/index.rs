@@ -0,0 +1,251 @@
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::skipped;
+use crate::ui;
+use crate::utils::hash_file_with;
+
+/// Files hashed in parallel between two `update_batched` callbacks
+const UPDATE_BATCH: usize = 64;
+
+/// What the index remembers about one file
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub size: u64,
+    /// Modification time in nanoseconds since the epoch
+    pub mtime_ns: u64,
+    /// Inode number (0 where the platform has none), so a file replaced by
+    /// another with the same size and mtime is still re-hashed
+    #[serde(default)]
+    pub inode: u64,
+    /// Content digests by algorithm name ("sha256", "blake3", "xxh3")
+    #[serde(default)]
+    pub digests: BTreeMap<String, String>,
+    /// Modification time at which the current content was first seen
+    pub content_mtime_ns: u64,
+}
+
+impl IndexEntry {
+    pub fn digest(&self, algorithm: &str) -> Option<&str> {
+        self.digests.get(algorithm).map(String::as_str)
+    }
+}
+
+/// Current state of a file, with a fresh hash only if the indexed one was stale
+struct Observation {
+    key: String,
+    size: u64,
+    mtime_ns: u64,
+    inode: u64,
+    /// Whether the file's metadata still matches its entry
+    unchanged: bool,
+    new_hash: Option<String>,
+}
+
+/// Persistent content hash index keyed by absolute path, stored as JSON in the user cache directory
+#[derive(Default, Serialize, Deserialize)]
+pub struct HashIndex {
+    entries: HashMap<String, IndexEntry>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl HashIndex {
+    /// Location of the index file (`$XDG_CACHE_HOME/ftools`, `~/.cache/ftools` or `%LOCALAPPDATA%\ftools`)
+    pub fn default_path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+        Some(base.join("ftools").join("index.json"))
+    }
+
+    /// Load the index, starting empty if it doesn't exist or can't be read
+    pub fn load() -> Self {
+        let Some(path) = Self::default_path() else {
+            return Self::default();
+        };
+        match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                ui::print_verbose(&format!(
+                    "ignoring corrupt index {} ({})",
+                    path.display(),
+                    e
+                ));
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Write the index back if anything changed
+    pub fn save(&self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let Some(path) = Self::default_path() else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Whether `file` has an up-to-date `algorithm` digest, so `update` won't read it
+    pub fn is_fresh(&self, file: &Path, algorithm: &str) -> bool {
+        let Ok(metadata) = file.metadata() else {
+            return false;
+        };
+        let Ok(key) = fs::canonicalize(file) else {
+            return false;
+        };
+        self.entries
+            .get(&key.display().to_string())
+            .is_some_and(|e| matches(e, &metadata) && e.digest(algorithm).is_some())
+    }
+
+    /// Bring the `algorithm` digests for `files` up to date and return the
+    /// entries in order. Only files whose size, mtime or inode changed since
+    /// the last run (or that lack this digest) are re-hashed.
+    pub fn update(&mut self, files: &[PathBuf], algorithm: &str) -> Vec<Option<IndexEntry>> {
+        self.update_batched(files, algorithm, |_, _, _| {})
+    }
+
+    /// `update` in batches, handing each batch of files and their entries to
+    /// `after_batch` as soon as it is hashed (e.g. to save progress)
+    pub fn update_batched(
+        &mut self,
+        files: &[PathBuf],
+        algorithm: &str,
+        mut after_batch: impl FnMut(&Self, &[PathBuf], &[Option<IndexEntry>]),
+    ) -> Vec<Option<IndexEntry>> {
+        let pb = ui::work_progress(files.len() as u64);
+
+        let mut entries = Vec::with_capacity(files.len());
+        for batch in files.chunks(UPDATE_BATCH) {
+            let observed: Vec<Option<Observation>> = batch
+                .par_iter()
+                .map(|file| {
+                    let result = self.observe(file, algorithm);
+                    pb.inc(1);
+                    result
+                })
+                .collect();
+            let batch_entries: Vec<Option<IndexEntry>> = observed
+                .into_iter()
+                .map(|obs| self.apply(obs?, algorithm))
+                .collect();
+            after_batch(self, batch, &batch_entries);
+            entries.extend(batch_entries);
+        }
+        pb.finish_and_clear();
+        entries
+    }
+
+    /// Store a fresh hash from `observe`, or look up the unchanged entry
+    fn apply(&mut self, observation: Observation, algorithm: &str) -> Option<IndexEntry> {
+        let Observation {
+            key,
+            size,
+            mtime_ns,
+            inode,
+            unchanged,
+            new_hash,
+        } = observation;
+        let Some(digest) = new_hash else {
+            return self.entries.get(&key).cloned();
+        };
+
+        let old = self.entries.get(&key);
+        // Same content re-touched keeps its original content time
+        let content_mtime_ns = match old {
+            Some(old) if unchanged || old.digest(algorithm) == Some(&digest) => {
+                old.content_mtime_ns
+            }
+            _ => mtime_ns,
+        };
+        // Digests of other algorithms stay valid only if the file is untouched
+        let mut digests = match old {
+            Some(old) if unchanged => old.digests.clone(),
+            _ => BTreeMap::new(),
+        };
+        digests.insert(algorithm.to_string(), digest);
+
+        let entry = IndexEntry {
+            size,
+            mtime_ns,
+            inode,
+            digests,
+            content_mtime_ns,
+        };
+        self.entries.insert(key, entry.clone());
+        self.dirty = true;
+        Some(entry)
+    }
+
+    fn observe(&self, file: &Path, algorithm: &str) -> Option<Observation> {
+        let metadata = file.metadata().ok()?;
+        let key = fs::canonicalize(file).ok()?.display().to_string();
+
+        let entry = self.entries.get(&key);
+        let unchanged = entry.is_some_and(|e| matches(e, &metadata));
+        let fresh = unchanged && entry.is_some_and(|e| e.digest(algorithm).is_some());
+        let new_hash = if fresh {
+            None
+        } else {
+            match hash_file_with(file, algorithm) {
+                Ok(hash) => Some(hash),
+                Err(e) => {
+                    skipped::record_error(file, &e);
+                    return None;
+                }
+            }
+        };
+
+        Some(Observation {
+            key,
+            size: metadata.len(),
+            mtime_ns: mtime_ns(&metadata),
+            inode: inode(&metadata),
+            unchanged,
+            new_hash,
+        })
+    }
+}
+
+/// Whether an entry still describes the file with this metadata
+fn matches(entry: &IndexEntry, metadata: &Metadata) -> bool {
+    entry.size == metadata.len()
+        && entry.mtime_ns == mtime_ns(metadata)
+        && entry.inode == inode(metadata)
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+/// Modification time of a file in nanoseconds since the epoch (0 if unavailable)
+pub fn mtime_ns(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,277 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes read from the start of a file to recognise it
+const HEADER_LEN: u64 = 32;
+
+/// A format recognised by its leading bytes
+pub struct FileType {
+    /// Extension given to files of this type that have a wrong one
+    pub extension: &'static str,
+    /// Other extensions that are right for it, e.g. docx for a zip
+    pub aliases: &'static [&'static str],
+}
+
+impl FileType {
+    /// Whether `extension` (any case) is right for this type
+    pub fn matches(&self, extension: &str) -> bool {
+        let extension = extension.to_lowercase();
+        self.extension == extension || self.aliases.contains(&extension.as_str())
+    }
+}
+
+const fn file_type(extension: &'static str, aliases: &'static [&'static str]) -> FileType {
+    FileType { extension, aliases }
+}
+
+const PNG: FileType = file_type("png", &["apng"]);
+const JPEG: FileType = file_type("jpg", &["jpeg", "jpe", "jfif"]);
+const GIF: FileType = file_type("gif", &[]);
+const WEBP: FileType = file_type("webp", &[]);
+const TIFF: FileType = file_type(
+    "tif",
+    // Camera raw formats are TIFF inside
+    &[
+        "tiff", "dng", "cr2", "nef", "nrw", "arw", "srw", "orf", "pef", "erf",
+    ],
+);
+const PSD: FileType = file_type("psd", &["psb"]);
+const HEIC: FileType = file_type("heic", &["heif", "hif"]);
+const AVIF: FileType = file_type("avif", &[]);
+const PDF: FileType = file_type("pdf", &["ai"]);
+const ZIP: FileType = file_type(
+    "zip",
+    // Formats that are zip archives underneath
+    &[
+        "docx", "xlsx", "pptx", "docm", "xlsm", "pptm", "odt", "ods", "odp", "odg", "epub", "jar",
+        "war", "ear", "apk", "aab", "ipa", "xpi", "crx", "whl", "nupkg", "vsix", "kmz", "3mf",
+        "cbz", "sketch", "xps",
+    ],
+);
+const GZIP: FileType = file_type("gz", &["tgz", "svgz"]);
+const BZIP2: FileType = file_type("bz2", &["tbz", "tbz2"]);
+const XZ: FileType = file_type("xz", &["txz"]);
+const ZSTD: FileType = file_type("zst", &["tzst"]);
+const SEVEN_ZIP: FileType = file_type("7z", &[]);
+const RAR: FileType = file_type("rar", &["cbr"]);
+const MP4: FileType = file_type("mp4", &["m4v", "m4a", "m4b", "m4p", "mov", "3gp", "3g2"]);
+const MOV: FileType = file_type("mov", &["qt", "mp4"]);
+const MATROSKA: FileType = file_type("mkv", &["webm", "mka", "mks", "mk3d"]);
+const AVI: FileType = file_type("avi", &[]);
+const WAV: FileType = file_type("wav", &["wave"]);
+const MP3: FileType = file_type("mp3", &[]);
+const FLAC: FileType = file_type("flac", &[]);
+const OGG: FileType = file_type("ogg", &["oga", "ogv", "ogx", "opus", "spx"]);
+const MIDI: FileType = file_type("mid", &["midi"]);
+const SQLITE: FileType = file_type("sqlite", &["sqlite3", "db", "db3"]);
+const WOFF: FileType = file_type("woff", &[]);
+const WOFF2: FileType = file_type("woff2", &[]);
+const WASM: FileType = file_type("wasm", &[]);
+
+/// Every format `detect` recognises
+const KNOWN: [&FileType; 29] = [
+    &PNG, &JPEG, &GIF, &WEBP, &TIFF, &PSD, &HEIC, &AVIF, &PDF, &ZIP, &GZIP, &BZIP2, &XZ, &ZSTD,
+    &SEVEN_ZIP, &RAR, &MP4, &MOV, &MATROSKA, &AVI, &WAV, &MP3, &FLAC, &OGG, &MIDI, &SQLITE, &WOFF,
+    &WOFF2, &WASM,
+];
+
+/// Whether `extension` (any case) belongs to one of the known formats.
+/// Others may be formats built on a known one, like .cr3 on MP4.
+pub fn is_known_extension(extension: &str) -> bool {
+    KNOWN.iter().any(|file_type| file_type.matches(extension))
+}
+
+/// Type of the content `header` starts, if it's one of the known formats
+pub fn detect(header: &[u8]) -> Option<&'static FileType> {
+    let starts = |magic: &[u8]| header.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+    let found = if starts(b"\x89PNG\r\n\x1a\n") {
+        &PNG
+    } else if starts(b"\xff\xd8\xff") {
+        &JPEG
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        &GIF
+    } else if starts(b"RIFF") && at(8, b"WEBP") {
+        &WEBP
+    } else if starts(b"RIFF") && at(8, b"WAVE") {
+        &WAV
+    } else if starts(b"RIFF") && at(8, b"AVI ") {
+        &AVI
+    } else if starts(b"II*\0") || starts(b"MM\0*") {
+        &TIFF
+    } else if starts(b"8BPS") {
+        &PSD
+    } else if starts(b"%PDF-") {
+        &PDF
+    } else if starts(b"PK\x03\x04") || starts(b"PK\x05\x06") {
+        &ZIP
+    } else if starts(b"\x1f\x8b") {
+        &GZIP
+    } else if starts(b"BZh") {
+        &BZIP2
+    } else if starts(b"\xfd7zXZ\0") {
+        &XZ
+    } else if starts(b"\x28\xb5\x2f\xfd") {
+        &ZSTD
+    } else if starts(b"7z\xbc\xaf\x27\x1c") {
+        &SEVEN_ZIP
+    } else if starts(b"Rar!\x1a\x07") {
+        &RAR
+    } else if at(4, b"ftyp") {
+        // ISO media files name their flavour right after "ftyp"
+        match header.get(8..12) {
+            Some(b"qt  ") => &MOV,
+            Some(b"heic" | b"heix" | b"heim" | b"heis" | b"mif1" | b"msf1") => &HEIC,
+            Some(b"avif" | b"avis") => &AVIF,
+            _ => &MP4,
+        }
+    } else if starts(b"\x1a\x45\xdf\xa3") {
+        &MATROSKA
+    } else if starts(b"ID3") || starts(b"\xff\xfb") {
+        &MP3
+    } else if starts(b"fLaC") {
+        &FLAC
+    } else if starts(b"OggS") {
+        &OGG
+    } else if starts(b"MThd") {
+        &MIDI
+    } else if starts(b"SQLite format 3\0") {
+        &SQLITE
+    } else if starts(b"wOFF") {
+        &WOFF
+    } else if starts(b"wOF2") {
+        &WOFF2
+    } else if starts(b"\0asm") {
+        &WASM
+    } else {
+        return None;
+    };
+    Some(found)
+}
+
+/// Type of the file at `path`, judged by its first bytes
+pub fn detect_file(path: &Path) -> io::Result<Option<&'static FileType>> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    File::open(path)?
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)?;
+    Ok(detect(&header))
+}
+
+/// Width and height of the image at `path` from its header, for PNG, JPEG,
+/// GIF, BMP and WebP files
+pub fn image_size(path: &Path) -> io::Result<Option<(u32, u32)>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    file.by_ref().take(HEADER_LEN).read_to_end(&mut header)?;
+
+    let le16 = |at: usize| {
+        header
+            .get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as u32)
+    };
+    let be32 = |at: usize| {
+        header
+            .get(at..at + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let le24 = |at: usize| {
+        header
+            .get(at..at + 3)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], 0]))
+    };
+    let starts = |magic: &[u8]| header.starts_with(magic);
+
+    let size = if starts(b"\x89PNG\r\n\x1a\n") {
+        be32(16).zip(be32(20))
+    } else if starts(b"GIF8") {
+        le16(6).zip(le16(8))
+    } else if starts(b"BM") {
+        let dimension = |at: usize| {
+            header
+                .get(at..at + 4)
+                .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]).unsigned_abs())
+        };
+        dimension(18).zip(dimension(22))
+    } else if starts(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+        match header.get(12..16) {
+            Some(b"VP8 ") => le16(26)
+                .zip(le16(28))
+                .map(|(w, h)| (w & 0x3fff, h & 0x3fff)),
+            Some(b"VP8L") => header.get(21..25).map(|b| {
+                let (b0, b1, b2, b3) = (b[0] as u32, b[1] as u32, b[2] as u32, b[3] as u32);
+                (
+                    1 + (b0 | (b1 & 0x3f) << 8),
+                    1 + (b1 >> 6 | b2 << 2 | (b3 & 0x0f) << 10),
+                )
+            }),
+            Some(b"VP8X") => le24(24).zip(le24(27)).map(|(w, h)| (w + 1, h + 1)),
+            _ => None,
+        }
+    } else if starts(b"\xff\xd8") {
+        file.seek(SeekFrom::Start(2))?;
+        return jpeg_size(&mut file);
+    } else {
+        None
+    };
+    Ok(size)
+}
+
+/// Walk the JPEG segments after the start marker to the frame header
+fn jpeg_size(file: &mut impl Read) -> io::Result<Option<(u32, u32)>> {
+    let mut byte = [0u8; 1];
+    loop {
+        // Markers are 0xFF followed by a code; extra 0xFF bytes are padding
+        file.read_exact(&mut byte)?;
+        if byte[0] != 0xff {
+            return Ok(None);
+        }
+        let mut marker = 0xff;
+        while marker == 0xff {
+            file.read_exact(&mut byte)?;
+            marker = byte[0];
+        }
+        // Markers without a length
+        if matches!(marker, 0x01 | 0xd0..=0xd9) {
+            continue;
+        }
+        let mut length = [0u8; 2];
+        file.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length) as u64;
+        if length < 2 {
+            return Ok(None);
+        }
+        // Start-of-frame markers, leaving out DHT, JPG and DAC
+        if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            let mut frame = [0u8; 5];
+            file.read_exact(&mut frame)?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]) as u32;
+            let width = u16::from_be_bytes([frame[3], frame[4]]) as u32;
+            return Ok(Some((width, height)));
+        }
+        io::copy(&mut file.by_ref().take(length - 2), &mut io::sink())?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let png = detect(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        assert_eq!(png.extension, "png");
+        assert!(detect(b"PK\x03\x04\x14\0").unwrap().matches("DOCX"));
+        assert_eq!(
+            detect(b"\0\0\0\x18ftypqt  \0\0\x02\0").unwrap().extension,
+            "mov"
+        );
+        assert!(!detect(b"%PDF-1.7").unwrap().matches("jpg"));
+        assert!(detect(b"plain text").is_none());
+        assert!(detect(b"").is_none());
+        assert!(is_known_extension("JPEG"));
+        assert!(!is_known_extension("cr3"));
+    }
+}
@@ -1,9 +1,29 @@
+mod archive;
+mod checkpoint;
 mod commands;
+mod config;
+mod exit;
+mod index;
+mod magic;
+mod minhash;
+mod output;
+mod plan;
+mod plugin;
+mod quarantine;
+mod skipped;
+mod spill;
+mod tui;
 mod ui;
 mod utils;
 
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, ArgGroup, Parser, Subcommand};
 use colored::Colorize;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use output::{DupesOutputArgs, OutputArgs};
+use ui::{Element, PathList, Themed, Verbosity};
 
 #[derive(Parser)]
 #[command(name = "ftools")]
@@ -11,35 +31,177 @@ use colored::Colorize;
 #[command(version = "1.0.0")]
 #[command(about = "A powerful CLI toolkit for file operations", long_about = None)]
 #[command(propagate_version = true)]
+#[command(
+    after_help = "Exit codes: 0 success, 1 negative result (no match, differences, hash mismatch), 2 error, 3 partial failure"
+)]
 struct Cli {
+    /// Print only results and errors (no banners, progress or status messages)
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Show diagnostics on stderr (-v: errors and timing, -vv: every skipped path)
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Print only the result paths, one per line, byte-for-byte as stored on disk
+    #[arg(long)]
+    raw_paths: bool,
+
+    /// Like --raw-paths but NUL-terminated (for `xargs -0`)
+    #[arg(short = '0', long, conflicts_with = "raw_paths")]
+    null: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+// Parsed once at startup, so the size of the largest variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Find duplicate files by content hash
     #[command(name = "dupes")]
+    #[command(group(ArgGroup::new("action").args(["delete", "hardlink", "symlink", "move_to"])))]
+    #[command(group(ArgGroup::new("changes").args(["delete", "hardlink", "symlink", "move_to", "prompt", "script"]).multiple(true)))]
     FindDuplicates {
-        /// Directory to scan
+        /// Directories to scan ("-" reads file paths from stdin)
         #[arg(default_value = ".")]
-        path: String,
+        paths: Vec<String>,
 
-        /// Minimum file size in bytes (skip smaller files)
+        /// Minimum file size, e.g. "4096" or "10MB" (skip smaller files)
         #[arg(short, long, default_value = "1")]
-        min_size: u64,
+        min_size: String,
+
+        /// Maximum file size, e.g. "1GB" (skip larger files)
+        #[arg(long)]
+        max_size: Option<String>,
 
         /// File extension filter (e.g., "jpg,png,gif")
         #[arg(short, long)]
         extensions: Option<String>,
 
-        /// Output results to JSON file
-        #[arg(short, long)]
-        output: Option<String>,
-
-        /// Delete duplicates (keep first occurrence)
+        /// Delete duplicates, keeping one copy per group (see --keep)
         #[arg(long, default_value = "false")]
         delete: bool,
+
+        /// Replace duplicates with hardlinks to the kept copy (same filesystem only)
+        #[arg(long, default_value = "false")]
+        hardlink: bool,
+
+        /// Replace duplicates with symlinks to the kept copy, relative (default) or absolute;
+        /// works across filesystems
+        #[arg(long, value_name = "MODE", num_args = 0..=1, default_missing_value = "relative", require_equals = true)]
+        symlink: Option<String>,
+
+        /// Move duplicates into this quarantine directory (mirroring their absolute
+        /// paths) and list them in its manifest for --restore
+        #[arg(long, value_name = "DIR")]
+        move_to: Option<String>,
+
+        /// Move every file listed in a quarantine directory's manifest back
+        #[arg(long, value_name = "DIR", conflicts_with = "changes")]
+        restore: Option<String>,
+
+        /// Treat files under this directory as the originals: only duplicates of them
+        /// found in PATHS are reported, deleted, linked or moved (repeatable)
+        #[arg(long, value_name = "DIR")]
+        reference: Vec<String>,
+
+        /// Never delete, link over or move files matching this glob, whatever --keep
+        /// or --prompt choose (repeatable; also `protect` under [dupes] in config.toml)
+        #[arg(long, value_name = "GLOB")]
+        protect: Vec<String>,
+
+        /// Don't cross into other file systems (mounts, network shares, /proc)
+        #[arg(short = 'x', long, default_value = "false")]
+        one_file_system: bool,
+
+        /// Report whole directories with identical contents instead of single files
+        #[arg(long, default_value = "false", conflicts_with_all = ["changes", "reference", "keep", "size_tolerance", "extensions", "min_size", "max_size", "resume", "sort", "limit", "fuzzy_text", "protect"])]
+        dirs: bool,
+
+        /// Which copy to keep in each group: newest, oldest, shortest-path, longest-path,
+        /// in:<dir> or regex:<pattern> (default: the first path in sorted order)
+        #[arg(long, value_name = "STRATEGY")]
+        keep: Option<String>,
+
+        /// Choose which files to keep in each group; the rest are deleted (or linked/moved)
+        #[arg(long, default_value = "false")]
+        prompt: bool,
+
+        /// Show what --delete/--hardlink/--symlink/--move-to/--prompt would change without touching anything
+        #[arg(long, num_args = 0..=1, default_value = "false", default_missing_value = "true", action = ArgAction::Set, requires = "changes")]
+        dry_run: bool,
+
+        /// Write the planned rm/ln/mv commands to this shell script for review
+        /// instead of running them (deletes unless --hardlink/--symlink/--move-to);
+        /// an existing file is never overwritten
+        #[arg(long, value_name = "FILE")]
+        script: Option<String>,
+
+        /// Compare duplicates byte for byte with the kept copy before changing them,
+        /// skipping any group that doesn't match (always done with xxh3 and for
+        /// hashes from the cache)
+        #[arg(long, default_value = "false", requires = "changes")]
+        paranoid: bool,
+
+        /// Delete, link or move without asking for confirmation
+        #[arg(
+            short = 'y',
+            long,
+            alias = "force",
+            short_alias = 'f',
+            default_value = "false"
+        )]
+        yes: bool,
+
+        /// Also report files whose sizes differ by up to this much (e.g. "16", "1KB", "0.5%")
+        /// when one is the other plus a leading/trailing part; these are never deleted.
+        /// Files under 256 bytes are left out
+        #[arg(long, value_name = "BYTES|%")]
+        size_tolerance: Option<String>,
+
+        /// Also report text files that are at least this similar (e.g. "95%" or "0.95"),
+        /// such as edited copies or drifted configs; these are never deleted
+        #[arg(long, value_name = "SIMILARITY")]
+        fuzzy_text: Option<String>,
+
+        /// Content hash: sha256, blake3 (faster) or xxh3 (fastest, not cryptographic)
+        #[arg(short, long, default_value = "sha256")]
+        algorithm: String,
+
+        /// Don't read or update the hash cache (~/.cache/ftools/index.json)
+        #[arg(long, default_value = "false")]
+        no_cache: bool,
+
+        /// Continue an interrupted scan from its checkpoint (written while scanning)
+        /// instead of walking and hashing everything again
+        #[arg(long, default_value = "false")]
+        resume: bool,
+
+        /// Keep the file list within about this much memory (e.g. "512MB", at least 8MB)
+        /// by sorting it by size on disk; for trees too large to list in memory. Files
+        /// are then hashed a batch at a time without the hash cache, and the groups
+        /// found go to disk too and are listed smallest first. The --output/--csv
+        /// report and any changes still hold the groups they cover in memory.
+        #[arg(long, value_name = "SIZE", conflicts_with_all = ["resume", "size_tolerance", "fuzzy_text", "dirs", "sort"])]
+        max_memory: Option<String>,
+
+        /// Order groups by wasted space, file count or file size, largest first
+        /// (waste, count, size; default: by path, or smallest first with --max-memory)
+        #[arg(long, value_name = "KEY")]
+        sort: Option<String>,
+
+        /// Only report the first N groups; changes are limited to these groups too
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+
+        /// Also write one CSV row per file (group, hash, size, keep/dupe, path) to this file
+        #[arg(long, value_name = "FILE")]
+        csv: Option<String>,
+
+        #[command(flatten)]
+        output: DupesOutputArgs,
     },
 
     /// Search for text pattern in files (grep-like)
@@ -48,22 +210,96 @@ enum Commands {
         /// Pattern to search (supports regex)
         pattern: String,
 
-        /// Directory to search in
+        /// Directories to search in ("-" reads file paths from stdin)
         #[arg(default_value = ".")]
-        path: String,
+        paths: Vec<String>,
 
         /// File extension filter
         #[arg(short, long)]
         extensions: Option<String>,
 
+        /// Only search files whose path matches this glob, e.g. 'src/**/*.test.ts';
+        /// '!glob' excludes, '/glob' matches from the search root (repeatable)
+        #[arg(short = 'g', long = "glob", value_name = "GLOB")]
+        globs: Vec<String>,
+
+        /// Only search files modified within this time, e.g. "30m", "24h", "7d"
+        #[arg(long, value_name = "DURATION")]
+        modified_within: Option<String>,
+
         /// Case insensitive search
         #[arg(short, long, default_value = "false")]
         ignore_case: bool,
 
+        /// Ignore case unless the pattern has an uppercase letter
+        /// (default with smart_case = true under [search] in the config)
+        #[arg(
+            short = 'S',
+            long,
+            default_value = "false",
+            conflicts_with = "ignore_case"
+        )]
+        smart_case: bool,
+
+        /// Match case exactly, overriding smart_case from the config
+        #[arg(short = 's', long, default_value = "false", conflicts_with_all = ["ignore_case", "smart_case"])]
+        case_sensitive: bool,
+
+        /// Only match whole words, so 'id' doesn't match 'identifier' or 'void'
+        #[arg(short = 'w', long, default_value = "false")]
+        word_regexp: bool,
+
         /// Show only filenames
         #[arg(short = 'l', long, default_value = "false")]
         files_only: bool,
 
+        /// Print only the number of matching lines of each file (path:count) and the total
+        #[arg(short = 'c', long, default_value = "false", conflicts_with_all = ["files_only", "by_dir", "replace"])]
+        count: bool,
+
+        /// Print each match on its own line instead of the lines containing them
+        #[arg(short = 'o', long, default_value = "false", conflicts_with_all = ["files_only", "count", "invert_match", "by_dir", "replace"])]
+        only_matching: bool,
+
+        /// With -o, put path:line: before each match
+        #[arg(short = 'H', long, default_value = "false", requires = "only_matching")]
+        with_filename: bool,
+
+        /// Print results as ripgrep's JSON Lines messages (begin, match, context, end, summary)
+        #[arg(long, default_value = "false", conflicts_with_all = ["files_only", "count", "only_matching", "by_dir", "replace", "multiline"])]
+        json: bool,
+
+        /// Print each file's results as soon as it's searched rather than in walk order
+        #[arg(long, default_value = "false")]
+        stream: bool,
+
+        /// Browse the matches in a full-screen list with a preview; '/' filters,
+        /// Enter opens $EDITOR at the line
+        #[arg(long, default_value = "false", conflicts_with_all = ["files_only", "count", "only_matching", "json", "invert_match", "by_dir", "replace"])]
+        tui: bool,
+
+        /// Finish with statistics: files scanned and skipped, bytes read,
+        /// time taken and the files with the most matches
+        #[arg(long, default_value = "false", conflicts_with_all = ["json", "tui"])]
+        stats: bool,
+
+        /// Skip files larger than this, e.g. "1GB" (0 for no limit)
+        #[arg(long, default_value = "100MB")]
+        max_filesize: String,
+
+        /// Also search the files inside .zip, .jar, .tar and .tar.gz archives,
+        /// reported as archive.zip!inner/path
+        #[arg(long, default_value = "false", conflicts_with = "write")]
+        archives: bool,
+
+        /// Search .gz, .zst and .bz2 files decompressed, e.g. rotated logs
+        #[arg(short = 'z', long, default_value = "false", conflicts_with = "write")]
+        search_zip: bool,
+
+        /// Select the lines that don't match (with -l, the files without any match)
+        #[arg(short = 'v', long, default_value = "false", conflicts_with_all = ["replace", "multiline"])]
+        invert_match: bool,
+
         /// Show line numbers
         #[arg(short = 'n', long, default_value = "true")]
         line_numbers: bool,
@@ -71,6 +307,42 @@ enum Commands {
         /// Context lines before/after match
         #[arg(short = 'C', long, default_value = "0")]
         context: usize,
+
+        /// Stop searching a file after this many matching lines
+        #[arg(short = 'm', long, value_name = "N", conflicts_with = "write")]
+        max_count: Option<usize>,
+
+        /// Stop the whole search once this many matches were found
+        #[arg(long, value_name = "N", conflicts_with = "write")]
+        max_total: Option<usize>,
+
+        /// Let matches span lines: each file is searched as one buffer
+        /// (^ and $ still match at line breaks; use (?s) for . to match newlines)
+        #[arg(short = 'U', long, default_value = "false", conflicts_with = "replace")]
+        multiline: bool,
+
+        /// Report match counts per directory instead of matching lines
+        #[arg(long, default_value = "false", conflicts_with = "files_only")]
+        by_dir: bool,
+
+        /// Show matching lines with the matches replaced (supports $1, ${name} for groups)
+        #[arg(long, value_name = "REPLACEMENT", conflicts_with = "by_dir")]
+        replace: Option<String>,
+
+        /// Write the --replace changes to the files instead of previewing them
+        #[arg(long, default_value = "false", requires = "replace")]
+        write: bool,
+
+        /// Show the --replace changes as unified diffs (3 lines of context, or -C)
+        #[arg(long, default_value = "false", requires = "replace")]
+        diff: bool,
+
+        /// Keep each changed file's original as <file>.bak (.bak.1, .bak.2... if taken)
+        #[arg(long, default_value = "false", requires = "write")]
+        backup: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Bulk rename files with regex pattern
@@ -81,32 +353,187 @@ enum Commands {
         path: String,
 
         /// Search pattern (regex)
-        #[arg(short, long)]
-        find: String,
+        #[arg(
+            short,
+            long,
+            requires = "replace",
+            required_unless_present_any = [
+                "case",
+                "sanitize",
+                "lowercase_ext",
+                "fix_extensions",
+                "fix_encoding",
+                "edit",
+                "from_file",
+                "undo"
+            ]
+        )]
+        find: Option<String>,
+
+        /// Match --find regardless of case, so .jpg also finds .JPG and .Jpg
+        #[arg(short, long, default_value = "false", requires = "find")]
+        ignore_case: bool,
 
-        /// Replacement string (supports $1, $2 for groups)
-        #[arg(short, long)]
-        replace: String,
+        /// Match --find against the path below PATH (with / separators) and
+        /// let --replace move files into other, automatically created
+        /// directories, e.g. "(\d{4})-(\d{2})-.*" to "$1/$2/$0"
+        #[arg(
+            long,
+            default_value = "false",
+            requires = "find",
+            conflicts_with = "edit"
+        )]
+        full_path: bool,
+
+        /// Other regex flags for --find: i (ignore case), m (multi-line),
+        /// s (. matches newline), x (verbose), U (swap greed), u (Unicode \w, \d
+        /// and case folding; on by default). Letters after "-" turn a flag off,
+        /// e.g. "-u" for ASCII-only classes
+        #[arg(long, value_name = "FLAGS", requires = "find", allow_hyphen_values = true)]
+        regex_flags: Option<String>,
+
+        /// Replacement string (supports $1, $2 for groups, {n}, {n:03} or
+        /// {n+10} for a counter that goes up by one per file in name order, and
+        /// {mtime:%Y-%m-%d}, {ctime:...} or {exif_date:...} for file dates, and
+        /// {parent}, {ext}, {size}, {hash8}, {width} and {height} for details of
+        /// the file)
+        #[arg(short, long, requires = "find")]
+        replace: Option<String>,
+
+        /// Change the case of the name without its extension: lower, upper,
+        /// title, snake, kebab or camel (after --find/--replace if given)
+        #[arg(long, value_name = "CASE")]
+        case: Option<String>,
+
+        /// Make names safe on Windows, SMB shares and web servers: replace
+        /// characters like :<>?*"| and control characters, collapse spaces and
+        /// trim trailing dots and spaces
+        #[arg(long, default_value = "false")]
+        sanitize: bool,
+
+        /// With --sanitize, also turn accented letters into plain ASCII
+        #[arg(long, default_value = "false", requires = "sanitize")]
+        ascii: bool,
+
+        /// Lowercase file extensions (.JPG to .jpg) and leave the rest of the
+        /// name alone
+        #[arg(long, default_value = "false")]
+        lowercase_ext: bool,
+
+        /// Give files whose content (png, jpg, pdf, zip, mp4...) doesn't match
+        /// their extension the right one; unknown extensions (.cr3, .xlsb) are kept
+        #[arg(long, default_value = "false")]
+        fix_extensions: bool,
+
+        /// Repair names written in another encoding (latin1, cp1251,
+        /// shift_jis, gbk...) or garbled by being decoded as one
+        #[arg(long, value_name = "ENCODING")]
+        fix_encoding: Option<String>,
+
+        /// Reverse an earlier run from the ftools-rename-*.json file it left
+        /// in the directory
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = [
+                "find",
+                "case",
+                "sanitize",
+                "lowercase_ext",
+                "fix_extensions",
+                "fix_encoding"
+            ]
+        )]
+        undo: Option<String>,
+
+        /// Rename as listed in a CSV or TSV file of old path, new path pairs
+        /// (a first row of headers like from,to is skipped)
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = [
+                "find",
+                "case",
+                "sanitize",
+                "lowercase_ext",
+                "fix_extensions",
+                "fix_encoding",
+                "edit",
+                "undo",
+                "full_path",
+                "extensions",
+                "include",
+                "older_than",
+                "newer_than",
+                "min_size",
+                "max_size"
+            ]
+        )]
+        from_file: Option<String>,
 
         /// File extension filter
         #[arg(short, long)]
         extensions: Option<String>,
 
-        /// Dry run - show changes without applying
-        #[arg(long, default_value = "true")]
+        /// Only rename files whose path matches this glob, e.g. 'IMG_*.jpg' or
+        /// '2024/**'; '!glob' excludes, '/glob' matches from the directory (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+
+        /// Only rename files last modified more than this long ago, e.g. "30d"
+        #[arg(long, value_name = "DURATION")]
+        older_than: Option<String>,
+
+        /// Only rename files modified within this time, e.g. "24h", "7d"
+        #[arg(long, value_name = "DURATION")]
+        newer_than: Option<String>,
+
+        /// Only rename files at least this big, e.g. "10MB"
+        #[arg(long, value_name = "SIZE")]
+        min_size: Option<String>,
+
+        /// Only rename files at most this big, e.g. "1GB"
+        #[arg(long, value_name = "SIZE")]
+        max_size: Option<String>,
+
+        /// Show changes without applying (default; pass --dry-run=false to rename)
+        #[arg(long, num_args = 0..=1, default_value = "true", default_missing_value = "true", action = ArgAction::Set)]
         dry_run: bool,
 
         /// Recursive rename in subdirectories
         #[arg(short = 'R', long, default_value = "false")]
         recursive: bool,
+
+        /// Rename directories as well as files (deepest first)
+        #[arg(long, default_value = "false")]
+        dirs: bool,
+
+        /// What to do when a new name is taken: skip the rename, overwrite
+        /// the existing file (put back if the run rolls back, but not by
+        /// --undo), or add a suffix like " (1)" (default: abort)
+        #[arg(long, value_name = "ACTION")]
+        on_conflict: Option<String>,
+
+        /// Ask before each rename ([y]es/[n]o/[e]dit/[a]ll/[q]uit) and apply
+        /// the ones accepted
+        #[arg(long, default_value = "false", conflicts_with = "undo")]
+        interactive: bool,
+
+        /// Edit the new names in $EDITOR (one "old<TAB>new" line per file)
+        /// and apply the result
+        #[arg(long, default_value = "false", conflicts_with_all = ["undo", "interactive"])]
+        edit: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Analyze disk usage by directory or file type
     #[command(name = "size")]
     DiskUsage {
-        /// Directory to analyze
+        /// Directories to analyze
         #[arg(default_value = ".")]
-        path: String,
+        paths: Vec<String>,
 
         /// Number of top items to show
         #[arg(short, long, default_value = "20")]
@@ -116,26 +543,118 @@ enum Commands {
         #[arg(short, long, default_value = "false")]
         by_type: bool,
 
+        /// Group by owning user and group (Unix only; on Windows every file is listed
+        /// under "(unknown)")
+        #[arg(long, default_value = "false", conflicts_with_all = ["by_type", "direct_only"])]
+        by_owner: bool,
+
+        /// Rank rows by size, count (files, to find inode hogs), name or path
+        #[arg(long, default_value = "size")]
+        sort: String,
+
+        /// Reverse sort order
+        #[arg(long, default_value = "false")]
+        reverse: bool,
+
+        /// Rank directories by inodes (files, subdirectories, links) instead
+        /// of bytes, for file systems that ran out of inodes
+        #[arg(long, default_value = "false", conflicts_with_all = ["sort", "by_type", "by_owner", "direct_only", "diff_snapshot"])]
+        inodes: bool,
+
+        /// Under each directory, show the three extensions taking the most
+        /// space in it
+        #[arg(long, default_value = "false", conflicts_with_all = ["by_type", "by_owner", "direct_only"])]
+        drilldown: bool,
+
         /// Show hidden files
         #[arg(long, default_value = "false")]
         hidden: bool,
 
+        /// Also count node_modules, target, .git and the other directories
+        /// skipped by default (extend the list under [ignore] in the config)
+        #[arg(long, default_value = "false")]
+        no_default_ignores: bool,
+
+        /// Leave out paths matching this glob (e.g. 'node_modules', '*.log',
+        /// '.cache/**') and report how much they hold (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
         /// Minimum size to display (e.g., "1MB", "500KB")
         #[arg(long)]
         min: Option<String>,
 
-        /// Export to CSV
-        #[arg(long)]
+        /// With --by-type, fold extensions with fewer files than this into
+        /// an "(other)" row
+        #[arg(long, default_value = "0", requires = "by_type")]
+        min_files: usize,
+
+        /// Levels of subdirectories to show in the tree
+        #[arg(short, long, default_value = "1")]
+        depth: usize,
+
+        /// Rank directories by the files directly in them instead of
+        /// everything below them
+        #[arg(long, default_value = "false", conflicts_with = "by_type")]
+        direct_only: bool,
+
+        /// Rank by space allocated on disk (what df counts) and show it next
+        /// to the apparent size, so sparse and compressed files don't mislead
+        #[arg(long, default_value = "false")]
+        du: bool,
+
+        /// Count every hardlink to the same data in full instead of once
+        #[arg(long, default_value = "false")]
+        count_links: bool,
+
+        /// Don't cross into other file systems (mounts, network shares, /proc)
+        #[arg(short = 'x', long, default_value = "false")]
+        one_file_system: bool,
+
+        /// Count the entries in a quick first pass so the progress line can
+        /// show an ETA (worth it on slow network shares)
+        #[arg(long, default_value = "false")]
+        eta: bool,
+
+        /// Also save the whole directory tree with sizes and file counts as
+        /// nested JSON
+        #[arg(long, value_name = "FILE")]
+        json: Option<String>,
+
+        /// Also save an interactive treemap of the tree as a single HTML
+        /// page that works offline (click a box to zoom in)
+        #[arg(long, value_name = "FILE")]
+        html: Option<String>,
+
+        /// Record every directory's size to FILE, to compare against later
+        #[arg(long, value_name = "FILE")]
+        save_snapshot: Option<String>,
+
+        /// Show which directories grew or shrank since a snapshot saved
+        /// with --save-snapshot
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["by_type", "by_owner", "direct_only", "drilldown"])]
+        diff_snapshot: Option<String>,
+
+        /// Rescan and redraw the tree every INTERVAL (e.g. "5s", "1m"),
+        /// marking directories that grew or shrank since the last refresh
+        #[arg(long, value_name = "INTERVAL", conflicts_with_all = ["by_type", "by_owner", "direct_only", "diff_snapshot", "json", "html", "save_snapshot", "csv", "output"])]
+        watch: Option<String>,
+
+        /// Export to CSV (same as --output FILE --output-format csv)
+        #[arg(long, value_name = "FILE", conflicts_with = "output")]
         csv: Option<String>,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Calculate file hash (SHA256, SHA512, MD5)
     #[command(name = "hash")]
     Hash {
-        /// Files to hash
-        files: Vec<String>,
+        /// Files to hash ("-" reads file paths from stdin)
+        files: Vec<PathBuf>,
 
-        /// Hash algorithm (sha256, sha512, md5)
+        /// Hash algorithm (sha256, sha512, md5, blake3, xxh3)
         #[arg(short, long, default_value = "sha256")]
         algorithm: String,
 
@@ -146,6 +665,13 @@ enum Commands {
         /// Output format (text, json)
         #[arg(short, long, default_value = "text")]
         format: String,
+
+        /// Digest encoding (hex, HEX, base64)
+        #[arg(long, default_value = "hex")]
+        encoding: String,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Compare two directories for differences
@@ -164,6 +690,17 @@ enum Commands {
         /// Show only differences
         #[arg(short, long, default_value = "false")]
         diff_only: bool,
+
+        /// Print nothing; report the result only through the exit code
+        #[arg(short, long, default_value = "false")]
+        quiet: bool,
+
+        /// Differences that cause exit code 1 (added, removed, modified, any)
+        #[arg(long, value_delimiter = ',', default_value = "any")]
+        fail_on: Vec<String>,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Find empty files and directories
@@ -184,6 +721,17 @@ enum Commands {
         /// Delete empty items
         #[arg(long, default_value = "false")]
         delete: bool,
+
+        /// Show what --delete would remove without deleting anything
+        #[arg(long, num_args = 0..=1, default_value = "false", default_missing_value = "true", action = ArgAction::Set, requires = "delete")]
+        dry_run: bool,
+
+        /// Delete without asking for confirmation (-f is taken by --files here)
+        #[arg(short = 'y', long, alias = "force", default_value = "false")]
+        yes: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// List files with sorting and filtering
@@ -212,14 +760,17 @@ enum Commands {
         /// Long format with details
         #[arg(short, long, default_value = "false")]
         long: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Find files exceeding a size threshold
     #[command(name = "large")]
     FindLarge {
-        /// Directory to scan
+        /// Directories to scan
         #[arg(default_value = ".")]
-        path: String,
+        paths: Vec<String>,
 
         /// Minimum size (e.g., "100MB", "1GB")
         #[arg(short, long, default_value = "100MB")]
@@ -228,14 +779,25 @@ enum Commands {
         /// Number of results
         #[arg(short, long, default_value = "50")]
         top: usize,
+
+        /// Hide files whose hash is listed in this checksum manifest (e.g. sha256sum output)
+        #[arg(long, value_name = "MANIFEST")]
+        not_in: Option<String>,
+
+        /// Don't cross into other file systems (mounts, network shares, /proc)
+        #[arg(short = 'x', long, default_value = "false")]
+        one_file_system: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Find recently modified files
     #[command(name = "recent")]
     Recent {
-        /// Directory to scan
+        /// Directories to scan
         #[arg(default_value = ".")]
-        path: String,
+        paths: Vec<String>,
 
         /// Time range (e.g., "1h", "24h", "7d", "30d")
         #[arg(short, long, default_value = "24h")]
@@ -244,89 +806,401 @@ enum Commands {
         /// Number of results
         #[arg(short, long, default_value = "50")]
         top: usize,
+
+        /// Only report files whose content changed (uses the persistent hash index)
+        #[arg(long, default_value = "false")]
+        content_changed: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Sort files into category folders by extension or glob rules
+    #[command(name = "organize")]
+    Organize {
+        /// Directory to organize
+        #[arg(default_value = ".")]
+        path: String,
+
+        /// Use category rules from a TOML file instead of the built-in ones
+        #[arg(long)]
+        rules: Option<String>,
+
+        /// Print the active category rules as TOML and exit
+        #[arg(long, default_value = "false")]
+        print_rules: bool,
+
+        /// Validate a rules file (unknown keys, overlaps, unreachable rules) and exit
+        #[arg(long, value_name = "FILE")]
+        check_rules: Option<String>,
+
+        /// Move files (default only previews the plan)
+        #[arg(long, default_value = "false")]
+        apply: bool,
+
+        /// Show what --apply would move without moving anything
+        #[arg(long, num_args = 0..=1, default_value = "false", default_missing_value = "true", action = ArgAction::Set, requires = "apply")]
+        dry_run: bool,
+
+        /// Move without asking for confirmation
+        #[arg(short = 'y', long, alias = "force", default_value = "false")]
+        yes: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Display file statistics for a directory
     #[command(name = "stats")]
     Stats {
-        /// Directory to analyze
+        /// Directories to analyze (exactly two with --compare)
         #[arg(default_value = ".")]
-        path: String,
+        paths: Vec<String>,
 
         /// Show hidden files
         #[arg(long, default_value = "false")]
         hidden: bool,
+
+        /// Show the statistics of two directories side by side
+        #[arg(long, default_value = "false")]
+        compare: bool,
+
+        /// Don't cross into other file systems (mounts, network shares, /proc)
+        #[arg(short = 'x', long, default_value = "false")]
+        one_file_system: bool,
+
+        #[command(flatten)]
+        output: OutputArgs,
     },
+
+    /// Run an external `ftools-<name>` plugin found on PATH
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    ui::set_verbosity(match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::Trace,
+    });
+    if cli.raw_paths || cli.null {
+        // Path lists replace the normal report, so banners and progress go too
+        ui::set_verbosity(Verbosity::Quiet);
+        ui::set_path_list(if cli.null {
+            PathList::Null
+        } else {
+            PathList::Lines
+        });
+    }
+    // A broken config file only costs the theme and ignore list, not the
+    // command
+    match config::Config::load().and_then(|config| {
+        utils::set_ignored_dirs(utils::ignored_dirs(&config.ignore));
+        ui::Theme::from_config(&config.theme)
+    }) {
+        Ok(theme) => ui::set_theme(theme),
+        Err(e) => eprintln!(
+            "{} Ignoring config file: {}",
+            ui::chars::WARNING.themed(Element::Warning).bold(),
+            e
+        ),
+    }
+    let started = Instant::now();
+
     let result = match cli.command {
         Commands::FindDuplicates {
-            path,
+            paths,
             min_size,
+            max_size,
             extensions,
             output,
             delete,
-        } => commands::duplicates::run(&path, min_size, extensions, output, delete),
+            hardlink,
+            symlink,
+            move_to,
+            restore,
+            keep,
+            prompt,
+            dry_run,
+            yes,
+            size_tolerance,
+            algorithm,
+            no_cache,
+            reference,
+            dirs,
+            csv,
+            resume,
+            paranoid,
+            script,
+            max_memory,
+            sort,
+            limit,
+            fuzzy_text,
+            protect,
+            one_file_system,
+        } => commands::duplicates::run(
+            &paths,
+            commands::duplicates::DupesOptions {
+                min_size,
+                max_size,
+                extensions,
+                output: output.into(),
+                delete,
+                hardlink,
+                symlink,
+                move_to,
+                restore,
+                keep,
+                prompt,
+                dry_run,
+                yes,
+                size_tolerance,
+                algorithm,
+                no_cache,
+                reference,
+                dirs,
+                csv,
+                resume,
+                paranoid,
+                script,
+                max_memory,
+                sort,
+                limit,
+                fuzzy_text,
+                protect,
+                one_file_system,
+            },
+        ),
 
         Commands::Search {
             pattern,
-            path,
+            paths,
             extensions,
+            globs,
+            modified_within,
             ignore_case,
+            smart_case,
+            case_sensitive,
+            word_regexp,
             files_only,
+            count,
+            only_matching,
+            with_filename,
+            json,
+            stream,
+            tui,
+            stats,
+            max_filesize,
+            archives,
+            search_zip,
+            invert_match,
             line_numbers,
             context,
+            max_count,
+            max_total,
+            multiline,
+            by_dir,
+            replace,
+            diff,
+            write,
+            backup,
+            output,
         } => commands::search::run(
             &pattern,
-            &path,
-            extensions,
-            ignore_case,
-            files_only,
-            line_numbers,
-            context,
+            &paths,
+            commands::search::SearchOptions {
+                extensions,
+                globs,
+                modified_within,
+                ignore_case,
+                smart_case,
+                case_sensitive,
+                word_regexp,
+                files_only,
+                count,
+                only_matching,
+                with_filename,
+                json,
+                stream,
+                tui,
+                stats,
+                max_filesize,
+                archives,
+                search_zip,
+                invert_match,
+                line_numbers,
+                context,
+                max_count,
+                max_total,
+                multiline,
+                by_dir,
+                replace,
+                diff,
+                write,
+                backup,
+                output,
+            },
         ),
 
         Commands::BulkRename {
             path,
             find,
+            ignore_case,
+            regex_flags,
+            full_path,
             replace,
+            case,
+            sanitize,
+            ascii,
+            lowercase_ext,
+            fix_extensions,
+            fix_encoding,
+            undo,
+            from_file,
             extensions,
+            include,
+            older_than,
+            newer_than,
+            min_size,
+            max_size,
             dry_run,
             recursive,
-        } => commands::rename::run(&path, &find, &replace, extensions, dry_run, recursive),
+            dirs,
+            on_conflict,
+            interactive,
+            edit,
+            output,
+        } => commands::rename::run(
+            &path,
+            commands::rename::RenameOptions {
+                find,
+                ignore_case,
+                regex_flags,
+                full_path,
+                replace,
+                case,
+                sanitize,
+                ascii,
+                lowercase_ext,
+                fix_extensions,
+                fix_encoding,
+                undo,
+                from_file,
+                extensions,
+                include,
+                older_than,
+                newer_than,
+                min_size,
+                max_size,
+                dry_run,
+                recursive,
+                dirs,
+                on_conflict,
+                interactive,
+                edit,
+                output,
+            },
+        ),
 
         Commands::DiskUsage {
-            path,
+            paths,
             top,
             by_type,
+            by_owner,
+            sort,
+            reverse,
+            inodes,
+            drilldown,
             hidden,
+            no_default_ignores,
+            exclude,
             min,
+            min_files,
+            depth,
+            direct_only,
+            du,
+            count_links,
+            eta,
+            one_file_system,
+            json,
+            html,
+            save_snapshot,
+            diff_snapshot,
+            watch,
             csv,
-        } => commands::disk::run(&path, top, by_type, hidden, min, csv),
+            output,
+        } => {
+            let output = match csv {
+                Some(csv) => OutputArgs {
+                    output: Some(csv),
+                    output_format: Some("csv".to_string()),
+                    ..OutputArgs::default()
+                },
+                None => output,
+            };
+            commands::disk::run(
+                &paths,
+                commands::disk::DiskOptions {
+                    top,
+                    by_type,
+                    by_owner,
+                    sort,
+                    reverse,
+                    inodes,
+                    drilldown,
+                    hidden,
+                    no_default_ignores,
+                    exclude,
+                    min,
+                    min_files,
+                    depth,
+                    direct_only,
+                    du,
+                    count_links,
+                    eta,
+                    one_file_system,
+                    json,
+                    html,
+                    save_snapshot,
+                    diff_snapshot,
+                    watch,
+                },
+                &output,
+            )
+        }
 
         Commands::Hash {
             files,
             algorithm,
             verify,
             format,
-        } => commands::hash::run(files, &algorithm, verify, &format),
+            encoding,
+            output,
+        } => commands::hash::run(files, &algorithm, verify, &format, &encoding, &output),
 
         Commands::Compare {
             dir1,
             dir2,
             content,
             diff_only,
-        } => commands::compare::run(&dir1, &dir2, content, diff_only),
+            quiet,
+            fail_on,
+            output,
+        } => commands::compare::run(&dir1, &dir2, content, diff_only, quiet, fail_on, &output),
 
         Commands::FindEmpty {
             path,
             dirs,
             files,
             delete,
-        } => commands::empty::run(&path, dirs, files, delete),
+            dry_run,
+            yes,
+            output,
+        } => commands::empty::run(&path, dirs, files, delete, dry_run, yes, &output),
 
         Commands::List {
             path,
@@ -335,18 +1209,76 @@ fn main() {
             recursive,
             pattern,
             long,
-        } => commands::list::run(&path, &sort, reverse, recursive, pattern, long),
+            output,
+        } => commands::list::run(&path, &sort, reverse, recursive, pattern, long, &output),
 
-        Commands::FindLarge { path, size, top } => commands::large::run(&path, &size, top),
+        Commands::FindLarge {
+            paths,
+            size,
+            top,
+            not_in,
+            one_file_system,
+            output,
+        } => commands::large::run(&paths, &size, top, not_in, one_file_system, &output),
 
-        Commands::Recent { path, within, top } => commands::recent::run(&path, &within, top),
+        Commands::Recent {
+            paths,
+            within,
+            top,
+            content_changed,
+            output,
+        } => commands::recent::run(&paths, &within, top, content_changed, &output),
+
+        Commands::Organize {
+            path,
+            rules,
+            print_rules,
+            check_rules,
+            apply,
+            dry_run,
+            yes,
+            output,
+        } => commands::organize::run(
+            &path,
+            commands::organize::OrganizeOptions {
+                rules,
+                print_rules,
+                check_rules,
+                apply,
+                dry_run,
+                yes,
+            },
+            &output,
+        ),
+
+        Commands::Stats {
+            paths,
+            hidden,
+            compare,
+            one_file_system,
+            output,
+        } => commands::stats::run(&paths, hidden, compare, one_file_system, &output),
 
-        Commands::Stats { path, hidden } => commands::stats::run(&path, hidden),
+        Commands::External(args) => plugin::run(args),
     };
 
+    skipped::print_summary();
+    ui::print_verbose(&format!("finished in {:.2?}", started.elapsed()));
+
     if let Err(e) = result {
-        eprintln!("{} {}", "Error:".red().bold(), e);
-        std::process::exit(1);
+        let code = match e.downcast_ref::<exit::Exit>() {
+            Some(exit) => {
+                if let Some(message) = &exit.message {
+                    eprintln!("{} {}", "Error:".themed(Element::Error).bold(), message);
+                }
+                exit.code
+            }
+            None => {
+                eprintln!("{} {}", "Error:".themed(Element::Error).bold(), e);
+                exit::ERROR
+            }
+        };
+        std::process::exit(code);
     }
 }
 
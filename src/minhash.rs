@@ -0,0 +1,106 @@
+use std::collections::{HashMap, HashSet};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Hash functions per signature; the similarity estimate is within about
+/// ±1.5% around 95% similarity
+const HASHES: usize = 256;
+/// Signature rows hashed together when looking for candidate pairs: files
+/// sharing one band are compared, which catches nearly all pairs above ~80%
+const ROWS: usize = 8;
+/// Words per shingle; one changed word changes this many shingles
+const SHINGLE_WORDS: usize = 4;
+
+/// MinHash signature of the word shingles of a text
+pub struct Signature([u64; HASHES]);
+
+impl Signature {
+    /// Signature of `text`, or None when it has no words
+    pub fn of(text: &str) -> Option<Self> {
+        let words: Vec<u64> = text
+            .split_whitespace()
+            .map(|w| xxh3_64(w.as_bytes()))
+            .collect();
+        if words.is_empty() {
+            return None;
+        }
+
+        let mut mins = [u64::MAX; HASHES];
+        for shingle in words.windows(SHINGLE_WORDS.min(words.len())) {
+            let base = shingle.iter().fold(0u64, |h, w| mix(h.rotate_left(17) ^ w));
+            for (i, min) in mins.iter_mut().enumerate() {
+                *min = (*min).min(mix(base ^ seed(i)));
+            }
+        }
+        Some(Signature(mins))
+    }
+
+    /// Estimated share of shingles the two texts have in common (Jaccard similarity)
+    pub fn similarity(&self, other: &Signature) -> f64 {
+        let same = self.0.iter().zip(&other.0).filter(|(a, b)| a == b).count();
+        same as f64 / HASHES as f64
+    }
+}
+
+/// Pairs of signatures (by index, lower first) that agree on at least one
+/// band, so are likely similar
+pub fn candidate_pairs(signatures: &[&Signature]) -> Vec<(usize, usize)> {
+    let mut pairs = HashSet::new();
+    for band in 0..HASHES / ROWS {
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, signature) in signatures.iter().enumerate() {
+            let rows = &signature.0[band * ROWS..(band + 1) * ROWS];
+            let key = rows.iter().fold(band as u64, |h, r| mix(h ^ r));
+            buckets.entry(key).or_default().push(i);
+        }
+        for bucket in buckets.values() {
+            for (n, &i) in bucket.iter().enumerate() {
+                for &j in &bucket[n + 1..] {
+                    pairs.insert((i, j));
+                }
+            }
+        }
+    }
+    let mut pairs: Vec<(usize, usize)> = pairs.into_iter().collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+fn seed(i: usize) -> u64 {
+    mix(i as u64 + 1)
+}
+
+/// splitmix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        let base: Vec<String> = (0..400).map(|i| format!("word{}", i)).collect();
+        let text = base.join(" ");
+        let mut edited = base.clone();
+        edited[200] = "changed".to_string();
+        let edited = edited.join(" ");
+        let other: Vec<String> = (0..400).map(|i| format!("other{}", i)).collect();
+        let other = other.join(" ");
+
+        let signatures: Vec<Signature> = [&text, &edited, &other, &text]
+            .iter()
+            .map(|t| Signature::of(t).unwrap())
+            .collect();
+        assert_eq!(signatures[0].similarity(&signatures[3]), 1.0);
+        assert!(signatures[0].similarity(&signatures[1]) > 0.9);
+        assert!(signatures[0].similarity(&signatures[2]) < 0.1);
+
+        let pairs = candidate_pairs(&signatures.iter().collect::<Vec<_>>());
+        assert!(pairs.contains(&(0, 1)) && pairs.contains(&(0, 3)));
+        assert!(!pairs.contains(&(0, 2)));
+        assert!(Signature::of(" \n ").is_none());
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Result};
+use clap::Args;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::ui;
+
+/// `--output` / `--output-format` flags shared by every command
+#[derive(Args, Clone, Default)]
+pub struct OutputArgs {
+    /// Also write the full report to this file
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Report format: text, json or csv (default: from the file extension, else text)
+    #[arg(long, value_name = "FORMAT", requires = "output")]
+    pub output_format: Option<String>,
+
+    /// Write JSON rather than text to a file without a known extension
+    #[arg(skip)]
+    pub json_by_default: bool,
+}
+
+/// The `--output` flags of dupes, which has always taken `-o` and written
+/// JSON unless the file extension says otherwise
+#[derive(Args, Clone, Default)]
+pub struct DupesOutputArgs {
+    /// Also write the full report to this file
+    #[arg(short = 'o', long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Report format: text, json or csv (default: from the file extension, else json)
+    #[arg(long, value_name = "FORMAT", requires = "output")]
+    pub output_format: Option<String>,
+}
+
+impl From<DupesOutputArgs> for OutputArgs {
+    fn from(args: DupesOutputArgs) -> Self {
+        OutputArgs {
+            output: args.output,
+            output_format: args.output_format,
+            json_by_default: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+impl Format {
+    fn resolve(explicit: Option<&str>, path: &str, fallback: Format) -> Result<Self> {
+        if let Some(name) = explicit {
+            return match name.to_lowercase().as_str() {
+                "text" | "txt" => Ok(Format::Text),
+                "json" => Ok(Format::Json),
+                "csv" => Ok(Format::Csv),
+                _ => Err(anyhow!(
+                    "Unsupported output format: {}. Use text, json, or csv",
+                    name
+                )),
+            };
+        }
+
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        Ok(match ext.as_deref() {
+            Some("json") => Format::Json,
+            Some("csv") => Format::Csv,
+            Some("txt") => Format::Text,
+            _ => fallback,
+        })
+    }
+}
+
+/// Tabular results of a command, as written by `--output`
+pub struct Report {
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<Value>>,
+    /// Replaces the row objects in JSON output for commands with a nested shape
+    json: Option<Value>,
+}
+
+impl Report {
+    pub fn new(columns: &[&'static str]) -> Self {
+        Report {
+            columns: columns.to_vec(),
+            rows: Vec::new(),
+            json: None,
+        }
+    }
+
+    /// Add a row; values line up with the columns given to `new`
+    pub fn row(&mut self, values: Vec<Value>) {
+        debug_assert_eq!(values.len(), self.columns.len());
+        self.rows.push(values);
+    }
+
+    /// Use a custom JSON document instead of an array of row objects
+    pub fn with_json(mut self, json: Value) -> Self {
+        self.json = Some(json);
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        if let Some(json) = &self.json {
+            return json.clone();
+        }
+        Value::Array(
+            self.rows
+                .iter()
+                .map(|row| {
+                    let object: Map<String, Value> = self
+                        .columns
+                        .iter()
+                        .map(|c| c.to_string())
+                        .zip(row.iter().cloned())
+                        .collect();
+                    Value::Object(object)
+                })
+                .collect(),
+        )
+    }
+
+    fn write_csv(&self, out: impl Write) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(&self.columns)?;
+        for row in &self.rows {
+            writer.write_record(row.iter().map(cell_text))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_text(&self, mut out: impl Write) -> Result<()> {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(cell_text).collect())
+            .collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain(std::iter::once(c.len()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let header: Vec<String> = self.columns.iter().map(|c| c.to_uppercase()).collect();
+        for row in std::iter::once(&header).chain(cells.iter()) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            writeln!(out, "{}", line.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Write `report` to the `--output` file, if one was requested
+pub fn write_report(args: &OutputArgs, report: &Report) -> Result<()> {
+    let Some(path) = &args.output else {
+        return Ok(());
+    };
+    let fallback = if args.json_by_default {
+        Format::Json
+    } else {
+        Format::Text
+    };
+    let format = Format::resolve(args.output_format.as_deref(), path, fallback)?;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, &report.to_json())?;
+            writeln!(out)?;
+        }
+        Format::Csv => report.write_csv(&mut out)?,
+        Format::Text => report.write_text(&mut out)?,
+    }
+    out.flush()?;
+
+    ui::print_success(&format!("Report saved to {}", path));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_format_resolve() {
+        let text = Format::Text;
+        assert_eq!(Format::resolve(None, "out.JSON", text).unwrap(), Format::Json);
+        assert_eq!(Format::resolve(None, "out.csv", text).unwrap(), Format::Csv);
+        assert_eq!(Format::resolve(None, "out", text).unwrap(), Format::Text);
+        assert_eq!(Format::resolve(None, "out", Format::Json).unwrap(), Format::Json);
+        assert_eq!(Format::resolve(None, "out.txt", Format::Json).unwrap(), Format::Text);
+        assert_eq!(
+            Format::resolve(Some("csv"), "out.json", text).unwrap(),
+            Format::Csv
+        );
+        assert!(Format::resolve(Some("xml"), "out", text).is_err());
+    }
+
+    #[test]
+    fn test_report_writers() {
+        let mut report = Report::new(&["path", "size"]);
+        report.row(vec![json!("a, b.txt"), json!(10)]);
+
+        let mut csv_out = Vec::new();
+        report.write_csv(&mut csv_out).unwrap();
+        assert_eq!(
+            String::from_utf8(csv_out).unwrap(),
+            "path,size\n\"a, b.txt\",10\n"
+        );
+
+        let mut text_out = Vec::new();
+        report.write_text(&mut text_out).unwrap();
+        assert_eq!(
+            String::from_utf8(text_out).unwrap(),
+            "PATH      SIZE\na, b.txt  10\n"
+        );
+
+        assert_eq!(report.to_json(), json!([{"path": "a, b.txt", "size": 10}]));
+    }
+}
@@ -0,0 +1,779 @@
+use anyhow::Result;
+use colored::*;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::exit::{self, Exit};
+use crate::ui::{self, chars, Element, Themed};
+use crate::utils::{format_bytes, hardlink_id, link_count};
+
+/// One filesystem change a destructive command intends to make
+pub enum Action {
+    DeleteFile {
+        path: PathBuf,
+        size: u64,
+    },
+    DeleteDir {
+        path: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// Replace `path` with a hardlink to `target`, which has the same content
+    Hardlink {
+        path: PathBuf,
+        target: PathBuf,
+        size: u64,
+    },
+    /// Replace `path` with a symlink reading `link`, which leads to `target`
+    Symlink {
+        path: PathBuf,
+        target: PathBuf,
+        link: PathBuf,
+        size: u64,
+    },
+    /// Relocate a file, creating the target's parent directories
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+impl Action {
+    /// The file or directory the action changes
+    pub fn path(&self) -> &Path {
+        match self {
+            Action::DeleteFile { path, .. }
+            | Action::DeleteDir { path }
+            | Action::Hardlink { path, .. }
+            | Action::Symlink { path, .. } => path,
+            Action::Rename { from, .. } | Action::Move { from, .. } => from,
+        }
+    }
+}
+
+/// What happened when a plan was carried out
+#[derive(Default)]
+pub struct Outcome {
+    pub files_deleted: usize,
+    pub dirs_deleted: usize,
+    pub renamed: usize,
+    pub linked: usize,
+    pub failed: usize,
+    pub freed: u64,
+    /// Renames undone after a later step of a transaction failed
+    pub rolled_back: usize,
+    /// Source and destination of every successful move
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    /// Old and new path of every successful rename
+    pub renames: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Ordered list of actions, shown as-is for `--dry-run` and executed otherwise
+#[derive(Default)]
+pub struct Plan {
+    actions: Vec<Action>,
+    /// Planned removals per hardlinked file, so only the last one counts as freed
+    unlinked: HashMap<(u64, u64), u64>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn delete_file(&mut self, path: &Path) {
+        let size = self.freed_by_unlinking(path);
+        self.actions.push(Action::DeleteFile {
+            path: path.to_path_buf(),
+            size,
+        });
+    }
+
+    pub fn delete_dir(&mut self, path: &Path) {
+        self.actions.push(Action::DeleteDir {
+            path: path.to_path_buf(),
+        });
+    }
+
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        self.actions.push(Action::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+    }
+
+    pub fn hardlink(&mut self, path: &Path, target: &Path) {
+        let size = self.freed_by_unlinking(path);
+        self.actions.push(Action::Hardlink {
+            path: path.to_path_buf(),
+            target: target.to_path_buf(),
+            size,
+        });
+    }
+
+    /// Replace `path` with a symlink to `target`, relative to the link's
+    /// directory unless `absolute`
+    pub fn symlink(&mut self, path: &Path, target: &Path, absolute: bool) {
+        let size = self.freed_by_unlinking(path);
+        let resolved = fs::canonicalize(target).unwrap_or_else(|_| target.to_path_buf());
+        let dir = path.parent().and_then(|p| {
+            fs::canonicalize(if p.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                p
+            })
+            .ok()
+        });
+        let link = match dir {
+            Some(dir) if !absolute => relative_path(&dir, &resolved),
+            _ => resolved,
+        };
+        self.actions.push(Action::Symlink {
+            path: path.to_path_buf(),
+            target: target.to_path_buf(),
+            link,
+            size,
+        });
+    }
+
+    pub fn move_file(&mut self, from: &Path, to: &Path) {
+        self.actions.push(Action::Move {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+    }
+
+    /// Bytes released by removing `path`: nothing while other hardlinks to
+    /// its data remain, counting the ones this plan already removes
+    fn freed_by_unlinking(&mut self, path: &Path) -> u64 {
+        let Ok(metadata) = path.metadata() else {
+            return 0;
+        };
+        if let Some(id) = hardlink_id(&metadata) {
+            let removed = self.unlinked.entry(id).or_default();
+            *removed += 1;
+            if *removed < link_count(&metadata) {
+                return 0;
+            }
+        }
+        metadata.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Bytes released once every file deletion or hardlink has run
+    pub fn bytes_freed(&self) -> u64 {
+        self.actions
+            .iter()
+            .map(|a| match a {
+                Action::DeleteFile { size, .. }
+                | Action::Hardlink { size, .. }
+                | Action::Symlink { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// One-line description of the whole plan, e.g. "3 files, 1 directory (4.2 MiB freed)"
+    pub fn summary(&self) -> String {
+        let (mut files, mut dirs, mut renames, mut links, mut symlinks, mut moves) =
+            (0, 0, 0, 0, 0, 0);
+        for action in &self.actions {
+            match action {
+                Action::DeleteFile { .. } => files += 1,
+                Action::DeleteDir { .. } => dirs += 1,
+                Action::Rename { .. } => renames += 1,
+                Action::Hardlink { .. } => links += 1,
+                Action::Symlink { .. } => symlinks += 1,
+                Action::Move { .. } => moves += 1,
+            }
+        }
+
+        let mut parts = Vec::new();
+        if files > 0 {
+            parts.push(plural(files, "file", "files"));
+        }
+        if dirs > 0 {
+            parts.push(plural(dirs, "directory", "directories"));
+        }
+        if renames > 0 {
+            parts.push(plural(renames, "rename", "renames"));
+        }
+        if links > 0 {
+            parts.push(plural(links, "hardlink", "hardlinks"));
+        }
+        if symlinks > 0 {
+            parts.push(plural(symlinks, "symlink", "symlinks"));
+        }
+        if moves > 0 {
+            parts.push(plural(moves, "move", "moves"));
+        }
+        let mut summary = parts.join(", ");
+        if files > 0 || links > 0 || symlinks > 0 {
+            summary.push_str(&format!(" ({} freed)", format_bytes(self.bytes_freed())));
+        }
+        summary
+    }
+
+    /// Ask the user to approve the plan unless `assume_yes` (--yes) was given.
+    /// Without a terminal to ask on, the plan is refused rather than run unattended.
+    pub fn confirm(&self, assume_yes: bool) -> Result<bool> {
+        if assume_yes {
+            return Ok(true);
+        }
+
+        let verb = if self
+            .actions
+            .iter()
+            .all(|a| matches!(a, Action::DeleteFile { .. } | Action::DeleteDir { .. }))
+        {
+            "delete"
+        } else {
+            "apply"
+        };
+        if !io::stdin().is_terminal() {
+            return Err(Exit::with_message(
+                exit::ERROR,
+                format!(
+                    "Refusing to {} without confirmation (stdin is not a terminal); pass --yes",
+                    verb
+                ),
+            )
+            .into());
+        }
+
+        Ok(ui::confirm(&format!(
+            "About to {} {}. Continue?",
+            verb,
+            self.summary()
+        )))
+    }
+
+    /// Print every action without touching the filesystem
+    pub fn print_dry_run(&self) {
+        ui::print_section("Dry Run (nothing will be changed)");
+        for action in &self.actions {
+            match action {
+                Action::DeleteFile { path, size } => println!(
+                    "  {} {} {}",
+                    chars::CROSS_MARK.themed(Element::Error),
+                    path.display(),
+                    format!("({})", format_bytes(*size)).dimmed()
+                ),
+                Action::DeleteDir { path } => {
+                    println!(
+                        "  {} {}",
+                        chars::CROSS_MARK.themed(Element::Error),
+                        path.display()
+                    )
+                }
+                Action::Rename { from, to } => println!(
+                    "  {} {}  {}  {}",
+                    chars::BULLET.dimmed(),
+                    display_name(from, to).themed(Element::Error),
+                    chars::ARROW.dimmed(),
+                    display_name(to, from).themed(Element::Success)
+                ),
+                Action::Hardlink { path, target, .. } => println!(
+                    "  {} {}  {}  {}",
+                    chars::BULLET.dimmed(),
+                    path.display(),
+                    chars::ARROW.dimmed(),
+                    target.display().to_string().themed(Element::Muted)
+                ),
+                Action::Symlink { path, link, .. } => println!(
+                    "  {} {}  {}  {}",
+                    chars::BULLET.dimmed(),
+                    path.display(),
+                    chars::ARROW.dimmed(),
+                    link.display().to_string().themed(Element::Muted)
+                ),
+                Action::Move { from, to } => println!(
+                    "  {} {}  {}  {}",
+                    chars::BULLET.dimmed(),
+                    from.display(),
+                    chars::ARROW.dimmed(),
+                    to.display().to_string().themed(Element::Muted)
+                ),
+            }
+        }
+        println!();
+        println!(
+            "{} Would apply: {}",
+            chars::ARROW.themed(Element::Muted),
+            self.summary().themed(Element::Warning).bold()
+        );
+    }
+
+    /// Write the plan as a POSIX shell script for review instead of running it.
+    /// `notes` are comments placed before the action at the given index.
+    pub fn write_script(&self, out: &mut impl Write, notes: &[(usize, String)]) -> io::Result<()> {
+        writeln!(out, "#!/bin/sh")?;
+        writeln!(
+            out,
+            "# Generated by ftools on {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M")
+        )?;
+        writeln!(out, "# Plan: {}. Review before running.", self.summary())?;
+        writeln!(out, "set -e")?;
+
+        let mut notes = notes.iter().peekable();
+        for (i, action) in self.actions.iter().enumerate() {
+            while let Some((_, note)) = notes.next_if(|(at, _)| *at == i) {
+                writeln!(out)?;
+                for line in note.lines() {
+                    writeln!(out, "# {}", line)?;
+                }
+            }
+            match action {
+                Action::DeleteFile { path, .. } => shell_line(out, "rm --", &[path])?,
+                Action::DeleteDir { path } => shell_line(out, "rmdir --", &[path])?,
+                Action::Rename { from, to } => {
+                    if let Some(parent) = to.parent()
+                        && to.parent() != from.parent()
+                    {
+                        shell_line(out, "mkdir -p --", &[parent])?;
+                    }
+                    shell_line(out, "mv --", &[from, to])?
+                }
+                Action::Hardlink { path, target, .. } => {
+                    shell_line(out, "ln -f --", &[target, path])?
+                }
+                Action::Symlink { path, link, .. } => shell_line(out, "ln -sf --", &[link, path])?,
+                Action::Move { from, to } => {
+                    if let Some(parent) = to.parent() {
+                        shell_line(out, "mkdir -p --", &[parent])?;
+                    }
+                    shell_line(out, "mv --", &[from, to])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Carry out the actions in order, printing each one as it completes
+    pub fn execute(&self) -> Outcome {
+        self.execute_with(|_| Ok(()))
+    }
+
+    /// `execute`, calling `before` ahead of each action; an action whose
+    /// `before` fails counts as failed and isn't carried out
+    pub fn execute_with(&self, mut before: impl FnMut(&Action) -> io::Result<()>) -> Outcome {
+        let mut outcome = Outcome::default();
+        for action in &self.actions {
+            match before(action) {
+                Ok(()) => {
+                    execute_action(action, &mut outcome);
+                }
+                Err(e) => {
+                    outcome.failed += 1;
+                    ui::print_error(&format!("{} ({})", action.path().display(), e));
+                }
+            }
+        }
+        outcome
+    }
+
+    /// Carry out a plan of renames as one transaction: at the first failure,
+    /// stop and rename everything done so far back. Renames that can't be
+    /// reverted stay in `outcome.renames`.
+    pub fn execute_or_roll_back(&self) -> Outcome {
+        let mut outcome = Outcome::default();
+        for (i, action) in self.actions.iter().enumerate() {
+            if execute_action(action, &mut outcome) {
+                continue;
+            }
+            if outcome.renames.is_empty() {
+                break;
+            }
+            println!();
+            ui::print_warning(&format!(
+                "Step {} of {} failed, rolling back {}",
+                i + 1,
+                self.actions.len(),
+                plural(outcome.renames.len(), "rename", "renames")
+            ));
+            let mut kept = Vec::new();
+            while let Some((from, to)) = outcome.renames.pop() {
+                match fs::rename(&to, &from) {
+                    Ok(()) => {
+                        remove_empty_parents(&to, &from);
+                        outcome.renamed -= 1;
+                        outcome.rolled_back += 1;
+                        println!(
+                            "  {} {}",
+                            chars::CHECK.themed(Element::Success),
+                            display_name(&from, &to).dimmed()
+                        );
+                    }
+                    Err(e) => {
+                        ui::print_error(&format!("{} ({})", to.display(), e));
+                        kept.push((from, to));
+                    }
+                }
+            }
+            kept.reverse();
+            outcome.renames = kept;
+            break;
+        }
+        outcome
+    }
+}
+
+/// Carry out one action, printing it, and record the result in `outcome`.
+/// Returns whether it succeeded.
+fn execute_action(action: &Action, outcome: &mut Outcome) -> bool {
+    // std::fs switches to `\\?\` verbatim paths on its own for Windows paths
+    // longer than MAX_PATH, so deep trees need no special handling here
+    let result = match action {
+        Action::DeleteFile { path, .. } => fs::remove_file(path),
+        Action::DeleteDir { path } => fs::remove_dir(path),
+        Action::Rename { from, to } => rename(from, to),
+        Action::Hardlink { path, target, .. } => replace_with_hardlink(path, target),
+        Action::Symlink {
+            path, target, link, ..
+        } => replace_with_symlink(path, target, link),
+        Action::Move { from, to } => move_file(from, to),
+    };
+    let succeeded = result.is_ok();
+
+    match (action, result) {
+        (Action::DeleteFile { path, size }, Ok(())) => {
+            outcome.files_deleted += 1;
+            outcome.freed += size;
+            println!(
+                "  {} {}",
+                chars::CROSS_MARK.themed(Element::Error),
+                path.display().to_string().dimmed()
+            );
+        }
+        (Action::DeleteDir { path }, Ok(())) => {
+            outcome.dirs_deleted += 1;
+            println!(
+                "  {} {}",
+                chars::CROSS_MARK.themed(Element::Error),
+                path.display().to_string().dimmed()
+            );
+        }
+        (Action::Rename { from, to }, Ok(())) => {
+            outcome.renamed += 1;
+            outcome.renames.push((from.clone(), to.clone()));
+            println!(
+                "  {} {}",
+                chars::CHECK.themed(Element::Success),
+                display_name(to, from)
+            );
+        }
+        (Action::Hardlink { path, size, .. } | Action::Symlink { path, size, .. }, Ok(())) => {
+            outcome.linked += 1;
+            outcome.freed += size;
+            println!(
+                "  {} {}",
+                chars::CHECK.themed(Element::Success),
+                path.display().to_string().dimmed()
+            );
+        }
+        (Action::Move { from, to }, Ok(())) => {
+            println!(
+                "  {} {}",
+                chars::CHECK.themed(Element::Success),
+                from.display().to_string().dimmed()
+            );
+            outcome.moved.push((from.clone(), to.clone()));
+        }
+        (action, Err(e)) => {
+            outcome.failed += 1;
+            ui::print_error(&format!("{} ({})", action.path().display(), e));
+        }
+    }
+    succeeded
+}
+
+/// Write `command` followed by each path in single quotes. Paths go in as raw
+/// bytes, so names that aren't valid UTF-8 survive too.
+fn shell_line(out: &mut impl Write, command: &str, paths: &[&Path]) -> io::Result<()> {
+    out.write_all(command.as_bytes())?;
+    for path in paths {
+        out.write_all(b" ")?;
+        out.write_all(&shell_quote(path))?;
+    }
+    out.write_all(b"\n")
+}
+
+/// `path` quoted for sh: inside single quotes, with each `'` written as `'\''`
+fn shell_quote(path: &Path) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        if byte == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(byte);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// Rename `from` to `to`, creating the new parent directory when a rename
+/// moves it somewhere that doesn't exist yet
+fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent()
+        && !parent.as_os_str().is_empty()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)
+}
+
+/// Remove the directories a rename to `moved` created, now that the file is
+/// back at `back`: each empty parent up to the first one `back` is in
+pub fn remove_empty_parents(moved: &Path, back: &Path) {
+    for dir in moved.ancestors().skip(1) {
+        if dir.as_os_str().is_empty() || back.starts_with(dir) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// Move a file to `to`, creating its parent directories. A file that turned
+/// up at `to` in the meantime is never replaced: the move fails instead.
+/// Within a filesystem the file is hardlinked to `to` and then unlinked
+/// (renamed where hardlinks aren't supported); across filesystems it is
+/// copied into a new file, synced, and only then removed.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_and_remove(from, to),
+        Err(_) if fs::symlink_metadata(to).is_ok() => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        )),
+        Err(_) => fs::rename(from, to),
+    }
+}
+
+/// Copy `from` into a new file at `to` and remove `from` once the copy is on disk
+fn copy_and_remove(from: &Path, to: &Path) -> io::Result<()> {
+    let mut source = fs::File::open(from)?;
+    let mut copy = fs::File::options().write(true).create_new(true).open(to)?;
+    let copied = io::copy(&mut source, &mut copy)
+        .and_then(|_| copy.set_permissions(source.metadata()?.permissions()))
+        .and_then(|()| copy.sync_all());
+    if let Err(e) = copied {
+        drop(copy);
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+    fs::remove_file(from)
+}
+
+/// Swap `path` for a hardlink to `target` without a moment where `path` is
+/// missing: link under a temporary name next to it, then rename over it
+fn replace_with_hardlink(path: &Path, target: &Path) -> io::Result<()> {
+    if !same_filesystem(path, target)? {
+        return Err(io::Error::other(format!(
+            "{} is on a different filesystem",
+            target.display()
+        )));
+    }
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".ftools-link");
+    let temp = path.with_file_name(temp_name);
+
+    fs::hard_link(target, &temp)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+/// Swap `path` for a symlink reading `link`. The original is set aside first
+/// and put back if the link can't be created or doesn't lead to `target`.
+fn replace_with_symlink(path: &Path, target: &Path, link: &Path) -> io::Result<()> {
+    let backup = link_aside(path)?;
+    if let Err(e) = fs::remove_file(path) {
+        let _ = fs::remove_file(&backup);
+        return Err(e);
+    }
+    let result = create_symlink(link, path).and_then(|()| {
+        if fs::canonicalize(path)? == fs::canonicalize(target)? {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "link does not lead to {}",
+                target.display()
+            )))
+        }
+    });
+    match result {
+        Ok(()) => fs::remove_file(&backup),
+        Err(e) => {
+            if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+                let _ = fs::remove_file(path);
+            }
+            fs::rename(&backup, path)?;
+            Err(e)
+        }
+    }
+}
+
+/// Hardlink `path` as `.<file>.ftools-backup`, or `.ftools-backup1`,
+/// `.ftools-backup2`... when that is taken, and return the name used.
+/// Linking never replaces an existing file.
+fn link_aside(path: &Path) -> io::Result<PathBuf> {
+    for n in 0.. {
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(".ftools-backup");
+        if n > 0 {
+            name.push(n.to_string());
+        }
+        let backup = path.with_file_name(name);
+        match fs::hard_link(path, &backup) {
+            Ok(()) => return Ok(backup),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(unix)]
+fn create_symlink(link: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(link, path)
+}
+
+#[cfg(windows)]
+fn create_symlink(link: &Path, path: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(link, path)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_link: &Path, _path: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Path to `target` as seen from directory `dir`, e.g. "../photos/a.jpg".
+/// Both must be absolute; with nothing in common (another drive) `target` is kept.
+fn relative_path(dir: &Path, target: &Path) -> PathBuf {
+    let dir: Vec<_> = dir.components().collect();
+    let target_parts: Vec<_> = target.components().collect();
+    let common = dir
+        .iter()
+        .zip(&target_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return target.to_path_buf();
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    for part in &target_parts[common..] {
+        relative.push(part);
+    }
+    relative
+}
+
+#[cfg(unix)]
+fn same_filesystem(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(a.metadata()?.dev() == b.metadata()?.dev())
+}
+
+/// Elsewhere `fs::hard_link` itself refuses to link across volumes
+#[cfg(not(unix))]
+fn same_filesystem(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// File name alone when both sides share a directory, otherwise the full path
+fn display_name(path: &Path, other: &Path) -> String {
+    if path.parent() == other.parent() {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        path.display().to_string()
+    }
+}
+
+fn plural(count: usize, singular: &str, plural: &str) -> String {
+    format!("{} {}", count, if count == 1 { singular } else { plural })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote(Path::new("a b.txt")), b"'a b.txt'");
+        assert_eq!(shell_quote(Path::new("it's")), b"'it'\\''s'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_relative_path() {
+        let rel = |dir: &str, target: &str| relative_path(Path::new(dir), Path::new(target));
+        assert_eq!(rel("/a/b", "/a/b/c.txt"), PathBuf::from("c.txt"));
+        assert_eq!(rel("/a/b", "/a/d/c.txt"), PathBuf::from("../d/c.txt"));
+        assert_eq!(rel("/a/b/e", "/x/c.txt"), PathBuf::from("../../../x/c.txt"));
+    }
+
+    #[test]
+    fn test_execute_or_roll_back() {
+        let dir = std::env::temp_dir().join(format!("ftools-rollback-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "").unwrap();
+
+        let mut plan = Plan::new();
+        plan.rename(&dir.join("a.txt"), &dir.join("new/a.txt"));
+        plan.rename(&dir.join("missing.txt"), &dir.join("b.txt"));
+        let outcome = plan.execute_or_roll_back();
+        assert_eq!((outcome.renamed, outcome.failed, outcome.rolled_back), (0, 1, 1));
+        assert!(outcome.renames.is_empty());
+        assert!(dir.join("a.txt").exists());
+        assert!(!dir.join("new").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_file() {
+        let dir = std::env::temp_dir().join(format!("ftools-move-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.txt"), "b").unwrap();
+
+        // A file already at the target stays, and so does the source
+        let err = move_file(&dir.join("a.txt"), &dir.join("b.txt")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "b");
+        assert!(dir.join("a.txt").exists());
+
+        move_file(&dir.join("a.txt"), &dir.join("sub/a.txt")).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("sub/a.txt")).unwrap(), "a");
+        assert!(!dir.join("a.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
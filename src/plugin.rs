@@ -0,0 +1,131 @@
+use anyhow::Result;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::Config;
+use crate::exit::{self, Exit};
+use crate::ui::{self, PathList, Verbosity};
+use crate::utils::current_ignored_dirs;
+
+/// Prefix of executables that extend ftools (`ftools-<name>` runs as `ftools <name>`)
+const PLUGIN_PREFIX: &str = "ftools-";
+
+/// Run an external `ftools-<name>` plugin with the remaining arguments,
+/// exiting with its status
+pub fn run(args: Vec<OsString>) -> Result<()> {
+    let Some((name, rest)) = args.split_first() else {
+        return Err(Exit::with_message(exit::ERROR, "No command given").into());
+    };
+    let name = name.to_string_lossy();
+
+    let Some(program) = find_plugin(&name) else {
+        return Err(Exit::with_message(
+            exit::ERROR,
+            format!(
+                "unknown command '{}' (no {}{} found on PATH, see 'ftools --help')",
+                name, PLUGIN_PREFIX, name
+            ),
+        )
+        .into());
+    };
+
+    ui::print_verbose(&format!("running plugin {}", program.display()));
+
+    let mut command = Command::new(&program);
+    command.args(rest);
+    command.envs(plugin_env());
+
+    exec(command)
+}
+
+/// Settings passed to plugins so they can follow the user's global flags
+fn plugin_env() -> Vec<(&'static str, OsString)> {
+    let verbosity = match ui::verbosity() {
+        Verbosity::Quiet => "quiet",
+        Verbosity::Normal => "normal",
+        Verbosity::Verbose => "verbose",
+        Verbosity::Trace => "trace",
+    };
+    let color = if colored::control::SHOULD_COLORIZE.should_colorize() {
+        "always"
+    } else {
+        "never"
+    };
+    let output = match ui::path_list() {
+        PathList::Off => "text",
+        PathList::Lines => "raw-paths",
+        PathList::Null => "null",
+    };
+
+    let mut env = vec![
+        ("FTOOLS_VERSION", env!("CARGO_PKG_VERSION").into()),
+        ("FTOOLS_VERBOSITY", verbosity.into()),
+        ("FTOOLS_COLOR", color.into()),
+        ("FTOOLS_OUTPUT", output.into()),
+        ("FTOOLS_IGNORE", current_ignored_dirs().join(",").into()),
+    ];
+    if let Some(path) = Config::default_path() {
+        env.push(("FTOOLS_CONFIG", path.into_os_string()));
+    }
+    env
+}
+
+/// Locate `ftools-<name>` in the directories on PATH
+fn find_plugin(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return None;
+    }
+    let file_name = format!("{}{}", PLUGIN_PREFIX, name);
+
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(|dir| candidates(&dir, &file_name))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(windows)]
+fn candidates(dir: &Path, file_name: &str) -> Vec<PathBuf> {
+    let exts = env::var("PATHEXT").unwrap_or_else(|_| ".EXE;.BAT;.CMD".to_string());
+    exts.split(';')
+        .filter(|e| !e.is_empty())
+        .map(|ext| dir.join(format!("{}{}", file_name, ext.to_lowercase())))
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn candidates(dir: &Path, file_name: &str) -> Vec<PathBuf> {
+    vec![dir.join(file_name)]
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Replace the current process with the plugin
+#[cfg(unix)]
+fn exec(mut command: Command) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+    let err = command.exec();
+    Err(Exit::with_message(exit::ERROR, format!("failed to run plugin: {}", err)).into())
+}
+
+/// Run the plugin and pass its exit code through
+#[cfg(not(unix))]
+fn exec(mut command: Command) -> Result<()> {
+    let status = command.status()?;
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(Exit::silent(code).into()),
+        None => Err(Exit::silent(exit::ERROR).into()),
+    }
+}
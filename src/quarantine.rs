@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::exit;
+use crate::plan::move_file;
+use crate::ui::{self, chars, Element, Themed};
+
+/// Name of the manifest kept at the top of a quarantine directory, one
+/// JSON entry per line
+const MANIFEST: &str = "ftools-quarantine.jsonl";
+
+/// One file moved into quarantine
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// Where the file was before it was moved
+    pub original: PathBuf,
+    /// Location inside the quarantine directory, relative to it
+    pub quarantined: PathBuf,
+    pub size: u64,
+    pub hash: String,
+    /// The copy that stayed in place
+    pub kept: PathBuf,
+    pub moved_at: String,
+}
+
+impl Entry {
+    /// Paths are stored absolute so a restore works from any directory
+    pub fn new(original: &Path, quarantined: &Path, size: u64, hash: &str, kept: &Path) -> Self {
+        let absolute = |p: &Path| std::path::absolute(p).unwrap_or_else(|_| p.to_path_buf());
+        Entry {
+            original: absolute(original),
+            quarantined: quarantined.to_path_buf(),
+            size,
+            hash: hash.to_string(),
+            kept: absolute(kept),
+            moved_at: Local::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Where `file` goes inside `dir`: its absolute path mirrored below the
+/// quarantine root, so files from several scan roots never collide. A copy
+/// quarantined there by an earlier run is kept, and the new one numbered
+/// ("a.txt.1", "a.txt.2"...).
+pub fn target_path(dir: &Path, file: &Path) -> PathBuf {
+    let target = mirrored_path(dir, file);
+    if target.symlink_metadata().is_err() {
+        return target;
+    }
+    let name = target.file_name().unwrap_or_default().to_os_string();
+    (1..)
+        .map(|n| {
+            let mut numbered = name.clone();
+            numbered.push(format!(".{}", n));
+            target.with_file_name(numbered)
+        })
+        .find(|candidate| candidate.symlink_metadata().is_err())
+        .unwrap_or(target)
+}
+
+fn mirrored_path(dir: &Path, file: &Path) -> PathBuf {
+    let absolute = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    let mut target = dir.to_path_buf();
+    for component in absolute.components() {
+        match component {
+            Component::Normal(part) => target.push(part),
+            // "C:" becomes a plain "C" directory
+            Component::Prefix(prefix) => target.push(
+                prefix
+                    .as_os_str()
+                    .to_string_lossy()
+                    .replace([':', '\\', '?'], ""),
+            ),
+            _ => {}
+        }
+    }
+    target
+}
+
+fn load(dir: &Path) -> Result<Vec<Entry>> {
+    match fs::read_to_string(dir.join(MANIFEST)) {
+        Ok(data) => data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(Into::into))
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save(dir: &Path, entries: &[Entry]) -> Result<()> {
+    let path = dir.join(MANIFEST);
+    if entries.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        return Ok(());
+    }
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut data = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut data, entry)?;
+        data.push(b'\n');
+    }
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Add a file to the manifest of `dir` before it is moved there, so a run
+/// cut short never leaves a quarantined file that can't be restored
+pub fn record(dir: &Path, entry: &Entry) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(MANIFEST))?
+        .write_all(&line)
+}
+
+/// Move every file listed in the manifest of `dir` back to where it came
+/// from. Files whose original path is taken again stay in quarantine.
+pub fn restore(dir: &Path) -> Result<()> {
+    let entries = load(dir)?;
+    if entries.is_empty() {
+        return Err(anyhow!("No {} found in {}", MANIFEST, dir.display()));
+    }
+
+    ui::print_start("Restoring from", &dir.display().to_string());
+    ui::print_blank();
+
+    let mut remaining = Vec::new();
+    let mut restored = 0;
+    for entry in entries {
+        let source = dir.join(&entry.quarantined);
+        // Recorded, but the move failed or never ran
+        if source.symlink_metadata().is_err() && entry.original.exists() {
+            continue;
+        }
+        let result = if entry.original.exists() {
+            Err(anyhow!("{} already exists", entry.original.display()))
+        } else {
+            move_file(&source, &entry.original).map_err(Into::into)
+        };
+
+        match result {
+            Ok(()) => {
+                restored += 1;
+                remove_empty_parents(&source, dir);
+                println!(
+                    "  {} {}",
+                    chars::CHECK.themed(Element::Success),
+                    entry.original.display()
+                );
+            }
+            Err(e) => {
+                ui::print_error(&format!("{} ({})", source.display(), e));
+                remaining.push(entry);
+            }
+        }
+    }
+    save(dir, &remaining)?;
+
+    println!();
+    ui::print_success(&format!("Restored {} files", restored));
+    exit::partial(remaining.len(), "restores")
+}
+
+/// Remove the directories left empty between `file` and the quarantine root
+fn remove_empty_parents(file: &Path, root: &Path) {
+    for parent in file.ancestors().skip(1) {
+        if parent == root || fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_target_path() {
+        assert_eq!(
+            target_path(Path::new("/q"), Path::new("/nonexistent/a/b.txt")),
+            PathBuf::from("/q/nonexistent/a/b.txt")
+        );
+    }
+
+    #[test]
+    fn test_restore() {
+        let root = std::env::temp_dir().join(format!("ftools-quarantine-{}", std::process::id()));
+        let (scan, dir) = (root.join("scan"), root.join("quarantine"));
+        fs::create_dir_all(&scan).unwrap();
+        let quarantine = |name: &str| {
+            let original = scan.join(name);
+            fs::write(&original, name).unwrap();
+            let target = target_path(&dir, &original);
+            let relative = target.strip_prefix(&dir).unwrap();
+            let entry = Entry::new(&original, relative, 1, "hash", &scan.join("kept"));
+            record(&dir, &entry).unwrap();
+            move_file(&original, &target).unwrap();
+            target
+        };
+
+        // The same path quarantined twice keeps both copies
+        let first = quarantine("a.txt");
+        let second = quarantine("a.txt");
+        assert_ne!(first, second);
+        assert!(first.exists() && second.exists());
+        // An entry recorded for a move that didn't happen is dropped
+        let entry = Entry::new(&scan.join("b.txt"), Path::new("b.txt"), 1, "hash", &scan);
+        record(&dir, &entry).unwrap();
+        fs::write(scan.join("b.txt"), "b").unwrap();
+
+        // The first copy goes back; the second finds its place taken
+        assert!(restore(&dir).is_err());
+        assert_eq!(fs::read_to_string(scan.join("a.txt")).unwrap(), "a.txt");
+        assert!(!first.exists() && second.exists());
+        let left = load(&dir).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(dir.join(&left[0].quarantined), second);
+
+        fs::remove_file(scan.join("a.txt")).unwrap();
+        restore(&dir).unwrap();
+        assert!(scan.join("a.txt").exists());
+        assert!(!dir.join(MANIFEST).exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
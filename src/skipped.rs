@@ -0,0 +1,128 @@
+use colored::*;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::ui::{self, chars, Element, Themed, Verbosity};
+
+/// Why a path could not be processed
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+    PermissionDenied,
+    NotFound,
+    SymlinkLoop,
+    Io,
+}
+
+impl Reason {
+    fn from_io(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => Reason::PermissionDenied,
+            io::ErrorKind::NotFound => Reason::NotFound,
+            _ => Reason::Io,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Reason::PermissionDenied => "permission denied",
+            Reason::NotFound => "not found",
+            Reason::SymlinkLoop => "symlink loop",
+            Reason::Io => "I/O error",
+        }
+    }
+}
+
+struct Skip {
+    path: String,
+    reason: Reason,
+    detail: String,
+}
+
+/// Paths skipped during this run, reported once the command finishes
+static SKIPPED: Mutex<Vec<Skip>> = Mutex::new(Vec::new());
+
+fn record(path: String, reason: Reason, detail: String) {
+    ui::print_trace(&format!("skipped: {} ({})", path, detail));
+    if let Ok(mut skipped) = SKIPPED.lock() {
+        skipped.push(Skip {
+            path,
+            reason,
+            detail,
+        });
+    }
+}
+
+/// Record a file that could not be read or examined
+pub fn record_io(path: &Path, err: &io::Error) {
+    record(
+        path.display().to_string(),
+        Reason::from_io(err),
+        err.to_string(),
+    );
+}
+
+/// Record a failure from any operation on `path` (hashing, searching, ...)
+pub fn record_error(path: &Path, err: &anyhow::Error) {
+    match err.downcast_ref::<io::Error>() {
+        Some(io_err) => record_io(path, io_err),
+        None => record(path.display().to_string(), Reason::Io, err.to_string()),
+    }
+}
+
+/// Record an entry a directory walk could not visit
+pub fn record_walk(err: &walkdir::Error) {
+    let path = err
+        .path()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default();
+    let reason = if err.loop_ancestor().is_some() {
+        Reason::SymlinkLoop
+    } else {
+        err.io_error().map(Reason::from_io).unwrap_or(Reason::Io)
+    };
+    record(path, reason, err.to_string());
+}
+
+/// Footer such as "37 paths skipped (permission denied: 30, I/O error: 7)";
+/// each path is listed at -v
+pub fn print_summary() {
+    let Ok(skipped) = SKIPPED.lock() else {
+        return;
+    };
+    if skipped.is_empty() || ui::is_quiet() {
+        return;
+    }
+
+    let mut by_reason: BTreeMap<Reason, usize> = BTreeMap::new();
+    for skip in skipped.iter() {
+        *by_reason.entry(skip.reason).or_insert(0) += 1;
+    }
+    let breakdown: Vec<String> = by_reason
+        .iter()
+        .map(|(reason, count)| format!("{}: {}", reason.label(), count))
+        .collect();
+
+    eprintln!();
+    eprintln!(
+        "{} {} {} skipped ({})",
+        chars::WARNING.themed(Element::Warning).bold(),
+        skipped.len().to_string().themed(Element::Warning).bold(),
+        if skipped.len() == 1 { "path" } else { "paths" },
+        breakdown.join(", ")
+    );
+
+    if ui::verbosity() >= Verbosity::Verbose {
+        for skip in skipped.iter() {
+            eprintln!(
+                "  {} {} {}",
+                chars::DOT.themed(Element::Muted),
+                skip.path,
+                format!("({})", skip.detail).themed(Element::Muted)
+            );
+        }
+    } else {
+        eprintln!("  {}", "run with -v to list them".themed(Element::Muted));
+    }
+}
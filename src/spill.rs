@@ -0,0 +1,445 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use crate::checkpoint::IndexedFile;
+use crate::utils::{bytes_to_path, private_temp_dir};
+
+/// Smallest memory budget accepted; less would only multiply the run files
+pub const MIN_BUDGET: u64 = 8 * 1024 * 1024;
+
+/// Sorts scanned files by size within a memory budget: files are buffered
+/// until the budget is used up, then sorted and written to a temporary run
+/// file. `into_groups` merges the runs back, one size at a time.
+pub struct SizeSorter {
+    budget: usize,
+    used: usize,
+    buffer: Vec<IndexedFile>,
+    runs: Vec<PathBuf>,
+    /// Private directory of the run files, made at the first spill
+    dir: Option<PathBuf>,
+}
+
+impl SizeSorter {
+    pub fn new(budget: u64) -> Self {
+        SizeSorter {
+            budget: budget.max(MIN_BUDGET) as usize,
+            used: 0,
+            buffer: Vec::new(),
+            runs: Vec::new(),
+            dir: None,
+        }
+    }
+
+    /// Number of run files written so far
+    pub fn spills(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn push(&mut self, file: IndexedFile) -> io::Result<()> {
+        self.used += std::mem::size_of::<IndexedFile>() + file.path.as_os_str().len();
+        self.buffer.push(file);
+        if self.used >= self.budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.buffer.sort_by_key(|f| f.size);
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => self.dir.insert(private_temp_dir("ftools-spill")?),
+        };
+        let path = dir.join(format!("run-{}", self.runs.len()));
+        let mut out = BufWriter::new(File::options().write(true).create_new(true).open(&path)?);
+        for file in self.buffer.drain(..) {
+            write_record(&mut out, &file)?;
+        }
+        out.flush()?;
+        self.runs.push(path);
+        self.used = 0;
+        Ok(())
+    }
+
+    /// Files grouped by size, smallest size first
+    pub fn into_groups(mut self) -> io::Result<SizeGroups> {
+        let mut sources = Vec::with_capacity(self.runs.len() + 1);
+        for run in &self.runs {
+            sources.push(Source::Run(BufReader::new(File::open(run)?)));
+        }
+        self.buffer.sort_by_key(|f| f.size);
+        sources.push(Source::Memory(std::mem::take(&mut self.buffer).into_iter()));
+
+        let mut groups = SizeGroups {
+            heads: Vec::with_capacity(sources.len()),
+            sources,
+            heap: BinaryHeap::new(),
+            dir: std::mem::take(&mut self.dir),
+        };
+        for i in 0..groups.sources.len() {
+            groups.heads.push(None);
+            groups.advance(i)?;
+        }
+        Ok(groups)
+    }
+}
+
+enum Source {
+    Run(BufReader<File>),
+    Memory(std::vec::IntoIter<IndexedFile>),
+}
+
+impl Source {
+    fn next_file(&mut self) -> io::Result<Option<IndexedFile>> {
+        match self {
+            Source::Run(reader) => read_record(reader),
+            Source::Memory(files) => Ok(files.next()),
+        }
+    }
+}
+
+/// Merge of the sorted runs, yielding each size with all files of that size
+pub struct SizeGroups {
+    sources: Vec<Source>,
+    /// Next file of each source
+    heads: Vec<Option<IndexedFile>>,
+    /// Size of each source's next file, smallest on top
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+    dir: Option<PathBuf>,
+}
+
+impl SizeGroups {
+    fn advance(&mut self, source: usize) -> io::Result<()> {
+        let next = self.sources[source].next_file()?;
+        if let Some(file) = &next {
+            self.heap.push(Reverse((file.size, source)));
+        }
+        self.heads[source] = next;
+        Ok(())
+    }
+}
+
+impl Iterator for SizeGroups {
+    type Item = io::Result<(u64, Vec<IndexedFile>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((size, _)) = *self.heap.peek()?;
+        let mut files = Vec::new();
+        while let Some(&Reverse((next_size, source))) = self.heap.peek() {
+            if next_size != size {
+                break;
+            }
+            self.heap.pop();
+            files.extend(self.heads[source].take());
+            if let Err(e) = self.advance(source) {
+                return Some(Err(e));
+            }
+        }
+        Some(Ok((size, files)))
+    }
+}
+
+impl Drop for SizeGroups {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+impl Drop for SizeSorter {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// A value with a binary layout of its own, so `Records` can keep it on disk
+pub trait Record: Sized {
+    fn write(&self, out: &mut dyn Write) -> io::Result<()>;
+
+    /// The next record, or `None` at the end of the file
+    fn read(input: &mut dyn Read) -> io::Result<Option<Self>>;
+}
+
+/// Records in the order they were pushed: in memory, or under a memory
+/// budget appended to a temporary file and read back one at a time.
+/// `finish` must be called after the last push.
+pub enum Records<T> {
+    Memory(Vec<T>),
+    Disk(RecordFile<T>),
+}
+
+/// The file behind `Records::Disk`, made at the first push
+pub struct RecordFile<T> {
+    out: Option<BufWriter<File>>,
+    dir: Option<PathBuf>,
+    len: usize,
+    record: PhantomData<T>,
+}
+
+impl<T: Record + 'static> Records<T> {
+    pub fn new(on_disk: bool) -> Self {
+        if on_disk {
+            Records::Disk(RecordFile {
+                out: None,
+                dir: None,
+                len: 0,
+                record: PhantomData,
+            })
+        } else {
+            Records::Memory(Vec::new())
+        }
+    }
+
+    pub fn on_disk(&self) -> bool {
+        matches!(self, Records::Disk(_))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Records::Memory(records) => records.len(),
+            Records::Disk(file) => file.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, record: T) -> io::Result<()> {
+        let file = match self {
+            Records::Memory(records) => {
+                records.push(record);
+                return Ok(());
+            }
+            Records::Disk(file) => file,
+        };
+        let out = match &mut file.out {
+            Some(out) => out,
+            None => {
+                let dir = file.dir.insert(private_temp_dir("ftools-spill")?);
+                let path = dir.join("records");
+                let created = File::options().write(true).create_new(true).open(path)?;
+                file.out.insert(BufWriter::new(created))
+            }
+        };
+        record.write(out)?;
+        file.len += 1;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Records::Disk(RecordFile { out: Some(out), .. }) => out.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Call `f` with each record in turn
+    pub fn for_each<E: From<io::Error>>(
+        &self,
+        mut f: impl FnMut(&T) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match self {
+            Records::Memory(records) => records.iter().try_for_each(f),
+            Records::Disk(file) => {
+                let Some(dir) = &file.dir else {
+                    return Ok(());
+                };
+                let mut input = BufReader::new(File::open(dir.join("records"))?);
+                while let Some(record) = T::read(&mut input)? {
+                    f(&record)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The records, taken out one at a time
+    pub fn drain(self) -> io::Result<Box<dyn Iterator<Item = io::Result<T>>>> {
+        match self {
+            Records::Memory(records) => Ok(Box::new(records.into_iter().map(Ok))),
+            Records::Disk(mut file) => {
+                // Written out, and closed before its directory is removed
+                file.out = None;
+                let input = match &file.dir {
+                    Some(dir) => Some(BufReader::new(File::open(dir.join("records"))?)),
+                    None => None,
+                };
+                Ok(Box::new(RecordReader { input, file }))
+            }
+        }
+    }
+}
+
+/// Reads `Records::Disk` back, keeping its file until done
+struct RecordReader<T> {
+    input: Option<BufReader<File>>,
+    file: RecordFile<T>,
+}
+
+impl<T: Record> Iterator for RecordReader<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.input.as_mut()?;
+        let record = T::read(input).transpose();
+        if record.is_none() {
+            self.input = None;
+            if let Some(dir) = self.file.dir.take() {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+        record
+    }
+}
+
+impl<T> Drop for RecordFile<T> {
+    fn drop(&mut self) {
+        self.out = None;
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+pub fn write_u64(out: &mut dyn Write, n: u64) -> io::Result<()> {
+    out.write_all(&n.to_le_bytes())
+}
+
+pub fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// The number a record starts with, or `None` at the end of the file
+pub fn read_first_u64(input: &mut dyn Read) -> io::Result<Option<u64>> {
+    match read_u64(input) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        result => result.map(Some),
+    }
+}
+
+/// Length, then bytes
+pub fn write_bytes(out: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    write_u64(out, bytes.len() as u64)?;
+    out.write_all(bytes)
+}
+
+pub fn read_bytes(input: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; read_u64(input)? as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub fn write_path(out: &mut dyn Write, path: &Path) -> io::Result<()> {
+    write_bytes(out, path.as_os_str().as_encoded_bytes())
+}
+
+pub fn read_path(input: &mut dyn Read) -> io::Result<PathBuf> {
+    Ok(bytes_to_path(&read_bytes(input)?))
+}
+
+/// size, reference flag, hardlink id (flag, device, inode), path length, path bytes
+fn write_record(out: &mut impl Write, file: &IndexedFile) -> io::Result<()> {
+    let path = file.path.as_os_str().as_encoded_bytes();
+    let (linked, (device, inode)) = match file.link {
+        Some(id) => (1u8, id),
+        None => (0u8, (0, 0)),
+    };
+    out.write_all(&file.size.to_le_bytes())?;
+    out.write_all(&[u8::from(file.reference), linked])?;
+    out.write_all(&device.to_le_bytes())?;
+    out.write_all(&inode.to_le_bytes())?;
+    out.write_all(&(path.len() as u64).to_le_bytes())?;
+    out.write_all(path)
+}
+
+fn read_record(input: &mut impl Read) -> io::Result<Option<IndexedFile>> {
+    let mut size = [0u8; 8];
+    match input.read_exact(&mut size) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let mut flags = [0u8; 2];
+    input.read_exact(&mut flags)?;
+    let mut numbers = [[0u8; 8]; 3];
+    for number in &mut numbers {
+        input.read_exact(number)?;
+    }
+    let [device, inode, length] = numbers.map(u64::from_le_bytes);
+    let mut path = vec![0u8; length as usize];
+    input.read_exact(&mut path)?;
+
+    Ok(Some(IndexedFile {
+        path: bytes_to_path(&path),
+        size: u64::from_le_bytes(size),
+        link: (flags[1] == 1).then_some((device, inode)),
+        reference: flags[0] == 1,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_groups_merge_runs() {
+        let mut sorter = SizeSorter::new(0);
+        sorter.budget = 3 * (std::mem::size_of::<IndexedFile>() + 1);
+        for (name, size) in [
+            ("a", 5),
+            ("b", 1),
+            ("c", 5),
+            ("d", 3),
+            ("e", 1),
+            ("f", 5),
+            ("g", 2),
+        ] {
+            sorter
+                .push(IndexedFile {
+                    path: PathBuf::from(name),
+                    size,
+                    link: (name == "c").then_some((1, 2)),
+                    reference: name == "d",
+                })
+                .unwrap();
+        }
+        assert_eq!(sorter.spills(), 2);
+
+        let groups: Vec<(u64, Vec<IndexedFile>)> =
+            sorter.into_groups().unwrap().map(Result::unwrap).collect();
+        let summary: Vec<(u64, Vec<String>)> = groups
+            .iter()
+            .map(|(size, files)| {
+                let mut names: Vec<String> =
+                    files.iter().map(|f| f.path.display().to_string()).collect();
+                names.sort();
+                (*size, names)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, vec!["b".to_string(), "e".to_string()]),
+                (2, vec!["g".to_string()]),
+                (3, vec!["d".to_string()]),
+                (5, vec!["a".to_string(), "c".to_string(), "f".to_string()]),
+            ]
+        );
+        let c = groups[3]
+            .1
+            .iter()
+            .find(|f| f.path.as_os_str() == "c")
+            .unwrap();
+        assert_eq!(c.link, Some((1, 2)));
+        assert!(groups[2].1[0].reference);
+    }
+}
@@ -0,0 +1,276 @@
+use anyhow::{anyhow, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Lines moved by PageUp/PageDown
+const PAGE: usize = 20;
+
+/// One matching line to browse
+pub struct Hit {
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: usize,
+    pub text: String,
+}
+
+/// Browse `hits` in a full-screen list with a preview of the file around
+/// the selected line; `/` filters the list, Enter opens $EDITOR at the line
+pub fn browse(hits: Vec<Hit>) -> Result<()> {
+    let mut browser = Browser {
+        shown: (0..hits.len()).collect(),
+        hits,
+        filter: String::new(),
+        editing: false,
+        list: ListState::default().with_selected(Some(0)),
+        preview: None,
+    };
+    let mut terminal = ratatui::try_init()?;
+    let result = browser.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+struct Browser {
+    hits: Vec<Hit>,
+    /// Indices of the hits that contain the filter
+    shown: Vec<usize>,
+    filter: String,
+    /// Whether keys go to the filter
+    editing: bool,
+    list: ListState,
+    /// Lines of the file being previewed
+    preview: Option<(PathBuf, Vec<String>)>,
+}
+
+impl Browser {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                return Ok(());
+            }
+
+            if self.editing {
+                match key.code {
+                    KeyCode::Enter => self.editing = false,
+                    KeyCode::Esc => {
+                        self.editing = false;
+                        self.filter.clear();
+                        self.refilter();
+                    }
+                    KeyCode::Backspace => {
+                        self.filter.pop();
+                        self.refilter();
+                    }
+                    KeyCode::Char(c) => {
+                        self.filter.push(c);
+                        self.refilter();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('/') => self.editing = true,
+                KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+                KeyCode::PageDown => self.move_by(PAGE as isize),
+                KeyCode::PageUp => self.move_by(-(PAGE as isize)),
+                KeyCode::Home | KeyCode::Char('g') => self.list.select(Some(0)),
+                KeyCode::End | KeyCode::Char('G') => {
+                    self.list.select(Some(self.shown.len().saturating_sub(1)))
+                }
+                KeyCode::Enter => {
+                    let Some(hit) = self.current() else {
+                        continue;
+                    };
+                    let (path, line) = (hit.path.clone(), hit.line);
+                    ratatui::restore();
+                    let opened = open_in_editor(&path, line);
+                    *terminal = ratatui::try_init()?;
+                    opened?;
+                    // The file may have been edited
+                    self.preview = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn current(&self) -> Option<&Hit> {
+        let selected = self.list.selected()?;
+        self.shown.get(selected).map(|&i| &self.hits[i])
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let last = self.shown.len().saturating_sub(1);
+        let selected = self.list.selected().unwrap_or(0);
+        self.list
+            .select(Some(selected.saturating_add_signed(delta).min(last)));
+    }
+
+    /// Keep the hits whose path, line number or text contain the filter
+    /// (ignoring case)
+    fn refilter(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.shown = (0..self.hits.len())
+            .filter(|&i| {
+                let hit = &self.hits[i];
+                format!("{}:{} {}", hit.path.display(), hit.line, hit.text)
+                    .to_lowercase()
+                    .contains(&filter)
+            })
+            .collect();
+        self.list.select(Some(0));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [top, body, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(body);
+
+        let cursor = if self.editing { "▏" } else { "" };
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                Span::styled("Filter: ", Style::new().add_modifier(Modifier::BOLD)),
+                Span::raw(format!("{}{}", self.filter, cursor)),
+            ])),
+            top,
+        );
+
+        let items: Vec<ListItem> = self
+            .shown
+            .iter()
+            .map(|&i| {
+                let hit = &self.hits[i];
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        format!("{}:{} ", hit.path.display(), hit.line),
+                        Style::new().fg(Color::Cyan),
+                    ),
+                    Span::raw(hit.text.trim().to_string()),
+                ]))
+            })
+            .collect();
+        let title = format!(" {} of {} matches ", self.shown.len(), self.hits.len());
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list);
+
+        self.draw_preview(frame, preview_area);
+
+        frame.render_widget(
+            Paragraph::new("↑↓ move  / filter  Enter open in $EDITOR  q quit")
+                .style(Style::new().fg(Color::DarkGray)),
+            help,
+        );
+    }
+
+    /// The selected hit's file, centred on its line
+    fn draw_preview(&mut self, frame: &mut Frame, area: Rect) {
+        let Some((path, line)) = self.current().map(|hit| (hit.path.clone(), hit.line)) else {
+            frame.render_widget(Block::bordered(), area);
+            return;
+        };
+        if self.preview.as_ref().is_none_or(|(p, _)| *p != path) {
+            // Archive members and vanished files have nothing to show
+            let lines = fs::read(&path)
+                .map(|data| {
+                    String::from_utf8_lossy(&data)
+                        .lines()
+                        .map(|l| l.replace('\t', "    "))
+                        .collect()
+                })
+                .unwrap_or_default();
+            self.preview = Some((path.clone(), lines));
+        }
+        let Some((_, lines)) = &self.preview else {
+            return;
+        };
+
+        let height = area.height.saturating_sub(2) as usize;
+        let start = line.saturating_sub(1 + height / 2);
+        let text: Vec<Line> = lines
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(height)
+            .map(|(i, content)| {
+                let number =
+                    Span::styled(format!("{:>5} ", i + 1), Style::new().fg(Color::DarkGray));
+                let style = if i + 1 == line {
+                    Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                } else {
+                    Style::new()
+                };
+                Line::from(vec![number, Span::styled(content.as_str(), style)])
+            })
+            .collect();
+        let title = format!(" {} ", path.display());
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+}
+
+/// Open `path` at `line` in $VISUAL or $EDITOR (vi without either) and wait
+/// for the editor to close
+pub fn open_in_editor(path: &Path, line: usize) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let mut command = Command::new(program);
+    command.args(words);
+
+    // Editors differ in how they take a line number; most accept +N. GUI
+    // editors return at once unless told to wait.
+    let name = Path::new(program)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match name.as_str() {
+        "code" | "codium" => {
+            command
+                .arg("--wait")
+                .arg("--goto")
+                .arg(format!("{}:{}", path.display(), line))
+        }
+        "subl" | "zed" => command
+            .arg("--wait")
+            .arg(format!("{}:{}", path.display(), line)),
+        _ => command.arg(format!("+{}", line)).arg(path),
+    };
+
+    let status = command
+        .status()
+        .map_err(|e| anyhow!("Could not run {}: {}", program, e))?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
@@ -1,7 +1,16 @@
+use anyhow::{anyhow, Result};
 use colored::*;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
 
-/// Professional CLI UI module - No emojis, clean design
-/// Inspired by: ripgrep, fd, exa, bat, tokei
+use crate::config::ThemeConfig;
+
+// Professional CLI UI module - No emojis, clean design
+// Inspired by: ripgrep, fd, exa, bat, tokei
 
 // Box drawing characters and icons
 pub mod chars {
@@ -23,9 +32,319 @@ pub mod chars {
     pub const WARNING: &str = "!";
 }
 
+/// Parts of the output that take their color from the theme
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Element {
+    Header,
+    Section,
+    Path,
+    Size,
+    Match,
+    Success,
+    Error,
+    Warning,
+    Info,
+    Text,
+    Muted,
+    Progress,
+}
+
+/// Element names as written in the `[theme]` config table
+const ELEMENT_NAMES: [(&str, Element); 12] = [
+    ("header", Element::Header),
+    ("section", Element::Section),
+    ("path", Element::Path),
+    ("size", Element::Size),
+    ("match", Element::Match),
+    ("success", Element::Success),
+    ("error", Element::Error),
+    ("warning", Element::Warning),
+    ("info", Element::Info),
+    ("text", Element::Text),
+    ("muted", Element::Muted),
+    ("progress", Element::Progress),
+];
+
+/// Color for each element; `None` leaves the text uncolored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Theme {
+    colors: [Option<Color>; ELEMENT_NAMES.len()],
+}
+
+impl Theme {
+    /// Bright colors for dark terminals
+    pub const DEFAULT: Theme = Theme {
+        colors: [
+            Some(Color::BrightCyan),
+            Some(Color::BrightYellow),
+            Some(Color::BrightBlue),
+            Some(Color::BrightYellow),
+            Some(Color::BrightRed),
+            Some(Color::BrightGreen),
+            Some(Color::BrightRed),
+            Some(Color::BrightYellow),
+            Some(Color::BrightBlue),
+            Some(Color::BrightWhite),
+            Some(Color::BrightBlack),
+            Some(Color::BrightCyan),
+        ],
+    };
+
+    /// Darker colors that stay readable on white backgrounds
+    pub const LIGHT: Theme = Theme {
+        colors: [
+            Some(Color::Blue),
+            Some(Color::Magenta),
+            Some(Color::Blue),
+            Some(Color::Magenta),
+            Some(Color::Red),
+            Some(Color::Green),
+            Some(Color::Red),
+            Some(Color::Yellow),
+            Some(Color::Blue),
+            Some(Color::Black),
+            Some(Color::BrightBlack),
+            Some(Color::Blue),
+        ],
+    };
+
+    /// No colors at all, only bold and dim
+    pub const MONOCHROME: Theme = Theme {
+        colors: [None; ELEMENT_NAMES.len()],
+    };
+
+    pub fn builtin(name: &str) -> Option<Theme> {
+        match name.to_lowercase().as_str() {
+            "default" | "dark" => Some(Theme::DEFAULT),
+            "light" | "light-terminal" => Some(Theme::LIGHT),
+            "monochrome" | "mono" => Some(Theme::MONOCHROME),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self, element: Element) -> Option<Color> {
+        self.colors[element as usize]
+    }
+
+    /// Resolve the configured theme: a palette or built-in by name, then the
+    /// per-element overrides from `[theme.colors]`
+    pub fn from_config(config: &ThemeConfig) -> Result<Theme> {
+        let name = config.name.as_deref().unwrap_or("default");
+        let mut theme = match config.palettes.get(name) {
+            Some(palette) => {
+                let base = palette.get("base").map(String::as_str).unwrap_or("default");
+                let mut theme = Theme::builtin(base).ok_or_else(|| {
+                    anyhow!("Unknown base theme '{}' for palette '{}'", base, name)
+                })?;
+                theme.apply(palette.iter().filter(|(key, _)| key.as_str() != "base"))?;
+                theme
+            }
+            None => Theme::builtin(name).ok_or_else(|| {
+                anyhow!(
+                    "Unknown theme '{}'. Use default, light, monochrome or a [theme.palettes] entry",
+                    name
+                )
+            })?,
+        };
+        theme.apply(config.colors.iter())?;
+        Ok(theme)
+    }
+
+    fn apply<'a>(&mut self, colors: impl Iterator<Item = (&'a String, &'a String)>) -> Result<()> {
+        for (key, value) in colors {
+            let element = ELEMENT_NAMES
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, element)| *element)
+                .ok_or_else(|| anyhow!("Unknown theme element '{}'", key))?;
+            self.colors[element as usize] = parse_color(value)?;
+        }
+        Ok(())
+    }
+
+    /// Style suffix for indicatif templates; 24-bit colors have no equivalent there
+    fn progress_style(&self) -> &'static str {
+        match self.color(Element::Progress) {
+            Some(Color::Black) => ".black",
+            Some(Color::Red) => ".red",
+            Some(Color::Green) => ".green",
+            Some(Color::Yellow) => ".yellow",
+            Some(Color::Blue) => ".blue",
+            Some(Color::Magenta) => ".magenta",
+            Some(Color::Cyan) => ".cyan",
+            Some(Color::White) => ".white",
+            Some(Color::BrightBlack) => ".black.bright",
+            Some(Color::BrightRed) => ".red.bright",
+            Some(Color::BrightGreen) => ".green.bright",
+            Some(Color::BrightYellow) => ".yellow.bright",
+            Some(Color::BrightBlue) => ".blue.bright",
+            Some(Color::BrightMagenta) => ".magenta.bright",
+            Some(Color::BrightCyan) => ".cyan.bright",
+            Some(Color::BrightWhite) => ".white.bright",
+            Some(Color::TrueColor { .. }) | None => "",
+        }
+    }
+}
+
+/// Parse a color name ("cyan", "bright blue", "bright_blue"), "#rrggbb" or "none"
+pub fn parse_color(value: &str) -> Result<Option<Color>> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    if let Some(hex) = value.strip_prefix('#') {
+        let rgb = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)
+            .ok_or_else(|| anyhow!("Invalid color: {}", value))?;
+        return Ok(Some(Color::TrueColor {
+            r: (rgb >> 16) as u8,
+            g: (rgb >> 8) as u8,
+            b: rgb as u8,
+        }));
+    }
+    value
+        .replace(['_', '-'], " ")
+        .parse::<Color>()
+        .map(Some)
+        .map_err(|_| anyhow!("Invalid color: {}", value))
+}
+
+static THEME: RwLock<Theme> = RwLock::new(Theme::DEFAULT);
+
+/// Set the process-wide color theme
+pub fn set_theme(theme: Theme) {
+    if let Ok(mut current) = THEME.write() {
+        *current = theme;
+    }
+}
+
+/// Current color theme
+pub fn theme() -> Theme {
+    THEME.read().map(|t| *t).unwrap_or(Theme::DEFAULT)
+}
+
+/// Color text by its role in the output, e.g. `path.themed(Element::Path)`
+pub trait Themed {
+    fn themed(self, element: Element) -> ColoredString;
+}
+
+impl<T: Colorize> Themed for T {
+    fn themed(self, element: Element) -> ColoredString {
+        match theme().color(element) {
+            Some(color) => self.color(color),
+            None => self.normal(),
+        }
+    }
+}
+
+/// Output verbosity, selected once from the global `--quiet` / `-v` flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet = 0,
+    Normal = 1,
+    Verbose = 2,
+    Trace = 3,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Set the process-wide verbosity level
+pub fn set_verbosity(level: Verbosity) {
+    VERBOSITY.store(level as u8, Ordering::Relaxed);
+}
+
+/// Current verbosity level
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::Trace,
+    }
+}
+
+/// True when only results should be printed
+pub fn is_quiet() -> bool {
+    verbosity() == Verbosity::Quiet
+}
+
+/// How result paths are printed when a lossless path list was requested
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathList {
+    Off = 0,
+    /// One raw path per line (`--raw-paths`)
+    Lines = 1,
+    /// NUL-terminated raw paths (`--null`)
+    Null = 2,
+}
+
+static PATH_LIST: AtomicU8 = AtomicU8::new(PathList::Off as u8);
+
+/// Set the process-wide path list mode
+pub fn set_path_list(mode: PathList) {
+    PATH_LIST.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Current path list mode
+pub fn path_list() -> PathList {
+    match PATH_LIST.load(Ordering::Relaxed) {
+        1 => PathList::Lines,
+        2 => PathList::Null,
+        _ => PathList::Off,
+    }
+}
+
+/// True when commands should print only their result paths via `emit_path`
+pub fn is_path_list() -> bool {
+    PATH_LIST.load(Ordering::Relaxed) != PathList::Off as u8
+}
+
+/// Write one result path byte-for-byte as stored on disk, followed by a
+/// newline or NUL depending on the path list mode
+pub fn emit_path(path: &Path) {
+    let terminator: &[u8] = if PATH_LIST.load(Ordering::Relaxed) == PathList::Null as u8 {
+        b"\0"
+    } else {
+        b"\n"
+    };
+    let mut out = io::stdout().lock();
+    let _ = out
+        .write_all(path.as_os_str().as_encoded_bytes())
+        .and_then(|_| out.write_all(terminator));
+}
+
+/// Print a blank spacer line (suppressed in quiet mode)
+pub fn print_blank() {
+    if !is_quiet() {
+        println!();
+    }
+}
+
+/// Print a diagnostic line to stderr (-v and above)
+pub fn print_verbose(message: &str) {
+    if verbosity() >= Verbosity::Verbose {
+        eprintln!(
+            "{} {}",
+            chars::DOT.themed(Element::Muted),
+            message.themed(Element::Muted)
+        );
+    }
+}
+
+/// Print a per-file trace line to stderr (-vv)
+pub fn print_trace(message: &str) {
+    if verbosity() >= Verbosity::Trace {
+        eprintln!("{} {}", chars::DOT.themed(Element::Muted), message.dimmed());
+    }
+}
 
 /// Print a styled header box with a "vibrant" feel
 pub fn print_header(title: &str) {
+    if is_quiet() {
+        return;
+    }
+
     let width = 60;
     let title_len = title.len();
     let padding_left = (width - title_len - 4) / 2;
@@ -34,22 +353,22 @@ pub fn print_header(title: &str) {
     println!();
     println!(
         "{}{}{}",
-        chars::TL_CORNER.bright_cyan(),
-        chars::H_LINE.repeat(width - 2).bright_cyan(),
-        chars::TR_CORNER.bright_cyan()
+        chars::TL_CORNER.themed(Element::Header),
+        chars::H_LINE.repeat(width - 2).themed(Element::Header),
+        chars::TR_CORNER.themed(Element::Header)
     );
-    
-    print!("{}", chars::V_LINE.bright_cyan());
+
+    print!("{}", chars::V_LINE.themed(Element::Header));
     print!("{}", " ".repeat(padding_left));
-    print!("{}", title.bright_white().bold().on_bright_blue());
+    print!("{}", title.themed(Element::Header).bold());
     print!("{}", " ".repeat(padding_right));
-    println!("{}", chars::V_LINE.bright_cyan());
+    println!("{}", chars::V_LINE.themed(Element::Header));
 
     println!(
         "{}{}{}",
-        chars::BL_CORNER.bright_cyan(),
-        chars::H_LINE.repeat(width - 2).bright_cyan(),
-        chars::BR_CORNER.bright_cyan()
+        chars::BL_CORNER.themed(Element::Header),
+        chars::H_LINE.repeat(width - 2).themed(Element::Header),
+        chars::BR_CORNER.themed(Element::Header)
     );
 }
 
@@ -58,9 +377,11 @@ pub fn print_section(title: &str) {
     println!();
     println!(
         "{} {} {}",
-        chars::H_LINE.repeat(3).bright_black(),
-        title.bright_yellow().bold(),
-        chars::H_LINE.repeat(45 - title.len()).bright_black()
+        chars::H_LINE.repeat(3).themed(Element::Muted),
+        title.themed(Element::Section).bold(),
+        chars::H_LINE
+            .repeat(45usize.saturating_sub(title.chars().count()))
+            .themed(Element::Muted)
     );
 }
 
@@ -72,20 +393,26 @@ pub fn print_line(width: usize) {
 
 /// Print operation start message
 pub fn print_start(operation: &str, target: &str) {
+    if is_quiet() {
+        return;
+    }
     println!(
         "{} {} {}",
-        chars::ARROW.bright_cyan(),
-        operation.bright_white(),
-        target.bright_yellow()
+        chars::ARROW.themed(Element::Header),
+        operation.themed(Element::Text),
+        target.themed(Element::Section)
     );
 }
 
 /// Print success message
 pub fn print_success(message: &str) {
+    if is_quiet() {
+        return;
+    }
     println!(
         "{} {}",
-        chars::CHECK.bright_green().bold(),
-        message.bright_green()
+        chars::CHECK.themed(Element::Success).bold(),
+        message.themed(Element::Success)
     );
 }
 
@@ -93,8 +420,8 @@ pub fn print_success(message: &str) {
 pub fn print_error(message: &str) {
     println!(
         "{} {}",
-        chars::CROSS_MARK.bright_red().bold(),
-        message.bright_red()
+        chars::CROSS_MARK.themed(Element::Error).bold(),
+        message.themed(Element::Error)
     );
 }
 
@@ -102,17 +429,20 @@ pub fn print_error(message: &str) {
 pub fn print_warning(message: &str) {
     println!(
         "{} {}",
-        chars::WARNING.bright_yellow().bold(),
-        message.bright_yellow()
+        chars::WARNING.themed(Element::Warning).bold(),
+        message.themed(Element::Warning)
     );
 }
 
 /// Print info message
 pub fn print_info(message: &str) {
+    if is_quiet() {
+        return;
+    }
     println!(
         "{} {}",
-        chars::INFO.bright_blue().bold(),
-        message.bright_white()
+        chars::INFO.themed(Element::Info).bold(),
+        message.themed(Element::Text)
     );
 }
 
@@ -120,8 +450,8 @@ pub fn print_info(message: &str) {
 pub fn print_kv(key: &str, value: &str) {
     println!(
         "  {:.<24} {}",
-        format!("{} ", key).bright_black(),
-        value.bright_white()
+        format!("{} ", key).themed(Element::Muted),
+        value.themed(Element::Text)
     );
 }
 
@@ -129,7 +459,7 @@ pub fn print_kv(key: &str, value: &str) {
 pub fn print_kv_colored(key: &str, value: ColoredString) {
     println!(
         "  {:.<24} {}",
-        format!("{} ", key).bright_black(),
+        format!("{} ", key).themed(Element::Muted),
         value
     );
 }
@@ -140,36 +470,148 @@ pub fn progress_bar(percentage: f64, width: usize) -> String {
     let empty = width.saturating_sub(filled);
     
     let filled_part = if filled > 0 {
-        "█".repeat(filled).bright_cyan()
+        "█".repeat(filled).themed(Element::Progress)
     } else {
         "".normal()
     };
     
     let empty_part = if empty > 0 {
-        "░".repeat(empty).bright_black()
+        "░".repeat(empty).themed(Element::Muted)
     } else {
         "".normal()
     };
 
     format!(
         "{}{}{}{}",
-        chars::V_LINE.bright_black(),
+        chars::V_LINE.themed(Element::Muted),
         filled_part,
         empty_part,
-        chars::V_LINE.bright_black()
+        chars::V_LINE.themed(Element::Muted)
     )
 }
 
 
 
+/// Whether live progress should be drawn (not quiet, stderr is a terminal)
+fn progress_enabled() -> bool {
+    !is_quiet() && std::io::stderr().is_terminal()
+}
+
+/// Spinner for a walk of unknown length, showing files seen and throughput
+pub fn scan_spinner(label: &str) -> ProgressBar {
+    if !progress_enabled() {
+        return ProgressBar::hidden();
+    }
+
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template(&format!(
+                "  {{spinner{}}} {{msg}} {{pos}} files ({{per_sec}}) [{{elapsed}}]",
+                theme().progress_style()
+            ))
+            .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+    );
+    pb.set_message(label.to_string());
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
+
+/// Progress for a disk usage walk: a spinner with entries per second, or
+/// with `total` (entries counted beforehand) a bar with an ETA. The message
+/// carries the bytes found so far.
+pub fn scan_progress(total: Option<u64>) -> ProgressBar {
+    if !progress_enabled() {
+        return ProgressBar::hidden();
+    }
+
+    let style = theme().progress_style();
+    let (pb, template) = match total {
+        Some(total) => (
+            ProgressBar::new(total),
+            format!(
+                "  [{{elapsed_precise}}] [{{bar:30{}}}] {{pos}}/{{len}} entries, {{msg}} ({{per_sec}}, ETA {{eta}})",
+                style
+            ),
+        ),
+        None => (
+            ProgressBar::new_spinner(),
+            format!(
+                "  {{spinner{}}} Scanning {{pos}} entries, {{msg}} ({{per_sec}}) [{{elapsed}}]",
+                style
+            ),
+        ),
+    };
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&template)
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("━━─"),
+    );
+    pb.set_message("0 B");
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
+
+/// Progress bar for a known number of work items
+pub fn work_progress(total: u64) -> ProgressBar {
+    if !progress_enabled() {
+        return ProgressBar::hidden();
+    }
+
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&format!(
+                "  [{{elapsed_precise}}] [{{bar:40{}}}] {{pos}}/{{len}} ({{per_sec}})",
+                theme().progress_style()
+            ))
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("━━─"),
+    );
+    pb
+}
+
+/// Ask a yes/no question on stderr; only an explicit "y" or "yes" counts as yes
+pub fn confirm(question: &str) -> bool {
+    eprint!(
+        "{} {} [y/N] ",
+        chars::WARNING.themed(Element::Warning).bold(),
+        question
+    );
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Ask for a line of input on stderr; `None` once stdin is closed
+pub fn prompt(question: &str) -> Option<String> {
+    eprint!(
+        "{} {} ",
+        chars::ARROW.themed(Element::Info).bold(),
+        question
+    );
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_string()),
+    }
+}
+
 /// Print a result count
 pub fn print_count(count: usize, singular: &str, plural: &str) {
     let word = if count == 1 { singular } else { plural };
     println!(
         "\n{} {} {}",
-        chars::ARROW.bright_black(),
-        count.to_string().bright_green().bold(),
-        word.bright_black()
+        chars::ARROW.themed(Element::Muted),
+        count.to_string().themed(Element::Success).bold(),
+        word.themed(Element::Muted)
     );
 }
 
@@ -183,4 +625,39 @@ mod tests {
         assert!(bar.contains("█"));
         assert!(bar.contains("░"));
     }
+
+    #[test]
+    fn test_theme_from_config() {
+        let mut config = ThemeConfig {
+            name: Some("ocean".to_string()),
+            ..Default::default()
+        };
+        config.palettes.insert(
+            "ocean".to_string(),
+            [("base", "light"), ("path", "#268bd2")]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+        config
+            .colors
+            .insert("size".to_string(), "bright_magenta".to_string());
+
+        let theme = Theme::from_config(&config).unwrap();
+        assert_eq!(
+            theme.color(Element::Path),
+            Some(Color::TrueColor {
+                r: 0x26,
+                g: 0x8b,
+                b: 0xd2
+            })
+        );
+        assert_eq!(theme.color(Element::Size), Some(Color::BrightMagenta));
+        assert_eq!(theme.color(Element::Text), Some(Color::Black));
+
+        config.colors.insert("sizes".to_string(), "red".to_string());
+        assert!(Theme::from_config(&config).is_err());
+        assert_eq!(parse_color("none").unwrap(), None);
+        assert!(parse_color("#12345").is_err());
+    }
 }
@@ -1,9 +1,18 @@
 use anyhow::Result;
+use glob::{MatchOptions, Pattern};
 use humansize::{format_size, BINARY};
 use sha2::{Digest, Sha256, Sha512};
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::config::IgnoreConfig;
+use crate::skipped;
+use crate::ui;
 
 /// Format bytes to human readable size
 pub fn format_bytes(bytes: u64) -> String {
@@ -106,6 +115,90 @@ pub fn hash_file_md5(path: &Path) -> Result<String> {
     Ok(format!("{:x}", context.compute()))
 }
 
+/// Calculate BLAKE3 hash of a file
+pub fn hash_file_blake3(path: &Path) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    read_chunks(path, |chunk| {
+        hasher.update(chunk);
+    })?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Calculate 64-bit XXH3 hash of a file (fast, not cryptographic)
+pub fn hash_file_xxh3(path: &Path) -> Result<String> {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    read_chunks(path, |chunk| hasher.update(chunk))?;
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
+/// Feed a file to `consume` in large chunks, so SIMD hashers see full blocks
+fn read_chunks(path: &Path, mut consume: impl FnMut(&[u8])) -> Result<()> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 256 * 1024];
+
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        consume(&buffer[..bytes_read]);
+    }
+}
+
+/// Algorithms accepted by `hash_file_with`
+pub const HASH_ALGORITHMS: [&str; 5] = ["sha256", "sha512", "md5", "blake3", "xxh3"];
+
+/// Hash a file with an algorithm named on the command line
+pub fn hash_file_with(path: &Path, algorithm: &str) -> Result<String> {
+    match algorithm {
+        "sha256" => hash_file_sha256(path),
+        "sha512" => hash_file_sha512(path),
+        "md5" => hash_file_md5(path),
+        "blake3" => hash_file_blake3(path),
+        "xxh3" => hash_file_xxh3(path),
+        _ => Err(anyhow::anyhow!("Unsupported algorithm: {}", algorithm)),
+    }
+}
+
+/// Hash a file with the algorithm implied by a hex digest length
+/// (32 = MD5, 64 = SHA256, 128 = SHA512)
+pub fn hash_file_for_digest_len(path: &Path, hex_len: usize) -> Result<String> {
+    match hex_len {
+        32 => hash_file_md5(path),
+        64 => hash_file_sha256(path),
+        128 => hash_file_sha512(path),
+        _ => Err(anyhow::anyhow!(
+            "No hash algorithm produces {} hex digits",
+            hex_len
+        )),
+    }
+}
+
+/// Read the digests listed in a checksum manifest, lowercased. Accepts
+/// `sha256sum`/`md5sum` output and BSD-style `SHA256 (file) = digest` lines.
+pub fn read_checksum_manifest(path: &Path) -> Result<HashSet<String>> {
+    let content = fs::read_to_string(path)?;
+    let mut digests = HashSet::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let digest = match line.rsplit_once(") = ") {
+            Some((_, digest)) => digest.trim(),
+            None => line.split_whitespace().next().unwrap_or(""),
+        };
+
+        if matches!(digest.len(), 32 | 64 | 128) && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            digests.insert(digest.to_lowercase());
+        }
+    }
+
+    Ok(digests)
+}
+
 /// Check if a file matches the given extensions filter
 pub fn matches_extensions(path: &Path, extensions: &Option<String>) -> bool {
     match extensions {
@@ -122,38 +215,344 @@ pub fn matches_extensions(path: &Path, extensions: &Option<String>) -> bool {
     }
 }
 
-/// Check if path should be skipped (hidden files, common ignore patterns)
-pub fn should_skip(path: &Path, include_hidden: bool) -> bool {
-    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-    
-    // Skip hidden files if not requested
-    if !include_hidden && name.starts_with('.') {
-        return true;
+/// How path globs match: `*` and `?` stay within one path component, only
+/// `**` crosses directories
+const GLOB_MATCH: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Path globs (search -g, rename --include): a file is selected when it
+/// matches one of the `include` globs (if any) and none of the `!exclude` ones.
+/// Globs starting with / are anchored to the root the file was found under.
+pub struct Globs {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    roots: Vec<PathBuf>,
+}
+
+struct Glob {
+    pattern: Pattern,
+    /// Matched against the path below the root
+    anchored: bool,
+}
+
+impl Globs {
+    pub fn new(globs: &[String], roots: &[impl AsRef<Path>]) -> Result<Self> {
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        for glob in globs {
+            let (list, glob) = match glob.strip_prefix('!') {
+                Some(rest) => (&mut exclude, rest),
+                None => (&mut include, glob.as_str()),
+            };
+            list.push(match glob.strip_prefix('/') {
+                Some(relative) => Glob {
+                    pattern: Pattern::new(relative)
+                        .map_err(|e| anyhow::anyhow!("Invalid glob {}: {}", glob, e))?,
+                    anchored: true,
+                },
+                None => Glob {
+                    pattern: path_glob(glob)?,
+                    anchored: false,
+                },
+            });
+        }
+        let roots = roots.iter().map(|r| r.as_ref().to_path_buf()).collect();
+        Ok(Globs {
+            include,
+            exclude,
+            roots,
+        })
     }
-    
-    // Skip common non-useful directories
-    let skip_dirs = [
-        "node_modules",
-        ".git",
-        ".svn",
-        ".hg",
-        "__pycache__",
-        ".cache",
-        "target",
-        ".idea",
-        ".vscode",
-        "vendor",
-        "dist",
-        "build",
-    ];
-    
-    if path.is_dir() && skip_dirs.contains(&name) {
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let relative = self
+            .roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        let relative: PathBuf = relative
+            .components()
+            .skip_while(|c| matches!(c, Component::CurDir))
+            .collect();
+        let matches = |glob: &Glob| {
+            let path = if glob.anchored { &relative } else { path };
+            glob_matches(&glob.pattern, path)
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Compile a path glob; without a leading / it may match from any directory
+/// down, so `src/**/*.ts` holds under every root
+pub fn path_glob(glob: &str) -> Result<Pattern> {
+    let anchored = if glob.starts_with('/') || glob.starts_with("**") {
+        glob.to_string()
+    } else {
+        format!("**/{}", glob)
+    };
+    Pattern::new(&anchored).map_err(|e| anyhow::anyhow!("Invalid glob {}: {}", glob, e))
+}
+
+/// Whether `path` matches a glob from `path_glob`, with `*` not crossing `/`
+pub fn glob_matches(pattern: &Pattern, path: &Path) -> bool {
+    pattern.matches_path_with(path, GLOB_MATCH)
+}
+
+/// Unwrap a directory walk entry, recording traversal errors for the end-of-run summary
+pub fn walk_ok(entry: walkdir::Result<DirEntry>) -> Option<DirEntry> {
+    match entry {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            skipped::record_walk(&e);
+            None
+        }
+    }
+}
+
+/// Create a new directory only this user can enter, named `prefix` plus a
+/// unique suffix under the system temp directory. The directory is never an
+/// existing one, so another user can't plant files or symlinks in it.
+pub fn private_temp_dir(prefix: &str) -> io::Result<PathBuf> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    for attempt in 0u64..100 {
+        let suffix = xxhash_rust::xxh3::xxh3_64(&[seed, attempt].map(u64::to_le_bytes).concat());
+        let dir = std::env::temp_dir().join(format!(
+            "{}-{}-{:x}",
+            prefix,
+            std::process::id(),
+            suffix
+        ));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "could not create a unique temporary directory",
+    ))
+}
+
+/// Path argument that means "read the file list from stdin"
+pub const STDIN_PATH: &str = "-";
+
+/// Read newline- or NUL-delimited file paths from stdin
+pub fn read_stdin_paths() -> Result<Vec<PathBuf>> {
+    let mut input = Vec::new();
+    io::stdin().lock().read_to_end(&mut input)?;
+    Ok(split_path_list(&input))
+}
+
+/// Split a `find -print0` / `find` style list into paths (NUL wins if present)
+fn split_path_list(input: &[u8]) -> Vec<PathBuf> {
+    let separator = if input.contains(&0) { b'\0' } else { b'\n' };
+
+    input
+        .split(|b| *b == separator)
+        .map(|raw| {
+            if separator == b'\n' {
+                raw.strip_suffix(b"\r").unwrap_or(raw)
+            } else {
+                raw
+            }
+        })
+        .filter(|raw| !raw.is_empty())
+        .map(bytes_to_path)
+        .collect()
+}
+
+/// Path from raw bytes as read from stdin or written by `as_encoded_bytes`
+#[cfg(unix)]
+pub fn bytes_to_path(raw: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(raw))
+}
+
+#[cfg(not(unix))]
+pub fn bytes_to_path(raw: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(raw).into_owned())
+}
+
+/// Drop root arguments that repeat or sit inside another root, so files
+/// under overlapping roots are visited only once
+pub fn distinct_roots(paths: &[String]) -> Vec<String> {
+    let canonical: Vec<Option<PathBuf>> = paths
+        .iter()
+        .map(|p| {
+            if p == STDIN_PATH {
+                None
+            } else {
+                fs::canonicalize(p).ok()
+            }
+        })
+        .collect();
+
+    paths
+        .iter()
+        .enumerate()
+        .filter(|(i, path)| {
+            let Some(own) = &canonical[*i] else {
+                return true;
+            };
+            let covered = canonical.iter().enumerate().any(|(j, other)| {
+                other.as_ref().is_some_and(|other| {
+                    (own != other && own.starts_with(other)) || (own == other && j < *i)
+                })
+            });
+            if covered {
+                ui::print_verbose(&format!(
+                    "skipped: {} (already covered by another path)",
+                    path
+                ));
+            }
+            !covered
+        })
+        .map(|(_, path)| path.clone())
+        .collect()
+}
+
+/// Walk every root in turn, recording traversal errors. Links are never followed:
+/// symlinks and Windows junctions show up with a symlink file type, so callers that
+/// test `entry.file_type()` neither descend into them nor count their targets twice
+pub fn walk_roots(
+    paths: &[String],
+    one_file_system: bool,
+) -> impl Iterator<Item = DirEntry> + use<> {
+    distinct_roots(paths).into_iter().flat_map(move |root| {
+        WalkDir::new(root)
+            .follow_links(false)
+            .same_file_system(one_file_system)
+            .into_iter()
+            .filter_map(walk_ok)
+    })
+}
+
+/// Regular files to process for the path arguments: everything under each directory
+/// (skipping hidden/ignored entries unless `include_hidden`, and other file
+/// systems with `one_file_system`), or the explicit list from stdin for a "-"
+/// argument
+pub fn input_files(
+    paths: &[String],
+    include_hidden: bool,
+    one_file_system: bool,
+) -> Result<Box<dyn Iterator<Item = PathBuf>>> {
+    let mut stdin_files = Vec::new();
+    if paths.iter().any(|p| p == STDIN_PATH) {
+        stdin_files = read_stdin_paths()?;
+    }
+    let stdin_files = stdin_files.into_iter().filter(|p| match p.metadata() {
+        Ok(metadata) if metadata.is_file() => true,
+        Ok(_) => {
+            ui::print_verbose(&format!("skipped: {} (not a file)", p.display()));
+            false
+        }
+        Err(e) => {
+            skipped::record_io(p, &e);
+            false
+        }
+    });
+
+    let roots: Vec<String> = paths.iter().filter(|p| *p != STDIN_PATH).cloned().collect();
+    let walked = walk_roots(&roots, one_file_system)
+        .filter(|e| e.file_type().is_file())
+        .map(DirEntry::into_path)
+        .filter(move |p| !should_skip(p, include_hidden));
+    Ok(Box::new(stdin_files.chain(walked)))
+}
+
+/// Directories left out of scans unless the config says otherwise
+pub const DEFAULT_IGNORED_DIRS: [&str; 12] = [
+    "node_modules",
+    ".git",
+    ".svn",
+    ".hg",
+    "__pycache__",
+    ".cache",
+    "target",
+    ".idea",
+    ".vscode",
+    "vendor",
+    "dist",
+    "build",
+];
+
+static IGNORED_DIRS: RwLock<Option<Vec<String>>> = RwLock::new(None);
+
+/// The ignore list after the `[ignore]` config table: `dirs` replaces the
+/// built-in list, then `add` and `keep` extend or trim it
+pub fn ignored_dirs(config: &IgnoreConfig) -> Vec<String> {
+    let mut dirs: Vec<String> = match &config.dirs {
+        Some(dirs) => dirs.clone(),
+        None => DEFAULT_IGNORED_DIRS.iter().map(|d| d.to_string()).collect(),
+    };
+    for dir in &config.add {
+        if !dirs.contains(dir) {
+            dirs.push(dir.clone());
+        }
+    }
+    dirs.retain(|dir| !config.keep.contains(dir));
+    dirs
+}
+
+/// Set the process-wide list of ignored directory names
+pub fn set_ignored_dirs(dirs: Vec<String>) {
+    if let Ok(mut current) = IGNORED_DIRS.write() {
+        *current = Some(dirs);
+    }
+}
+
+/// The process-wide list of ignored directory names
+pub fn current_ignored_dirs() -> Vec<String> {
+    match IGNORED_DIRS.read().as_deref() {
+        Ok(Some(dirs)) => dirs.clone(),
+        _ => DEFAULT_IGNORED_DIRS.iter().map(|d| d.to_string()).collect(),
+    }
+}
+
+/// Check if path is a directory on the ignore list (node_modules, target, ...)
+pub fn is_ignored_dir(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let listed = match IGNORED_DIRS.read().as_deref() {
+        Ok(Some(dirs)) => dirs.iter().any(|d| d == name),
+        _ => DEFAULT_IGNORED_DIRS.contains(&name),
+    };
+    if listed && path.is_dir() {
+        ui::print_trace(&format!("skip {} (ignored directory)", path.display()));
         return true;
     }
-    
     false
 }
 
+/// Check if path is hidden (name starts with a dot)
+pub fn is_hidden(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|name| name.starts_with('.'));
+    if hidden {
+        ui::print_trace(&format!("skip {} (hidden)", path.display()));
+    }
+    hidden
+}
+
+/// Check if path should be skipped (hidden files, common ignore patterns)
+pub fn should_skip(path: &Path, include_hidden: bool) -> bool {
+    (!include_hidden && is_hidden(path)) || is_ignored_dir(path)
+}
+
 /// Get file extension as lowercase string
 pub fn get_extension(path: &Path) -> String {
     path.extension()
@@ -162,3 +561,388 @@ pub fn get_extension(path: &Path) -> String {
         .unwrap_or_else(|| "(no ext)".to_string())
 }
 
+/// Number of hardlinks to a file's data (always 1 where that isn't known)
+#[cfg(unix)]
+pub fn link_count(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+pub fn link_count(_metadata: &Metadata) -> u64 {
+    1
+}
+
+/// Bytes the file at `path` takes up on disk: its allocated blocks, fewer
+/// than its length for sparse or compressed files and more for small ones
+/// (the length where that isn't known)
+#[cfg(unix)]
+pub fn allocated_size(_path: &Path, metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512
+}
+
+/// On Windows: the compressed or sparse size NTFS reports, rounded up to
+/// whole clusters of the volume
+#[cfg(windows)]
+pub fn allocated_size(path: &Path, metadata: &Metadata) -> u64 {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetCompressedFileSizeW;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut high = 0u32;
+    // SAFETY: `wide` is NUL-terminated and `high` is a valid out pointer
+    let low = unsafe { GetCompressedFileSizeW(wide.as_ptr(), &mut high) };
+    // u32::MAX is also a valid low half, so only the error code tells
+    if low == u32::MAX && io::Error::last_os_error().raw_os_error() != Some(0) {
+        return metadata.len();
+    }
+    let size = (u64::from(high) << 32) | u64::from(low);
+    match cluster_size(&wide) {
+        Some(cluster) => size.div_ceil(cluster) * cluster,
+        None => size,
+    }
+}
+
+/// Cluster size of the volume holding the NUL-terminated wide `path`, cached
+/// per volume
+#[cfg(windows)]
+fn cluster_size(path: &[u16]) -> Option<u64> {
+    use std::sync::Mutex;
+    use windows_sys::Win32::Storage::FileSystem::{GetDiskFreeSpaceW, GetVolumePathNameW};
+
+    static CLUSTER_SIZES: Mutex<Option<HashMap<Vec<u16>, u64>>> = Mutex::new(None);
+
+    let mut root = vec![0u16; path.len() + 1];
+    // SAFETY: `path` is NUL-terminated and `root` holds as many characters
+    // as the buffer length says; a volume root is never longer than its path
+    if unsafe { GetVolumePathNameW(path.as_ptr(), root.as_mut_ptr(), root.len() as u32) } == 0 {
+        return None;
+    }
+    // Keep the root and its NUL, not the rest of the buffer, as the cache key
+    let end = root.iter().position(|&c| c == 0).unwrap_or(root.len() - 1);
+    root.truncate(end + 1);
+    let mut sizes = CLUSTER_SIZES.lock().ok()?;
+    let sizes = sizes.get_or_insert_with(HashMap::new);
+    if let Some(&size) = sizes.get(&root) {
+        return Some(size);
+    }
+    let (mut sectors, mut bytes, mut free, mut total) = (0u32, 0u32, 0u32, 0u32);
+    // SAFETY: `root` is NUL-terminated and the out pointers are valid
+    if unsafe { GetDiskFreeSpaceW(root.as_ptr(), &mut sectors, &mut bytes, &mut free, &mut total) }
+        == 0
+    {
+        return None;
+    }
+    let size = u64::from(sectors) * u64::from(bytes);
+    if size == 0 {
+        return None;
+    }
+    sizes.insert(root, size);
+    Some(size)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn allocated_size(_path: &Path, metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Size and free space of the volume a path is on
+#[derive(Clone, Copy, PartialEq)]
+pub struct DiskSpace {
+    pub total: u64,
+    /// Free for unprivileged users (excludes the root reserve)
+    pub available: u64,
+    pub free: u64,
+}
+
+impl DiskSpace {
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.free)
+    }
+}
+
+#[cfg(unix)]
+pub fn disk_space(path: &Path) -> Option<DiskSpace> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out pointer
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some(DiskSpace {
+        total: stat.f_blocks as u64 * block,
+        available: stat.f_bavail as u64 * block,
+        free: stat.f_bfree as u64 * block,
+    })
+}
+
+#[cfg(windows)]
+pub fn disk_space(path: &Path) -> Option<DiskSpace> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let (mut available, mut total, mut free) = (0u64, 0u64, 0u64);
+    // SAFETY: `wide` is NUL-terminated and the out pointers are valid
+    if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, &mut total, &mut free) } == 0 {
+        return None;
+    }
+    Some(DiskSpace {
+        total,
+        available,
+        free,
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn disk_space(_path: &Path) -> Option<DiskSpace> {
+    None
+}
+
+/// Files below this size are never called sparse: block rounding and inline
+/// data make small files look odd either way
+const SPARSE_MIN_SIZE: u64 = 1024 * 1024;
+
+/// Whether a file has far fewer blocks allocated than its length says (VM
+/// images, preallocated databases, compressed file systems)
+pub fn is_sparse(size: u64, allocated: u64) -> bool {
+    size >= SPARSE_MIN_SIZE && allocated < size / 2
+}
+
+/// Device and inode of a file with more than one hardlink, identifying the
+/// data all its paths share
+#[cfg(unix)]
+pub fn hardlink_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn hardlink_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Owning user and group ids of a file
+#[cfg(unix)]
+pub fn owner_ids(metadata: &Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn owner_ids(_metadata: &Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+/// User and group names by id, looked up through the system's user database
+/// (so LDAP and other NSS sources count) and cached per id; ids it doesn't
+/// know (e.g. from another machine) show as numbers
+#[derive(Default)]
+pub struct OwnerNames {
+    users: RefCell<HashMap<u32, String>>,
+    groups: RefCell<HashMap<u32, String>>,
+}
+
+impl OwnerNames {
+    pub fn load() -> Self {
+        Self::default()
+    }
+
+    pub fn user(&self, uid: u32) -> String {
+        self.users
+            .borrow_mut()
+            .entry(uid)
+            .or_insert_with(|| user_name(uid).unwrap_or_else(|| uid.to_string()))
+            .clone()
+    }
+
+    pub fn group(&self, gid: u32) -> String {
+        self.groups
+            .borrow_mut()
+            .entry(gid)
+            .or_insert_with(|| group_name(gid).unwrap_or_else(|| gid.to_string()))
+            .clone()
+    }
+}
+
+/// Calls a `get*id_r` function, growing its string buffer while it reports
+/// ERANGE; `lookup` returns the error code and the name on success
+#[cfg(unix)]
+fn lookup_name(
+    mut lookup: impl FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<*const libc::c_char>),
+) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        match lookup(&mut buffer) {
+            (libc::ERANGE, _) if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+            (0, Some(name)) => {
+                let name = unsafe { std::ffi::CStr::from_ptr(name) };
+                return Some(name.to_string_lossy().into_owned());
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    lookup_name(|buffer| {
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getpwuid_r(uid, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result)
+        };
+        (code, (!result.is_null()).then_some(entry.pw_name as *const _))
+    })
+}
+
+#[cfg(unix)]
+fn group_name(gid: u32) -> Option<String> {
+    lookup_name(|buffer| {
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getgrgid_r(gid, &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result)
+        };
+        (code, (!result.is_null()).then_some(entry.gr_name as *const _))
+    })
+}
+
+#[cfg(not(unix))]
+fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn group_name(_gid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_owner_names() {
+        let names = OwnerNames::load();
+        assert_eq!(names.user(0), "root");
+        assert_eq!(names.user(0), "root");
+        assert_eq!(names.user(u32::MAX - 7), (u32::MAX - 7).to_string());
+        assert!(!names.group(0).is_empty());
+    }
+
+    #[test]
+    fn test_is_sparse() {
+        assert!(is_sparse(50 << 30, 1 << 30));
+        assert!(!is_sparse(50 << 30, 50 << 30));
+        assert!(!is_sparse(4096, 0));
+    }
+
+    #[test]
+    fn test_ignored_dirs() {
+        let config = IgnoreConfig {
+            dirs: None,
+            add: vec!["Pods".into()],
+            keep: vec!["build".into(), "dist".into()],
+        };
+        let dirs = ignored_dirs(&config);
+        assert!(dirs.contains(&"node_modules".to_string()));
+        assert!(dirs.contains(&"Pods".to_string()));
+        assert!(!dirs.contains(&"build".to_string()));
+
+        let config = IgnoreConfig {
+            dirs: Some(vec!["node_modules".into()]),
+            ..Default::default()
+        };
+        assert_eq!(ignored_dirs(&config), ["node_modules"]);
+    }
+
+    #[test]
+    fn test_globs() {
+        let root = ["."];
+        let globs = Globs::new(&["src/**/*.test.ts".into(), "!**/vendor/**".into()], &root).unwrap();
+        assert!(globs.matches(Path::new("./src/a.test.ts")));
+        assert!(globs.matches(Path::new("/work/app/src/ui/b.test.ts")));
+        assert!(!globs.matches(Path::new("./src/a.ts")));
+        assert!(!globs.matches(Path::new("./src/vendor/a.test.ts")));
+
+        let globs = Globs::new(&["!target/**".into()], &root).unwrap();
+        assert!(globs.matches(Path::new("./src/main.rs")));
+        assert!(!globs.matches(Path::new("./target/debug/build.rs")));
+        assert!(Globs::new(&["a[".into()], &root).is_err());
+
+        // * stays within one directory
+        let globs = Globs::new(&["src/*.rs".into()], &root).unwrap();
+        assert!(globs.matches(Path::new("./src/main.rs")));
+        assert!(!globs.matches(Path::new("./src/deep/er/x.rs")));
+        let globs = Globs::new(&["IMG_*".into()], &root).unwrap();
+        assert!(globs.matches(Path::new("./photos/IMG_1.jpg")));
+        assert!(globs.matches(Path::new("IMG_1.jpg")));
+        assert!(!globs.matches(Path::new("./IMG_dir/notes.txt")));
+
+        // A leading / anchors the glob to the root
+        let globs = Globs::new(&["/src/*.rs".into()], &root).unwrap();
+        assert!(globs.matches(Path::new("./src/main.rs")));
+        assert!(!globs.matches(Path::new("./lib/src/main.rs")));
+        let globs = Globs::new(&["/src/*.rs".into()], &["/work/app"]).unwrap();
+        assert!(globs.matches(Path::new("/work/app/src/main.rs")));
+    }
+
+    #[test]
+    fn test_private_temp_dir() {
+        let a = private_temp_dir("ftools-test").unwrap();
+        let b = private_temp_dir("ftools-test").unwrap();
+        assert_ne!(a, b);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&a).unwrap().permissions().mode() & 0o777, 0o700);
+        }
+        fs::remove_dir(&a).unwrap();
+        fs::remove_dir(&b).unwrap();
+    }
+
+    #[test]
+    fn test_split_path_list() {
+        assert_eq!(
+            split_path_list(b"a.txt\r\nsub/b c.txt\n\n"),
+            vec![PathBuf::from("a.txt"), PathBuf::from("sub/b c.txt")]
+        );
+        assert_eq!(
+            split_path_list(b"with\nnewline\0b\0"),
+            vec![PathBuf::from("with\nnewline"), PathBuf::from("b")]
+        );
+    }
+
+    #[test]
+    fn test_hash_file_with() {
+        let path = std::env::temp_dir().join(format!("ftools-abc-{}", std::process::id()));
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            hash_file_with(&path, "blake3").unwrap(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(hash_file_with(&path, "xxh3").unwrap(), "78af5f94892f3950");
+        assert!(hash_file_with(&path, "crc32").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_distinct_roots() {
+        let base = std::env::temp_dir().join(format!("ftools-roots-{}", std::process::id()));
+        fs::create_dir_all(base.join("a/sub")).unwrap();
+        fs::create_dir_all(base.join("b")).unwrap();
+        let root = |p: &str| base.join(p).display().to_string();
+
+        let roots = distinct_roots(&[root("a/sub"), root("a"), root("b"), root("a")]);
+        assert_eq!(roots, vec![root("a"), root("b")]);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}